@@ -0,0 +1,109 @@
+//! Bytes per enemy snapshot, the way they used to go out (one full precision
+//! `EntityPosition` per enemy) against the way they go out now (one `EnemyPositions` with
+//! quantized positions, compressed when that was agreed on).
+//!
+//!     cargo bench --bench snapshot_size
+
+// only the wire format is needed, the rest of common just comes along, tests included since
+// benches are built with `cfg(test)`
+#[allow(dead_code, unused_imports)]
+#[path = "../src/common/mod.rs"]
+mod common;
+
+use std::time::Instant;
+
+use glam::Vec2;
+
+use common::{
+    compression,
+    level::Level,
+    quantize::{dequantize_pos, quantize_pos},
+    rng::Rng,
+    server_to_client::{PackedPosition, ServerToClientMessage, ServerToClientPacket},
+};
+
+/// IPv4 and UDP headers, paid once per datagram.
+const DATAGRAM_OVERHEAD: usize = 28;
+const ENEMY_COUNTS: [usize; 4] = [4, 16, 64, 256];
+const ROUNDS: u32 = 10_000;
+
+fn serialize(message: ServerToClientMessage) -> Vec<u8> {
+    let packet = ServerToClientPacket {
+        seq: 0,
+        reliable_seq: None,
+        message,
+    };
+    bincode::serialize(&packet).expect("packets serialize")
+}
+
+/// Enemies tend to bunch up around the players, so they are scattered around a few spots.
+fn enemies(rng: &mut Rng, count: usize, bounds: Vec2) -> Vec<(u32, Vec2)> {
+    let spots = [bounds * 0.25, bounds * 0.5, bounds * Vec2::new(0.75, 0.4)];
+    (0..count)
+        .map(|i| {
+            let spread = Vec2::new(rng.unit() - 0.5, rng.unit() - 0.5) * 96.0;
+            let pos = (spots[i % spots.len()] + spread).clamp(Vec2::ZERO, bounds);
+            (100 + i as u32, pos)
+        })
+        .collect()
+}
+
+fn main() {
+    let level = Level::load("arena").expect("run from the repository root");
+    let bounds = level.bounds();
+    let mut rng = Rng::new(49);
+
+    println!(
+        "{} ({} x {}), bytes per snapshot including {} bytes of headers per datagram",
+        level.name, bounds.x, bounds.y, DATAGRAM_OVERHEAD
+    );
+    println!(
+        "{:>8} {:>12} {:>12} {:>12} {:>8} {:>10} {:>10}",
+        "enemies", "before", "quantized", "compressed", "saved", "error", "pack+unpack"
+    );
+    for count in ENEMY_COUNTS {
+        let enemies = enemies(&mut rng, count, bounds);
+
+        let before: usize = enemies
+            .iter()
+            .map(|&(entity_id, pos)| {
+                serialize(ServerToClientMessage::EntityPosition { entity_id, pos }).len()
+                    + DATAGRAM_OVERHEAD
+            })
+            .sum();
+
+        let positions: Vec<PackedPosition> = enemies
+            .iter()
+            .map(|&(entity_id, pos)| PackedPosition {
+                entity_id,
+                pos: quantize_pos(pos, bounds),
+            })
+            .collect();
+        let error = enemies
+            .iter()
+            .zip(&positions)
+            .map(|(&(_, pos), packed)| dequantize_pos(packed.pos, bounds).distance(pos))
+            .fold(0.0, f32::max);
+        let packet = serialize(ServerToClientMessage::EnemyPositions { positions });
+        let quantized = compression::pack(&packet, false).len() + DATAGRAM_OVERHEAD;
+        let compressed = compression::pack(&packet, true).len() + DATAGRAM_OVERHEAD;
+
+        let started = Instant::now();
+        for _ in 0..ROUNDS {
+            let datagram = compression::pack(&packet, true);
+            std::hint::black_box(compression::unpack(&datagram));
+        }
+        let per_round = started.elapsed() / ROUNDS;
+
+        println!(
+            "{:>8} {:>12} {:>12} {:>12} {:>7.0}% {:>10.3} {:>10.1?}",
+            count,
+            before,
+            quantized,
+            compressed,
+            (1.0 - compressed as f32 / before as f32) * 100.0,
+            error,
+            per_round
+        );
+    }
+}
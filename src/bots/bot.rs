@@ -0,0 +1,233 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use glam::Vec2;
+use hecs::World;
+use tokio::io;
+
+use crate::{
+    client::{
+        self,
+        components::{Enemy, InputControlled, Transform},
+        message_processing::process_message_queue,
+        network_entities::interval_transmit_position,
+        settings::POSITION_TRANSMIT_FREQUENCY,
+        state::State,
+        udp_networking::{CLIENT_ID, INCOMING_MESSAGE_QUEUE, OUTBOUND_MESSAGE_QUEUE},
+    },
+    common::{
+        client_to_server::ClientToServerMessageData,
+        game_objects::{MatchPhase, SyncMode},
+        inputs::PlayingInputs,
+        rng::Rng,
+    },
+};
+
+use super::{link::Link, report::DesyncStats};
+
+/// Room management requests are repeated at most this often, in ticks.
+const REQUEST_INTERVAL: u32 = 60;
+/// Random bots pick a new direction this often, in ticks.
+const WANDER_INTERVAL: u32 = 45;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Script {
+    /// Wanders in random directions and sprays at the nearest enemy.
+    Random,
+    /// Runs in circles and shoots whenever it can.
+    Circle,
+}
+
+/// A client without a window: the regular client state and world, driven by a script.
+pub struct Bot {
+    pub index: usize,
+    pub name: String,
+    pub ecs: World,
+    pub state: State,
+    pub client_id: u32,
+    pub link: Arc<Link>,
+    /// Room this bot should be in, decided by the runner. `None` to open one.
+    pub target_room: Option<String>,
+    /// Leaders open a room and start the match once their group is in and ready.
+    pub leader: bool,
+    pub group_size: usize,
+    pub desync: DesyncStats,
+    script: Script,
+    /// Leaders switch their room to this before starting.
+    sync_mode: SyncMode,
+    rng: Rng,
+    ticks: u32,
+    last_request: Option<u32>,
+}
+
+impl Bot {
+    pub async fn connect(
+        index: usize,
+        server_addr: SocketAddr,
+        script: Script,
+        sync_mode: SyncMode,
+        leader: bool,
+        group_size: usize,
+    ) -> io::Result<Bot> {
+        let link = Link::connect(server_addr).await?;
+        let name = format!("bot{}", index);
+        link.send(ClientToServerMessageData::Join { name: name.clone() });
+        let mut state = State::new();
+        state.server = Some(server_addr);
+        Ok(Bot {
+            index,
+            name,
+            ecs: World::new(),
+            state,
+            client_id: 0,
+            link,
+            target_room: None,
+            leader,
+            group_size,
+            desync: DesyncStats::default(),
+            script,
+            sync_mode,
+            rng: Rng::new(0x9e37_79b9_7f4a_7c15 ^ index as u64),
+            ticks: 0,
+            last_request: None,
+        })
+    }
+
+    /// Runs one client step for this bot through the regular client code. That code talks
+    /// to the connection through process-wide queues, so the bot's own are swapped in
+    /// around it. Bots must be ticked one at a time.
+    pub async fn tick(&mut self) {
+        CLIENT_ID.store(self.client_id, std::sync::atomic::Ordering::SeqCst);
+        loop {
+            for _ in 0..INCOMING_MESSAGE_QUEUE.capacity() {
+                let Some(message) = self.link.incoming.pop() else {
+                    break;
+                };
+                if INCOMING_MESSAGE_QUEUE.push(message).is_err() {
+                    eprintln!("Inbound message queue full: dropping message");
+                }
+            }
+            process_message_queue(&mut self.ecs, &mut self.state).await;
+            if self.link.incoming.is_empty() {
+                break;
+            }
+        }
+        self.client_id = CLIENT_ID.load(std::sync::atomic::Ordering::SeqCst);
+
+        self.think();
+        client::game::advance(&mut self.ecs, &mut self.state);
+        self.ticks += 1;
+        interval_transmit_position(
+            self.ticks,
+            POSITION_TRANSMIT_FREQUENCY,
+            &self.ecs,
+            &self.state,
+        );
+
+        while let Some(message) = OUTBOUND_MESSAGE_QUEUE.pop() {
+            if self.link.outbound.push(message).is_err() {
+                eprintln!("Outbound message queue full: dropping message");
+            }
+        }
+    }
+
+    /// Gets into the right room, readies up and plays.
+    fn think(&mut self) {
+        self.state.playing_inputs = PlayingInputs::new();
+        match (&self.state.room, &self.target_room) {
+            (None, None) if self.leader => self.request(ClientToServerMessageData::CreateRoom),
+            (None, Some(code)) => {
+                let code = code.clone();
+                self.request(ClientToServerMessageData::JoinRoom { code });
+            }
+            (None, None) => {}
+            (Some(_), _) => match self.state.phase {
+                MatchPhase::Lobby => self.ready_up(),
+                MatchPhase::InGame => self.play(),
+                MatchPhase::Results => {}
+            },
+        }
+    }
+
+    fn ready_up(&mut self) {
+        let own_id = self.client_id;
+        let ready = self
+            .state
+            .roster
+            .iter()
+            .find(|entry| entry.client_id == own_id)
+            .is_some_and(|entry| entry.ready);
+        if !ready {
+            self.request(ClientToServerMessageData::SetReady { ready: true });
+            return;
+        }
+        let everyone_ready = self.state.roster.len() >= self.group_size
+            && self.state.roster.iter().all(|entry| entry.ready);
+        if !self.leader || self.state.host != Some(own_id) {
+            return;
+        }
+        if self.state.sync_mode != self.sync_mode {
+            let mode = self.sync_mode;
+            self.request(ClientToServerMessageData::SetSyncMode { mode });
+        } else if everyone_ready {
+            self.request(ClientToServerMessageData::StartMatch);
+        }
+    }
+
+    fn play(&mut self) {
+        let Some(pos) = self
+            .ecs
+            .query::<&Transform>()
+            .with::<&InputControlled>()
+            .iter()
+            .next()
+            .map(|(_, transform)| transform.pos)
+        else {
+            return;
+        };
+        let nearest_enemy = self
+            .ecs
+            .query::<&Transform>()
+            .with::<&Enemy>()
+            .iter()
+            .map(|(_, transform)| transform.pos)
+            .min_by(|a, b| a.distance(pos).total_cmp(&b.distance(pos)));
+
+        let inputs = &mut self.state.playing_inputs;
+        match self.script {
+            Script::Random => {
+                if self.ticks.is_multiple_of(WANDER_INTERVAL) {
+                    inputs.left = self.rng.chance(2);
+                    inputs.right = !inputs.left && self.rng.chance(2);
+                    inputs.up = self.rng.chance(2);
+                    inputs.down = !inputs.up && self.rng.chance(2);
+                } else {
+                    *inputs = self.state.previous_playing_inputs;
+                }
+                inputs.shoot = nearest_enemy.is_some() && self.rng.chance(3);
+                inputs.revive = self.rng.chance(30);
+            }
+            Script::Circle => {
+                let quarter = (self.ticks / WANDER_INTERVAL) % 4;
+                inputs.right = quarter == 0;
+                inputs.down = quarter == 1;
+                inputs.left = quarter == 2;
+                inputs.up = quarter == 3;
+                inputs.shoot = true;
+            }
+        }
+        let angle = self.rng.next_u32() as f32 / u32::MAX as f32 * std::f32::consts::TAU;
+        self.state.mouse_pos = nearest_enemy.unwrap_or(pos + Vec2::from_angle(angle) * 50.0);
+    }
+
+    /// Sends a room management request, unless one went out recently.
+    fn request(&mut self, data: ClientToServerMessageData) {
+        if self
+            .last_request
+            .is_some_and(|last| self.ticks - last < REQUEST_INTERVAL)
+        {
+            return;
+        }
+        self.last_request = Some(self.ticks);
+        self.link.send(data);
+    }
+}
@@ -0,0 +1,229 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use crossbeam::queue::ArrayQueue;
+use tokio::{io, net::UdpSocket, sync::Mutex};
+
+use crate::common::{
+    client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+    compression,
+    network_settings::RECEIPT_INTERVAL_MS,
+    reliability::{PacketCounter, ReliableReceiver, ReliableSender},
+    server_to_client::{ServerToClientMessage, ServerToClientPacket},
+    util::get_utc_now,
+};
+
+/// What one bot's connection saw, for the report.
+#[derive(Default, Clone, Copy)]
+pub struct LinkStats {
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub reliable_sent: u64,
+    /// Reliable messages sent again because no ack came back in time.
+    pub resent: u64,
+    pub rtt_sum: i64,
+    pub rtt_samples: u64,
+    pub rtt_max: i64,
+    /// Reliable messages handed on from the server, and the newest sequence number that came in.
+    /// Sequence numbers are handed out one by one, so the gap is what is still missing.
+    pub reliable_received: u64,
+    pub newest_reliable: Option<u32>,
+    pub parse_errors: u64,
+}
+
+impl LinkStats {
+    pub fn rtt_avg(&self) -> Option<i64> {
+        (self.rtt_samples > 0).then(|| self.rtt_sum / self.rtt_samples as i64)
+    }
+
+    /// Share of reliable sends that had to be repeated, in percent.
+    pub fn outbound_loss(&self) -> f32 {
+        if self.reliable_sent == 0 {
+            return 0.0;
+        }
+        self.resent as f32 / self.reliable_sent as f32 * 100.0
+    }
+
+    /// Share of the server's reliable messages still missing, in percent.
+    pub fn inbound_loss(&self) -> f32 {
+        let Some(newest) = self.newest_reliable else {
+            return 0.0;
+        };
+        let expected = newest as u64 + 1;
+        expected.saturating_sub(self.reliable_received) as f32 / expected as f32 * 100.0
+    }
+}
+
+struct Reliability {
+    sender: ReliableSender<ClientToServerMessage>,
+    /// First send time of reliable messages that have not been resent, for RTT samples.
+    first_sent: HashMap<u32, i64>,
+    stats: LinkStats,
+}
+
+/// One bot's connection: its own socket, queues and reliability, since the client's
+/// networking only ever holds one connection per process.
+pub struct Link {
+    pub incoming: ArrayQueue<ServerToClientMessage>,
+    pub outbound: ArrayQueue<ClientToServerMessage>,
+    reliability: Mutex<Reliability>,
+}
+
+impl Link {
+    pub async fn connect(server_addr: SocketAddr) -> io::Result<Arc<Link>> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(server_addr).await?;
+        let socket = Arc::new(socket);
+
+        let link = Arc::new(Link {
+            incoming: ArrayQueue::new(1024),
+            outbound: ArrayQueue::new(256),
+            reliability: Mutex::new(Reliability {
+                sender: ReliableSender::new(),
+                first_sent: HashMap::new(),
+                stats: LinkStats::default(),
+            }),
+        });
+        tokio::spawn(receive_incoming_messages(socket.clone(), link.clone()));
+        tokio::spawn(transmit_outbound_messages(socket, link.clone()));
+        Ok(link)
+    }
+
+    pub fn send(&self, data: ClientToServerMessageData) {
+        if self
+            .outbound
+            .push(ClientToServerMessage::new(data))
+            .is_err()
+        {
+            eprintln!("Outbound message queue full: dropping message");
+        }
+    }
+
+    pub async fn stats(&self) -> LinkStats {
+        self.reliability.lock().await.stats
+    }
+}
+
+async fn receive_incoming_messages(socket: Arc<UdpSocket>, link: Arc<Link>) -> io::Result<()> {
+    let mut buffer = [0; 65536];
+    let mut reliable_receiver = ReliableReceiver::new();
+    let mut packets = PacketCounter::new();
+    let mut last_receipt = get_utc_now();
+    loop {
+        let nbytes = match socket.recv(&mut buffer).await {
+            Ok(nbytes) => nbytes,
+            Err(e) => {
+                eprintln!("Receive error: {}", e);
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        // bots never say hello, so nothing comes compressed
+        let result: Option<ServerToClientPacket> = compression::unpack(&buffer[..nbytes])
+            .and_then(|bytes| bincode::deserialize(&bytes).ok());
+        let mut reliability = link.reliability.lock().await;
+        reliability.stats.packets_received += 1;
+        reliability.stats.bytes_received += nbytes as u64;
+        let Some(packet) = result else {
+            reliability.stats.parse_errors += 1;
+            continue;
+        };
+        packets.on_packet(packet.seq);
+        let now = get_utc_now();
+        if now - last_receipt >= RECEIPT_INTERVAL_MS {
+            last_receipt = now;
+            if let Some((up_to, count)) = packets.receipt() {
+                link.send(ClientToServerMessageData::Receipt { up_to, count });
+            }
+        }
+
+        if let ServerToClientMessage::Ack { seq } = packet.message {
+            reliability.sender.ack(seq, get_utc_now());
+            if let Some(first_sent) = reliability.first_sent.remove(&seq) {
+                let rtt = get_utc_now() - first_sent;
+                reliability.stats.rtt_sum += rtt;
+                reliability.stats.rtt_samples += 1;
+                reliability.stats.rtt_max = reliability.stats.rtt_max.max(rtt);
+            }
+            continue;
+        }
+        let ready = match packet.reliable_seq {
+            None => vec![packet.message],
+            Some(seq) => {
+                let Some(ready) = reliable_receiver.accept(seq, packet.message) else {
+                    continue;
+                };
+                // ack every copy, since the previous ack may have been lost
+                link.send(ClientToServerMessageData::Ack { seq });
+                reliability.stats.reliable_received += ready.len() as u64;
+                if reliability
+                    .stats
+                    .newest_reliable
+                    .is_none_or(|newest| seq > newest)
+                {
+                    reliability.stats.newest_reliable = Some(seq);
+                }
+                ready
+            }
+        };
+        for message in ready {
+            if link.incoming.push(message).is_err() {
+                eprintln!("Inbound message queue full: dropping message");
+            }
+        }
+    }
+}
+
+async fn transmit_outbound_messages(socket: Arc<UdpSocket>, link: Arc<Link>) -> io::Result<()> {
+    loop {
+        while let Some(mut message) = link.outbound.pop() {
+            if message.data.is_reliable() {
+                let now = get_utc_now();
+                let mut reliability = link.reliability.lock().await;
+                let seq = reliability.sender.track(message.clone(), now);
+                reliability.first_sent.insert(seq, now);
+                reliability.stats.reliable_sent += 1;
+                message.reliable_seq = Some(seq);
+            }
+            transmit_message(&socket, &link, &message).await;
+        }
+
+        // anything the server hasn't acked in a while goes out again
+        let resends = {
+            let mut reliability = link.reliability.lock().await;
+            let resends = reliability.sender.due_for_resend(get_utc_now());
+            for (seq, _) in resends.iter() {
+                // a resent message's ack can't tell which copy it answers
+                reliability.first_sent.remove(seq);
+            }
+            reliability.stats.resent += resends.len() as u64;
+            resends
+        };
+        for (seq, mut message) in resends {
+            message.reliable_seq = Some(seq);
+            transmit_message(&socket, &link, &message).await;
+        }
+
+        // hundreds of these share the runtime
+        tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+    }
+}
+
+async fn transmit_message(socket: &UdpSocket, link: &Link, message: &ClientToServerMessage) {
+    match bincode::serialize(message) {
+        Ok(binary_message) => {
+            let datagram = compression::pack(&binary_message, false);
+            if let Err(e) = socket.send(&datagram).await {
+                eprintln!("Send error: {}", e);
+                return;
+            }
+            let mut reliability = link.reliability.lock().await;
+            reliability.stats.packets_sent += 1;
+            reliability.stats.bytes_sent += datagram.len() as u64;
+        }
+        Err(e) => {
+            eprintln!("Error serializing message: {:?}", e);
+        }
+    }
+}
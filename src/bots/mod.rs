@@ -0,0 +1,3 @@
+pub mod bot;
+pub mod link;
+pub mod report;
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use glam::Vec2;
+
+use crate::{
+    client::components::{InputControlled, NetworkId, Player, Transform},
+    common::game_objects::MatchPhase,
+};
+
+use super::{bot::Bot, link::LinkStats};
+
+/// How far this bot's copies of other players were from where their owners had them.
+#[derive(Default, Clone, Copy)]
+pub struct DesyncStats {
+    pub samples: u64,
+    pub distance_sum: f32,
+    pub distance_max: f32,
+    /// Times a player in the same match was missing from this bot's world entirely.
+    pub missing: u64,
+}
+
+impl DesyncStats {
+    pub fn distance_avg(&self) -> f32 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.distance_sum / self.samples as f32
+    }
+}
+
+/// Compares every bot's view of the others against the others' own positions.
+/// Only possible because all the bots live in one process.
+pub fn measure_desync(bots: &mut [Bot]) {
+    // (room, entity id) -> where its owner has it. Entity ids are only unique per room.
+    let mut truth: HashMap<(String, u32), Vec2> = HashMap::new();
+    for bot in bots.iter() {
+        let Some(room) = &bot.state.room else {
+            continue;
+        };
+        if bot.state.phase != MatchPhase::InGame {
+            continue;
+        }
+        for (_, (id, transform)) in bot
+            .ecs
+            .query::<(&NetworkId, &Transform)>()
+            .with::<&InputControlled>()
+            .iter()
+        {
+            truth.insert((room.clone(), id.id), transform.pos);
+        }
+    }
+
+    for bot in bots.iter_mut() {
+        let Some(room) = &bot.state.room else {
+            continue;
+        };
+        if bot.state.phase != MatchPhase::InGame {
+            continue;
+        }
+        let mut seen: HashMap<u32, Vec2> = HashMap::new();
+        for (_, (id, transform)) in bot
+            .ecs
+            .query::<(&NetworkId, &Transform)>()
+            .with::<&Player>()
+            .without::<&InputControlled>()
+            .iter()
+        {
+            seen.insert(id.id, transform.pos);
+        }
+        let own: Vec<u32> = bot
+            .ecs
+            .query::<&NetworkId>()
+            .with::<&InputControlled>()
+            .iter()
+            .map(|(_, id)| id.id)
+            .collect();
+        for ((owner_room, entity_id), true_pos) in truth.iter() {
+            if owner_room != room || own.contains(entity_id) {
+                continue;
+            }
+            match seen.get(entity_id) {
+                Some(pos) => {
+                    let distance = pos.distance(*true_pos);
+                    bot.desync.samples += 1;
+                    bot.desync.distance_sum += distance;
+                    bot.desync.distance_max = bot.desync.distance_max.max(distance);
+                }
+                None => bot.desync.missing += 1,
+            }
+        }
+    }
+}
+
+pub async fn print_report(bots: &[Bot], seconds: u64) {
+    println!();
+    println!(
+        "{:<8} {:>4} {:>5} {:>8} {:>8} {:>8} {:>8} {:>9} {:>9} {:>8} {:>8}",
+        "bot",
+        "id",
+        "room",
+        "rtt avg",
+        "rtt max",
+        "out loss",
+        "in loss",
+        "desync",
+        "desync max",
+        "samples",
+        "missing"
+    );
+    let mut total = LinkStats::default();
+    let mut desync = DesyncStats::default();
+    for bot in bots {
+        let stats = bot.link.stats().await;
+        println!(
+            "{:<8} {:>4} {:>5} {:>8} {:>6}ms {:>7.1}% {:>7.1}% {:>8.1}px {:>8.1}px {:>8} {:>8}",
+            bot.name,
+            bot.client_id,
+            bot.state.room.as_deref().unwrap_or("-"),
+            stats
+                .rtt_avg()
+                .map(|rtt| format!("{}ms", rtt))
+                .unwrap_or_else(|| "-".to_string()),
+            stats.rtt_max,
+            stats.outbound_loss(),
+            stats.inbound_loss(),
+            bot.desync.distance_avg(),
+            bot.desync.distance_max,
+            bot.desync.samples,
+            bot.desync.missing,
+        );
+
+        total.packets_sent += stats.packets_sent;
+        total.packets_received += stats.packets_received;
+        total.bytes_sent += stats.bytes_sent;
+        total.bytes_received += stats.bytes_received;
+        total.reliable_sent += stats.reliable_sent;
+        total.resent += stats.resent;
+        total.rtt_sum += stats.rtt_sum;
+        total.rtt_samples += stats.rtt_samples;
+        total.rtt_max = total.rtt_max.max(stats.rtt_max);
+        total.reliable_received += stats.reliable_received;
+        total.parse_errors += stats.parse_errors;
+        desync.samples += bot.desync.samples;
+        desync.distance_sum += bot.desync.distance_sum;
+        desync.distance_max = desync.distance_max.max(bot.desync.distance_max);
+        desync.missing += bot.desync.missing;
+    }
+
+    let seconds = seconds.max(1);
+    println!();
+    println!("{} bots over {}s", bots.len(), seconds);
+    println!(
+        "sent {} packets ({} B/s), received {} packets ({} B/s), {} unreadable",
+        total.packets_sent,
+        total.bytes_sent / seconds,
+        total.packets_received,
+        total.bytes_received / seconds,
+        total.parse_errors
+    );
+    println!(
+        "rtt avg {}, max {}ms; {} of {} reliable sends repeated ({:.1}%)",
+        total
+            .rtt_avg()
+            .map(|rtt| format!("{}ms", rtt))
+            .unwrap_or_else(|| "-".to_string()),
+        total.rtt_max,
+        total.resent,
+        total.reliable_sent,
+        total.outbound_loss()
+    );
+    println!(
+        "desync avg {:.1}px, max {:.1}px over {} samples; {} missing players",
+        desync.distance_avg(),
+        desync.distance_max,
+        desync.samples,
+        desync.missing
+    );
+
+    let sessions: Vec<_> = bots
+        .iter()
+        .filter_map(|bot| bot.state.rollback.as_ref())
+        .collect();
+    if !sessions.is_empty() {
+        println!(
+            "rollback: {} rollbacks over {} steps, {} stalls in {} sessions",
+            sessions
+                .iter()
+                .map(|session| session.rollbacks)
+                .sum::<u32>(),
+            sessions
+                .iter()
+                .map(|session| session.resimulated_steps)
+                .sum::<u32>(),
+            sessions.iter().map(|session| session.stalls).sum::<u32>(),
+            sessions.len()
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use glam::Vec2;
+use hecs::World;
+use raylib::prelude::{Camera2D, Vector2};
+
+use super::{
+    components::{InputControlled, Transform},
+    graphics::DIMS,
+    state::State,
+};
+
+/// Fraction of the remaining distance to the player covered each step.
+pub const CAMERA_SMOOTHING: f32 = 0.12;
+/// Further than this and the camera jumps instead of gliding, e.g. on spawn.
+pub const CAMERA_SNAP_DISTANCE: f32 = 120.0;
+
+/// Which part of the world ends up on screen. `target` is the world position at the
+/// center of the render texture.
+pub struct Camera {
+    pub target: Vec2,
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Self {
+            target: DIMS.as_vec2() / 2.0,
+        }
+    }
+
+    pub fn world_to_screen(&self, world_pos: Vec2) -> Vec2 {
+        world_pos - self.target.round() + DIMS.as_vec2() / 2.0
+    }
+
+    pub fn screen_to_world(&self, screen_pos: Vec2) -> Vec2 {
+        screen_pos + self.target.round() - DIMS.as_vec2() / 2.0
+    }
+
+    pub fn to_camera_2d(&self) -> Camera2D {
+        let offset = DIMS.as_vec2() / 2.0;
+        Camera2D {
+            offset: Vector2::new(offset.x, offset.y),
+            target: Vector2::new(self.target.x.round(), self.target.y.round()),
+            rotation: 0.0,
+            zoom: 1.0,
+        }
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Eases the camera toward our own player without showing anything past the edge of the level.
+pub fn follow_player(ecs: &World, state: &mut State) {
+    let Some(player_pos) = ecs
+        .query::<&Transform>()
+        .with::<&InputControlled>()
+        .iter()
+        .next()
+        .map(|(_, transform)| transform.pos)
+    else {
+        return;
+    };
+    let goal = clamp_to_play_field(player_pos, state.play_field);
+    let camera = &mut state.camera;
+    if camera.target.distance(goal) > CAMERA_SNAP_DISTANCE {
+        camera.target = goal;
+    } else {
+        camera.target =
+            clamp_to_play_field(camera.target.lerp(goal, CAMERA_SMOOTHING), state.play_field);
+    }
+
+    // the view moved, so the same screen position points somewhere else now
+    state.mouse_pos = state.camera.screen_to_world(state.mouse_screen_pos);
+}
+
+fn clamp_to_play_field(target: Vec2, play_field: Vec2) -> Vec2 {
+    let half_view = DIMS.as_vec2() / 2.0;
+    // a level smaller than the screen is simply centered
+    let min = half_view.min(play_field / 2.0);
+    let max = (play_field - half_view).max(play_field / 2.0);
+    target.clamp(min, max)
+}
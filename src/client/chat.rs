@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+
+use raylib::prelude::*;
+
+use crate::common::client_to_server::{ClientToServerMessage, ClientToServerMessageData};
+
+use super::{state::State, udp_networking::OUTBOUND_MESSAGE_QUEUE};
+
+pub const CHAT_HISTORY_LEN: usize = 50;
+pub const CHAT_VISIBLE_LINES: usize = 6;
+/// Seconds a line stays fully visible, then how long it takes to fade out.
+pub const CHAT_FADE_AFTER: f64 = 8.0;
+pub const CHAT_FADE_DURATION: f64 = 2.0;
+/// Matches the server's limit, so nothing typed gets cut off.
+pub const CHAT_MAX_DRAFT: usize = 120;
+
+pub struct ChatLine {
+    /// `None` for notices from the server itself.
+    pub from: Option<u32>,
+    pub text: String,
+    pub received_at: f64,
+}
+
+pub struct Chat {
+    pub typing: bool,
+    pub draft: String,
+    /// Oldest first.
+    pub lines: VecDeque<ChatLine>,
+    /// How many lines up from the newest the history is scrolled.
+    pub scroll: usize,
+    /// Time of the current frame, in seconds.
+    pub now: f64,
+}
+
+impl Chat {
+    pub fn new() -> Self {
+        Self {
+            typing: false,
+            draft: String::new(),
+            lines: VecDeque::new(),
+            scroll: 0,
+            now: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, from: Option<u32>, text: String) {
+        if self.lines.len() == CHAT_HISTORY_LEN {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(ChatLine {
+            from,
+            text,
+            received_at: self.now,
+        });
+    }
+
+    /// The lines currently on screen, oldest first, with how opaque each one is.
+    pub fn visible_lines(&self) -> Vec<(&ChatLine, f32)> {
+        let end = self.lines.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(CHAT_VISIBLE_LINES);
+        self.lines
+            .range(start..end)
+            .map(|line| (line, self.alpha(line)))
+            .filter(|(_, alpha)| *alpha > 0.0)
+            .collect()
+    }
+
+    /// Old lines fade out, but everything is readable again while typing.
+    fn alpha(&self, line: &ChatLine) -> f32 {
+        if self.typing {
+            return 1.0;
+        }
+        let age = self.now - line.received_at;
+        (1.0 - (age - CHAT_FADE_AFTER) / CHAT_FADE_DURATION).clamp(0.0, 1.0) as f32
+    }
+}
+
+impl Default for Chat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn sender_label(state: &State, from: Option<u32>) -> String {
+    match from {
+        None => "server".to_string(),
+        Some(id) => state.name_of(id),
+    }
+}
+
+/// Enter opens the chat box and sends, Escape cancels. Returns true if the keyboard
+/// belonged to the chat box this frame, so it shouldn't also move the player.
+pub fn process_chat_input(rl: &mut RaylibHandle, state: &mut State) -> bool {
+    let chat = &mut state.chat;
+    chat.now = rl.get_time();
+
+    if !chat.typing {
+        if rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+            chat.typing = true;
+            // drop whatever was typed to get here
+            while rl.get_char_pressed().is_some() {}
+            return true;
+        }
+        return false;
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) {
+        chat.typing = false;
+        chat.draft.clear();
+        chat.scroll = 0;
+        return true;
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+        chat.typing = false;
+        chat.scroll = 0;
+        let message = std::mem::take(&mut chat.draft);
+        if !message.trim().is_empty() {
+            send_chat(message);
+        }
+        return true;
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE)
+        || rl.is_key_pressed_repeat(KeyboardKey::KEY_BACKSPACE)
+    {
+        chat.draft.pop();
+    }
+    while let Some(c) = rl.get_char_pressed() {
+        if !c.is_control() && chat.draft.chars().count() < CHAT_MAX_DRAFT {
+            chat.draft.push(c);
+        }
+    }
+
+    let wheel = rl.get_mouse_wheel_move();
+    if rl.is_key_pressed(KeyboardKey::KEY_PAGE_UP) || wheel > 0.0 {
+        chat.scroll = (chat.scroll + 1).min(chat.lines.len().saturating_sub(1));
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_PAGE_DOWN) || wheel < 0.0 {
+        chat.scroll = chat.scroll.saturating_sub(1);
+    }
+    true
+}
+
+/// Shown once the server echoes it back, so we see what everyone else sees, after it
+/// was cleaned up, and nothing the server turned down.
+fn send_chat(message: String) {
+    if OUTBOUND_MESSAGE_QUEUE
+        .push(ClientToServerMessage::new(
+            ClientToServerMessageData::ChatMessage { message },
+        ))
+        .is_err()
+    {
+        eprintln!("Outbound message queue full: dropping message");
+    }
+}
@@ -1,11 +1,19 @@
 use glam::Vec2;
 use hecs::Entity;
 
+use crate::common::{collision::ShapeKind, inputs::PlayingInputs};
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Transform {
     pub pos: Vec2,
 }
 
+/// The entity id the server knows this entity by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetworkId {
+    pub id: u32,
+}
+
 pub struct OwnedByClient {
     pub client_id: u32,
 }
@@ -14,20 +22,60 @@ pub struct Player;
 
 pub struct InputControlled;
 
+/// What the player is pressing this step and where they aim. Filled from the keyboard for
+/// our own player, and from everyone's inputs during a rollback session.
+#[derive(Clone, Copy)]
+pub struct Controls {
+    pub inputs: PlayingInputs,
+    pub aim: Vec2,
+}
+
+/// Steps until the player's weapon can fire again.
+#[derive(Clone, Copy)]
+pub struct Weapon {
+    pub cooldown: u32,
+}
+
 pub struct Health {
     pub hp: u32,
 }
 
+/// Out of the fight until a teammate revives them.
+pub struct Downed;
+
+/// `dims` are half extents; circles use `dims.x` as their radius.
 #[derive(Clone, Copy)]
 pub struct Shape {
+    pub kind: ShapeKind,
     pub dims: Vec2,
 }
+impl Shape {
+    pub fn circle(radius: f32) -> Self {
+        Self {
+            kind: ShapeKind::Circle,
+            dims: Vec2::splat(radius),
+        }
+    }
+
+    pub fn rect(half_extents: Vec2) -> Self {
+        Self {
+            kind: ShapeKind::Rect,
+            dims: half_extents,
+        }
+    }
+}
 
 #[derive(Clone, Copy)]
 pub struct Physics {
     pub vel: Vec2,
 }
 
+/// Fraction of velocity kept each step.
+#[derive(Clone, Copy)]
+pub struct Drag {
+    pub factor: f32,
+}
+
 pub struct CaptureInPlayField;
 
 pub struct FreeToLeavePlayField;
@@ -65,4 +113,10 @@ pub struct LookAt {
 #[derive(Clone, Copy)]
 pub struct Enemy;
 
+#[derive(Clone, Copy)]
+pub struct Projectile {
+    pub owner_client_id: u32,
+    pub damage: u32,
+}
+
 pub struct Wall;
@@ -0,0 +1,241 @@
+use hecs::World;
+
+use crate::common::{
+    checksum::{world_checksum, EntityDigest},
+    client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+    migration::RoomSnapshot,
+    util::get_utc_now,
+};
+
+use super::{
+    components::{AttachedTo, Downed, Enemy, Health, NetworkId, OwnedBy, Player},
+    entity_archetypes::{spawn_item, spawn_player},
+    network_entities::find_entity,
+    settings::{DESYNC_MISMATCHES_BEFORE_SNAPSHOT, SNAPSHOT_RETRY_MS},
+    state::State,
+    systems::carrying::attach,
+    udp_networking::OUTBOUND_MESSAGE_QUEUE,
+};
+
+/// How often our world has disagreed with the server's, for the debug overlay.
+pub struct DesyncCounts {
+    pub checks: u32,
+    pub mismatches: u32,
+    /// Mismatches in a row. A single one is usually just a message still on its way.
+    pub consecutive: u32,
+    pub snapshots: u32,
+    pub last_step: u32,
+    pub last_snapshot_request: i64,
+}
+
+impl DesyncCounts {
+    pub fn new() -> Self {
+        Self {
+            checks: 0,
+            mismatches: 0,
+            consecutive: 0,
+            snapshots: 0,
+            last_step: 0,
+            last_snapshot_request: 0,
+        }
+    }
+}
+
+impl Default for DesyncCounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Our side of `world_checksum`, sorted. Enemies are not part of it.
+pub fn entity_digests(ecs: &World) -> Vec<EntityDigest> {
+    let mut digests = Vec::new();
+    for (_, (id, health, downed, attached, player)) in ecs
+        .query::<(
+            &NetworkId,
+            Option<&Health>,
+            Option<&Downed>,
+            Option<&AttachedTo>,
+            Option<&Player>,
+        )>()
+        .without::<&Enemy>()
+        .iter()
+    {
+        let held_by = attached
+            .and_then(|attached| ecs.get::<&NetworkId>(attached.entity).ok())
+            .map(|holder| holder.id);
+        let player = player.is_some();
+        digests.push(EntityDigest {
+            entity_id: id.id,
+            hp: health
+                .filter(|_| player)
+                .map(|health| health.hp)
+                .unwrap_or(0),
+            downed: downed.is_some(),
+            held_by,
+        });
+    }
+    digests.sort();
+    digests
+}
+
+/// Compares our world with the server's checksum for `step`, asking for a snapshot once
+/// they have disagreed for long enough. Checksums come in order, after whatever the server
+/// sent before them, so our world is as of `step` too. One for a step we are already past,
+/// left over from before a snapshot or a migration, is not checked at all.
+pub fn check_world(ecs: &World, state: &mut State, step: u32, checksum: u64) {
+    let counts = &mut state.desync;
+    if step < counts.last_step {
+        return;
+    }
+    counts.checks += 1;
+    counts.last_step = step;
+    if world_checksum(state.wave, &entity_digests(ecs)) == checksum {
+        counts.consecutive = 0;
+        return;
+    }
+    counts.mismatches += 1;
+    counts.consecutive += 1;
+
+    if counts.consecutive < DESYNC_MISMATCHES_BEFORE_SNAPSHOT {
+        return;
+    }
+    request_snapshot(state);
+}
+
+/// Asks the server for the whole world, unless we did so a moment ago.
+pub fn request_snapshot(state: &mut State) {
+    let now = get_utc_now();
+    if now - state.desync.last_snapshot_request < SNAPSHOT_RETRY_MS {
+        return;
+    }
+    state.desync.last_snapshot_request = now;
+    println!("world out of sync, asking for a snapshot");
+    if OUTBOUND_MESSAGE_QUEUE
+        .push(ClientToServerMessage::new(
+            ClientToServerMessageData::RequestSnapshot,
+        ))
+        .is_err()
+    {
+        eprintln!("Outbound message queue full: dropping message");
+    }
+}
+
+/// Logs where we went wrong and brings the entities that differ in line with `snapshot`.
+/// Positions are left to the regular updates.
+pub fn apply_snapshot(ecs: &mut World, state: &mut State, snapshot: &RoomSnapshot) {
+    state.desync.snapshots += 1;
+    state.desync.consecutive = 0;
+    state.desync.last_step = state.desync.last_step.max(snapshot.step_count);
+    if state.wave != snapshot.wave {
+        println!("desync: wave {} should be {}", state.wave, snapshot.wave);
+        state.wave = snapshot.wave;
+    }
+    state.phase = snapshot.phase;
+
+    let ours = entity_digests(ecs);
+    let theirs = snapshot.entity_digests();
+    for digest in &ours {
+        if !theirs
+            .iter()
+            .any(|theirs| theirs.entity_id == digest.entity_id)
+        {
+            println!("desync: entity {} should not exist", digest.entity_id);
+            if let Some(entity) = find_entity(ecs, digest.entity_id) {
+                let _ = ecs.despawn(entity);
+            }
+        }
+    }
+    let mut differing = Vec::new();
+    for digest in &theirs {
+        match ours.iter().find(|ours| ours.entity_id == digest.entity_id) {
+            Some(ours) if ours == digest => continue,
+            Some(ours) => println!(
+                "desync: entity {} is {:?}, should be {:?}",
+                digest.entity_id, ours, digest
+            ),
+            None => {
+                println!("desync: entity {} is missing", digest.entity_id);
+                spawn_from_snapshot(ecs, state, snapshot, digest.entity_id);
+            }
+        }
+        differing.push(*digest);
+    }
+
+    // holders may only just have been spawned above
+    for digest in differing {
+        let Some(entity) = find_entity(ecs, digest.entity_id) else {
+            continue;
+        };
+        if ecs.satisfies::<&Player>(entity).unwrap_or(false) {
+            let _ = ecs.insert_one(entity, Health { hp: digest.hp });
+            if digest.downed {
+                let _ = ecs.insert_one(entity, Downed);
+            } else {
+                let _ = ecs.remove_one::<Downed>(entity);
+            }
+            continue;
+        }
+        let _ = ecs.remove::<(AttachedTo, OwnedBy)>(entity);
+        if let Some(holder) = digest.held_by.and_then(|holder| find_entity(ecs, holder)) {
+            attach(ecs, entity, holder);
+        }
+    }
+}
+
+fn spawn_from_snapshot(
+    ecs: &mut World,
+    state: &mut State,
+    snapshot: &RoomSnapshot,
+    entity_id: u32,
+) {
+    if let Some(player) = snapshot
+        .players
+        .iter()
+        .find(|player| player.entity_id == entity_id)
+    {
+        spawn_player(ecs, state, player.owner_client_id, entity_id, player.pos);
+    } else if let Some(item) = snapshot
+        .items
+        .iter()
+        .find(|item| item.entity_id == entity_id)
+    {
+        spawn_item(ecs, entity_id, item.pos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+    use hecs::World;
+
+    use crate::{
+        client::{entity_archetypes::spawn_player, state::State},
+        common::checksum::world_checksum,
+    };
+
+    use super::{check_world, entity_digests};
+
+    #[test]
+    fn checksums_for_steps_already_passed_are_not_checked() {
+        let mut ecs = World::new();
+        let mut state = State::new();
+        spawn_player(&mut ecs, &mut state, 1000, 7, Vec2::new(50.0, 50.0));
+        let right = world_checksum(state.wave, &entity_digests(&ecs));
+
+        check_world(&ecs, &mut state, 30, right);
+        check_world(&ecs, &mut state, 60, right ^ 1);
+        assert_eq!(state.desync.checks, 2);
+        assert_eq!(state.desync.consecutive, 1);
+
+        // from before the last one, and whatever it says it is not counted
+        check_world(&ecs, &mut state, 45, right ^ 1);
+        assert_eq!(state.desync.checks, 2);
+        assert_eq!(state.desync.mismatches, 1);
+
+        check_world(&ecs, &mut state, 90, right);
+        assert_eq!(state.desync.checks, 3);
+        assert_eq!(state.desync.consecutive, 0);
+        assert_eq!(state.desync.last_step, 90);
+    }
+}
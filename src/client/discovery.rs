@@ -0,0 +1,247 @@
+use std::{
+    collections::BTreeSet,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use crossbeam::queue::ArrayQueue;
+use lazy_static::lazy_static;
+use raylib::prelude::*;
+use tokio::{io, net::UdpSocket};
+
+use crate::common::{
+    discovery::{DiscoveryQuery, DiscoveryReply},
+    master::{FromMasterMessage, ToMasterMessage},
+    network_settings::{
+        master_server_addr, DISCOVERY_INTERVAL_MS, DISCOVERY_PORT, SERVER_HOST_ADDR,
+    },
+    util::get_utc_now,
+};
+
+use super::state::State;
+
+lazy_static! {
+    pub static ref DISCOVERED_SERVERS: Arc<ArrayQueue<DiscoveredServer>> =
+        Arc::new(ArrayQueue::new(64));
+    /// Cleared once we connect somewhere, which stops the discovery task.
+    pub static ref DISCOVERING: Arc<AtomicBool> = Arc::new(AtomicBool::new(true));
+}
+
+pub const MAX_ADDRESS_LENGTH: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct DiscoveredServer {
+    /// Game socket address, ready to connect to.
+    pub addr: SocketAddr,
+    pub name: String,
+    pub players: u32,
+    pub level_id: String,
+    /// `None` for servers only known from the master list so far.
+    pub ping_ms: Option<i64>,
+}
+
+pub struct ServerEntry {
+    pub addr: SocketAddr,
+    /// Filled in once the server answers a discovery query.
+    pub info: Option<DiscoveredServer>,
+}
+
+/// The first screen: servers found on the network plus addresses typed in by hand.
+pub struct ServerBrowser {
+    pub entries: Vec<ServerEntry>,
+    pub selected: usize,
+    /// Address being typed, added to the list on Enter.
+    pub address: String,
+}
+
+impl ServerBrowser {
+    pub fn new() -> Self {
+        let entries = SERVER_HOST_ADDR
+            .parse()
+            .map(|addr| vec![ServerEntry { addr, info: None }])
+            .unwrap_or_default();
+        Self {
+            entries,
+            selected: 0,
+            address: String::new(),
+        }
+    }
+
+    fn update(&mut self, server: DiscoveredServer) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.addr == server.addr)
+        {
+            Some(entry) => {
+                let ping_ms = server
+                    .ping_ms
+                    .or(entry.info.as_ref().and_then(|info| info.ping_ms));
+                entry.info = Some(DiscoveredServer { ping_ms, ..server });
+            }
+            None => self.entries.push(ServerEntry {
+                addr: server.addr,
+                info: Some(server),
+            }),
+        }
+    }
+}
+
+impl Default for ServerBrowser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Broadcasts a query every `DISCOVERY_INTERVAL_MS` and hands the replies to the browser.
+/// Loopback is asked directly as well, since broadcasts don't always reach it.
+/// The master server is asked for its list too, and servers on it get queried for a ping.
+/// Without a master server to ask, the LAN is still searched.
+pub async fn discover_servers() -> io::Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.set_broadcast(true)?;
+    let master_server_addr = master_server_addr();
+    let master_addr = match master_server_addr.to_socket_addrs() {
+        Ok(mut addrs) => addrs.next(),
+        Err(e) => {
+            eprintln!(
+                "Could not resolve master server {}: {}",
+                master_server_addr, e
+            );
+            None
+        }
+    };
+    let mut targets: BTreeSet<SocketAddr> = BTreeSet::from([
+        ([255, 255, 255, 255], DISCOVERY_PORT).into(),
+        ([127, 0, 0, 1], DISCOVERY_PORT).into(),
+    ]);
+    let interval = tokio::time::Duration::from_millis(DISCOVERY_INTERVAL_MS);
+
+    let mut buffer = [0; 8192];
+    while DISCOVERING.load(std::sync::atomic::Ordering::SeqCst) {
+        let query = DiscoveryQuery {
+            send_time: get_utc_now(),
+        };
+        match bincode::serialize(&query) {
+            Ok(binary_query) => {
+                for target in &targets {
+                    if let Err(e) = socket.send_to(&binary_query, target).await {
+                        eprintln!("Discovery query to {} failed: {}", target, e);
+                    }
+                }
+            }
+            Err(e) => eprintln!("Error serializing discovery query: {:?}", e),
+        }
+        if let Some(master_addr) = master_addr {
+            ask_master(&socket, master_addr, 0).await;
+        }
+
+        // collect replies until it is time to ask again
+        let deadline = tokio::time::Instant::now() + interval;
+        while let Ok(received) =
+            tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await
+        {
+            let (nbytes, from) = received?;
+            if Some(from) == master_addr {
+                let Ok(FromMasterMessage::ServerList { servers, next }) =
+                    bincode::deserialize(&buffer[..nbytes])
+                else {
+                    continue;
+                };
+                if let Some(next) = next {
+                    ask_master(&socket, from, next).await;
+                }
+                for listing in servers {
+                    targets.insert(SocketAddr::new(listing.addr.ip(), DISCOVERY_PORT));
+                    let server = DiscoveredServer {
+                        addr: listing.addr,
+                        name: listing.name,
+                        players: listing.players,
+                        level_id: listing.level_id,
+                        ping_ms: None,
+                    };
+                    if DISCOVERED_SERVERS.push(server).is_err() {
+                        eprintln!("Discovered server queue full: dropping reply");
+                    }
+                }
+                continue;
+            }
+            let Ok(reply) = bincode::deserialize::<DiscoveryReply>(&buffer[..nbytes]) else {
+                continue;
+            };
+            let server = DiscoveredServer {
+                addr: SocketAddr::new(from.ip(), reply.game_port),
+                name: reply.name,
+                players: reply.players,
+                level_id: reply.level_id,
+                ping_ms: Some(get_utc_now() - reply.send_time),
+            };
+            if DISCOVERED_SERVERS.push(server).is_err() {
+                eprintln!("Discovered server queue full: dropping reply");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Asks the master server for its list from listing `first` on.
+async fn ask_master(socket: &UdpSocket, master_addr: SocketAddr, first: u32) {
+    match bincode::serialize(&ToMasterMessage::list_servers(first)) {
+        Ok(binary_query) => {
+            if let Err(e) = socket.send_to(&binary_query, master_addr).await {
+                eprintln!("Master server query failed: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Error serializing master server query: {:?}", e),
+    }
+}
+
+/// Up/Down pick a server, Enter connects. Typing an address and pressing Enter adds it.
+pub fn process_server_browser_input(rl: &mut RaylibHandle, state: &mut State) {
+    let browser = &mut state.server_browser;
+    while let Some(server) = DISCOVERED_SERVERS.pop() {
+        browser.update(server);
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+        browser.selected = browser.selected.saturating_sub(1);
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+        browser.selected = (browser.selected + 1).min(browser.entries.len().saturating_sub(1));
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE)
+        || rl.is_key_pressed_repeat(KeyboardKey::KEY_BACKSPACE)
+    {
+        browser.address.pop();
+    }
+    while let Some(c) = rl.get_char_pressed() {
+        let allowed = c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '-' | '[' | ']');
+        if allowed && browser.address.len() < MAX_ADDRESS_LENGTH {
+            browser.address.push(c);
+        }
+    }
+
+    if !rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+        return;
+    }
+    if browser.address.is_empty() {
+        if let Some(entry) = browser.entries.get(browser.selected) {
+            state.connect_to = Some(entry.addr);
+        }
+        return;
+    }
+    let address = std::mem::take(&mut browser.address);
+    match address.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => {
+            if !browser.entries.iter().any(|entry| entry.addr == addr) {
+                browser.entries.push(ServerEntry { addr, info: None });
+            }
+            browser.selected = browser
+                .entries
+                .iter()
+                .position(|entry| entry.addr == addr)
+                .unwrap_or(0);
+        }
+        _ => eprintln!("Could not resolve {}", address),
+    }
+}
@@ -1,26 +1,570 @@
+use glam::Vec2;
 use hecs::World;
-use raylib::prelude::{Color, RaylibDraw, RaylibDrawHandle, RaylibTextureMode};
+use raylib::prelude::{Color, RaylibDraw, RaylibDrawHandle, RaylibMode2DExt, RaylibTextureMode};
 
 use super::{
-    components::{Shape, Transform},
+    chat::sender_label,
+    components::{
+        Attachable, Downed, Enemy, Health, InputControlled, OwnedByClient, Player, Projectile,
+        Score, Shape, Transform, Wall,
+    },
+    graphics::DIMS,
+    lobby::is_host,
+    replay::ReplayViewer,
     state::State,
+    udp_networking::{CLIENT_ID, INCOMING_MESSAGE_QUEUE, OUTBOUND_MESSAGE_QUEUE},
 };
+use crate::common::tick::FRAMES_PER_SECOND;
+use crate::common::{
+    collision::ShapeKind,
+    game_objects::{MatchPhase, PlayerStats, SyncMode, PLAYER_COLOR_COUNT},
+    level::Tile,
+};
+
+/// Indexed by the color players pick in the lobby.
+pub const PLAYER_COLORS: [Color; PLAYER_COLOR_COUNT as usize] = [
+    Color::new(0, 121, 241, 255),
+    Color::new(230, 41, 55, 255),
+    Color::new(0, 228, 48, 255),
+    Color::new(253, 249, 0, 255),
+    Color::new(200, 122, 255, 255),
+    Color::new(255, 161, 0, 255),
+];
 
 pub fn draw(ecs: &World, state: &State, d: &mut RaylibTextureMode<RaylibDrawHandle>) {
+    if let Some(viewer) = &state.replay {
+        draw_world(ecs, state, d);
+        draw_hud(ecs, state, d);
+        if state.show_scoreboard {
+            draw_stats_table(d, state, "Scoreboard", "", &state.scoreboard);
+        }
+        draw_replay_controls(viewer, d);
+        return;
+    }
+    if state.server.is_none() {
+        draw_server_browser(state, d);
+        return;
+    }
+    if state.room.is_none() {
+        draw_room_browser(state, d);
+        return;
+    }
+
+    draw_world(ecs, state, d);
+
+    // everything below is screen space, on top of the world
     d.draw_text("Multiplayer!", 12, 12, 12, Color::WHITE);
-    let mouse_pos = d.get_mouse_position();
-    d.draw_circle(mouse_pos.x as i32, mouse_pos.y as i32, 6.0, Color::GREEN);
+    draw_downed_markers(ecs, state, d);
+    draw_crosshair(state, d);
+    draw_hud(ecs, state, d);
+    draw_chat(state, d);
+    if state.show_debug {
+        draw_debug_overlay(state, d);
+    }
+    match state.phase {
+        MatchPhase::Lobby => draw_lobby(state, d),
+        MatchPhase::InGame => {
+            if let Some(results) = &state.wave_results {
+                let title = format!("Wave {} cleared!", results.wave);
+                draw_stats_table(d, state, &title, "", &results.stats);
+            } else if state.show_scoreboard {
+                draw_stats_table(d, state, "Scoreboard", "", &state.scoreboard);
+            }
+        }
+        MatchPhase::Results => {
+            let title = format!("Game over on wave {}", state.wave);
+            let hint = if is_host(state) {
+                "space: back to lobby"
+            } else {
+                "waiting for the host"
+            };
+            let stats = match &state.wave_results {
+                Some(results) => &results.stats,
+                None => &state.scoreboard,
+            };
+            draw_stats_table(d, state, &title, hint, stats);
+        }
+    }
+}
 
-    draw_players(ecs, state, d);
+pub fn draw_world(ecs: &World, state: &State, d: &mut RaylibTextureMode<RaylibDrawHandle>) {
+    let world = &mut d.begin_mode2D(state.camera.to_camera_2d());
+    draw_level(state, world);
+    draw_walls(ecs, state, world);
+    draw_items(ecs, state, world);
+    draw_enemies(ecs, state, world);
+    draw_players(ecs, state, world);
+    draw_projectiles(ecs, state, world);
 }
 
-pub fn draw_players(ecs: &World, _state: &State, d: &mut RaylibTextureMode<RaylibDrawHandle>) {
-    for (_, (transform, shape)) in ecs.query::<(&Transform, &Shape)>().iter() {
-        d.draw_circle(
-            transform.pos.x as i32,
-            transform.pos.y as i32,
-            shape.dims.x as f32,
-            Color::BLUE,
+/// Network health numbers, toggled with F3.
+pub fn draw_debug_overlay(state: &State, d: &mut impl RaylibDraw) {
+    let desync = &state.desync;
+    let mut lines = vec![
+        format!("step {}", desync.last_step),
+        format!("checks {}", desync.checks),
+        format!("desyncs {}", desync.mismatches),
+        format!("snapshots {}", desync.snapshots),
+        format!(
+            "dropped {} in, {} out",
+            INCOMING_MESSAGE_QUEUE.dropped(),
+            OUTBOUND_MESSAGE_QUEUE.dropped()
+        ),
+    ];
+    if let Some(session) = &state.rollback {
+        lines.push(format!("tick {}", session.tick));
+        lines.push(format!(
+            "rollbacks {} ({} steps)",
+            session.rollbacks, session.resimulated_steps
+        ));
+        lines.push(format!("stalls {}", session.stalls));
+    }
+    for (row, line) in lines.iter().enumerate() {
+        let color = if row == 2 && desync.consecutive > 0 {
+            Color::RED
+        } else {
+            Color::LIGHTGRAY
+        };
+        d.draw_text(line, 12, 28 + row as i32 * 10, 10, color);
+    }
+}
+
+/// Timeline and key hints along the bottom while watching a replay.
+pub fn draw_replay_controls(viewer: &ReplayViewer, d: &mut impl RaylibDraw) {
+    let playback = &viewer.playback;
+    let clock = |step: u32| {
+        let seconds = (step - playback.first_step) / FRAMES_PER_SECOND;
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    };
+    let mut status = format!(
+        "{} / {}  x{}",
+        clock(playback.state.step_count),
+        clock(playback.last_step),
+        viewer.speed
+    );
+    if viewer.paused {
+        status.push_str("  paused");
+    }
+    if viewer.free_camera {
+        status.push_str("  free cam");
+    }
+
+    let left = 8;
+    let bottom = DIMS.y as i32 - 12;
+    let length = (playback.last_step - playback.first_step).max(1) as f32;
+    let done = (playback.state.step_count - playback.first_step) as f32 / length;
+    let width = DIMS.x as i32 - 2 * left;
+    d.draw_rectangle(left, bottom - 26, width, 2, Color::DARKGRAY);
+    d.draw_rectangle(
+        left,
+        bottom - 26,
+        (width as f32 * done) as i32,
+        2,
+        Color::YELLOW,
+    );
+    d.draw_text(&status, left, bottom - 22, 10, Color::WHITE);
+    d.draw_text(
+        "space .  left/right  up/down  f wasd",
+        left,
+        bottom - 10,
+        10,
+        Color::LIGHTGRAY,
+    );
+}
+
+pub fn draw_server_browser(state: &State, d: &mut impl RaylibDraw) {
+    const ROW: i32 = 11;
+    let browser = &state.server_browser;
+    let left = 16;
+    let top = 16;
+    d.draw_text("Servers", left, top, 10, Color::YELLOW);
+
+    for (index, entry) in browser.entries.iter().enumerate() {
+        let y = top + ROW * (index as i32 + 2);
+        let text = match &entry.info {
+            Some(info) => {
+                let ping = info
+                    .ping_ms
+                    .map(|ping_ms| format!("{}ms", ping_ms))
+                    .unwrap_or_else(|| "?ms".to_string());
+                format!(
+                    "{}  {}p  {}  {}",
+                    info.name, info.players, info.level_id, ping
+                )
+            }
+            None => entry.addr.to_string(),
+        };
+        let selected = browser.address.is_empty() && index == browser.selected;
+        let color = if selected { Color::GREEN } else { Color::WHITE };
+        if selected {
+            d.draw_text(">", left - 8, y, 10, color);
+        }
+        d.draw_text(&text, left, y, 10, color);
+    }
+
+    let bottom = DIMS.y as i32 - 16;
+    d.draw_text(
+        &format!("add: {}_", browser.address),
+        left,
+        bottom - ROW,
+        10,
+        Color::WHITE,
+    );
+    d.draw_text(
+        "up/down: pick  enter: connect",
+        left,
+        bottom,
+        10,
+        Color::LIGHTGRAY,
+    );
+}
+
+pub fn draw_room_browser(state: &State, d: &mut impl RaylibDraw) {
+    const ROW: i32 = 11;
+    let browser = &state.room_browser;
+    let left = 16;
+    let top = 16;
+    d.draw_text("Rooms", left, top, 10, Color::YELLOW);
+
+    let mut rows = vec!["+ new room".to_string()];
+    for room in &browser.rooms {
+        let phase = match room.phase {
+            MatchPhase::Lobby => "lobby",
+            MatchPhase::InGame => "playing",
+            MatchPhase::Results => "results",
+        };
+        rows.push(format!(
+            "{}  {}p  {}  {}",
+            room.code, room.players, room.level_id, phase
+        ));
+    }
+    for (index, text) in rows.iter().enumerate() {
+        let y = top + ROW * (index as i32 + 2);
+        let selected = browser.code.is_empty() && index == browser.selected;
+        let color = if selected { Color::GREEN } else { Color::WHITE };
+        if selected {
+            d.draw_text(">", left - 8, y, 10, color);
+        }
+        d.draw_text(text, left, y, 10, color);
+    }
+
+    let bottom = DIMS.y as i32 - 16;
+    d.draw_text(
+        &format!("code: {}_", browser.code),
+        left,
+        bottom - ROW,
+        10,
+        Color::WHITE,
+    );
+    d.draw_text(
+        "up/down: pick  enter: join",
+        left,
+        bottom,
+        10,
+        Color::LIGHTGRAY,
+    );
+}
+
+pub fn draw_lobby(state: &State, d: &mut impl RaylibDraw) {
+    const ROW: i32 = 11;
+    let left = 16;
+    let top = 16;
+    let height = ROW * (state.roster.len() as i32 + 4);
+    d.draw_rectangle(
+        left - 4,
+        top - 4,
+        DIMS.x as i32 - 2 * (left - 4),
+        height + 8,
+        Color::new(0, 0, 0, 200),
+    );
+    let title = format!("Room {}", state.room.as_deref().unwrap_or(""));
+    d.draw_text(&title, left, top, 10, Color::YELLOW);
+    d.draw_text("l: leave", left + 164, top, 10, Color::LIGHTGRAY);
+    let sync = match state.sync_mode {
+        SyncMode::Snapshot => "snapshot",
+        SyncMode::Rollback => "rollback",
+    };
+    d.draw_text(sync, left + 84, top, 10, Color::LIGHTGRAY);
+
+    let client_id = CLIENT_ID.load(std::sync::atomic::Ordering::SeqCst);
+    for (row, entry) in state.roster.iter().enumerate() {
+        let y = top + ROW * (row as i32 + 2);
+        let color = PLAYER_COLORS[(entry.color % PLAYER_COLOR_COUNT) as usize];
+        d.draw_rectangle(left, y + 1, 8, 8, color);
+        let name_color = if entry.client_id == client_id {
+            Color::GREEN
+        } else {
+            Color::WHITE
+        };
+        d.draw_text(&entry.name, left + 12, y, 10, name_color);
+        if state.host == Some(entry.client_id) {
+            d.draw_text("host", left + 124, y, 10, Color::LIGHTGRAY);
+        } else if entry.bot {
+            d.draw_text("bot", left + 124, y, 10, Color::GRAY);
+        }
+        let (ready, ready_color) = if entry.ready {
+            ("ready", Color::GREEN)
+        } else {
+            ("...", Color::GRAY)
+        };
+        d.draw_text(ready, left + 164, y, 10, ready_color);
+    }
+
+    let hint = if is_host(state) {
+        "c: color  r: ready  m: sync  space: start"
+    } else {
+        "c: color  r: ready"
+    };
+    d.draw_text(hint, left, top + height - ROW, 10, Color::LIGHTGRAY);
+}
+
+/// Floor and the markers for spawn tiles. Walls are entities and drawn with the rest.
+pub fn draw_level(state: &State, d: &mut impl RaylibDraw) {
+    let Some(level) = &state.level else {
+        return;
+    };
+    let bounds = level.bounds();
+    d.draw_rectangle(
+        0,
+        0,
+        bounds.x as i32,
+        bounds.y as i32,
+        Color::new(24, 24, 32, 255),
+    );
+    let marker = level.tile_size as i32 / 2;
+    for (kind, color) in [
+        (Tile::PlayerSpawn, Color::new(40, 60, 110, 255)),
+        (Tile::EnemySpawner, Color::new(110, 30, 30, 255)),
+    ] {
+        for center in level.tile_centers(kind) {
+            d.draw_rectangle(
+                center.x as i32 - marker / 2,
+                center.y as i32 - marker / 2,
+                marker,
+                marker,
+                color,
+            );
+        }
+    }
+}
+
+pub fn draw_walls(ecs: &World, _state: &State, d: &mut impl RaylibDraw) {
+    for (_, (transform, shape)) in ecs.query::<(&Transform, &Shape)>().with::<&Wall>().iter() {
+        draw_shape(d, transform, shape, Color::GRAY);
+    }
+}
+
+pub fn draw_items(ecs: &World, _state: &State, d: &mut impl RaylibDraw) {
+    for (_, (transform, shape)) in ecs
+        .query::<(&Transform, &Shape)>()
+        .with::<&Attachable>()
+        .iter()
+    {
+        draw_shape(d, transform, shape, Color::YELLOW);
+    }
+}
+
+pub fn draw_enemies(ecs: &World, _state: &State, d: &mut impl RaylibDraw) {
+    for (_, (transform, shape)) in ecs.query::<(&Transform, &Shape)>().with::<&Enemy>().iter() {
+        draw_shape(d, transform, shape, Color::RED);
+    }
+}
+
+pub fn draw_players(ecs: &World, state: &State, d: &mut impl RaylibDraw) {
+    for (_, (transform, shape, owner, downed)) in ecs
+        .query::<(&Transform, &Shape, &OwnedByClient, Option<&Downed>)>()
+        .with::<&Player>()
+        .iter()
+    {
+        let color = if downed.is_some() {
+            Color::DARKGRAY
+        } else {
+            state
+                .roster
+                .iter()
+                .find(|entry| entry.client_id == owner.client_id)
+                .map(|entry| PLAYER_COLORS[(entry.color % PLAYER_COLOR_COUNT) as usize])
+                .unwrap_or(Color::BLUE)
+        };
+        draw_shape(d, transform, shape, color);
+    }
+}
+
+pub fn draw_projectiles(ecs: &World, _state: &State, d: &mut impl RaylibDraw) {
+    for (_, (transform, shape)) in ecs
+        .query::<(&Transform, &Shape)>()
+        .with::<&Projectile>()
+        .iter()
+    {
+        draw_shape(d, transform, shape, Color::WHITE);
+    }
+}
+
+/// Points out downed teammates, pinned to the screen edge when they are out of view.
+pub fn draw_downed_markers(ecs: &World, state: &State, d: &mut impl RaylibDraw) {
+    let margin = Vec2::new(12.0, 8.0);
+    for (_, transform) in ecs
+        .query::<&Transform>()
+        .with::<(&Player, &Downed)>()
+        .without::<&InputControlled>()
+        .iter()
+    {
+        let pos = state
+            .camera
+            .world_to_screen(transform.pos)
+            .clamp(margin, DIMS.as_vec2() - margin);
+        d.draw_text(
+            "DOWN",
+            pos.x as i32 - 12,
+            pos.y as i32 - 4,
+            10,
+            Color::ORANGE,
+        );
+    }
+}
+
+pub fn draw_crosshair(state: &State, d: &mut impl RaylibDraw) {
+    let pos = state.mouse_screen_pos;
+    d.draw_circle_lines(pos.x as i32, pos.y as i32, 4.0, Color::GREEN);
+    d.draw_circle(pos.x as i32, pos.y as i32, 1.0, Color::GREEN);
+}
+
+pub fn draw_hud(ecs: &World, state: &State, d: &mut impl RaylibDraw) {
+    if let Some((_, (health, score, downed))) = ecs
+        .query::<(&Health, Option<&Score>, Option<&Downed>)>()
+        .with::<&InputControlled>()
+        .iter()
+        .next()
+    {
+        let status = if downed.is_some() {
+            "DOWN - wait for a revive".to_string()
+        } else {
+            format!("HP {}", health.hp)
+        };
+        d.draw_text(&status, 4, DIMS.y as i32 - 12, 10, Color::WHITE);
+        let score = score.map(|score| score.score).unwrap_or(0);
+        d.draw_text(
+            &format!("Score {}", score),
+            4,
+            DIMS.y as i32 - 24,
+            10,
+            Color::WHITE,
+        );
+    }
+    if state.wave > 0 {
+        d.draw_text(
+            &format!("Wave {}", state.wave),
+            DIMS.x as i32 - 48,
+            4,
+            10,
+            Color::WHITE,
+        );
+    }
+}
+
+pub fn draw_chat(state: &State, d: &mut impl RaylibDraw) {
+    const ROW: i32 = 10;
+    let chat = &state.chat;
+    let mut y = DIMS.y as i32 - 36;
+
+    if chat.typing {
+        d.draw_rectangle(
+            2,
+            y - 1,
+            DIMS.x as i32 - 4,
+            ROW + 1,
+            Color::new(0, 0, 0, 180),
         );
+        d.draw_text(&format!("> {}_", chat.draft), 4, y, 10, Color::WHITE);
+    }
+
+    for (line, alpha) in chat.visible_lines().into_iter().rev() {
+        y -= ROW;
+        let alpha = (alpha * 255.0) as u8;
+        let color = if line.from.is_none() {
+            Color::new(255, 161, 0, alpha)
+        } else {
+            Color::new(255, 255, 255, alpha)
+        };
+        let text = format!("{}: {}", sender_label(state, line.from), line.text);
+        d.draw_text(&text, 4, y, 10, color);
+    }
+}
+
+pub fn draw_stats_table(
+    d: &mut impl RaylibDraw,
+    state: &State,
+    title: &str,
+    subtitle: &str,
+    stats: &[PlayerStats],
+) {
+    const ROW: i32 = 11;
+    const COLUMNS: [i32; 6] = [0, 76, 104, 128, 162, 192];
+    const NAME_CHARS: usize = 12;
+    let left = 16;
+    let top = 16;
+    let height = ROW * (stats.len() as i32 + 3);
+    d.draw_rectangle(
+        left - 4,
+        top - 4,
+        DIMS.x as i32 - 2 * (left - 4),
+        height + 8,
+        Color::new(0, 0, 0, 200),
+    );
+    d.draw_text(title, left, top, 10, Color::YELLOW);
+    d.draw_text(subtitle, left + 112, top, 10, Color::LIGHTGRAY);
+
+    let header = ["player", "kills", "dmg", "revive", "death", "score"];
+    for (column, text) in COLUMNS.iter().zip(header) {
+        d.draw_text(text, left + column, top + ROW * 2, 10, Color::LIGHTGRAY);
+    }
+
+    let client_id = CLIENT_ID.load(std::sync::atomic::Ordering::SeqCst);
+    for (row, stats) in stats.iter().enumerate() {
+        let y = top + ROW * (row as i32 + 3);
+        let color = if stats.client_id == client_id {
+            Color::GREEN
+        } else {
+            Color::WHITE
+        };
+        let cells = [
+            state
+                .name_of(stats.client_id)
+                .chars()
+                .take(NAME_CHARS)
+                .collect::<String>(),
+            stats.kills.to_string(),
+            stats.damage_dealt.to_string(),
+            stats.revives.to_string(),
+            stats.deaths.to_string(),
+            stats.score().to_string(),
+        ];
+        for (column, text) in COLUMNS.iter().zip(cells) {
+            d.draw_text(&text, left + column, y, 10, color);
+        }
+    }
+}
+
+pub fn draw_shape(d: &mut impl RaylibDraw, transform: &Transform, shape: &Shape, color: Color) {
+    match shape.kind {
+        ShapeKind::Circle => {
+            d.draw_circle(
+                transform.pos.x as i32,
+                transform.pos.y as i32,
+                shape.dims.x,
+                color,
+            );
+        }
+        ShapeKind::Rect => {
+            let top_left = transform.pos - shape.dims;
+            let size = shape.dims * 2.0;
+            d.draw_rectangle(
+                top_left.x as i32,
+                top_left.y as i32,
+                size.x as i32,
+                size.y as i32,
+                color,
+            );
+        }
     }
 }
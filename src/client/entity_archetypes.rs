@@ -1,26 +1,52 @@
 use glam::Vec2;
 use hecs::World;
 
+use crate::common::{
+    game_objects::{
+        ENEMY_MAX_HP, ENEMY_RADIUS, ITEM_DRAG, ITEM_HALF_EXTENT, PLAYER_GRAB_RADIUS, PLAYER_MAX_HP,
+        PLAYER_RADIUS,
+    },
+    inputs::PlayingInputs,
+    level::Level,
+};
+
 use super::{
-    components::{Health, InputControlled, OwnedByClient, Physics, Player, Shape, Transform},
-    graphics::DIMS,
+    components::{
+        Attachable, CaptureInPlayField, Controls, Drag, Enemy, FreeToLeavePlayField, GrabZone,
+        Health, InputControlled, NetworkId, OwnedByClient, Physics, Player, Projectile, Shape,
+        Transform, Wall, Weapon,
+    },
     state::State,
     udp_networking::CLIENT_ID,
 };
 
-pub const PLAYER_SHAPE: Vec2 = Vec2::new(16.0, 16.0);
-pub fn spawn_player(ecs: &mut World, _state: &mut State, owner_client_id: u32) {
+pub const PLAYER_SHAPE: Vec2 = Vec2::new(PLAYER_RADIUS, PLAYER_RADIUS);
+pub fn spawn_player(
+    ecs: &mut World,
+    _state: &mut State,
+    owner_client_id: u32,
+    entity_id: u32,
+    pos: Vec2,
+) {
     let player_entity = ecs.spawn((
         Player,
-        Transform {
-            pos: DIMS.as_vec2() / 2.0,
-        },
+        Transform { pos },
         Physics { vel: Vec2::ZERO },
-        Shape { dims: PLAYER_SHAPE },
-        Health { hp: 100 },
+        Shape::circle(PLAYER_SHAPE.x),
+        Health { hp: PLAYER_MAX_HP },
         OwnedByClient {
             client_id: owner_client_id,
         },
+        CaptureInPlayField,
+        GrabZone {
+            radius: PLAYER_GRAB_RADIUS,
+        },
+        NetworkId { id: entity_id },
+        Controls {
+            inputs: PlayingInputs::new(),
+            aim: Vec2::ZERO,
+        },
+        Weapon { cooldown: 0 },
     ));
 
     {
@@ -30,3 +56,61 @@ pub fn spawn_player(ecs: &mut World, _state: &mut State, owner_client_id: u32) {
         }
     }
 }
+
+pub const ITEM_SHAPE: Vec2 = Vec2::new(ITEM_HALF_EXTENT, ITEM_HALF_EXTENT);
+pub fn spawn_item(ecs: &mut World, entity_id: u32, pos: Vec2) {
+    ecs.spawn((
+        Attachable,
+        Transform { pos },
+        Physics { vel: Vec2::ZERO },
+        Drag { factor: ITEM_DRAG },
+        Shape::rect(ITEM_SHAPE),
+        CaptureInPlayField,
+        NetworkId { id: entity_id },
+    ));
+}
+
+pub fn spawn_enemy(ecs: &mut World, entity_id: u32, pos: Vec2) {
+    ecs.spawn((
+        Enemy,
+        Transform { pos },
+        Shape::circle(ENEMY_RADIUS),
+        Health { hp: ENEMY_MAX_HP },
+        NetworkId { id: entity_id },
+    ));
+}
+
+pub const PROJECTILE_RADIUS: f32 = 2.0;
+pub const PROJECTILE_DAMAGE: u32 = 10;
+pub fn spawn_projectile(ecs: &mut World, owner_client_id: u32, pos: Vec2, vel: Vec2) {
+    ecs.spawn((
+        Projectile {
+            owner_client_id,
+            damage: PROJECTILE_DAMAGE,
+        },
+        Transform { pos },
+        Physics { vel },
+        Shape::circle(PROJECTILE_RADIUS),
+        FreeToLeavePlayField,
+    ));
+}
+
+pub fn spawn_wall(ecs: &mut World, pos: Vec2, half_extents: Vec2) {
+    ecs.spawn((Wall, Transform { pos }, Shape::rect(half_extents)));
+}
+
+/// Replaces whatever walls exist with the level's.
+pub fn spawn_level_walls(ecs: &mut World, level: &Level) {
+    let old_walls: Vec<_> = ecs
+        .query::<()>()
+        .with::<&Wall>()
+        .iter()
+        .map(|(e, _)| e)
+        .collect();
+    for entity in old_walls {
+        let _ = ecs.despawn(entity);
+    }
+    for (center, half_extents) in level.wall_boxes() {
+        spawn_wall(ecs, center, half_extents);
+    }
+}
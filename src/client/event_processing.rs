@@ -1,17 +1,49 @@
 use glam::Vec2;
 use raylib::prelude::*;
 
-use super::state::State;
+use crate::common::{game_objects::MatchPhase, inputs::PlayingInputs};
+
+use super::{
+    chat::process_chat_input, discovery::process_server_browser_input, lobby::process_lobby_input,
+    rooms::process_room_browser_input, state::State,
+};
 
 const PLAYER_SPEED: f32 = 1.0;
 
 pub fn process_events_and_input(rl: &mut RaylibHandle, state: &mut State) {
-    if rl.is_key_pressed(raylib::consts::KeyboardKey::KEY_ESCAPE) {
+    if state.server.is_none() || state.room.is_none() {
+        if rl.is_key_pressed(raylib::consts::KeyboardKey::KEY_ESCAPE) {
+            state.running = false;
+        }
+        if state.server.is_none() {
+            process_server_browser_input(rl, state);
+        } else {
+            process_room_browser_input(rl, state);
+        }
+        state.playing_inputs = PlayingInputs::new();
+        return;
+    }
+
+    // while typing, the keyboard belongs to the chat box
+    let chatting = process_chat_input(rl, state);
+    if !chatting && rl.is_key_pressed(raylib::consts::KeyboardKey::KEY_ESCAPE) {
         state.running = false;
     }
 
+    // set_mouse_scale already maps the window onto the render texture
     let mouse_pos_rl = rl.get_mouse_position();
-    let _mouse_pos = Vec2::new(mouse_pos_rl.x, mouse_pos_rl.y);
+    state.mouse_screen_pos = Vec2::new(mouse_pos_rl.x, mouse_pos_rl.y);
+    state.mouse_pos = state.camera.screen_to_world(state.mouse_screen_pos);
+
+    if chatting {
+        state.playing_inputs = PlayingInputs::new();
+        return;
+    }
+    if state.phase != MatchPhase::InGame {
+        process_lobby_input(rl, state);
+        state.playing_inputs = PlayingInputs::new();
+        return;
+    }
 
     let mut inputs = PlayingInputs::new();
 
@@ -32,10 +64,23 @@ pub fn process_events_and_input(rl: &mut RaylibHandle, state: &mut State) {
         inputs.shoot = true;
     }
 
+    if rl.is_mouse_button_down(raylib::consts::MouseButton::MOUSE_BUTTON_RIGHT) {
+        inputs.throw = true;
+    }
+
     if rl.is_key_down(raylib::consts::KeyboardKey::KEY_SPACE) {
         inputs.confirm = true;
     }
 
+    if rl.is_key_down(raylib::consts::KeyboardKey::KEY_E) {
+        inputs.revive = true;
+    }
+
+    state.show_scoreboard = rl.is_key_down(raylib::consts::KeyboardKey::KEY_TAB);
+    if rl.is_key_pressed(raylib::consts::KeyboardKey::KEY_F3) {
+        state.show_debug = !state.show_debug;
+    }
+
     if rl.is_key_down(raylib::consts::KeyboardKey::KEY_ONE) {
         inputs.weapon_1 = true;
     }
@@ -54,37 +99,3 @@ pub fn process_events_and_input(rl: &mut RaylibHandle, state: &mut State) {
 
     state.playing_inputs = inputs;
 }
-
-pub struct PlayingInputs {
-    pub left: bool,
-    pub right: bool,
-    pub up: bool,
-    pub down: bool,
-
-    pub shoot: bool,
-    pub confirm: bool,
-
-    pub weapon_1: bool,
-    pub weapon_2: bool,
-    pub weapon_3: bool,
-    pub weapon_4: bool,
-}
-impl PlayingInputs {
-    pub fn new() -> PlayingInputs {
-        PlayingInputs {
-            left: false,
-            right: false,
-            up: false,
-            down: false,
-
-            shoot: false,
-
-            confirm: false,
-
-            weapon_1: false,
-            weapon_2: false,
-            weapon_3: false,
-            weapon_4: false,
-        }
-    }
-}
@@ -1,8 +1,182 @@
 use hecs::World;
 
-use super::{state::State, systems};
+use super::{camera::follow_player, rollback, state::State, systems};
+
+/// One fixed step of whatever sync mode we are in.
+pub fn advance(ecs: &mut World, state: &mut State) {
+    state.server_step += 1;
+    if state.rollback.is_some() {
+        rollback::advance(ecs, state);
+    } else {
+        step(ecs, state);
+    }
+}
 
 pub fn step(ecs: &mut World, state: &mut State) {
+    systems::controlling::apply_local_inputs(ecs, state);
+    simulate(ecs, state);
+}
+
+/// Runs the systems on whatever `Controls` the players have been given.
+pub fn simulate(ecs: &mut World, state: &mut State) {
     systems::controlling::control_player(ecs, state);
+    // grabbing and reviving go through the server, which is not rolled back
+    if !state.resimulating {
+        systems::carrying::grab_and_release(ecs, state);
+        systems::reviving::revive_teammates(ecs, state);
+    }
+    systems::shooting::fire_weapons(ecs, state);
     systems::physics::step_physics(ecs, state);
+    systems::collision::collide_with_walls(ecs, state);
+    systems::projectiles::hit_enemies(ecs, state);
+    systems::play_field::keep_in_play_field(ecs, state);
+    systems::carrying::carry_attached(ecs, state);
+    follow_player(ecs, state);
+
+    state.previous_playing_inputs = state.playing_inputs;
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+    use hecs::World;
+
+    use crate::{
+        client::{
+            components::{Controls, OwnedByClient, Physics, Player, Projectile, Transform, Weapon},
+            entity_archetypes::{spawn_enemy, spawn_player, spawn_projectile, spawn_wall},
+            state::State,
+            udp_networking::CLIENT_ID,
+        },
+        common::{client_to_server::ClientToServerMessageData, inputs::PlayingInputs},
+    };
+
+    use super::simulate;
+
+    const RUNNER: u32 = 42;
+    const TARGET: Vec2 = Vec2::new(160.0, 100.0);
+
+    /// The same scene, but with everything spawned in the opposite order and the storage
+    /// churned up first when `scrambled`.
+    fn scene(scrambled: bool) -> (World, State, u32) {
+        let mut ecs = World::new();
+        let mut state = State::new();
+        let shooter = CLIENT_ID.load(std::sync::atomic::Ordering::SeqCst);
+        if scrambled {
+            for index in 0..5 {
+                spawn_projectile(&mut ecs, RUNNER, Vec2::splat(index as f32), Vec2::ZERO);
+                spawn_enemy(&mut ecs, 100 + index, Vec2::ZERO);
+            }
+            let junk: Vec<_> = ecs.iter().map(|entity| entity.entity()).collect();
+            for entity in junk {
+                let _ = ecs.despawn(entity);
+            }
+        }
+
+        let mut walls = vec![
+            (Vec2::new(200.0, 60.0), Vec2::new(4.0, 60.0)),
+            (Vec2::new(200.0, 100.0), Vec2::new(40.0, 4.0)),
+        ];
+        // two of them in the very same spot, whichever is hit first
+        let mut enemies = vec![(3, TARGET), (7, TARGET), (9, TARGET + Vec2::new(10.0, 4.0))];
+        if scrambled {
+            walls.reverse();
+            enemies.reverse();
+        }
+        let mut players = vec![
+            (shooter, 201, Vec2::new(100.0, 100.0)),
+            (RUNNER, 202, Vec2::new(150.0, 60.0)),
+        ];
+        if scrambled {
+            players.reverse();
+        }
+        for (pos, half_extents) in walls {
+            spawn_wall(&mut ecs, pos, half_extents);
+        }
+        for (entity_id, pos) in enemies {
+            spawn_enemy(&mut ecs, entity_id, pos);
+        }
+        for (client_id, entity_id, pos) in players {
+            spawn_player(&mut ecs, &mut state, client_id, entity_id, pos);
+        }
+        (ecs, state, shooter)
+    }
+
+    /// The shooter fires at the target while drifting up and down, the runner heads into
+    /// the corner where the walls meet.
+    fn press(ecs: &mut World, step: u32, shooter: u32) {
+        for (_, (owner, controls)) in ecs.query_mut::<(&OwnedByClient, &mut Controls)>() {
+            let mut inputs = PlayingInputs::new();
+            if owner.client_id == shooter {
+                inputs.shoot = true;
+                inputs.up = (step / 20).is_multiple_of(2);
+                inputs.down = !inputs.up;
+                controls.aim = TARGET;
+            } else {
+                inputs.right = true;
+                inputs.down = (step / 15).is_multiple_of(2);
+            }
+            controls.inputs = inputs;
+        }
+    }
+
+    /// Everything a step changes, in an order that does not depend on storage.
+    type Outcome = (Vec<(u32, Vec2, Vec2, u32)>, Vec<(Vec2, Vec2)>, Vec<u32>);
+
+    fn outcome(ecs: &World, hits: Vec<ClientToServerMessageData>) -> Outcome {
+        let mut players: Vec<(u32, Vec2, Vec2, u32)> = ecs
+            .query::<(&OwnedByClient, &Transform, &Physics, &Weapon)>()
+            .with::<&Player>()
+            .iter()
+            .map(|(_, (owner, transform, physics, weapon))| {
+                (owner.client_id, transform.pos, physics.vel, weapon.cooldown)
+            })
+            .collect();
+        players.sort_by_key(|(client_id, ..)| *client_id);
+        let mut projectiles: Vec<(Vec2, Vec2)> = ecs
+            .query::<(&Transform, &Physics)>()
+            .with::<&Projectile>()
+            .iter()
+            .map(|(_, (transform, physics))| (transform.pos, physics.vel))
+            .collect();
+        projectiles.sort_by(|a, b| {
+            let key = |(pos, vel): &(Vec2, Vec2)| [pos.x, pos.y, vel.x, vel.y].map(f32::to_bits);
+            key(a).cmp(&key(b))
+        });
+        let hits = hits
+            .into_iter()
+            .map(|hit| match hit {
+                ClientToServerMessageData::HitEnemy { entity_id, .. } => entity_id,
+                _ => unreachable!(),
+            })
+            .collect();
+        (players, projectiles, hits)
+    }
+
+    #[test]
+    fn the_same_inputs_give_the_same_world_however_it_is_stored() {
+        let (mut ecs, mut state, shooter) = scene(false);
+        let (mut scrambled_ecs, mut scrambled_state, _) = scene(true);
+        let mut hit = false;
+        for step in 0..200 {
+            let mut outcomes = Vec::new();
+            for (ecs, state) in [
+                (&mut ecs, &mut state),
+                (&mut scrambled_ecs, &mut scrambled_state),
+            ] {
+                press(ecs, step, shooter);
+                state.held_hits = Some(Vec::new());
+                simulate(ecs, state);
+                let hits = state.held_hits.take().unwrap();
+                outcomes.push(outcome(ecs, hits));
+            }
+            assert_eq!(
+                outcomes[0], outcomes[1],
+                "worlds differ after step {}",
+                step
+            );
+            hit |= !outcomes[0].2.is_empty();
+        }
+        assert!(hit);
+    }
 }
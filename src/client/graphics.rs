@@ -19,6 +19,8 @@ pub fn init_graphics() -> (RaylibHandle, RaylibThread, RenderTexture2D) {
     }
 
     rl.set_window_size(WINDOW_DIMS.x as i32, WINDOW_DIMS.y as i32);
+    // escape cancels typing in chat, quitting is handled in process_events_and_input
+    rl.set_exit_key(None);
     if FULLSCREEN {
         rl.toggle_fullscreen();
         let monitor = get_current_monitor();
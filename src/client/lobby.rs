@@ -0,0 +1,77 @@
+use raylib::prelude::*;
+
+use crate::common::{
+    client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+    game_objects::{MatchPhase, RosterEntry, SyncMode, PLAYER_COLOR_COUNT},
+};
+
+use super::{
+    rooms::leave_room,
+    state::State,
+    udp_networking::{CLIENT_ID, OUTBOUND_MESSAGE_QUEUE},
+};
+
+/// Lobby: C cycles color, R toggles ready, Space starts the match and M switches between
+/// snapshot and rollback sync if we are the host.
+/// Results: Space sends everyone back to the lobby if we are the host.
+/// L leaves the room from either.
+pub fn process_lobby_input(rl: &RaylibHandle, state: &State) {
+    if rl.is_key_pressed(KeyboardKey::KEY_L) {
+        leave_room();
+        return;
+    }
+    let space = rl.is_key_pressed(KeyboardKey::KEY_SPACE);
+    match state.phase {
+        MatchPhase::Lobby => {
+            let Some(me) = own_entry(state) else {
+                return;
+            };
+            if rl.is_key_pressed(KeyboardKey::KEY_C) {
+                send(ClientToServerMessageData::SetColor {
+                    color: (me.color + 1) % PLAYER_COLOR_COUNT,
+                });
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_R) {
+                send(ClientToServerMessageData::SetReady { ready: !me.ready });
+            }
+            if space && is_host(state) {
+                send(ClientToServerMessageData::StartMatch);
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_M) && is_host(state) {
+                let mode = match state.sync_mode {
+                    SyncMode::Snapshot => SyncMode::Rollback,
+                    SyncMode::Rollback => SyncMode::Snapshot,
+                };
+                send(ClientToServerMessageData::SetSyncMode { mode });
+            }
+        }
+        MatchPhase::Results => {
+            if space && is_host(state) {
+                send(ClientToServerMessageData::ReturnToLobby);
+            }
+        }
+        MatchPhase::InGame => {}
+    }
+}
+
+pub fn own_entry(state: &State) -> Option<&RosterEntry> {
+    let client_id = CLIENT_ID.load(std::sync::atomic::Ordering::SeqCst);
+    state
+        .roster
+        .iter()
+        .find(|entry| entry.client_id == client_id)
+}
+
+pub fn is_host(state: &State) -> bool {
+    let client_id = CLIENT_ID.load(std::sync::atomic::Ordering::SeqCst);
+    state.host == Some(client_id)
+}
+
+fn send(data: ClientToServerMessageData) {
+    if OUTBOUND_MESSAGE_QUEUE
+        .push(ClientToServerMessage::new(data))
+        .is_err()
+    {
+        eprintln!("Outbound message queue full: dropping message");
+    }
+}
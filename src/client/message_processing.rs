@@ -1,15 +1,46 @@
+use glam::Vec2;
 use hecs::World;
 
 use crate::{
-    client::{entity_archetypes::spawn_player, udp_networking::CLIENT_ID},
-    common::server_to_client::ServerToClientMessage,
+    client::{
+        components::{
+            AttachedTo, Downed, Health, InputControlled, OwnedBy, OwnedByClient, Physics, Player,
+            Score, Transform,
+        },
+        desync::{apply_snapshot, check_world},
+        entity_archetypes::{
+            spawn_enemy, spawn_item, spawn_level_walls, spawn_player, spawn_projectile,
+        },
+        network_entities::find_entity,
+        rollback,
+        rooms::reset_room,
+        systems::carrying::attach,
+        udp_networking::{CLIENT_ID, SEND_COMPRESSED},
+    },
+    common::{
+        game_objects::{MatchPhase, SyncMode},
+        level::Level,
+        quantize::{dequantize_pos, dequantize_vel},
+        server_to_client::ServerToClientMessage,
+        util::get_utc_now,
+    },
 };
 
-use super::{state::State, udp_networking::INCOMING_MESSAGE_QUEUE};
+use super::{
+    state::{State, WaveResults},
+    udp_networking::INCOMING_MESSAGE_QUEUE,
+};
 
 pub async fn process_message_queue(ecs: &mut World, state: &mut State) {
     while let Some(message) = INCOMING_MESSAGE_QUEUE.pop() {
+        state.last_heard_from_server = get_utc_now();
         match message {
+            ServerToClientMessage::Hello { compression } => {
+                SEND_COMPRESSED.store(compression, std::sync::atomic::Ordering::Relaxed);
+                if compression {
+                    println!("server agreed to compression");
+                }
+            }
             ServerToClientMessage::ClientIDAssignment { new_client_id } => {
                 CLIENT_ID.store(new_client_id, std::sync::atomic::Ordering::SeqCst);
                 println!("new id assigned: {}", new_client_id);
@@ -17,29 +48,93 @@ pub async fn process_message_queue(ecs: &mut World, state: &mut State) {
             ServerToClientMessage::Welcome { server_message } => {
                 println!("Server says: {}", server_message);
             }
-            ServerToClientMessage::ClientJoined { id } => {
-                println!("Client {} joined", id);
+            ServerToClientMessage::ClientJoined { id, name } => {
+                println!("{} joined", name);
+                state.names.insert(id, name.clone());
+                state.chat.push(None, format!("{} joined", name));
             }
             ServerToClientMessage::ClientLeft { id } => {
-                println!("Client {} left", id);
+                let name = state.name_of(id);
+                println!("{} left", name);
+                state.chat.push(None, format!("{} left", name));
+                if let Some(session) = &mut state.rollback {
+                    session.remove_peer(id);
+                }
+            }
+            ServerToClientMessage::Roster { players, host } => {
+                for entry in &players {
+                    state.names.insert(entry.client_id, entry.name.clone());
+                }
+                state.roster = players;
+                state.host = host;
+            }
+            ServerToClientMessage::RoomList { rooms } => {
+                let browser = &mut state.room_browser;
+                browser.selected = browser.selected.min(rooms.len());
+                browser.rooms = rooms;
+            }
+            ServerToClientMessage::JoinedRoom { code } => {
+                println!("joined room {}", code);
+                reset_room(ecs, state);
+                state.chat.push(None, format!("joined room {}", code));
+                state.room = Some(code);
+            }
+            ServerToClientMessage::LeftRoom => {
+                reset_room(ecs, state);
+                state.room = None;
+            }
+            ServerToClientMessage::MatchPhaseChanged { phase } => {
+                state.phase = phase;
+                if phase == MatchPhase::Lobby {
+                    state.wave = 0;
+                    state.wave_results = None;
+                }
+                if phase != MatchPhase::InGame {
+                    state.rollback = None;
+                } else if state.sync_mode == SyncMode::Rollback && state.rollback.is_none() {
+                    state.rollback = Some(rollback::start(state));
+                }
+            }
+            ServerToClientMessage::SyncModeChanged { mode } => {
+                println!("syncing by {:?}", mode);
+                state.sync_mode = mode;
+            }
+            ServerToClientMessage::PeerInputs {
+                client_id,
+                tick,
+                inputs,
+            } => {
+                if let Some(session) = &mut state.rollback {
+                    session.receive(client_id, tick, &inputs);
+                }
             }
             ServerToClientMessage::ChatMessage { from, message } => {
-                println!("{} says: {}", from, message);
+                println!("{} says: {}", state.name_of(from), message);
+                state.chat.push(Some(from), message);
+            }
+            ServerToClientMessage::Notice { message } => {
+                state.chat.push(None, message);
             }
             ServerToClientMessage::SpawnPlayer {
                 owner_client_id,
                 entity_id,
+                pos,
             } => {
-                spawn_player(ecs, state, owner_client_id);
+                spawn_player(ecs, state, owner_client_id, entity_id, pos);
                 println!("player spawned {}", entity_id);
             }
-            ServerToClientMessage::EntityPosition {
-                entity_id: _entity_id,
-                pos: _pos,
-            } => {
-                // if let Some(player) = state.players.get_mut(&entity_id) {
-                //     player.pos = pos;
-                // }
+            ServerToClientMessage::EntityPosition { entity_id, pos } => {
+                move_remote_entity(ecs, entity_id, pos);
+            }
+            ServerToClientMessage::EnemyPositions { positions } => {
+                // quantized against the level, so nothing can be made of them without it
+                let Some(bounds) = state.level.as_ref().map(Level::bounds) else {
+                    continue;
+                };
+                for packed in positions {
+                    let pos = dequantize_pos(packed.pos, bounds);
+                    move_remote_entity(ecs, packed.entity_id, pos);
+                }
             }
             ServerToClientMessage::AllPlayers { players: _players } => {
                 // for player in players {
@@ -59,6 +154,163 @@ pub async fn process_message_queue(ecs: &mut World, state: &mut State) {
                 // put every entity into entities
                 let _ = (for_client_id, entities);
             }
+            ServerToClientMessage::SpawnItem { entity_id, pos } => {
+                spawn_item(ecs, entity_id, pos);
+            }
+            ServerToClientMessage::ItemGrabbed {
+                entity_id,
+                holder_entity_id,
+            } => {
+                if let (Some(item), Some(holder)) = (
+                    find_entity(ecs, entity_id),
+                    find_entity(ecs, holder_entity_id),
+                ) {
+                    attach(ecs, item, holder);
+                }
+            }
+            ServerToClientMessage::ItemReleased {
+                entity_id,
+                pos,
+                vel,
+            } => {
+                if let Some(item) = find_entity(ecs, entity_id) {
+                    let _ = ecs.remove::<(AttachedTo, OwnedBy)>(item);
+                    if let Ok(mut transform) = ecs.get::<&mut Transform>(item) {
+                        transform.pos = pos;
+                    }
+                    if let Ok(mut physics) = ecs.get::<&mut Physics>(item) {
+                        physics.vel = vel;
+                    }
+                }
+            }
+            ServerToClientMessage::GrabDenied { entity_id } => {
+                println!("grab of {} denied", entity_id);
+            }
+            // acks are consumed by the network task
+            ServerToClientMessage::Ack { .. } => {}
+            ServerToClientMessage::ProjectileFired {
+                owner_client_id,
+                pos,
+                vel,
+            } => {
+                let Some(bounds) = state.level.as_ref().map(Level::bounds) else {
+                    continue;
+                };
+                let pos = dequantize_pos(pos, bounds);
+                spawn_projectile(ecs, owner_client_id, pos, dequantize_vel(vel));
+            }
+            ServerToClientMessage::SpawnEnemy { entity_id, pos } => {
+                // one we kept from before a migration comes round again
+                if find_entity(ecs, entity_id).is_none() {
+                    spawn_enemy(ecs, entity_id, pos);
+                }
+            }
+            ServerToClientMessage::DespawnEntity { entity_id } => {
+                if let Some(entity) = find_entity(ecs, entity_id) {
+                    let _ = ecs.despawn(entity);
+                }
+            }
+            ServerToClientMessage::PlayerHealth {
+                entity_id,
+                hp,
+                downed,
+            } => {
+                if let Some(entity) = find_entity(ecs, entity_id) {
+                    let _ = ecs.insert_one(entity, Health { hp });
+                    if downed {
+                        let _ = ecs.insert_one(entity, Downed);
+                    } else {
+                        let _ = ecs.remove_one::<Downed>(entity);
+                    }
+                }
+            }
+            ServerToClientMessage::Scoreboard { stats } => {
+                let mut scores = Vec::new();
+                for (entity, owner) in ecs.query::<&OwnedByClient>().with::<&Player>().iter() {
+                    if let Some(stats) = stats.iter().find(|s| s.client_id == owner.client_id) {
+                        scores.push((
+                            entity,
+                            Score {
+                                owner: entity,
+                                score: stats.score(),
+                            },
+                        ));
+                    }
+                }
+                for (entity, score) in scores {
+                    let _ = ecs.insert_one(entity, score);
+                }
+                state.scoreboard = stats;
+            }
+            ServerToClientMessage::WaveStarted { wave } => {
+                state.wave = wave;
+                state.wave_results = None;
+            }
+            ServerToClientMessage::WaveEnded {
+                wave,
+                cleared,
+                stats,
+            } => {
+                state.scoreboard = stats.clone();
+                state.wave_results = Some(WaveResults {
+                    wave,
+                    cleared,
+                    stats,
+                });
+            }
+            ServerToClientMessage::LoadLevel { level_id, checksum } => {
+                let level = match Level::load(&level_id) {
+                    Ok(level) => level,
+                    Err(e) => {
+                        eprintln!("Error loading level {}: {}", level_id, e);
+                        continue;
+                    }
+                };
+                if level.checksum != checksum {
+                    eprintln!(
+                        "Level {} differs from the server's copy, expect things to look off",
+                        level_id
+                    );
+                }
+                println!("loaded level {}", level.name);
+                spawn_level_walls(ecs, &level);
+                state.play_field = level.bounds();
+                state.level = Some(level);
+            }
+            ServerToClientMessage::Successor { client_id, addr } => {
+                state.successor = Some((client_id, addr));
+            }
+            ServerToClientMessage::MigrationSnapshotPart {
+                id,
+                index,
+                count,
+                bytes,
+            } => {
+                if let Some(snapshot) = state.migration_parts.accept(id, index, count, bytes) {
+                    state.migration_snapshot = Some(snapshot);
+                }
+            }
+            ServerToClientMessage::WorldChecksum { step, checksum } => {
+                state.server_step = step;
+                check_world(ecs, state, step, checksum);
+            }
+            ServerToClientMessage::WorldSnapshot { snapshot } => {
+                apply_snapshot(ecs, state, &snapshot);
+            }
+            ServerToClientMessage::RejoinToken { token } => {
+                state.rejoin_token = Some(token);
+            }
+        }
+    }
+}
+
+fn move_remote_entity(ecs: &World, entity_id: u32, pos: Vec2) {
+    // our own player is simulated locally
+    if let Some(entity) = find_entity(ecs, entity_id) {
+        if !ecs.satisfies::<&InputControlled>(entity).unwrap_or(false) {
+            if let Ok(mut transform) = ecs.get::<&mut Transform>(entity) {
+                transform.pos = pos;
+            }
         }
     }
 }
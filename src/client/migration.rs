@@ -0,0 +1,110 @@
+use std::net::SocketAddr;
+
+use hecs::World;
+
+use crate::{
+    common::{
+        client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+        util::get_utc_now,
+    },
+    server,
+};
+
+use super::{
+    discovery::{discover_servers, DISCOVERING},
+    rooms::reset_room,
+    settings::HOST_TIMEOUT_MS,
+    state::State,
+    udp_networking::{close_connection, init_connection, CLIENT_ID, OUTBOUND_MESSAGE_QUEUE},
+};
+
+/// True once the server of the room we are in has been quiet for too long.
+pub fn host_lost(state: &State) -> bool {
+    state.server.is_some()
+        && !state.hosting
+        && state.room.is_some()
+        && get_utc_now() - state.last_heard_from_server > HOST_TIMEOUT_MS
+}
+
+/// Moves the session over to the successor the server named. If that is us, the server
+/// is started in this process from the last snapshot, on the port the others know us by.
+/// Everyone then rejoins under their old client id.
+pub async fn migrate(ecs: &mut World, state: &mut State, name: &str) {
+    close_connection().await;
+    let own_id = CLIENT_ID.load(std::sync::atomic::Ordering::SeqCst);
+    let (Some(old_server), Some((successor_id, successor_addr))) =
+        (state.server, state.successor.take())
+    else {
+        println!("server is gone and nobody can take over");
+        back_to_server_browser(ecs, state);
+        return;
+    };
+
+    let server_addr = if successor_id == own_id {
+        let (Some(snapshot), Some(local_addr)) =
+            (state.migration_snapshot.take(), state.local_addr)
+        else {
+            back_to_server_browser(ecs, state);
+            return;
+        };
+        let bind_addr = SocketAddr::from(([0, 0, 0, 0], local_addr.port()));
+        match server::listen::resume(bind_addr, snapshot, own_id).await {
+            Ok(listen_addr) => {
+                state.hosting = true;
+                SocketAddr::from(([127, 0, 0, 1], listen_addr.port()))
+            }
+            Err(e) => {
+                eprintln!("Could not take over as host: {}", e);
+                back_to_server_browser(ecs, state);
+                return;
+            }
+        }
+    } else if successor_addr.ip().is_loopback() {
+        // the successor shared a machine with the old server
+        SocketAddr::new(old_server.ip(), successor_addr.port())
+    } else {
+        successor_addr
+    };
+    println!("host lost, migrating to {}", server_addr);
+    let successor_name = state.name_of(successor_id);
+    state.chat.push(
+        None,
+        format!("host left, {} is taking over", successor_name),
+    );
+
+    reset_room(ecs, state);
+    state.room = None;
+    state.migration_snapshot = None;
+    let local_addr = match init_connection(server_addr).await {
+        Ok(local_addr) => local_addr,
+        Err(e) => {
+            eprintln!("Error connecting to {}: {:?}", server_addr, e);
+            back_to_server_browser(ecs, state);
+            return;
+        }
+    };
+    state.server = Some(server_addr);
+    state.local_addr = Some(local_addr);
+    state.last_heard_from_server = get_utc_now();
+
+    let message = ClientToServerMessage::new(ClientToServerMessageData::Rejoin {
+        previous_client_id: own_id,
+        // without one we come back as somebody new
+        token: state.rejoin_token.unwrap_or_default(),
+        name: name.to_string(),
+    });
+    if OUTBOUND_MESSAGE_QUEUE.push(message).is_err() {
+        eprintln!("Outbound message queue full: dropping message");
+    }
+}
+
+fn back_to_server_browser(ecs: &mut World, state: &mut State) {
+    reset_room(ecs, state);
+    state.room = None;
+    state.server = None;
+    state.local_addr = None;
+    state.successor = None;
+    state.migration_snapshot = None;
+    DISCOVERING.store(true, std::sync::atomic::Ordering::SeqCst);
+    tokio::spawn(discover_servers());
+}
@@ -1,10 +1,20 @@
+pub mod camera;
+pub mod chat;
 pub mod components;
+pub mod desync;
+pub mod discovery;
 pub mod draw;
 pub mod entity_archetypes;
 pub mod event_processing;
 pub mod game;
 pub mod graphics;
+pub mod lobby;
 pub mod message_processing;
+pub mod migration;
+pub mod network_entities;
+pub mod replay;
+pub mod rollback;
+pub mod rooms;
 pub mod settings;
 pub mod state;
 pub mod systems;
@@ -0,0 +1,39 @@
+use hecs::{Entity, World};
+
+use crate::common::client_to_server::{ClientToServerMessage, ClientToServerMessageData};
+
+use super::{
+    components::{InputControlled, NetworkId, Transform},
+    state::State,
+    udp_networking::OUTBOUND_MESSAGE_QUEUE,
+};
+
+pub fn find_entity(ecs: &World, network_id: u32) -> Option<Entity> {
+    ecs.query::<&NetworkId>()
+        .iter()
+        .find(|(_, id)| id.id == network_id)
+        .map(|(entity, _)| entity)
+}
+
+pub fn interval_transmit_position(current_frame: u32, interval: u32, ecs: &World, _state: &State) {
+    if !current_frame.is_multiple_of(interval) {
+        return;
+    }
+    for (_, (transform, id)) in ecs
+        .query::<(&Transform, &NetworkId)>()
+        .with::<&InputControlled>()
+        .iter()
+    {
+        if OUTBOUND_MESSAGE_QUEUE
+            .push(ClientToServerMessage::new(
+                ClientToServerMessageData::EntityPosition {
+                    entity_id: id.id,
+                    pos: transform.pos,
+                },
+            ))
+            .is_err()
+        {
+            eprintln!("Outbound message queue full: dropping message");
+        }
+    }
+}
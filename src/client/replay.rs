@@ -0,0 +1,201 @@
+use std::path::Path;
+
+use glam::Vec2;
+use hecs::World;
+use raylib::prelude::*;
+
+use crate::{
+    common::{level::Level, tick::FRAMES_PER_SECOND},
+    server::{replay::Playback, state::State as RoomState},
+};
+
+use super::{
+    components::{Downed, Health, Wall},
+    entity_archetypes::{
+        spawn_enemy, spawn_item, spawn_level_walls, spawn_player, spawn_projectile,
+    },
+    network_entities::find_entity,
+    settings::{REPLAY_CAMERA_SPEED, REPLAY_MAX_SPEED, REPLAY_MIN_SPEED, REPLAY_SEEK_STEPS},
+    state::State,
+    udp_networking::CLIENT_ID,
+};
+
+/// Watching a recorded match instead of playing one.
+pub struct ReplayViewer {
+    pub playback: Playback,
+    pub paused: bool,
+    /// Replay steps per real step.
+    pub speed: f32,
+    /// The camera stays where it is put instead of following the first player.
+    pub free_camera: bool,
+    /// Steps owed to the playback, carried over between frames.
+    pub step_debt: f32,
+}
+
+/// Opens a replay file and shows its first step.
+pub fn open(ecs: &mut World, state: &mut State, path: &Path) -> std::io::Result<()> {
+    let playback = Playback::load(path)?;
+    // nobody in the recording is us
+    CLIENT_ID.store(u32::MAX, std::sync::atomic::Ordering::SeqCst);
+    load_level(ecs, state, &playback.state.level);
+    state.room = Some(playback.state.code.clone());
+    state.replay = Some(ReplayViewer {
+        playback,
+        paused: false,
+        speed: 1.0,
+        free_camera: false,
+        step_debt: 0.0,
+    });
+    show(ecs, state);
+    Ok(())
+}
+
+fn load_level(ecs: &mut World, state: &mut State, level: &Level) {
+    spawn_level_walls(ecs, level);
+    state.play_field = level.bounds();
+    state.level = Some(level.clone());
+}
+
+pub fn process_replay_input(rl: &mut RaylibHandle, state: &mut State) {
+    if rl.is_key_pressed(KeyboardKey::KEY_ESCAPE) {
+        state.running = false;
+    }
+    state.show_scoreboard = rl.is_key_down(KeyboardKey::KEY_TAB);
+    let Some(viewer) = &mut state.replay else {
+        return;
+    };
+
+    if rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
+        viewer.paused = !viewer.paused;
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+        viewer.speed = (viewer.speed * 2.0).min(REPLAY_MAX_SPEED);
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+        viewer.speed = (viewer.speed / 2.0).max(REPLAY_MIN_SPEED);
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_F) {
+        viewer.free_camera = !viewer.free_camera;
+    }
+
+    if viewer.free_camera {
+        let mut pan = Vec2::ZERO;
+        if rl.is_key_down(KeyboardKey::KEY_W) {
+            pan.y -= 1.0;
+        }
+        if rl.is_key_down(KeyboardKey::KEY_S) {
+            pan.y += 1.0;
+        }
+        if rl.is_key_down(KeyboardKey::KEY_A) {
+            pan.x -= 1.0;
+        }
+        if rl.is_key_down(KeyboardKey::KEY_D) {
+            pan.x += 1.0;
+        }
+        state.camera.target += pan * REPLAY_CAMERA_SPEED;
+    }
+}
+
+/// Moves the playback on by however many steps are due at its speed, or to wherever the
+/// seek keys point, and shows the result.
+pub async fn advance(rl: &RaylibHandle, ecs: &mut World, state: &mut State, dt: f32) {
+    let Some(viewer) = &mut state.replay else {
+        return;
+    };
+    let current = viewer.playback.state.step_count;
+    let seek_to = if rl.is_key_pressed(KeyboardKey::KEY_RIGHT) {
+        Some(current + REPLAY_SEEK_STEPS)
+    } else if rl.is_key_pressed(KeyboardKey::KEY_LEFT) {
+        Some(current.saturating_sub(REPLAY_SEEK_STEPS))
+    } else if rl.is_key_pressed(KeyboardKey::KEY_HOME) {
+        Some(viewer.playback.first_step)
+    } else {
+        None
+    };
+
+    if let Some(step) = seek_to {
+        viewer.playback.seek(step).await;
+        viewer.step_debt = 0.0;
+    } else if viewer.paused {
+        // step through one at a time
+        if rl.is_key_pressed(KeyboardKey::KEY_PERIOD) && !viewer.playback.at_end() {
+            viewer.playback.step().await;
+        }
+    } else {
+        viewer.step_debt += dt * FRAMES_PER_SECOND as f32 * viewer.speed;
+        // a long hitch should not turn into a fast forward
+        viewer.step_debt = viewer.step_debt.min(REPLAY_MAX_SPEED * 4.0);
+        while viewer.step_debt >= 1.0 && !viewer.playback.at_end() {
+            viewer.playback.step().await;
+            viewer.step_debt -= 1.0;
+        }
+    }
+    show(ecs, state);
+}
+
+/// Rebuilds the world we draw from the re-simulated room.
+fn show(ecs: &mut World, state: &mut State) {
+    let Some(viewer) = state.replay.take() else {
+        return;
+    };
+    let room: &RoomState = &viewer.playback.state;
+
+    let shown: Vec<_> = ecs
+        .query::<()>()
+        .without::<&Wall>()
+        .iter()
+        .map(|(entity, _)| entity)
+        .collect();
+    for entity in shown {
+        let _ = ecs.despawn(entity);
+    }
+
+    let mut players: Vec<_> = room.players.values().collect();
+    players.sort_by_key(|player| player.entity_id);
+    for player in &players {
+        spawn_player(
+            ecs,
+            state,
+            player.owner_client_id,
+            player.entity_id,
+            player.pos,
+        );
+        if let Some(entity) = find_entity(ecs, player.entity_id) {
+            let _ = ecs.insert_one(entity, Health { hp: player.hp });
+            if player.downed {
+                let _ = ecs.insert_one(entity, Downed);
+            }
+        }
+    }
+    for item in room.items.values() {
+        let pos = item
+            .held_by
+            .and_then(|holder| room.players.get(&holder))
+            .map(|holder| holder.pos)
+            .unwrap_or(item.pos);
+        spawn_item(ecs, item.entity_id, pos);
+    }
+    for enemy in room.enemies.values() {
+        spawn_enemy(ecs, enemy.entity_id, enemy.pos);
+        if let Some(entity) = find_entity(ecs, enemy.entity_id) {
+            let _ = ecs.insert_one(entity, Health { hp: enemy.hp });
+        }
+    }
+    for shot in &room.bots.shots {
+        spawn_projectile(ecs, shot.owner_client_id, shot.pos, shot.vel);
+    }
+
+    state.phase = room.phase;
+    state.roster = room.roster.values().cloned().collect();
+    for entry in &state.roster {
+        state.names.insert(entry.client_id, entry.name.clone());
+    }
+    state.host = room.host;
+    state.wave = room.waves.number;
+    state.scoreboard = room.scoreboard();
+
+    if let (false, Some(player)) = (viewer.free_camera, players.first()) {
+        state.camera.target = player.pos;
+    }
+    state.replay = Some(viewer);
+}
@@ -0,0 +1,536 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use glam::Vec2;
+use hecs::{Entity, World};
+
+use crate::common::{
+    client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+    inputs::{PlayingInputs, TickInputs},
+};
+
+use super::{
+    components::{Controls, OwnedByClient, Physics, Player, Projectile, Transform, Weapon},
+    desync::request_snapshot,
+    entity_archetypes::spawn_projectile,
+    game,
+    lobby::own_entry,
+    settings::{ROLLBACK_INPUT_DELAY_STEPS, ROLLBACK_INPUT_REDUNDANCY, ROLLBACK_MAX_STEPS},
+    state::State,
+    udp_networking::{CLIENT_ID, OUTBOUND_MESSAGE_QUEUE},
+};
+
+/// A match in rollback mode. Every player in it is simulated here from everyone's inputs,
+/// guessing the ones that have not arrived yet and going over the steps again once a guess
+/// turns out wrong. Enemies, items and health stay with the server.
+///
+/// Every peer has to come out of a step with the same players, so the systems a step runs
+/// only add, multiply, divide and take square roots, which come out the same everywhere,
+/// and visit entities in an order of their own rather than the world's.
+pub struct Session {
+    /// Next step to simulate.
+    pub tick: u32,
+    /// `None` while watching a match we joined too late to play in.
+    local_client: Option<u32>,
+    /// Keyed by client, then by step. Ours included.
+    inputs: BTreeMap<u32, BTreeMap<u32, TickInputs>>,
+    /// Clients that left, whose stray inputs are not waited for.
+    left: BTreeSet<u32>,
+    /// What every player was given at each step we may still roll back over, guesses and all.
+    used: BTreeMap<u32, BTreeMap<u32, TickInputs>>,
+    /// The world as it was before each of those steps.
+    saved: BTreeMap<u32, SavedWorld>,
+    /// Hits made in each step we may still play differently, reported once nobody's inputs
+    /// for it are guesses any more.
+    hits: BTreeMap<u32, Vec<ClientToServerMessageData>>,
+    /// Earliest step simulated on a guess that turned out wrong.
+    mispredicted: Option<u32>,
+    /// Watchers wait for the first inputs to learn which step the match is at.
+    aligned: bool,
+
+    pub rollbacks: u32,
+    pub resimulated_steps: u32,
+    pub stalls: u32,
+}
+
+/// Starts a session for the match that just began. A client not on the roster yet is
+/// catching up with a match already running and only watches it.
+pub fn start(state: &State) -> Session {
+    let client_id = CLIENT_ID.load(std::sync::atomic::Ordering::SeqCst);
+    let playing = own_entry(state).is_some();
+    let peers = state
+        .roster
+        .iter()
+        .filter(|entry| !entry.bot && entry.client_id != client_id)
+        .map(|entry| entry.client_id);
+    Session::new(playing.then_some(client_id), peers)
+}
+
+impl Session {
+    pub fn new(local_client: Option<u32>, peers: impl IntoIterator<Item = u32>) -> Self {
+        let mut inputs: BTreeMap<u32, BTreeMap<u32, TickInputs>> = peers
+            .into_iter()
+            .map(|client_id| (client_id, BTreeMap::new()))
+            .collect();
+        if let Some(client_id) = local_client {
+            // nothing was pressed before the match started
+            let idle = TickInputs::new(PlayingInputs::new(), Vec2::ZERO);
+            let delayed = (0..ROLLBACK_INPUT_DELAY_STEPS)
+                .map(|tick| (tick, idle))
+                .collect();
+            inputs.insert(client_id, delayed);
+        }
+        Self {
+            tick: 0,
+            local_client,
+            inputs,
+            left: BTreeSet::new(),
+            used: BTreeMap::new(),
+            saved: BTreeMap::new(),
+            hits: BTreeMap::new(),
+            mispredicted: None,
+            aligned: local_client.is_some(),
+
+            rollbacks: 0,
+            resimulated_steps: 0,
+            stalls: 0,
+        }
+    }
+
+    /// Whether this client's player is simulated from its inputs.
+    pub fn plays(&self, client_id: u32) -> bool {
+        self.inputs.contains_key(&client_id)
+    }
+
+    /// Stops waiting for someone who left.
+    pub fn remove_peer(&mut self, client_id: u32) {
+        if Some(client_id) != self.local_client {
+            self.inputs.remove(&client_id);
+            self.left.insert(client_id);
+        }
+    }
+
+    /// Takes in a peer's inputs from step `first` on, noting any that differ from what a
+    /// step was already simulated with.
+    pub fn receive(&mut self, client_id: u32, first: u32, inputs: &[TickInputs]) {
+        if Some(client_id) == self.local_client || self.left.contains(&client_id) {
+            return;
+        }
+        if !self.aligned && !inputs.is_empty() {
+            self.tick = first + inputs.len() as u32 - 1;
+            self.aligned = true;
+        }
+        let known = self.inputs.entry(client_id).or_default();
+        for (tick, input) in (first..).zip(inputs) {
+            known.insert(tick, *input);
+            let guessed = self.used.get(&tick).and_then(|used| used.get(&client_id));
+            if guessed.is_some_and(|guessed| guessed != input) {
+                self.mispredicted = Some(self.mispredicted.map_or(tick, |wrong| wrong.min(tick)));
+            }
+        }
+    }
+
+    /// First step some peer's inputs have not arrived for, or `None` with nobody to wait for.
+    fn heard_up_to(&self) -> Option<u32> {
+        self.inputs
+            .iter()
+            .filter(|(&client_id, _)| Some(client_id) != self.local_client)
+            .map(|(_, known)| known.keys().next_back().map_or(0, |tick| tick + 1))
+            .min()
+    }
+
+    /// Whether every peer's inputs are recent enough to keep guessing from.
+    fn can_advance(&self) -> bool {
+        self.heard_up_to()
+            .is_none_or(|heard_up_to| self.tick < heard_up_to + ROLLBACK_MAX_STEPS)
+    }
+
+    /// Hits from the steps everyone's inputs are in for, which no rollback can undo.
+    fn take_confirmed_hits(&mut self) -> Vec<ClientToServerMessageData> {
+        let confirmed = self
+            .heard_up_to()
+            .map_or(self.tick, |heard_up_to| heard_up_to.min(self.tick));
+        let unconfirmed = self.hits.split_off(&confirmed);
+        std::mem::replace(&mut self.hits, unconfirmed)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+
+    fn send_confirmed_hits(&mut self) {
+        for hit in self.take_confirmed_hits() {
+            if OUTBOUND_MESSAGE_QUEUE
+                .push(ClientToServerMessage::new(hit))
+                .is_err()
+            {
+                eprintln!("Outbound message queue full: dropping message");
+            }
+        }
+    }
+
+    /// Whatever we are pressing now is played `ROLLBACK_INPUT_DELAY_STEPS` from now.
+    fn press(&mut self, state: &State) {
+        let Some(client_id) = self.local_client else {
+            return;
+        };
+        let inputs = TickInputs::new(state.playing_inputs, state.mouse_pos);
+        self.inputs
+            .entry(client_id)
+            .or_default()
+            .insert(self.tick + ROLLBACK_INPUT_DELAY_STEPS, inputs);
+    }
+
+    fn send_inputs(&self) {
+        let Some(ours) = self
+            .local_client
+            .and_then(|client_id| self.inputs.get(&client_id))
+        else {
+            return;
+        };
+        let Some(&latest) = ours.keys().next_back() else {
+            return;
+        };
+        let from = (latest + 1).saturating_sub(ROLLBACK_INPUT_REDUNDANCY as u32);
+        let recent: Vec<(u32, TickInputs)> = ours
+            .range(from..)
+            .map(|(&tick, &inputs)| (tick, inputs))
+            .collect();
+        let tick = recent[0].0;
+        let inputs = recent.into_iter().map(|(_, inputs)| inputs).collect();
+        if OUTBOUND_MESSAGE_QUEUE
+            .push(ClientToServerMessage::new(
+                ClientToServerMessageData::Inputs { tick, inputs },
+            ))
+            .is_err()
+        {
+            eprintln!("Outbound message queue full: dropping message");
+        }
+    }
+
+    /// Simulates step `tick` with the inputs we have, the latest known standing in for any
+    /// that have not arrived.
+    fn simulate(&mut self, ecs: &mut World, state: &mut State) {
+        let tick = self.tick;
+        let saved = SavedWorld::save(ecs, self);
+        self.saved.insert(tick, saved);
+
+        let idle = TickInputs::new(PlayingInputs::new(), Vec2::ZERO);
+        let used: BTreeMap<u32, TickInputs> = self
+            .inputs
+            .iter()
+            .map(|(&client_id, known)| {
+                let inputs = known
+                    .range(..=tick)
+                    .next_back()
+                    .map_or(idle, |(_, inputs)| *inputs);
+                (client_id, inputs)
+            })
+            .collect();
+        for (_, (owner, controls)) in ecs
+            .query_mut::<(&OwnedByClient, &mut Controls)>()
+            .with::<&Player>()
+        {
+            if let Some(inputs) = used.get(&owner.client_id) {
+                controls.inputs = inputs.inputs;
+                controls.aim = inputs.aim;
+            }
+        }
+        self.used.insert(tick, used);
+
+        state.held_hits = Some(Vec::new());
+        game::simulate(ecs, state);
+        let hits = state.held_hits.take().unwrap_or_default();
+        if !hits.is_empty() {
+            self.hits.insert(tick, hits);
+        }
+        self.tick += 1;
+    }
+
+    /// Puts the world back to how it was before step `to` and plays it forward again. The
+    /// hits made since are made again, or not, on the way.
+    fn roll_back(&mut self, ecs: &mut World, state: &mut State, to: u32) {
+        // a peer fell further behind than we keep steps for, so the server has to set us
+        // straight instead
+        let Some(saved) = self.saved.get(&to) else {
+            request_snapshot(state);
+            return;
+        };
+        saved.restore(ecs, self);
+        self.hits.retain(|&tick, _| tick < to);
+
+        let end = self.tick;
+        self.tick = to;
+        state.resimulating = true;
+        while self.tick < end {
+            self.simulate(ecs, state);
+        }
+        state.resimulating = false;
+        self.rollbacks += 1;
+        self.resimulated_steps += end - to;
+    }
+
+    /// Nobody can be more than `ROLLBACK_MAX_STEPS` behind, so nothing older is needed,
+    /// except the latest inputs of each player to keep guessing from.
+    fn forget_old_steps(&mut self) {
+        let oldest = self.tick.saturating_sub(ROLLBACK_MAX_STEPS + 1);
+        self.saved = self.saved.split_off(&oldest);
+        self.used = self.used.split_off(&oldest);
+        for known in self.inputs.values_mut() {
+            let latest = known.keys().next_back().copied();
+            known.retain(|&tick, _| tick >= oldest || Some(tick) == latest);
+        }
+    }
+}
+
+/// Runs the next step of the session once any wrong guesses have been put right, or waits
+/// when a peer has fallen too far behind to keep guessing for.
+pub fn advance(ecs: &mut World, state: &mut State) {
+    let Some(mut session) = state.rollback.take() else {
+        return;
+    };
+    if let Some(tick) = session.mispredicted.take() {
+        session.roll_back(ecs, state, tick);
+    }
+    if session.aligned && session.can_advance() {
+        session.press(state);
+        session.simulate(ecs, state);
+        session.forget_old_steps();
+    } else {
+        session.stalls += 1;
+    }
+    session.send_inputs();
+    session.send_confirmed_hits();
+    state.rollback = Some(session);
+}
+
+/// Everything a step of the session changes: its players' bodies and weapons and the shots
+/// they fired.
+struct SavedWorld {
+    players: Vec<(Entity, Transform, Physics, Weapon)>,
+    projectiles: Vec<(u32, Transform, Physics)>,
+}
+
+impl SavedWorld {
+    fn save(ecs: &World, session: &Session) -> Self {
+        let players = ecs
+            .query::<(&OwnedByClient, &Transform, &Physics, &Weapon)>()
+            .with::<&Player>()
+            .iter()
+            .filter(|(_, (owner, ..))| session.plays(owner.client_id))
+            .map(|(entity, (_, transform, physics, weapon))| {
+                (entity, *transform, *physics, *weapon)
+            })
+            .collect();
+        let projectiles = ecs
+            .query::<(&Projectile, &Transform, &Physics)>()
+            .iter()
+            .filter(|(_, (projectile, ..))| session.plays(projectile.owner_client_id))
+            .map(|(_, (projectile, transform, physics))| {
+                (projectile.owner_client_id, *transform, *physics)
+            })
+            .collect();
+        Self {
+            players,
+            projectiles,
+        }
+    }
+
+    fn restore(&self, ecs: &mut World, session: &Session) {
+        for (entity, transform, physics, weapon) in &self.players {
+            if let Ok((now_transform, now_physics, now_weapon)) =
+                ecs.query_one_mut::<(&mut Transform, &mut Physics, &mut Weapon)>(*entity)
+            {
+                *now_transform = *transform;
+                *now_physics = *physics;
+                *now_weapon = *weapon;
+            }
+        }
+
+        let fired: Vec<Entity> = ecs
+            .query::<&Projectile>()
+            .iter()
+            .filter(|(_, projectile)| session.plays(projectile.owner_client_id))
+            .map(|(entity, _)| entity)
+            .collect();
+        for entity in fired {
+            let _ = ecs.despawn(entity);
+        }
+        for (owner_client_id, transform, physics) in &self.projectiles {
+            spawn_projectile(ecs, *owner_client_id, transform.pos, physics.vel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+    use hecs::World;
+
+    use crate::{
+        client::{
+            components::{OwnedByClient, Player, Transform},
+            entity_archetypes::spawn_player,
+            settings::ROLLBACK_MAX_STEPS,
+            state::State,
+        },
+        common::{
+            client_to_server::ClientToServerMessageData,
+            inputs::{PlayingInputs, TickInputs},
+        },
+    };
+
+    use super::{advance, Session};
+
+    const LOCAL: u32 = 1;
+    const PEER: u32 = 2;
+
+    fn idle() -> TickInputs {
+        TickInputs::new(PlayingInputs::new(), Vec2::ZERO)
+    }
+
+    fn walking_right() -> TickInputs {
+        let mut inputs = PlayingInputs::new();
+        inputs.right = true;
+        TickInputs::new(inputs, Vec2::ZERO)
+    }
+
+    fn match_of_two() -> (World, State) {
+        let mut ecs = World::new();
+        let mut state = State::new();
+        spawn_player(&mut ecs, &mut state, LOCAL, 101, Vec2::new(100.0, 100.0));
+        spawn_player(&mut ecs, &mut state, PEER, 102, Vec2::new(100.0, 140.0));
+        state.rollback = Some(Session::new(Some(LOCAL), [PEER]));
+        (ecs, state)
+    }
+
+    fn session(state: &mut State) -> &mut Session {
+        state.rollback.as_mut().unwrap()
+    }
+
+    fn position_of(ecs: &World, client_id: u32) -> Vec2 {
+        ecs.query::<(&OwnedByClient, &Transform)>()
+            .with::<&Player>()
+            .iter()
+            .find(|(_, (owner, _))| owner.client_id == client_id)
+            .map(|(_, (_, transform))| transform.pos)
+            .unwrap()
+    }
+
+    #[test]
+    fn inputs_that_differ_from_the_guess_are_mispredictions() {
+        let (mut ecs, mut state) = match_of_two();
+        for _ in 0..4 {
+            advance(&mut ecs, &mut state);
+        }
+        // what was guessed anyway
+        session(&mut state).receive(PEER, 0, &[idle(), idle()]);
+        assert_eq!(session(&mut state).mispredicted, None);
+        session(&mut state).receive(PEER, 2, &[walking_right(), walking_right()]);
+        assert_eq!(session(&mut state).mispredicted, Some(2));
+        // steps not simulated yet were never guessed
+        session(&mut state).receive(PEER, 6, &[idle()]);
+        assert_eq!(session(&mut state).mispredicted, Some(2));
+        // our own inputs come back from the server too
+        session(&mut state).receive(LOCAL, 0, &[walking_right()]);
+        assert_eq!(session(&mut state).mispredicted, Some(2));
+    }
+
+    #[test]
+    fn stalls_once_a_peer_is_too_far_behind() {
+        let (mut ecs, mut state) = match_of_two();
+        for _ in 0..ROLLBACK_MAX_STEPS + 3 {
+            advance(&mut ecs, &mut state);
+        }
+        assert_eq!(session(&mut state).tick, ROLLBACK_MAX_STEPS);
+        assert_eq!(session(&mut state).stalls, 3);
+        assert!(!session(&mut state).can_advance());
+
+        session(&mut state).receive(PEER, 0, &[idle(); 2]);
+        assert!(session(&mut state).can_advance());
+        advance(&mut ecs, &mut state);
+        assert_eq!(session(&mut state).tick, ROLLBACK_MAX_STEPS + 1);
+    }
+
+    #[test]
+    fn rolling_back_ends_where_knowing_the_inputs_would_have() {
+        let (mut ecs, mut state) = match_of_two();
+        let (mut known_ecs, mut known_state) = match_of_two();
+        session(&mut known_state).receive(PEER, 0, &[walking_right(); 6]);
+        for _ in 0..6 {
+            advance(&mut ecs, &mut state);
+            advance(&mut known_ecs, &mut known_state);
+        }
+        assert_ne!(position_of(&ecs, PEER), position_of(&known_ecs, PEER));
+
+        session(&mut state).receive(PEER, 0, &[walking_right(); 6]);
+        advance(&mut ecs, &mut state);
+        advance(&mut known_ecs, &mut known_state);
+        assert_eq!(position_of(&ecs, PEER), position_of(&known_ecs, PEER));
+        assert_eq!(position_of(&ecs, LOCAL), position_of(&known_ecs, LOCAL));
+        let session = session(&mut state);
+        assert_eq!(session.rollbacks, 1);
+        assert_eq!(session.resimulated_steps, 6);
+    }
+
+    #[test]
+    fn rolling_back_past_what_was_kept_asks_for_a_snapshot() {
+        let (mut ecs, mut state) = match_of_two();
+        for tick in 0..20 {
+            session(&mut state).receive(PEER, tick, &[idle()]);
+            advance(&mut ecs, &mut state);
+        }
+        assert_eq!(state.desync.last_snapshot_request, 0);
+
+        let mut session = state.rollback.take().unwrap();
+        session.roll_back(&mut ecs, &mut state, 2);
+        assert_ne!(state.desync.last_snapshot_request, 0);
+        assert_eq!(session.rollbacks, 0);
+        assert_eq!(session.tick, 20);
+    }
+
+    #[test]
+    fn forgets_steps_no_peer_can_still_change() {
+        let (mut ecs, mut state) = match_of_two();
+        for tick in 0..30 {
+            if tick < 20 {
+                session(&mut state).receive(PEER, tick, &[idle()]);
+            }
+            advance(&mut ecs, &mut state);
+        }
+        let session = session(&mut state);
+        let oldest = session.tick - ROLLBACK_MAX_STEPS - 1;
+        assert_eq!(session.saved.keys().next(), Some(&oldest));
+        assert_eq!(session.used.keys().next(), Some(&oldest));
+        // the last inputs heard from the peer are still what it is guessed to press
+        let peer = &session.inputs[&PEER];
+        assert_eq!(peer.keys().copied().collect::<Vec<_>>(), vec![19]);
+        assert!(session.inputs[&LOCAL].keys().all(|&tick| tick >= oldest));
+    }
+
+    #[test]
+    fn hits_wait_until_their_step_is_confirmed() {
+        let mut session = Session::new(Some(LOCAL), [PEER]);
+        session.tick = 6;
+        for tick in [1, 3, 5] {
+            let hit = ClientToServerMessageData::HitEnemy {
+                entity_id: tick,
+                damage: 10,
+                pos: Vec2::ZERO,
+                view_step: tick,
+            };
+            session.hits.insert(tick, vec![hit]);
+        }
+        assert!(session.take_confirmed_hits().is_empty());
+
+        session.receive(PEER, 0, &[idle(); 4]);
+        let sent: Vec<u32> = session
+            .take_confirmed_hits()
+            .into_iter()
+            .map(|hit| match hit {
+                ClientToServerMessageData::HitEnemy { entity_id, .. } => entity_id,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(sent, vec![1, 3]);
+        assert_eq!(session.hits.keys().copied().collect::<Vec<_>>(), vec![5]);
+    }
+}
@@ -0,0 +1,111 @@
+use hecs::World;
+use raylib::prelude::*;
+
+use crate::common::{
+    client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+    game_objects::{MatchPhase, RoomInfo},
+};
+
+use super::{graphics::DIMS, state::State, udp_networking::OUTBOUND_MESSAGE_QUEUE};
+
+/// Seconds between room list refreshes while browsing.
+pub const ROOM_LIST_REFRESH: f64 = 2.0;
+pub const MAX_CODE_LENGTH: usize = 8;
+
+/// The screen shown before joining a room.
+pub struct RoomBrowser {
+    pub rooms: Vec<RoomInfo>,
+    /// 0 is "create a new room", the rest index into `rooms` shifted by one.
+    pub selected: usize,
+    /// A typed room code wins over the selection.
+    pub code: String,
+    pub last_refresh: f64,
+}
+
+impl RoomBrowser {
+    pub fn new() -> Self {
+        Self {
+            rooms: Vec::new(),
+            selected: 0,
+            code: String::new(),
+            last_refresh: 0.0,
+        }
+    }
+}
+
+impl Default for RoomBrowser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Up/Down pick a room, typing enters a code, Enter joins or creates.
+pub fn process_room_browser_input(rl: &mut RaylibHandle, state: &mut State) {
+    let browser = &mut state.room_browser;
+
+    let now = rl.get_time();
+    if now - browser.last_refresh > ROOM_LIST_REFRESH {
+        browser.last_refresh = now;
+        send(ClientToServerMessageData::ListRooms);
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_UP) {
+        browser.selected = browser.selected.saturating_sub(1);
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_DOWN) {
+        browser.selected = (browser.selected + 1).min(browser.rooms.len());
+    }
+    if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE)
+        || rl.is_key_pressed_repeat(KeyboardKey::KEY_BACKSPACE)
+    {
+        browser.code.pop();
+    }
+    while let Some(c) = rl.get_char_pressed() {
+        if c.is_ascii_alphabetic() && browser.code.len() < MAX_CODE_LENGTH {
+            browser.code.push(c.to_ascii_uppercase());
+        }
+    }
+
+    if rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+        if !browser.code.is_empty() {
+            let code = std::mem::take(&mut browser.code);
+            send(ClientToServerMessageData::JoinRoom { code });
+        } else if browser.selected == 0 {
+            send(ClientToServerMessageData::CreateRoom);
+        } else if let Some(room) = browser.rooms.get(browser.selected - 1) {
+            send(ClientToServerMessageData::JoinRoom {
+                code: room.code.clone(),
+            });
+        }
+    }
+}
+
+/// Forgets everything about the room we were in, before entering another or after leaving.
+pub fn reset_room(ecs: &mut World, state: &mut State) {
+    ecs.clear();
+    state.level = None;
+    state.play_field = DIMS.as_vec2();
+    state.phase = MatchPhase::Lobby;
+    state.roster.clear();
+    state.host = None;
+    state.wave = 0;
+    state.scoreboard.clear();
+    state.wave_results = None;
+    state.rollback = None;
+    // the next room counts its steps from wherever it is at
+    state.desync.last_step = 0;
+    state.desync.consecutive = 0;
+}
+
+pub fn leave_room() {
+    send(ClientToServerMessageData::LeaveRoom);
+}
+
+fn send(data: ClientToServerMessageData) {
+    if OUTBOUND_MESSAGE_QUEUE
+        .push(ClientToServerMessage::new(data))
+        .is_err()
+    {
+        eprintln!("Outbound message queue full: dropping message");
+    }
+}
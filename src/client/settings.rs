@@ -1 +1,33 @@
-
+/// Messages waiting to be handled, and waiting to go out. Past this, unreliable ones are
+/// dropped, oldest first.
+pub const INCOMING_QUEUE_CAPACITY: usize = 64;
+pub const OUTBOUND_QUEUE_CAPACITY: usize = 64;
+/// Ask the server to compress packets both ways.
+pub const REQUEST_COMPRESSION: bool = true;
+/// Exchange keys with the server and encrypt everything after that. The server's key comes
+/// in a reply nothing vouches for, so this keeps out eavesdroppers and spoofed packets, but
+/// not someone in the middle who answers the key request with a key of their own.
+pub const SECURE_CONNECTION: bool = true;
+/// The server's key is asked for again after this long without an answer.
+pub const KEY_REQUEST_INTERVAL_MS: i64 = 250;
+/// The server counts as gone after this long without a word from it.
+pub const HOST_TIMEOUT_MS: i64 = 4000;
+/// Checksums that must disagree in a row before we ask the server for a snapshot.
+pub const DESYNC_MISMATCHES_BEFORE_SNAPSHOT: u32 = 2;
+/// A snapshot that never arrived is asked for again after this long.
+pub const SNAPSHOT_RETRY_MS: i64 = 1000;
+/// Replay seeking jumps this many steps.
+pub const REPLAY_SEEK_STEPS: u32 = 5 * 60;
+pub const REPLAY_MIN_SPEED: f32 = 0.25;
+pub const REPLAY_MAX_SPEED: f32 = 8.0;
+/// Free camera pan per frame, in pixels.
+pub const REPLAY_CAMERA_SPEED: f32 = 3.0;
+/// Rollback sessions apply our inputs this many steps after they are pressed, which gives
+/// them time to reach everyone else before they are needed.
+pub const ROLLBACK_INPUT_DELAY_STEPS: u32 = 2;
+/// How far we may run ahead of the slowest peer's inputs before waiting for them.
+pub const ROLLBACK_MAX_STEPS: u32 = 8;
+/// Steps of our inputs repeated in every message, to cover for lost ones.
+pub const ROLLBACK_INPUT_REDUNDANCY: usize = 8;
+/// Our player's position goes out every this many steps.
+pub const POSITION_TRANSMIT_FREQUENCY: u32 = 4;
@@ -1,25 +1,159 @@
-use super::event_processing::PlayingInputs;
+use std::{collections::HashMap, net::SocketAddr};
+
+use glam::Vec2;
+
+use crate::common::{
+    client_to_server::ClientToServerMessageData,
+    game_objects::{MatchPhase, PlayerStats, RosterEntry, SyncMode},
+    inputs::PlayingInputs,
+    level::Level,
+    migration::{RoomSnapshot, SnapshotParts},
+};
+
+use super::{
+    camera::Camera, chat::Chat, desync::DesyncCounts, discovery::ServerBrowser, graphics::DIMS,
+    replay::ReplayViewer, rollback::Session, rooms::RoomBrowser,
+};
+
+pub struct WaveResults {
+    pub wave: u32,
+    pub cleared: bool,
+    pub stats: Vec<PlayerStats>,
+}
 
 pub struct State {
     pub running: bool,
-    pub time_since_last_update: f32,
     // pub client_id: Option<u32>,
     pub players: Vec<u32>,
+    /// Server we are connected to, `None` while browsing servers.
+    pub server: Option<SocketAddr>,
+    pub server_browser: ServerBrowser,
+    /// Picked in the server browser, connected to by the main loop.
+    pub connect_to: Option<SocketAddr>,
+    /// Our end of the connection, where the server sees us.
+    pub local_addr: Option<SocketAddr>,
+    /// The server runs in this process, so there is no losing it.
+    pub hosting: bool,
+    pub last_heard_from_server: i64,
+    /// Server step the world we show is from: the step of the last checksum, counted on
+    /// by our own steps since. Hits carry it so the server can rewind to what we saw.
+    pub server_step: u32,
+    /// Who takes over if the server goes away, and where to find them.
+    pub successor: Option<(u32, SocketAddr)>,
+    /// Only kept while we are the successor.
+    pub migration_snapshot: Option<RoomSnapshot>,
+    /// Parts of the next migration snapshot that have come in so far.
+    pub migration_parts: SnapshotParts,
+    /// What the server gave us to get our client id back from whoever takes over.
+    pub rejoin_token: Option<u128>,
+    /// Code of the room we are in, `None` while browsing rooms.
+    pub room: Option<String>,
+    pub room_browser: RoomBrowser,
+    pub phase: MatchPhase,
+    /// Everyone connected right now, in join order.
+    pub roster: Vec<RosterEntry>,
+    /// Client allowed to start the match.
+    pub host: Option<u32>,
+    /// Every name seen this session, so people who left still show up on the scoreboard.
+    pub names: HashMap<u32, String>,
+
+    /// None until the server tells us which level to load.
+    pub level: Option<Level>,
+    /// Size of the area entities are kept inside, the level bounds once one is loaded.
+    pub play_field: Vec2,
+    pub camera: Camera,
 
     pub playing_inputs: PlayingInputs,
+    /// Inputs as of the last sim step, for detecting presses.
+    pub previous_playing_inputs: PlayingInputs,
+    /// Mouse position in render texture coordinates, for the HUD.
+    pub mouse_screen_pos: Vec2,
+    /// Mouse position in world coordinates, for aiming.
+    pub mouse_pos: Vec2,
+
+    pub sync_mode: SyncMode,
+    /// Set while a rollback match is being played.
+    pub rollback: Option<Session>,
+    /// Set while steps are being run again after a rollback, so whatever they send to the
+    /// server is not sent twice.
+    pub resimulating: bool,
+    /// Set while the session runs a step, which collects the hits made in it to report
+    /// once nobody's inputs for that step can change any more.
+    pub held_hits: Option<Vec<ClientToServerMessageData>>,
+
+    pub chat: Chat,
+
+    pub wave: u32,
+    pub scoreboard: Vec<PlayerStats>,
+    pub show_scoreboard: bool,
+    /// Set between waves, while the results screen is up.
+    pub wave_results: Option<WaveResults>,
+
+    pub desync: DesyncCounts,
+    pub show_debug: bool,
+
+    /// Set when watching a replay rather than playing.
+    pub replay: Option<ReplayViewer>,
 }
 
 impl State {
     pub fn new() -> Self {
         Self {
             running: true,
-            time_since_last_update: 0.0,
 
             players: Vec::new(),
+            server: None,
+            server_browser: ServerBrowser::new(),
+            connect_to: None,
+            local_addr: None,
+            hosting: false,
+            last_heard_from_server: 0,
+            server_step: 0,
+            successor: None,
+            migration_snapshot: None,
+            migration_parts: SnapshotParts::new(),
+            rejoin_token: None,
+            room: None,
+            room_browser: RoomBrowser::new(),
+            phase: MatchPhase::Lobby,
+            roster: Vec::new(),
+            host: None,
+            names: HashMap::new(),
+
+            level: None,
+            play_field: DIMS.as_vec2(),
+            camera: Camera::new(),
 
             playing_inputs: PlayingInputs::new(),
+            previous_playing_inputs: PlayingInputs::new(),
+            mouse_screen_pos: Vec2::ZERO,
+            mouse_pos: Vec2::ZERO,
+
+            sync_mode: SyncMode::Snapshot,
+            rollback: None,
+            resimulating: false,
+            held_hits: None,
+
+            chat: Chat::new(),
+
+            wave: 0,
+            scoreboard: Vec::new(),
+            show_scoreboard: false,
+            wave_results: None,
+
+            desync: DesyncCounts::new(),
+            show_debug: false,
+
+            replay: None,
         }
     }
+
+    pub fn name_of(&self, client_id: u32) -> String {
+        self.names
+            .get(&client_id)
+            .cloned()
+            .unwrap_or_else(|| format!("client {}", client_id))
+    }
 }
 
 impl Default for State {
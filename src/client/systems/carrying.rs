@@ -0,0 +1,147 @@
+use glam::Vec2;
+use hecs::{Entity, World};
+
+use crate::{
+    client::{
+        components::{
+            Attachable, AttachedTo, GrabZone, InputControlled, NetworkId, OwnedBy, Physics, Shape,
+            Transform,
+        },
+        state::State,
+        udp_networking::OUTBOUND_MESSAGE_QUEUE,
+    },
+    common::{
+        client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+        game_objects::THROW_SPEED,
+    },
+};
+
+/// Confirm asks the server for the nearest item in reach, or drops the held one.
+/// Throw releases the held item toward the mouse.
+pub fn grab_and_release(ecs: &mut World, state: &mut State) {
+    let confirm_pressed = state.playing_inputs.confirm && !state.previous_playing_inputs.confirm;
+    let throw_pressed = state.playing_inputs.throw && !state.previous_playing_inputs.throw;
+    if !confirm_pressed && !throw_pressed {
+        return;
+    }
+
+    let Some((player, player_pos, grab_zone)) = ecs
+        .query::<(&Transform, &GrabZone)>()
+        .with::<&InputControlled>()
+        .iter()
+        .next()
+        .map(|(entity, (transform, grab_zone))| (entity, transform.pos, *grab_zone))
+    else {
+        return;
+    };
+
+    let held = ecs
+        .query::<&AttachedTo>()
+        .iter()
+        .find(|(_, attached)| attached.entity == player)
+        .map(|(entity, _)| entity);
+
+    if let Some(item) = held {
+        let vel = if throw_pressed {
+            (state.mouse_pos - player_pos).normalize_or_zero() * THROW_SPEED
+        } else {
+            Vec2::ZERO
+        };
+        release(ecs, item, vel);
+    } else if confirm_pressed {
+        let nearest = ecs
+            .query::<(&Transform, &NetworkId)>()
+            .with::<&Attachable>()
+            .without::<&AttachedTo>()
+            .iter()
+            .map(|(_, (transform, id))| (id.id, transform.pos.distance(player_pos)))
+            .filter(|(_, dist)| *dist <= grab_zone.radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+
+        // the item is only attached once the server confirms nobody else got it first
+        if let Some((entity_id, _)) = nearest {
+            if OUTBOUND_MESSAGE_QUEUE
+                .push(ClientToServerMessage::new(
+                    ClientToServerMessageData::RequestGrab { entity_id },
+                ))
+                .is_err()
+            {
+                eprintln!("Outbound message queue full: dropping message");
+            }
+        }
+    }
+}
+
+/// Keeps carried items pinned to their holder.
+pub fn carry_attached(ecs: &mut World, _state: &mut State) {
+    let mut pinned: Vec<(Entity, Vec2)> = Vec::new();
+    let mut orphaned: Vec<Entity> = Vec::new();
+    for (entity, attached) in ecs.query::<&AttachedTo>().iter() {
+        match ecs.get::<&Transform>(attached.entity) {
+            Ok(holder) => pinned.push((entity, holder.pos + attached.offset)),
+            Err(_) => orphaned.push(entity),
+        }
+    }
+
+    for (entity, pos) in pinned {
+        if let Ok(mut transform) = ecs.get::<&mut Transform>(entity) {
+            transform.pos = pos;
+        }
+        if let Ok(mut physics) = ecs.get::<&mut Physics>(entity) {
+            physics.vel = Vec2::ZERO;
+        }
+    }
+    for entity in orphaned {
+        let _ = ecs.remove::<(AttachedTo, OwnedBy)>(entity);
+    }
+}
+
+/// Pins `item` to the edge of `holder`, on the side it was picked up from.
+pub fn attach(ecs: &mut World, item: Entity, holder: Entity) {
+    let (Ok(item_transform), Ok(holder_transform)) = (
+        ecs.get::<&Transform>(item).map(|t| *t),
+        ecs.get::<&Transform>(holder).map(|t| *t),
+    ) else {
+        return;
+    };
+    let item_extent = ecs.get::<&Shape>(item).map(|s| s.dims.x).unwrap_or(0.0);
+    let holder_extent = ecs.get::<&Shape>(holder).map(|s| s.dims.x).unwrap_or(0.0);
+
+    let dir = (item_transform.pos - holder_transform.pos).normalize_or(Vec2::X);
+    let offset = dir * (holder_extent + item_extent);
+    let _ = ecs.insert(
+        item,
+        (
+            AttachedTo {
+                entity: holder,
+                offset,
+            },
+            OwnedBy { owner: holder },
+        ),
+    );
+}
+
+/// Lets go of `item` locally and tells the server where it ended up.
+pub fn release(ecs: &mut World, item: Entity, vel: Vec2) {
+    let _ = ecs.remove::<(AttachedTo, OwnedBy)>(item);
+    if let Ok(mut physics) = ecs.get::<&mut Physics>(item) {
+        physics.vel = vel;
+    }
+
+    let pos = ecs.get::<&Transform>(item).map(|t| t.pos);
+    let entity_id = ecs.get::<&NetworkId>(item).map(|id| id.id);
+    if let (Ok(pos), Ok(entity_id)) = (pos, entity_id) {
+        if OUTBOUND_MESSAGE_QUEUE
+            .push(ClientToServerMessage::new(
+                ClientToServerMessageData::ReleaseItem {
+                    entity_id,
+                    pos,
+                    vel,
+                },
+            ))
+            .is_err()
+        {
+            eprintln!("Outbound message queue full: dropping message");
+        }
+    }
+}
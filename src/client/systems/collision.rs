@@ -0,0 +1,72 @@
+use hecs::{Entity, World};
+
+use crate::{
+    client::{
+        components::{Physics, Projectile, Shape, Transform, Wall},
+        state::State,
+    },
+    common::{
+        collision::{bounds, penetration, slide},
+        spatial_hash::SpatialHash,
+    },
+};
+
+pub const WALL_GRID_CELL_SIZE: f32 = 32.0;
+
+/// Pushes every moving shape out of any wall it overlaps and lets it slide along the wall.
+/// Projectiles just stop there. Walls are pushed out of in order of position, so the result
+/// does not depend on how the world stores them.
+pub fn collide_with_walls(ecs: &mut World, _state: &mut State) {
+    let mut walls: Vec<(Transform, Shape)> = ecs
+        .query::<(&Transform, &Shape)>()
+        .with::<&Wall>()
+        .iter()
+        .map(|(_, (transform, shape))| (*transform, *shape))
+        .collect();
+    if walls.is_empty() {
+        return;
+    }
+    walls.sort_unstable_by(|(a, a_shape), (b, b_shape)| {
+        a.pos
+            .x
+            .total_cmp(&b.pos.x)
+            .then(a.pos.y.total_cmp(&b.pos.y))
+            .then(a_shape.dims.x.total_cmp(&b_shape.dims.x))
+            .then(a_shape.dims.y.total_cmp(&b_shape.dims.y))
+    });
+    let mut wall_grid = SpatialHash::new(WALL_GRID_CELL_SIZE);
+    for (index, (transform, shape)) in walls.iter().enumerate() {
+        let (min, max) = bounds(transform.pos, shape.kind, shape.dims);
+        wall_grid.insert(index, min, max);
+    }
+
+    let mut spent: Vec<Entity> = Vec::new();
+    for (entity, (transform, physics, shape, projectile)) in ecs
+        .query::<(&mut Transform, &mut Physics, &Shape, Option<&Projectile>)>()
+        .without::<&Wall>()
+        .iter()
+    {
+        let (min, max) = bounds(transform.pos, shape.kind, shape.dims);
+        for wall_index in wall_grid.query(min, max) {
+            let (wall_transform, wall_shape) = &walls[wall_index];
+            if let Some(push) = penetration(
+                transform.pos,
+                shape.kind,
+                shape.dims,
+                wall_transform.pos,
+                wall_shape.kind,
+                wall_shape.dims,
+            ) {
+                if projectile.is_some() {
+                    spent.push(entity);
+                    break;
+                }
+                transform.pos += push;
+                physics.vel = slide(physics.vel, push);
+            }
+        }
+    }
+    for entity in spent {
+        let _ = ecs.despawn(entity);
+    }
+}
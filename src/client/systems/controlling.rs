@@ -1,28 +1,41 @@
 use hecs::World;
 
 use crate::client::{
-    components::{InputControlled, Physics},
+    components::{Controls, Downed, InputControlled, Physics},
     state::State,
 };
 
+/// Hands the keyboard and mouse to the player we control.
+pub fn apply_local_inputs(ecs: &mut World, state: &mut State) {
+    for (_, controls) in ecs.query_mut::<&mut Controls>().with::<&InputControlled>() {
+        controls.inputs = state.playing_inputs;
+        controls.aim = state.mouse_pos;
+    }
+}
+
 pub const PLAYER_SPEED: f32 = 2.0;
-pub fn control_player(ecs: &mut World, state: &mut State) {
-    for (_, physics) in ecs
-        .query::<&mut Physics>()
-        .with::<&InputControlled>()
+pub fn control_player(ecs: &mut World, _state: &mut State) {
+    for (_, (physics, controls, downed)) in ecs
+        .query::<(&mut Physics, &Controls, Option<&Downed>)>()
         .iter()
     {
-        if state.playing_inputs.up {
+        if downed.is_some() {
+            physics.vel = glam::Vec2::ZERO;
+            continue;
+        }
+
+        let inputs = &controls.inputs;
+        if inputs.up {
             physics.vel.y = -PLAYER_SPEED;
-        } else if state.playing_inputs.down {
+        } else if inputs.down {
             physics.vel.y = PLAYER_SPEED;
         } else {
             physics.vel.y = 0.0;
         }
 
-        if state.playing_inputs.left {
+        if inputs.left {
             physics.vel.x = -PLAYER_SPEED;
-        } else if state.playing_inputs.right {
+        } else if inputs.right {
             physics.vel.x = PLAYER_SPEED;
         } else {
             physics.vel.x = 0.0;
@@ -1,2 +1,8 @@
+pub mod carrying;
+pub mod collision;
 pub mod controlling;
 pub mod physics;
+pub mod play_field;
+pub mod projectiles;
+pub mod reviving;
+pub mod shooting;
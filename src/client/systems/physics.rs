@@ -1,7 +1,7 @@
 use hecs::World;
 
 use crate::client::{
-    components::{Physics, Transform},
+    components::{Drag, Physics, Transform},
     state::State,
 };
 
@@ -9,4 +9,8 @@ pub fn step_physics(ecs: &mut World, _state: &mut State) {
     for (_, (transform, physics)) in ecs.query::<(&mut Transform, &mut Physics)>().iter() {
         transform.pos += physics.vel;
     }
+
+    for (_, (physics, drag)) in ecs.query::<(&mut Physics, &Drag)>().iter() {
+        physics.vel *= drag.factor;
+    }
 }
@@ -0,0 +1,48 @@
+use glam::Vec2;
+use hecs::{Entity, World};
+
+use crate::{
+    client::{
+        components::{CaptureInPlayField, FreeToLeavePlayField, Physics, Shape, Transform},
+        state::State,
+    },
+    common::collision::{bounds, slide},
+};
+
+pub fn keep_in_play_field(ecs: &mut World, state: &mut State) {
+    let play_field = state.play_field;
+
+    for (_, (transform, shape, physics)) in ecs
+        .query::<(&mut Transform, &Shape, Option<&mut Physics>)>()
+        .with::<&CaptureInPlayField>()
+        .iter()
+    {
+        let (min, max) = bounds(transform.pos, shape.kind, shape.dims);
+        let half_extents = (max - min) / 2.0;
+        let clamped = transform
+            .pos
+            .clamp(half_extents, (play_field - half_extents).max(half_extents));
+        let push = clamped - transform.pos;
+        if push != Vec2::ZERO {
+            transform.pos = clamped;
+            if let Some(physics) = physics {
+                physics.vel = slide(physics.vel, push);
+            }
+        }
+    }
+
+    let mut escaped: Vec<Entity> = Vec::new();
+    for (entity, (transform, shape)) in ecs
+        .query::<(&Transform, &Shape)>()
+        .with::<&FreeToLeavePlayField>()
+        .iter()
+    {
+        let (min, max) = bounds(transform.pos, shape.kind, shape.dims);
+        if max.x < 0.0 || max.y < 0.0 || min.x > play_field.x || min.y > play_field.y {
+            escaped.push(entity);
+        }
+    }
+    for entity in escaped {
+        let _ = ecs.despawn(entity);
+    }
+}
@@ -0,0 +1,87 @@
+use hecs::{Entity, World};
+
+use crate::{
+    client::{
+        components::{Enemy, NetworkId, Projectile, Shape, Transform},
+        state::State,
+        udp_networking::{CLIENT_ID, OUTBOUND_MESSAGE_QUEUE},
+    },
+    common::{
+        client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+        collision::{bounds, penetration},
+        spatial_hash::SpatialHash,
+    },
+};
+
+pub const ENEMY_GRID_CELL_SIZE: f32 = 16.0;
+
+/// Projectiles stop at the first enemy they touch. Only the shooter's own client reports the hit,
+/// so each shot is counted once no matter how many clients saw it. In a rollback session the
+/// session holds on to it until the step can no longer be played differently.
+pub fn hit_enemies(ecs: &mut World, state: &mut State) {
+    // by id rather than however the world happens to store them, so a shot between two
+    // enemies hits the same one on every client and every time it is simulated
+    let mut enemies: Vec<(u32, Transform, Shape)> = ecs
+        .query::<(&Transform, &Shape, &NetworkId)>()
+        .with::<&Enemy>()
+        .iter()
+        .map(|(_, (transform, shape, id))| (id.id, *transform, *shape))
+        .collect();
+    if enemies.is_empty() {
+        return;
+    }
+    enemies.sort_unstable_by_key(|(id, ..)| *id);
+    let mut enemy_grid = SpatialHash::new(ENEMY_GRID_CELL_SIZE);
+    for (index, (_, transform, shape)) in enemies.iter().enumerate() {
+        let (min, max) = bounds(transform.pos, shape.kind, shape.dims);
+        enemy_grid.insert(index, min, max);
+    }
+
+    let client_id = CLIENT_ID.load(std::sync::atomic::Ordering::SeqCst);
+    let mut spent: Vec<Entity> = Vec::new();
+    for (entity, (transform, shape, projectile)) in
+        ecs.query::<(&Transform, &Shape, &Projectile)>().iter()
+    {
+        let (min, max) = bounds(transform.pos, shape.kind, shape.dims);
+        let hit = enemy_grid.query(min, max).into_iter().find(|&index| {
+            let (_, enemy_transform, enemy_shape) = &enemies[index];
+            penetration(
+                transform.pos,
+                shape.kind,
+                shape.dims,
+                enemy_transform.pos,
+                enemy_shape.kind,
+                enemy_shape.dims,
+            )
+            .is_some()
+        });
+        let Some(index) = hit else {
+            continue;
+        };
+
+        spent.push(entity);
+        if projectile.owner_client_id != client_id {
+            continue;
+        }
+        let hit = ClientToServerMessageData::HitEnemy {
+            entity_id: enemies[index].0,
+            damage: projectile.damage,
+            pos: transform.pos,
+            view_step: state.server_step,
+        };
+        match &mut state.held_hits {
+            Some(held) => held.push(hit),
+            None => {
+                if OUTBOUND_MESSAGE_QUEUE
+                    .push(ClientToServerMessage::new(hit))
+                    .is_err()
+                {
+                    eprintln!("Outbound message queue full: dropping message");
+                }
+            }
+        }
+    }
+    for entity in spent {
+        let _ = ecs.despawn(entity);
+    }
+}
@@ -0,0 +1,47 @@
+use hecs::World;
+
+use crate::{
+    client::{
+        components::{Downed, GrabZone, InputControlled, NetworkId, Player, Transform},
+        state::State,
+        udp_networking::OUTBOUND_MESSAGE_QUEUE,
+    },
+    common::client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+};
+
+/// Asks the server to pick up the nearest downed teammate within reach.
+pub fn revive_teammates(ecs: &mut World, state: &mut State) {
+    if !state.playing_inputs.revive || state.previous_playing_inputs.revive {
+        return;
+    }
+
+    let Some((player_pos, grab_zone)) = ecs
+        .query::<(&Transform, &GrabZone)>()
+        .with::<&InputControlled>()
+        .without::<&Downed>()
+        .iter()
+        .next()
+        .map(|(_, (transform, grab_zone))| (transform.pos, *grab_zone))
+    else {
+        return;
+    };
+
+    let nearest = ecs
+        .query::<(&Transform, &NetworkId)>()
+        .with::<(&Player, &Downed)>()
+        .iter()
+        .map(|(_, (transform, id))| (id.id, transform.pos.distance(player_pos)))
+        .filter(|(_, dist)| *dist <= grab_zone.radius)
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+
+    if let Some((entity_id, _)) = nearest {
+        if OUTBOUND_MESSAGE_QUEUE
+            .push(ClientToServerMessage::new(
+                ClientToServerMessageData::RevivePlayer { entity_id },
+            ))
+            .is_err()
+        {
+            eprintln!("Outbound message queue full: dropping message");
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use hecs::World;
+
+use crate::client::{
+    components::{Controls, Downed, InputControlled, OwnedByClient, Transform, Weapon},
+    entity_archetypes::spawn_projectile,
+    state::State,
+    udp_networking::OUTBOUND_MESSAGE_QUEUE,
+};
+use crate::common::{
+    client_to_server::{ClientToServerMessage, ClientToServerMessageData},
+    game_objects::{FIRE_COOLDOWN, PROJECTILE_SPEED},
+};
+
+/// Shoots toward wherever each player aims. Our own shots go to the server, which checks
+/// our hits against them and passes them on to everyone else unless they fire them from
+/// our inputs anyway. Only the first time round, so a rollback does not fire them again.
+pub fn fire_weapons(ecs: &mut World, state: &mut State) {
+    let mut shots = Vec::new();
+    for (entity, (transform, controls, weapon, owner)) in ecs
+        .query_mut::<(&Transform, &Controls, &mut Weapon, &OwnedByClient)>()
+        .without::<&Downed>()
+    {
+        weapon.cooldown = weapon.cooldown.saturating_sub(1);
+        if !controls.inputs.shoot || weapon.cooldown > 0 {
+            continue;
+        }
+        let dir = (controls.aim - transform.pos).normalize_or_zero();
+        if dir == glam::Vec2::ZERO {
+            continue;
+        }
+        weapon.cooldown = FIRE_COOLDOWN;
+        shots.push((
+            entity,
+            owner.client_id,
+            transform.pos,
+            dir * PROJECTILE_SPEED,
+        ));
+    }
+
+    for (shooter, owner_client_id, pos, vel) in shots {
+        spawn_projectile(ecs, owner_client_id, pos, vel);
+
+        let ours = ecs.satisfies::<&InputControlled>(shooter).unwrap_or(false);
+        if ours
+            && !state.resimulating
+            && OUTBOUND_MESSAGE_QUEUE
+                .push(ClientToServerMessage::new(
+                    ClientToServerMessageData::Fire { pos, vel },
+                ))
+                .is_err()
+        {
+            eprintln!("Outbound message queue full: dropping message");
+        }
+    }
+}
@@ -1,50 +1,222 @@
-use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 
-use crossbeam::queue::ArrayQueue;
 use tokio::io::{self};
 use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 
 use lazy_static::lazy_static;
+use x25519_dalek::EphemeralSecret;
 
-use crate::common::client_to_server::ClientToServerMessage;
-use crate::common::network_settings::SERVER_HOST_ADDR;
-use crate::common::server_to_client::ServerToClientMessage;
+use crate::common::client_to_server::{
+    ClientToServerMessage, ClientToServerMessageBundle, ClientToServerMessageData,
+};
+use crate::common::compression;
+use crate::common::mailbox::{Mailbox, Overflow};
+use crate::common::network_settings::RECEIPT_INTERVAL_MS;
+use crate::common::reliability::{PacketCounter, ReliableReceiver, ReliableSender};
+use crate::common::secure::{self, Datagram, Session};
+use crate::common::server_to_client::{ServerToClientMessage, ServerToClientPacket};
+use crate::common::util::get_utc_now;
+use crate::server;
+use crate::server::client_bookkeeping::ClientMessageQueue;
+
+use super::settings::{
+    INCOMING_QUEUE_CAPACITY, KEY_REQUEST_INTERVAL_MS, OUTBOUND_QUEUE_CAPACITY, REQUEST_COMPRESSION,
+    SECURE_CONNECTION,
+};
 
 lazy_static! {
-    pub static ref INCOMING_MESSAGE_QUEUE: Arc<ArrayQueue<ServerToClientMessage>> =
-        Arc::new(ArrayQueue::new(64));
-    pub static ref OUTBOUND_MESSAGE_QUEUE: Arc<ArrayQueue<ClientToServerMessage>> =
-        Arc::new(ArrayQueue::new(64));
+    pub static ref INCOMING_MESSAGE_QUEUE: Arc<Mailbox<ServerToClientMessage>> =
+        Arc::new(Mailbox::new(INCOMING_QUEUE_CAPACITY));
+    pub static ref OUTBOUND_MESSAGE_QUEUE: Arc<Mailbox<ClientToServerMessage>> =
+        Arc::new(Mailbox::new(OUTBOUND_QUEUE_CAPACITY));
     pub static ref SERVER_DISCONNECTED: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     pub static ref CLIENT_ID: Arc<AtomicU32> = Arc::new(AtomicU32::new(0));
+    /// Set once the server agrees to compression in its `Hello`.
+    pub static ref SEND_COMPRESSED: AtomicBool = AtomicBool::new(false);
+    /// Keys of a secure connection, once the server's key is in.
+    static ref SESSION: Mutex<Option<Session>> = Mutex::new(None);
+    pub static ref RELIABLE_SENDER: Mutex<ReliableSender<ClientToServerMessage>> =
+        Mutex::new(ReliableSender::new());
+    /// rx/tx tasks of the current connection, so it can be torn down again.
+    static ref CONNECTION_TASKS: Mutex<Vec<JoinHandle<io::Result<()>>>> = Mutex::new(Vec::new());
 }
 
 ////////////////////////    CLIENT RX/TX TASKS    ////////////////////////
 
-pub async fn init_connection() -> tokio::io::Result<()> {
-    println!("connecting");
+/// Returns the local address of the connection, which is where the server sees us.
+pub async fn init_connection(server_addr: SocketAddr) -> tokio::io::Result<SocketAddr> {
+    println!("connecting to {}", server_addr);
     let socket = UdpSocket::bind("0.0.0.0:0").await?;
-    socket.connect(SERVER_HOST_ADDR).await?;
+    socket.connect(server_addr).await?;
+    let local_addr = socket.local_addr()?;
 
     println!("connected");
     let a_socket = Arc::new(socket);
 
     println!("spawning network tasks");
-    tokio::spawn(receive_incoming_messages(a_socket.clone()));
-    tokio::spawn(transmit_outbound_messages(a_socket.clone()));
-    Ok(())
+    // made up fresh for every connection
+    let secret = SECURE_CONNECTION.then(EphemeralSecret::random);
+    let mut tasks = CONNECTION_TASKS.lock().await;
+    tasks.push(tokio::spawn(receive_incoming_messages(
+        a_socket.clone(),
+        secret,
+    )));
+    tasks.push(tokio::spawn(transmit_outbound_messages(a_socket.clone())));
+
+    // goes out ahead of whatever the caller sends next
+    let hello = ClientToServerMessageData::Hello {
+        compression: REQUEST_COMPRESSION,
+    };
+    let _ = OUTBOUND_MESSAGE_QUEUE.push(ClientToServerMessage::new(hello));
+    Ok(local_addr)
+}
+
+/// Connects to the server running in this process. Messages are handed straight to it,
+/// with no socket, serialization or acks in between. Returns our client id.
+pub async fn init_local_connection() -> u32 {
+    let (client_id, mailbox) = server::client_bookkeeping::add_local_client().await;
+    println!("connected locally as {}", client_id);
+    CONNECTION_TASKS
+        .lock()
+        .await
+        .push(tokio::spawn(exchange_local_messages(client_id, mailbox)));
+    client_id
+}
+
+pub async fn exchange_local_messages(
+    client_id: u32,
+    mailbox: ClientMessageQueue,
+) -> io::Result<()> {
+    // only unreliable messages are ever dropped, and the queues count them
+    loop {
+        while let Some(message) = OUTBOUND_MESSAGE_QUEUE.pop() {
+            let message_bundle = ClientToServerMessageBundle::new(client_id, message);
+            // the server gets through its queue every millisecond, so only a stalled one
+            // would turn us away
+            if let Err(Overflow::Flooded) =
+                server::udp_networking::INCOMING_MESSAGE_QUEUE.push(message_bundle)
+            {
+                eprintln!("Server is not keeping up: dropping message");
+            }
+        }
+        while let Some(message) = mailbox.pop() {
+            let _ = INCOMING_MESSAGE_QUEUE.push(message);
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+    }
+}
+
+/// Stops the rx/tx tasks and forgets everything in flight. The socket is closed once
+/// this returns, so its port can be bound again.
+pub async fn close_connection() {
+    let tasks: Vec<_> = CONNECTION_TASKS.lock().await.drain(..).collect();
+    for task in tasks {
+        task.abort();
+        let _ = task.await;
+    }
+    *RELIABLE_SENDER.lock().await = ReliableSender::new();
+    *SESSION.lock().await = None;
+    SEND_COMPRESSED.store(false, Ordering::Relaxed);
+    while INCOMING_MESSAGE_QUEUE.pop().is_some() {}
+    while OUTBOUND_MESSAGE_QUEUE.pop().is_some() {}
+    println!("disconnected");
 }
 
-pub async fn receive_incoming_messages(socket: Arc<UdpSocket>) -> io::Result<()> {
-    let mut buffer = [0; 1024];
+/// `secret` is our half of the key exchange, for secure connections. Once that is done,
+/// nothing that doesn't open with the session is listened to.
+pub async fn receive_incoming_messages(
+    socket: Arc<UdpSocket>,
+    mut secret: Option<EphemeralSecret>,
+) -> io::Result<()> {
+    let secure = secret.is_some();
+    let mut buffer = [0; 65536];
+    let mut reliable_receiver = ReliableReceiver::new();
+    let mut packets = PacketCounter::new();
+    let mut last_receipt = get_utc_now();
     loop {
-        let nbytes = socket.recv(&mut buffer).await?;
-        let result: Result<ServerToClientMessage, _> = bincode::deserialize(&buffer[..nbytes]);
+        // a server that is not up (yet) shows up as errors here, keep listening
+        let nbytes = match socket.recv(&mut buffer).await {
+            Ok(nbytes) => nbytes,
+            Err(e) => {
+                eprintln!("Receive error: {}", e);
+                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let datagram = &buffer[..nbytes];
+        let contents = match secure::classify(datagram) {
+            Datagram::KeyReply {
+                server_public,
+                cookie,
+            } if secure => {
+                let mut session = SESSION.lock().await;
+                // later replies answer requests sent again while the first was on its way
+                if let Some(secret) = secret.take() {
+                    println!("server key {}", secure::fingerprint(&server_public));
+                    *session = Session::client(secret, server_public, cookie);
+                    if session.is_none() {
+                        eprintln!("Server sent an unusable key");
+                    }
+                }
+                continue;
+            }
+            Datagram::Sealed { .. } if secure => {
+                let opened = SESSION
+                    .lock()
+                    .await
+                    .as_mut()
+                    .and_then(|session| session.open(datagram));
+                match opened {
+                    Some(packet) => Cow::Owned(packet),
+                    None => {
+                        eprintln!("Dropping packet that failed authentication");
+                        continue;
+                    }
+                }
+            }
+            Datagram::Plain if !secure => Cow::Borrowed(datagram),
+            _ => continue,
+        };
+        let Some(bytes) = compression::unpack(&contents) else {
+            eprintln!("Malformed packet from the server");
+            continue;
+        };
+        let result: Result<ServerToClientPacket, _> = bincode::deserialize(&bytes);
         match result {
-            Ok(message) => {
-                if INCOMING_MESSAGE_QUEUE.push(message).is_err() {
-                    eprintln!("Inbound message queue full: dropping message");
+            Ok(packet) => {
+                packets.on_packet(packet.seq);
+                let now = get_utc_now();
+                if now - last_receipt >= RECEIPT_INTERVAL_MS {
+                    last_receipt = now;
+                    if let Some((up_to, count)) = packets.receipt() {
+                        let receipt = ClientToServerMessageData::Receipt { up_to, count };
+                        let _ = OUTBOUND_MESSAGE_QUEUE.push(ClientToServerMessage::new(receipt));
+                    }
+                }
+                if let ServerToClientMessage::Ack { seq } = packet.message {
+                    RELIABLE_SENDER.lock().await.ack(seq, get_utc_now());
+                    continue;
+                }
+                let ready = match packet.reliable_seq {
+                    None => vec![packet.message],
+                    Some(seq) => {
+                        let Some(ready) = reliable_receiver.accept(seq, packet.message) else {
+                            continue;
+                        };
+                        // ack every copy, since the previous ack may have been lost
+                        let ack = ClientToServerMessageData::Ack { seq };
+                        let _ = OUTBOUND_MESSAGE_QUEUE.push(ClientToServerMessage::new(ack));
+                        ready
+                    }
+                };
+                for message in ready {
+                    // only unreliable messages are ever dropped, and the queue counts them
+                    let _ = INCOMING_MESSAGE_QUEUE.push(message);
                 }
             }
             Err(e) => {
@@ -57,20 +229,60 @@ pub async fn receive_incoming_messages(socket: Arc<UdpSocket>) -> io::Result<()>
 }
 
 pub async fn transmit_outbound_messages(socket: Arc<UdpSocket>) -> io::Result<()> {
+    let mut last_key_request = None;
     loop {
-        // transmit any outbound messages
-        if let Some(message) = OUTBOUND_MESSAGE_QUEUE.pop() {
-            println!("Sending message: {:?}", message);
-            match bincode::serialize(&message) {
-                Ok(binary_message) => {
-                    socket.send(&binary_message).await?;
-                }
-                Err(e) => {
-                    eprintln!("Error serializing message: {:?}", e);
+        // nothing goes out in the clear on a secure connection, it waits for the keys
+        if SECURE_CONNECTION && SESSION.lock().await.is_none() {
+            let now = get_utc_now();
+            if last_key_request.is_none_or(|last| now - last >= KEY_REQUEST_INTERVAL_MS) {
+                last_key_request = Some(now);
+                if let Err(e) = socket.send(&secure::key_request()).await {
+                    eprintln!("Send error: {}", e);
                 }
             }
+            tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+            continue;
         }
 
-        // tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+        // transmit any outbound messages
+        while let Some(mut message) = OUTBOUND_MESSAGE_QUEUE.pop() {
+            if message.data.is_reliable() {
+                let seq = RELIABLE_SENDER
+                    .lock()
+                    .await
+                    .track(message.clone(), get_utc_now());
+                message.reliable_seq = Some(seq);
+            }
+            transmit_message(&socket, &message).await?;
+        }
+
+        // anything the server hasn't acked in a while goes out again
+        let resends = RELIABLE_SENDER.lock().await.due_for_resend(get_utc_now());
+        for (seq, mut message) in resends {
+            message.reliable_seq = Some(seq);
+            transmit_message(&socket, &message).await?;
+        }
+
+        // the queue is empty, give the game a moment to fill it
+        tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
     }
 }
+
+async fn transmit_message(socket: &UdpSocket, message: &ClientToServerMessage) -> io::Result<()> {
+    match bincode::serialize(message) {
+        Ok(binary_message) => {
+            let mut datagram =
+                compression::pack(&binary_message, SEND_COMPRESSED.load(Ordering::Relaxed));
+            if let Some(session) = SESSION.lock().await.as_mut() {
+                datagram = session.seal(&datagram);
+            }
+            if let Err(e) = socket.send(&datagram).await {
+                eprintln!("Send error: {}", e);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error serializing message: {:?}", e);
+        }
+    }
+    Ok(())
+}
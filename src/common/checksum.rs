@@ -0,0 +1,99 @@
+use std::hash::Hasher;
+
+use glam::Vec2;
+
+use super::game_objects::{Item, Player};
+
+/// FNV-1a. Unlike the std hashers it is the same in every process and on every machine,
+/// so two sides can compare what they got.
+pub struct Checksum(u64);
+
+impl Checksum {
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    /// Exact bits, so positions that differ in the last place still count as different.
+    pub fn write_vec2(&mut self, v: Vec2) {
+        self.write_u32(v.x.to_bits());
+        self.write_u32(v.y.to_bits());
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for Checksum {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+/// The part of an entity every client should agree with the server on exactly. Positions
+/// are left out since clients only hear them every few steps, and enemies altogether,
+/// since each client only hears of the ones near it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EntityDigest {
+    pub entity_id: u32,
+    pub hp: u32,
+    pub downed: bool,
+    pub held_by: Option<u32>,
+}
+
+impl EntityDigest {
+    pub fn of_player(player: &Player) -> Self {
+        Self {
+            entity_id: player.entity_id,
+            hp: player.hp,
+            downed: player.downed,
+            held_by: None,
+        }
+    }
+
+    pub fn of_item(item: &Item) -> Self {
+        Self {
+            entity_id: item.entity_id,
+            hp: 0,
+            downed: false,
+            held_by: item.held_by,
+        }
+    }
+}
+
+/// Digests of a room's entities, sorted, as `world_checksum` wants them.
+pub fn entity_digests<'a>(
+    players: impl IntoIterator<Item = &'a Player>,
+    items: impl IntoIterator<Item = &'a Item>,
+) -> Vec<EntityDigest> {
+    let mut digests: Vec<EntityDigest> = players
+        .into_iter()
+        .map(EntityDigest::of_player)
+        .chain(items.into_iter().map(EntityDigest::of_item))
+        .collect();
+    digests.sort();
+    digests
+}
+
+/// What the server sends every few steps for clients to check themselves against.
+/// `digests` must be sorted.
+pub fn world_checksum(wave: u32, digests: &[EntityDigest]) -> u64 {
+    let mut checksum = Checksum::new();
+    checksum.write_u32(wave);
+    for digest in digests {
+        checksum.write_u32(digest.entity_id);
+        checksum.write_u32(digest.hp);
+        checksum.write_u8(digest.downed as u8);
+        checksum.write_u32(digest.held_by.unwrap_or(u32::MAX));
+    }
+    checksum.finish()
+}
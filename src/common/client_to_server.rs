@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use super::game_objects::Player;
+use super::{
+    game_objects::{Player, SyncMode},
+    inputs::TickInputs,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClientToServerMessageBundle {
@@ -28,6 +31,8 @@ impl ClientToServerMessageBundle {
 pub struct ClientToServerMessage {
     // metadata
     pub send_time: i64,
+    /// Set by the transmit task when the message needs to be acked.
+    pub reliable_seq: Option<u32>,
 
     // actual message
     pub data: ClientToServerMessageData,
@@ -37,19 +42,53 @@ impl ClientToServerMessage {
     pub fn new(data: ClientToServerMessageData) -> Self {
         Self {
             send_time: crate::common::util::get_utc_now(),
+            reliable_seq: None,
             data,
         }
     }
 }
 
+/// New variants go at the end: replays and older peers know the others by position.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ClientToServerMessageData {
     Connect,
+    /// First thing a client sends, with the display name it would like.
+    Join {
+        name: String,
+    },
     Disconnect,
+    /// Sent instead of `Join` to a server that took over after the previous one went away,
+    /// to pick up where we left off.
+    Rejoin {
+        previous_client_id: u32,
+        /// The old server's `RejoinToken`, proof the id is ours.
+        token: u128,
+        name: String,
+    },
+    ListRooms,
+    /// Opens a new room and puts the sender in it.
+    CreateRoom,
+    JoinRoom {
+        code: String,
+    },
+    LeaveRoom,
     ChatMessage {
         message: String,
     },
-    RequestToSpawnPlayer,
+    SetColor {
+        color: u8,
+    },
+    SetReady {
+        ready: bool,
+    },
+    /// Host only, in the lobby.
+    SetSyncMode {
+        mode: SyncMode,
+    },
+    /// Host only: from the lobby into a match once everyone is ready.
+    StartMatch,
+    /// Host only: from the results back to the lobby.
+    ReturnToLobby,
     RequestAllEntities {
         from_client_id: u32,
     },
@@ -61,4 +100,63 @@ pub enum ClientToServerMessageData {
         client_id: u32,
         entities: Vec<Player>,
     },
+    RequestGrab {
+        entity_id: u32,
+    },
+    ReleaseItem {
+        entity_id: u32,
+        pos: glam::Vec2,
+        vel: glam::Vec2,
+    },
+    Ack {
+        seq: u32,
+    },
+    Fire {
+        pos: glam::Vec2,
+        vel: glam::Vec2,
+    },
+    /// A shot of ours touched an enemy at `pos`, in the world as of server step
+    /// `view_step`, which the server rewinds to before believing it.
+    HitEnemy {
+        entity_id: u32,
+        damage: u32,
+        pos: glam::Vec2,
+        view_step: u32,
+    },
+    RevivePlayer {
+        entity_id: u32,
+    },
+    /// Our world does not match the server's checksums; send all of it.
+    RequestSnapshot,
+    /// Rollback sessions: our inputs from step `tick` on. The last few steps go out every
+    /// time, so a lost packet is covered by the next one.
+    Inputs {
+        tick: u32,
+        inputs: Vec<TickInputs>,
+    },
+    /// Sent as soon as the socket is up, before anything else: what this client can do.
+    Hello {
+        /// We can take compressed packets, and would like to send them.
+        compression: bool,
+    },
+    /// Sent every `RECEIPT_INTERVAL_MS`: of the packets numbered up to `up_to`, `count`
+    /// arrived, counting from the first. Lost ones are made up for by the next.
+    Receipt {
+        up_to: u32,
+        count: u32,
+    },
+}
+
+impl ClientToServerMessageData {
+    /// Whether this message is resent until the server acks it.
+    pub fn is_reliable(&self) -> bool {
+        !matches!(
+            self,
+            ClientToServerMessageData::EntityPosition { .. }
+                | ClientToServerMessageData::Ack { .. }
+                | ClientToServerMessageData::RequestSnapshot
+                | ClientToServerMessageData::Inputs { .. }
+                | ClientToServerMessageData::Receipt { .. }
+        )
+    }
 }
@@ -0,0 +1,120 @@
+use glam::Vec2;
+
+/// How a shape's half extents are interpreted. Circles use `half_extents.x` as the radius.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShapeKind {
+    Circle,
+    Rect,
+}
+
+pub fn bounds(pos: Vec2, kind: ShapeKind, half_extents: Vec2) -> (Vec2, Vec2) {
+    let half_extents = match kind {
+        ShapeKind::Circle => Vec2::splat(half_extents.x),
+        ShapeKind::Rect => half_extents,
+    };
+    (pos - half_extents, pos + half_extents)
+}
+
+/// Smallest translation that pushes shape `a` out of shape `b`, or `None` if they don't overlap.
+pub fn penetration(
+    a_pos: Vec2,
+    a_kind: ShapeKind,
+    a_half_extents: Vec2,
+    b_pos: Vec2,
+    b_kind: ShapeKind,
+    b_half_extents: Vec2,
+) -> Option<Vec2> {
+    match (a_kind, b_kind) {
+        (ShapeKind::Circle, ShapeKind::Circle) => {
+            circle_vs_circle(a_pos, a_half_extents.x, b_pos, b_half_extents.x)
+        }
+        (ShapeKind::Circle, ShapeKind::Rect) => {
+            circle_vs_rect(a_pos, a_half_extents.x, b_pos, b_half_extents)
+        }
+        (ShapeKind::Rect, ShapeKind::Circle) => {
+            circle_vs_rect(b_pos, b_half_extents.x, a_pos, a_half_extents).map(|push| -push)
+        }
+        (ShapeKind::Rect, ShapeKind::Rect) => {
+            rect_vs_rect(a_pos, a_half_extents, b_pos, b_half_extents)
+        }
+    }
+}
+
+pub fn circle_vs_circle(a_pos: Vec2, a_radius: f32, b_pos: Vec2, b_radius: f32) -> Option<Vec2> {
+    let delta = a_pos - b_pos;
+    let dist = delta.length();
+    let overlap = a_radius + b_radius - dist;
+    if overlap <= 0.0 {
+        return None;
+    }
+    let normal = if dist > f32::EPSILON {
+        delta / dist
+    } else {
+        Vec2::Y
+    };
+    Some(normal * overlap)
+}
+
+pub fn circle_vs_rect(
+    circle_pos: Vec2,
+    radius: f32,
+    rect_pos: Vec2,
+    half_extents: Vec2,
+) -> Option<Vec2> {
+    let local = circle_pos - rect_pos;
+    let closest = local.clamp(-half_extents, half_extents);
+
+    // center is inside the box: push out along the shallowest axis
+    if closest == local {
+        let depth = half_extents - local.abs();
+        return Some(if depth.x < depth.y {
+            Vec2::new((depth.x + radius) * sign(local.x), 0.0)
+        } else {
+            Vec2::new(0.0, (depth.y + radius) * sign(local.y))
+        });
+    }
+
+    let delta = local - closest;
+    let dist = delta.length();
+    if dist >= radius {
+        return None;
+    }
+    Some(delta / dist * (radius - dist))
+}
+
+pub fn rect_vs_rect(
+    a_pos: Vec2,
+    a_half_extents: Vec2,
+    b_pos: Vec2,
+    b_half_extents: Vec2,
+) -> Option<Vec2> {
+    let delta = a_pos - b_pos;
+    let overlap = a_half_extents + b_half_extents - delta.abs();
+    if overlap.x <= 0.0 || overlap.y <= 0.0 {
+        return None;
+    }
+    Some(if overlap.x < overlap.y {
+        Vec2::new(overlap.x * sign(delta.x), 0.0)
+    } else {
+        Vec2::new(0.0, overlap.y * sign(delta.y))
+    })
+}
+
+/// Removes the part of `vel` heading into a surface, leaving the part that slides along it.
+pub fn slide(vel: Vec2, push: Vec2) -> Vec2 {
+    let normal = push.normalize_or_zero();
+    let into = vel.dot(normal);
+    if into < 0.0 {
+        vel - normal * into
+    } else {
+        vel
+    }
+}
+
+fn sign(x: f32) -> f32 {
+    if x < 0.0 {
+        -1.0
+    } else {
+        1.0
+    }
+}
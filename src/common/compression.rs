@@ -0,0 +1,74 @@
+use std::borrow::Cow;
+
+/// Packets smaller than this go out as they are, there is too little in them to squeeze.
+pub const COMPRESSION_THRESHOLD: usize = 128;
+/// Nothing that comes off the socket unpacks to more than this, whatever its header claims.
+pub const MAX_UNPACKED_SIZE: usize = 65536;
+
+/// First byte of every game datagram, saying how the rest of it is packed.
+const PLAIN: u8 = 0;
+const LZ4: u8 = 1;
+
+/// Wraps a serialized packet for the wire. If the peer agreed to compression and the packet
+/// is big enough it is compressed, unless that would not make it any smaller.
+pub fn pack(packet: &[u8], compress: bool) -> Vec<u8> {
+    if compress && packet.len() >= COMPRESSION_THRESHOLD {
+        let compressed = lz4_flex::compress_prepend_size(packet);
+        if compressed.len() < packet.len() {
+            let mut datagram = Vec::with_capacity(compressed.len() + 1);
+            datagram.push(LZ4);
+            datagram.extend_from_slice(&compressed);
+            return datagram;
+        }
+    }
+    let mut datagram = Vec::with_capacity(packet.len() + 1);
+    datagram.push(PLAIN);
+    datagram.extend_from_slice(packet);
+    datagram
+}
+
+/// The serialized packet inside a datagram, or `None` if it is not one of ours.
+pub fn unpack(datagram: &[u8]) -> Option<Cow<'_, [u8]>> {
+    let (&kind, rest) = datagram.split_first()?;
+    match kind {
+        PLAIN => Some(Cow::Borrowed(rest)),
+        LZ4 => {
+            // check the claimed size before trusting it with an allocation
+            let size = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+            if size > MAX_UNPACKED_SIZE {
+                return None;
+            }
+            lz4_flex::block::decompress(&rest[4..], size)
+                .ok()
+                .map(Cow::Owned)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack, unpack, COMPRESSION_THRESHOLD};
+
+    #[test]
+    fn packets_survive_the_round_trip() {
+        let small = vec![7; COMPRESSION_THRESHOLD - 1];
+        let large = vec![7; COMPRESSION_THRESHOLD * 4];
+        for compress in [false, true] {
+            for packet in [&small, &large] {
+                let datagram = pack(packet, compress);
+                assert_eq!(unpack(&datagram).as_deref(), Some(&packet[..]));
+            }
+        }
+        assert!(pack(&large, true).len() < large.len());
+        assert_eq!(pack(&small, true).len(), small.len() + 1);
+    }
+
+    #[test]
+    fn oversized_claims_are_refused() {
+        let mut datagram = pack(&[7; COMPRESSION_THRESHOLD * 4], true);
+        datagram[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(unpack(&datagram), None);
+        assert_eq!(unpack(&[]), None);
+    }
+}
@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+
+/// Broadcast by clients looking for servers on the local network.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiscoveryQuery {
+    /// Echoed back so the client can work out the ping.
+    pub send_time: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiscoveryReply {
+    pub send_time: i64,
+    pub name: String,
+    pub players: u32,
+    pub level_id: String,
+    /// Port the game socket listens on, on the same host the reply came from.
+    pub game_port: u16,
+}
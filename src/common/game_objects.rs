@@ -1,12 +1,32 @@
 use glam::Vec2;
 use serde::{Deserialize, Serialize};
 
+use super::collision::slide;
+
+pub const PLAYER_RADIUS: f32 = 16.0;
+pub const PLAYER_MAX_HP: u32 = 100;
+pub const ENEMY_RADIUS: f32 = 6.0;
+pub const ENEMY_MAX_HP: u32 = 30;
+pub const PROJECTILE_SPEED: f32 = 5.0;
+/// Steps between shots while the trigger is held.
+pub const FIRE_COOLDOWN: u32 = 8;
+/// Items this close to a player's center can be picked up.
+pub const PLAYER_GRAB_RADIUS: f32 = 28.0;
+pub const ITEM_HALF_EXTENT: f32 = 4.0;
+/// Thrown items keep this much of their speed every step.
+pub const ITEM_DRAG: f32 = 0.92;
+pub const THROW_SPEED: f32 = 4.0;
+/// Below this a thrown item counts as having come to rest.
+const ITEM_REST_SPEED: f32 = 0.01;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player {
     pub owner_client_id: u32,
     pub entity_id: u32,
     pub pos: Vec2,
     pub vel: Vec2,
+    pub hp: u32,
+    pub downed: bool,
 }
 impl Player {
     pub fn new(owner_client_id: u32, id: u32) -> Self {
@@ -15,6 +35,8 @@ impl Player {
             entity_id: id,
             pos: Vec2::ZERO,
             vel: Vec2::ZERO,
+            hp: PLAYER_MAX_HP,
+            downed: false,
         }
     }
 
@@ -22,3 +44,150 @@ impl Player {
         self.pos += self.vel;
     }
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Item {
+    pub entity_id: u32,
+    pub pos: Vec2,
+    /// Entity id of the player carrying it, if any.
+    pub held_by: Option<u32>,
+}
+impl Item {
+    pub fn new(id: u32, pos: Vec2) -> Self {
+        Self {
+            entity_id: id,
+            pos,
+            held_by: None,
+        }
+    }
+
+    /// Where an item let go of at `pos` with `vel` comes to rest, moved the way clients
+    /// move it every step: by its velocity, slowed by `ITEM_DRAG`, and kept inside `bounds`.
+    pub fn settle(mut pos: Vec2, mut vel: Vec2, bounds: Vec2) -> Vec2 {
+        let half_extents = Vec2::splat(ITEM_HALF_EXTENT);
+        while vel.length() > ITEM_REST_SPEED {
+            pos += vel;
+            vel *= ITEM_DRAG;
+            let clamped = pos.clamp(half_extents, (bounds - half_extents).max(half_extents));
+            vel = slide(vel, clamped - pos);
+            pos = clamped;
+        }
+        pos
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Enemy {
+    pub entity_id: u32,
+    pub pos: Vec2,
+    pub hp: u32,
+    /// Steps until it can hurt a player again.
+    pub attack_cooldown: u32,
+}
+impl Enemy {
+    pub fn new(id: u32, pos: Vec2) -> Self {
+        Self {
+            entity_id: id,
+            pos,
+            hp: ENEMY_MAX_HP,
+            attack_cooldown: 0,
+        }
+    }
+}
+
+/// Per-player tallies kept by the server.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub client_id: u32,
+    pub kills: u32,
+    pub damage_dealt: u32,
+    pub revives: u32,
+    pub deaths: u32,
+}
+impl PlayerStats {
+    pub fn new(client_id: u32) -> Self {
+        Self {
+            client_id,
+            ..Default::default()
+        }
+    }
+
+    pub fn score(&self) -> u32 {
+        self.kills * 100 + self.damage_dealt + self.revives * 50
+    }
+}
+
+/// How a room keeps everyone's world in step during a match. The host picks it in the lobby.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncMode {
+    /// Clients send their player's position and the server relays it.
+    Snapshot,
+    /// Clients only send their inputs and simulate every player from them, rolling back
+    /// whenever a late input turns out different from what was guessed.
+    Rollback,
+}
+
+/// Players pick one of this many colors in the lobby.
+pub const PLAYER_COLOR_COUNT: u8 = 6;
+
+/// One connected client as everyone else sees them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RosterEntry {
+    pub client_id: u32,
+    pub name: String,
+    /// Index into the client's player color palette.
+    pub color: u8,
+    pub ready: bool,
+    /// Played by the server until a human takes the slot.
+    pub bot: bool,
+}
+
+/// What the room browser shows about a room.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub code: String,
+    pub players: u32,
+    pub level_id: String,
+    pub phase: MatchPhase,
+}
+
+/// Where the server is in a match. Players gather in the lobby, play until a wave is
+/// failed, look at the results, then go back to the lobby.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchPhase {
+    Lobby,
+    InGame,
+    Results,
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::{Item, ITEM_DRAG, ITEM_HALF_EXTENT, THROW_SPEED};
+
+    #[test]
+    fn thrown_items_stop_where_step_by_step_drag_takes_them() {
+        let bounds = Vec2::new(1000.0, 1000.0);
+        let start = Vec2::new(100.0, 500.0);
+        let rest = Item::settle(start, Vec2::new(THROW_SPEED, 0.0), bounds);
+        // the distance covered adds up to speed / (1 - drag)
+        let expected = THROW_SPEED / (1.0 - ITEM_DRAG);
+        assert!((rest.x - start.x - expected).abs() < 0.5, "{}", rest.x);
+        assert_eq!(rest.y, start.y);
+
+        assert_eq!(Item::settle(start, Vec2::ZERO, bounds), start);
+    }
+
+    #[test]
+    fn thrown_items_stay_inside_the_level() {
+        let bounds = Vec2::new(100.0, 100.0);
+        let rest = Item::settle(
+            Vec2::new(90.0, 50.0),
+            Vec2::new(THROW_SPEED, -THROW_SPEED),
+            bounds,
+        );
+        assert_eq!(rest.x, bounds.x - ITEM_HALF_EXTENT);
+        assert!(rest.y < 50.0);
+    }
+}
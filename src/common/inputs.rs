@@ -0,0 +1,60 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+/// What a player is pressing this step. Clients fill it from the keyboard, server bots
+/// from their own judgement.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayingInputs {
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+
+    pub shoot: bool,
+    pub confirm: bool,
+    pub throw: bool,
+    pub revive: bool,
+
+    pub weapon_1: bool,
+    pub weapon_2: bool,
+    pub weapon_3: bool,
+    pub weapon_4: bool,
+}
+impl PlayingInputs {
+    pub fn new() -> PlayingInputs {
+        PlayingInputs {
+            left: false,
+            right: false,
+            up: false,
+            down: false,
+
+            shoot: false,
+
+            confirm: false,
+            throw: false,
+            revive: false,
+
+            weapon_1: false,
+            weapon_2: false,
+            weapon_3: false,
+            weapon_4: false,
+        }
+    }
+}
+
+/// One player's inputs for one step of a rollback session, which is all peers send each
+/// other. `aim` only matters while shooting and is zero otherwise, so moving the mouse
+/// around does not make every guess at it wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TickInputs {
+    pub inputs: PlayingInputs,
+    pub aim: Vec2,
+}
+impl TickInputs {
+    pub fn new(inputs: PlayingInputs, aim: Vec2) -> TickInputs {
+        TickInputs {
+            inputs,
+            aim: if inputs.shoot { aim } else { Vec2::ZERO },
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use std::{fmt, path::PathBuf};
+
+use glam::{UVec2, Vec2};
+
+/// Levels are plain text files in here, named `<level id>.txt`.
+pub const LEVELS_DIR: &str = "levels";
+/// Level ids come from the other side of the connection, so they are held to plain names
+/// that can't point anywhere outside `LEVELS_DIR`.
+pub const MAX_LEVEL_ID_LENGTH: usize = 64;
+
+/// Level files are a few `key = value` header lines, a `---` line, then one character per tile:
+///
+/// ```text
+/// name = Arena
+/// tile_size = 8
+/// ---
+/// ##########
+/// #P..I..E.#
+/// ##########
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tile {
+    Floor,
+    Wall,
+    PlayerSpawn,
+    EnemySpawner,
+    Pickup,
+}
+impl Tile {
+    pub fn from_char(c: char) -> Option<Tile> {
+        match c {
+            '.' | ' ' => Some(Tile::Floor),
+            '#' => Some(Tile::Wall),
+            'P' => Some(Tile::PlayerSpawn),
+            'E' => Some(Tile::EnemySpawner),
+            'I' => Some(Tile::Pickup),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(std::io::Error),
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// Not a plain name, such as one with a path in it.
+    InvalidId(String),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelError::Io(e) => write!(f, "{}", e),
+            LevelError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            LevelError::InvalidId(id) => write!(f, "`{}` is not a level id", id),
+        }
+    }
+}
+
+impl From<std::io::Error> for LevelError {
+    fn from(e: std::io::Error) -> Self {
+        LevelError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Level {
+    pub id: String,
+    pub name: String,
+    pub tile_size: f32,
+    pub dims: UVec2,
+    pub tiles: Vec<Tile>,
+    /// Hash of the file contents, so a client can tell its copy differs from the server's.
+    pub checksum: u32,
+}
+
+impl Level {
+    /// Letters, digits, `_` and `-` only.
+    pub fn is_valid_id(id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= MAX_LEVEL_ID_LENGTH
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    pub fn path(id: &str) -> Result<PathBuf, LevelError> {
+        if !Self::is_valid_id(id) {
+            return Err(LevelError::InvalidId(id.to_string()));
+        }
+        Ok(PathBuf::from(LEVELS_DIR).join(format!("{}.txt", id)))
+    }
+
+    pub fn load(id: &str) -> Result<Level, LevelError> {
+        let text = std::fs::read_to_string(Self::path(id)?)?;
+        Self::parse(id, &text)
+    }
+
+    pub fn parse(id: &str, text: &str) -> Result<Level, LevelError> {
+        let mut name = id.to_string();
+        let mut tile_size = 8.0;
+        let mut lines = text.lines().enumerate();
+
+        // header
+        for (index, line) in lines.by_ref() {
+            let line = line.trim();
+            if line == "---" {
+                break;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let parse_error = |message: &str| LevelError::Parse {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| parse_error("expected `key = value` or `---`"))?;
+            match key.trim() {
+                "name" => name = value.trim().to_string(),
+                "tile_size" => {
+                    tile_size = value
+                        .trim()
+                        .parse()
+                        .map_err(|_| parse_error("tile_size must be a number"))?
+                }
+                other => return Err(parse_error(&format!("unknown key `{}`", other))),
+            }
+        }
+
+        // grid
+        let rows: Vec<(usize, &str)> = lines
+            .map(|(index, line)| (index, line.trim_end()))
+            .filter(|(_, line)| !line.is_empty())
+            .collect();
+        let width = rows
+            .iter()
+            .map(|(_, row)| row.chars().count())
+            .max()
+            .unwrap_or(0);
+        if width == 0 {
+            return Err(LevelError::Parse {
+                line: 0,
+                message: "level has no tiles".to_string(),
+            });
+        }
+
+        let mut tiles = Vec::with_capacity(width * rows.len());
+        for (index, row) in &rows {
+            for (column, c) in row.chars().enumerate() {
+                let tile = Tile::from_char(c).ok_or_else(|| LevelError::Parse {
+                    line: index + 1,
+                    message: format!("unknown tile `{}` in column {}", c, column + 1),
+                })?;
+                tiles.push(tile);
+            }
+            // short rows are padded with floor
+            tiles.extend(std::iter::repeat_n(
+                Tile::Floor,
+                width - row.chars().count(),
+            ));
+        }
+
+        Ok(Level {
+            id: id.to_string(),
+            name,
+            tile_size,
+            dims: UVec2::new(width as u32, rows.len() as u32),
+            tiles,
+            checksum: checksum(text),
+        })
+    }
+
+    /// Size of the level in world units.
+    pub fn bounds(&self) -> Vec2 {
+        self.dims.as_vec2() * self.tile_size
+    }
+
+    pub fn tile(&self, x: u32, y: u32) -> Tile {
+        self.tiles[(y * self.dims.x + x) as usize]
+    }
+
+    pub fn tile_center(&self, x: u32, y: u32) -> Vec2 {
+        (UVec2::new(x, y).as_vec2() + 0.5) * self.tile_size
+    }
+
+    pub fn tile_centers(&self, kind: Tile) -> Vec<Vec2> {
+        let mut centers = Vec::new();
+        for y in 0..self.dims.y {
+            for x in 0..self.dims.x {
+                if self.tile(x, y) == kind {
+                    centers.push(self.tile_center(x, y));
+                }
+            }
+        }
+        centers
+    }
+
+    /// Walls as (center, half extents) boxes, with horizontal runs merged so a long wall
+    /// is one box instead of one per tile.
+    pub fn wall_boxes(&self) -> Vec<(Vec2, Vec2)> {
+        let mut boxes = Vec::new();
+        for y in 0..self.dims.y {
+            let mut x = 0;
+            while x < self.dims.x {
+                if self.tile(x, y) != Tile::Wall {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < self.dims.x && self.tile(x, y) == Tile::Wall {
+                    x += 1;
+                }
+                let min = Vec2::new(start as f32, y as f32) * self.tile_size;
+                let max = Vec2::new(x as f32, (y + 1) as f32) * self.tile_size;
+                boxes.push(((min + max) / 2.0, (max - min) / 2.0));
+            }
+        }
+        boxes
+    }
+}
+
+/// FNV-1a, which is plenty to notice two different files.
+pub fn checksum(text: &str) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in text.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{UVec2, Vec2};
+
+    use super::{Level, LevelError, Tile};
+
+    fn parse_error_line(text: &str) -> usize {
+        match Level::parse("test", text) {
+            Err(LevelError::Parse { line, .. }) => line,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_the_header_and_the_grid() {
+        let level = Level::parse(
+            "test",
+            "name = Test Room\ntile_size = 16\n---\n#####\n#P.E\n#I\n#####\n",
+        )
+        .unwrap();
+        assert_eq!(level.id, "test");
+        assert_eq!(level.name, "Test Room");
+        assert_eq!(level.tile_size, 16.0);
+        assert_eq!(level.dims, UVec2::new(5, 4));
+        assert_eq!(level.bounds(), Vec2::new(80.0, 64.0));
+        assert_eq!(level.tile(1, 1), Tile::PlayerSpawn);
+        assert_eq!(level.tile(3, 1), Tile::EnemySpawner);
+        assert_eq!(level.tile(1, 2), Tile::Pickup);
+        // short rows are padded with floor
+        assert_eq!(level.tile(4, 1), Tile::Floor);
+        assert_eq!(level.tile(4, 2), Tile::Floor);
+        assert_eq!(
+            level.tile_centers(Tile::PlayerSpawn),
+            vec![Vec2::new(24.0, 24.0)]
+        );
+    }
+
+    #[test]
+    fn the_header_is_optional_and_the_name_defaults_to_the_id() {
+        let level = Level::parse("plain", "---\n#.#\n").unwrap();
+        assert_eq!(level.name, "plain");
+        assert_eq!(level.tile_size, 8.0);
+        assert_eq!(level.dims, UVec2::new(3, 1));
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_line() {
+        assert_eq!(parse_error_line("colour = red\n---\n#\n"), 1);
+        assert_eq!(parse_error_line("\ntile_size = big\n---\n#\n"), 2);
+        assert_eq!(parse_error_line("just text\n---\n#\n"), 1);
+        assert_eq!(parse_error_line("---\n###\n#X#\n"), 3);
+        assert_eq!(parse_error_line("name = Empty\n---\n\n"), 0);
+    }
+
+    #[test]
+    fn the_checksum_follows_the_file() {
+        let a = Level::parse("a", "---\n#.#\n").unwrap();
+        let b = Level::parse("b", "---\n#.#\n").unwrap();
+        let c = Level::parse("a", "---\n#P#\n").unwrap();
+        assert_eq!(a.checksum, b.checksum);
+        assert_ne!(a.checksum, c.checksum);
+    }
+
+    #[test]
+    fn only_plain_names_are_level_ids() {
+        for id in ["arena", "arena_2", "big-one"] {
+            assert!(Level::path(id).is_ok(), "{}", id);
+        }
+        for id in [
+            "",
+            "../secret",
+            "levels/arena",
+            "/etc/passwd",
+            "a.b",
+            "arena\0",
+        ] {
+            assert!(
+                matches!(Level::load(id), Err(LevelError::InvalidId(_))),
+                "{:?}",
+                id
+            );
+        }
+        assert!(Level::path(&"a".repeat(65)).is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use super::{
+    client_to_server::{ClientToServerMessage, ClientToServerMessageBundle},
+    server_to_client::ServerToClientMessage,
+};
+
+/// Tells a mailbox which of its messages it may throw away, and who they are from.
+pub trait Delivery {
+    fn is_reliable(&self) -> bool;
+
+    /// Client the message came from, for mailboxes that limit how much each one queues.
+    fn sender(&self) -> Option<u32> {
+        None
+    }
+}
+
+impl Delivery for ServerToClientMessage {
+    fn is_reliable(&self) -> bool {
+        ServerToClientMessage::is_reliable(self)
+    }
+}
+
+impl Delivery for ClientToServerMessage {
+    fn is_reliable(&self) -> bool {
+        self.data.is_reliable()
+    }
+}
+
+impl Delivery for ClientToServerMessageBundle {
+    fn is_reliable(&self) -> bool {
+        self.message.is_reliable()
+    }
+
+    fn sender(&self) -> Option<u32> {
+        Some(self.client_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// The mailbox was full, so an unreliable message was dropped: the oldest one queued,
+    /// or the new one if nothing queued was unreliable.
+    Dropped,
+    /// Reliable messages have piled up past the backlog limit. The new one was still queued.
+    Backlogged,
+    /// The sender already has as many messages waiting as it may, so the new one was
+    /// refused, reliable or not.
+    Flooded,
+}
+
+/// Bounded queue with an overflow policy per message class. When it is full, the oldest
+/// unreliable message makes room for the new one, since a newer one supersedes it anyway.
+/// Reliable messages are never dropped; they queue up past the capacity instead, and a
+/// mailbox with a backlog limit reports when too many of them are waiting. A mailbox with a
+/// sender limit takes no more than that many messages from any one sender at a time, so
+/// one client can't fill it for everyone.
+pub struct Mailbox<T> {
+    inner: Mutex<Inner<T>>,
+    capacity: usize,
+    backlog_limit: Option<usize>,
+    sender_limit: Option<usize>,
+    dropped: AtomicU64,
+}
+
+struct Inner<T> {
+    queue: VecDeque<T>,
+    /// Messages waiting per sender.
+    queued_by: HashMap<u32, usize>,
+}
+
+impl<T: Delivery> Inner<T> {
+    fn forget(&mut self, message: &T) {
+        let Some(sender) = message.sender() else {
+            return;
+        };
+        if let Some(count) = self.queued_by.get_mut(&sender) {
+            *count -= 1;
+            if *count == 0 {
+                self.queued_by.remove(&sender);
+            }
+        }
+    }
+}
+
+impl<T: Delivery> Mailbox<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                queue: VecDeque::with_capacity(capacity),
+                queued_by: HashMap::new(),
+            }),
+            capacity,
+            backlog_limit: None,
+            sender_limit: None,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Pushes report `Overflow::Backlogged` once more than `limit` messages are queued,
+    /// which past the capacity are all reliable ones.
+    pub fn with_backlog_limit(capacity: usize, limit: usize) -> Self {
+        Self {
+            backlog_limit: Some(limit.max(capacity)),
+            ..Self::new(capacity)
+        }
+    }
+
+    /// Pushes report `Overflow::Flooded`, and queue nothing, once a sender has `limit`
+    /// messages waiting.
+    pub fn with_sender_limit(capacity: usize, limit: usize) -> Self {
+        Self {
+            sender_limit: Some(limit),
+            ..Self::new(capacity)
+        }
+    }
+
+    pub fn push(&self, message: T) -> Result<(), Overflow> {
+        let mut inner = self.inner.lock().expect("mailbox lock poisoned");
+        if let (Some(limit), Some(sender)) = (self.sender_limit, message.sender()) {
+            if inner.queued_by.get(&sender).copied().unwrap_or(0) >= limit {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return Err(Overflow::Flooded);
+            }
+        }
+        let mut result = Ok(());
+        if inner.queue.len() >= self.capacity {
+            match inner.queue.iter().position(|queued| !queued.is_reliable()) {
+                Some(oldest) => {
+                    if let Some(removed) = inner.queue.remove(oldest) {
+                        inner.forget(&removed);
+                    }
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    result = Err(Overflow::Dropped);
+                }
+                None if !message.is_reliable() => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Err(Overflow::Dropped);
+                }
+                None => {}
+            }
+        }
+        if let Some(sender) = message.sender() {
+            *inner.queued_by.entry(sender).or_insert(0) += 1;
+        }
+        inner.queue.push_back(message);
+        if self
+            .backlog_limit
+            .is_some_and(|limit| inner.queue.len() > limit)
+        {
+            return Err(Overflow::Backlogged);
+        }
+        result
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut inner = self.inner.lock().expect("mailbox lock poisoned");
+        let message = inner.queue.pop_front()?;
+        inner.forget(&message);
+        Some(message)
+    }
+
+    /// Throws away everything waiting from `sender`, for senders that are being cut off.
+    pub fn forget_sender(&self, sender: u32) {
+        let mut inner = self.inner.lock().expect("mailbox lock poisoned");
+        inner
+            .queue
+            .retain(|message| message.sender() != Some(sender));
+        inner.queued_by.remove(&sender);
+    }
+
+    /// Messages thrown away so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Delivery, Mailbox, Overflow};
+
+    #[derive(Debug, PartialEq)]
+    struct Message {
+        id: u32,
+        reliable: bool,
+        sender: u32,
+    }
+
+    impl Delivery for Message {
+        fn is_reliable(&self) -> bool {
+            self.reliable
+        }
+
+        fn sender(&self) -> Option<u32> {
+            Some(self.sender)
+        }
+    }
+
+    fn message(id: u32, reliable: bool) -> Message {
+        Message {
+            id,
+            reliable,
+            sender: 0,
+        }
+    }
+
+    fn message_from(sender: u32, id: u32) -> Message {
+        Message {
+            id,
+            reliable: true,
+            sender,
+        }
+    }
+
+    #[test]
+    fn full_mailboxes_drop_the_oldest_unreliable_message() {
+        let mailbox = Mailbox::new(3);
+        mailbox.push(message(0, true)).unwrap();
+        mailbox.push(message(1, false)).unwrap();
+        mailbox.push(message(2, false)).unwrap();
+
+        assert_eq!(mailbox.push(message(3, true)), Err(Overflow::Dropped));
+        assert_eq!(mailbox.dropped(), 1);
+        let ids: Vec<u32> = std::iter::from_fn(|| mailbox.pop())
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(ids, [0, 2, 3]);
+    }
+
+    #[test]
+    fn reliable_messages_queue_past_capacity_until_the_backlog_limit() {
+        let mailbox = Mailbox::with_backlog_limit(2, 4);
+        for id in 0..4 {
+            mailbox.push(message(id, true)).unwrap();
+        }
+        // nothing unreliable to make room with, so the new one goes
+        assert_eq!(mailbox.push(message(4, false)), Err(Overflow::Dropped));
+        assert_eq!(mailbox.push(message(5, true)), Err(Overflow::Backlogged));
+
+        let ids: Vec<u32> = std::iter::from_fn(|| mailbox.pop())
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(ids, [0, 1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn no_sender_queues_more_than_its_share() {
+        let mailbox = Mailbox::with_sender_limit(8, 2);
+        mailbox.push(message_from(1, 0)).unwrap();
+        mailbox.push(message_from(1, 1)).unwrap();
+        assert_eq!(mailbox.push(message_from(1, 2)), Err(Overflow::Flooded));
+        mailbox.push(message_from(2, 3)).unwrap();
+
+        // room again once one of theirs is taken out
+        assert_eq!(mailbox.pop().map(|message| message.id), Some(0));
+        mailbox.push(message_from(1, 4)).unwrap();
+
+        mailbox.forget_sender(1);
+        mailbox.push(message_from(1, 5)).unwrap();
+        let ids: Vec<u32> = std::iter::from_fn(|| mailbox.pop())
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(ids, [3, 5]);
+    }
+}
@@ -0,0 +1,57 @@
+use std::net::SocketAddr;
+
+use serde::{Deserialize, Serialize};
+
+use super::network_settings::MASTER_LIST_REQUEST_SIZE;
+
+/// What the master server knows about one game server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerListing {
+    /// Game socket address, ready to connect to.
+    pub addr: SocketAddr,
+    pub name: String,
+    pub players: u32,
+    pub level_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ToMasterMessage {
+    /// Sent by game servers every `MASTER_HEARTBEAT_INTERVAL_MS`. The first one registers.
+    Heartbeat {
+        name: String,
+        game_port: u16,
+        players: u32,
+        level_id: String,
+    },
+    /// Sent by clients, padded to `MASTER_LIST_REQUEST_SIZE`. The reply is never bigger
+    /// than the request, so the master can't be used to flood a forged sender address.
+    ListServers {
+        /// Index of the first listing wanted, for the pages after the first.
+        from: u32,
+        padding: Vec<u8>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FromMasterMessage {
+    ServerList {
+        servers: Vec<ServerListing>,
+        /// Where the next page starts, if the list goes on.
+        next: Option<u32>,
+    },
+}
+
+impl ToMasterMessage {
+    /// A request for the listings from `from` on, padded so a full page fits the reply.
+    pub fn list_servers(from: u32) -> Self {
+        let unpadded = ToMasterMessage::ListServers {
+            from,
+            padding: Vec::new(),
+        };
+        let size = bincode::serialized_size(&unpadded).unwrap_or(0) as usize;
+        ToMasterMessage::ListServers {
+            from,
+            padding: vec![0; MASTER_LIST_REQUEST_SIZE.saturating_sub(size)],
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    checksum::{entity_digests, EntityDigest},
+    game_objects::{Enemy, Item, MatchPhase, Player, PlayerStats, RosterEntry, SyncMode},
+    inputs::PlayingInputs,
+    rng::Rng,
+};
+
+/// Most of a serialized snapshot that goes in one message, well clear of the datagram size.
+pub const SNAPSHOT_PART_SIZE: usize = 1024;
+/// Snapshots claiming more parts than this are not put together.
+pub const MAX_SNAPSHOT_PARTS: u32 = 4096;
+
+/// Everything needed to carry a room's world on in another process.
+/// The level is loaded from disk there and checked against `level_checksum`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomSnapshot {
+    pub code: String,
+    pub level_id: String,
+    pub level_checksum: u32,
+
+    pub next_id: u32,
+    pub next_eid: u32,
+    pub step_count: u32,
+    pub rng: Rng,
+    pub phase: MatchPhase,
+    pub phase_steps: u32,
+    pub sync_mode: SyncMode,
+    pub roster: Vec<RosterEntry>,
+    pub host: Option<u32>,
+    pub players: Vec<Player>,
+    pub items: Vec<Item>,
+    pub enemies: Vec<Enemy>,
+    pub wave: u32,
+    /// `None` while a wave is being fought.
+    pub intermission_steps_left: Option<u32>,
+    pub stats: Vec<PlayerStats>,
+    pub bots: Vec<BotSnapshot>,
+    pub bot_shots: Vec<ShotSnapshot>,
+    /// Enemy positions of the last few steps, oldest first, for hits that arrive late.
+    pub history: Vec<(u32, Vec<(u32, Vec2)>)>,
+    pub gunfire: Vec<GunfireSnapshot>,
+    /// Keyed by client id: what each member has to show to get its id back after a
+    /// migration. Left empty in replays.
+    pub rejoin_tokens: Vec<(u32, u128)>,
+}
+
+/// What a server-played teammate carries over from one step to the next.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BotSnapshot {
+    pub client_id: u32,
+    pub inputs: PlayingInputs,
+    pub previous_inputs: PlayingInputs,
+    pub fire_cooldown: u32,
+}
+
+/// A bot's shot in flight.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShotSnapshot {
+    pub owner_client_id: u32,
+    pub pos: Vec2,
+    pub vel: Vec2,
+    pub steps_left: u32,
+}
+
+/// The shots a client fired lately, and how soon it may fire again.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GunfireSnapshot {
+    pub client_id: u32,
+    pub tokens: u32,
+    pub last_refill: u32,
+    /// Step heard of, where from and which way.
+    pub shots: Vec<(u32, Vec2, Vec2)>,
+}
+
+impl RoomSnapshot {
+    /// Sorted, as `world_checksum` wants them.
+    pub fn entity_digests(&self) -> Vec<EntityDigest> {
+        entity_digests(&self.players, &self.items)
+    }
+
+    /// The serialized snapshot, cut up to be sent one piece per message.
+    pub fn to_parts(&self) -> Vec<Vec<u8>> {
+        match bincode::serialize(self) {
+            Ok(bytes) => bytes
+                .chunks(SNAPSHOT_PART_SIZE)
+                .map(|part| part.to_vec())
+                .collect(),
+            Err(e) => {
+                eprintln!("Error serializing snapshot: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+}
+
+/// Puts a snapshot back together from its parts. Only one snapshot is gathered at a time,
+/// and a part of a different one starts over, so one lost part costs that snapshot only.
+pub struct SnapshotParts {
+    id: u32,
+    parts: Vec<Option<Vec<u8>>>,
+}
+
+impl SnapshotParts {
+    pub fn new() -> Self {
+        Self {
+            id: 0,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Takes part `index` of `count` of snapshot `id`, and hands back the snapshot once
+    /// that was the last one missing.
+    pub fn accept(
+        &mut self,
+        id: u32,
+        index: u32,
+        count: u32,
+        bytes: Vec<u8>,
+    ) -> Option<RoomSnapshot> {
+        if index >= count || count > MAX_SNAPSHOT_PARTS || bytes.len() > SNAPSHOT_PART_SIZE {
+            return None;
+        }
+        if id != self.id || self.parts.len() != count as usize {
+            self.id = id;
+            self.parts = vec![None; count as usize];
+        }
+        self.parts[index as usize] = Some(bytes);
+        if self.parts.iter().any(Option::is_none) {
+            return None;
+        }
+        let bytes: Vec<u8> = std::mem::take(&mut self.parts)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+        match bincode::deserialize(&bytes) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                eprintln!("Error parsing snapshot: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Default for SnapshotParts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,21 @@
+pub mod checksum;
 pub mod client_to_server;
+pub mod collision;
+pub mod compression;
+pub mod discovery;
 pub mod game_objects;
+pub mod inputs;
+pub mod level;
+pub mod mailbox;
+pub mod master;
+pub mod migration;
 pub mod network_settings;
+pub mod quantize;
+pub mod reliability;
+pub mod replay;
+pub mod rng;
+pub mod secure;
 pub mod server_to_client;
+pub mod spatial_hash;
+pub mod tick;
 pub mod util;
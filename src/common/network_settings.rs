@@ -1,4 +1,27 @@
 pub const SERVER_HOST_ADDR: &str = "127.0.0.1:8080";
-pub const CLIENT_CONNECT_TO_ADDR: &str = "127.0.0.1:8080";
+pub const CLIENT_CONNECT_TO_ADDR: &str = "0.0.0.0:8080";
+/// Servers answer discovery queries on this port, next to the game port.
+pub const DISCOVERY_PORT: u16 = 8081;
+/// Used unless the `MASTER_SERVER_ADDR` environment variable says otherwise.
+pub const MASTER_SERVER_ADDR: &str = "127.0.0.1:8082";
+pub const MASTER_LISTEN_ADDR: &str = "0.0.0.0:8082";
+pub const MASTER_HEARTBEAT_INTERVAL_MS: u64 = 5000;
+/// Servers the master hasn't heard from in this long are dropped from the list.
+pub const MASTER_EXPIRY_MS: i64 = 15000;
+/// Servers one address may list at once, so nobody can bury the list under fake ones.
+pub const MASTER_LISTINGS_PER_IP: usize = 4;
+/// Longer server names and level ids are not listed.
+pub const MASTER_MAX_NAME_LENGTH: usize = 64;
+/// Size list requests are padded to, which is also as big as a page of the list gets.
+pub const MASTER_LIST_REQUEST_SIZE: usize = 1200;
+/// How often clients ask around for servers while the server browser is open.
+pub const DISCOVERY_INTERVAL_MS: u64 = 2000;
+/// How often clients tell the server how many of its packets arrived.
+pub const RECEIPT_INTERVAL_MS: i64 = 250;
 // pub const SERVER_HOST_ADDR: &str = "72.234.70.195:8081";
 // pub const CLIENT_CONNECTION_ADDR: &str = "72.234.70.195:8081";
+
+/// Where the master server is, from `MASTER_SERVER_ADDR` in the environment if set.
+pub fn master_server_addr() -> String {
+    std::env::var("MASTER_SERVER_ADDR").unwrap_or_else(|_| MASTER_SERVER_ADDR.to_string())
+}
@@ -0,0 +1,56 @@
+use glam::{I16Vec2, Vec2};
+
+/// Fastest a quantized velocity can carry, in world units per step. Anything faster is
+/// clamped to it.
+pub const MAX_QUANTIZED_SPEED: f32 = 16.0;
+
+/// Spreads the level across the whole of an i16, so a level a few thousand units wide still
+/// resolves to a fraction of a unit. Positions outside the level are clamped to its edge.
+pub fn quantize_pos(pos: Vec2, bounds: Vec2) -> I16Vec2 {
+    let unit = (pos / bounds.max(Vec2::ONE)).clamp(Vec2::ZERO, Vec2::ONE);
+    (unit * u16::MAX as f32 + i16::MIN as f32)
+        .round()
+        .as_i16vec2()
+}
+
+pub fn dequantize_pos(pos: I16Vec2, bounds: Vec2) -> Vec2 {
+    (pos.as_vec2() - i16::MIN as f32) / u16::MAX as f32 * bounds.max(Vec2::ONE)
+}
+
+pub fn quantize_vel(vel: Vec2) -> I16Vec2 {
+    let unit = (vel / MAX_QUANTIZED_SPEED).clamp(Vec2::NEG_ONE, Vec2::ONE);
+    (unit * i16::MAX as f32).round().as_i16vec2()
+}
+
+pub fn dequantize_vel(vel: I16Vec2) -> Vec2 {
+    vel.as_vec2() / i16::MAX as f32 * MAX_QUANTIZED_SPEED
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::{dequantize_pos, dequantize_vel, quantize_pos, quantize_vel};
+
+    #[test]
+    fn round_trips_stay_within_a_step() {
+        let bounds = Vec2::new(2048.0, 1024.0);
+        for pos in [Vec2::ZERO, Vec2::new(1000.3, 511.7), bounds] {
+            let back = dequantize_pos(quantize_pos(pos, bounds), bounds);
+            assert!(back.distance(pos) < 0.05, "{} came back as {}", pos, back);
+        }
+        for vel in [Vec2::ZERO, Vec2::new(-5.0, 3.2), Vec2::new(15.9, -15.9)] {
+            let back = dequantize_vel(quantize_vel(vel));
+            assert!(back.distance(vel) < 0.001, "{} came back as {}", vel, back);
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let bounds = Vec2::new(640.0, 480.0);
+        let outside = dequantize_pos(quantize_pos(Vec2::new(-10.0, 500.0), bounds), bounds);
+        assert!(outside.distance(Vec2::new(0.0, 480.0)) < 0.05);
+        let fast = dequantize_vel(quantize_vel(Vec2::new(100.0, 0.0)));
+        assert!(fast.distance(Vec2::new(16.0, 0.0)) < 0.001);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// How long a reliable message waits for an ack before it is sent again.
+pub const RESEND_AFTER_MS: i64 = 200;
+/// How far ahead of the next expected sequence number messages are held on to.
+pub const RECEIVE_WINDOW: u32 = 1024;
+
+struct Pending<T> {
+    message: T,
+    first_sent: i64,
+    last_sent: i64,
+}
+
+/// Numbers reliable messages and holds on to them until the other side acks them.
+pub struct ReliableSender<T> {
+    next_seq: u32,
+    unacked: BTreeMap<u32, Pending<T>>,
+}
+
+impl<T: Clone> ReliableSender<T> {
+    pub fn new() -> Self {
+        Self {
+            next_seq: 0,
+            unacked: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a message that is about to be sent for the first time.
+    pub fn track(&mut self, message: T, now: i64) -> u32 {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.unacked.insert(
+            seq,
+            Pending {
+                message,
+                first_sent: now,
+                last_sent: now,
+            },
+        );
+        seq
+    }
+
+    /// Returns the round trip time, when it is clear which copy is being acked.
+    pub fn ack(&mut self, seq: u32, now: i64) -> Option<i64> {
+        let pending = self.unacked.remove(&seq)?;
+        (pending.first_sent == pending.last_sent).then(|| now - pending.first_sent)
+    }
+
+    /// Messages that went unacked for too long, marked as sent again.
+    pub fn due_for_resend(&mut self, now: i64) -> Vec<(u32, T)> {
+        let mut due = Vec::new();
+        for (&seq, pending) in self.unacked.iter_mut() {
+            if now - pending.last_sent >= RESEND_AFTER_MS {
+                pending.last_sent = now;
+                due.push((seq, pending.message.clone()));
+            }
+        }
+        due
+    }
+
+    /// The oldest message that went unacked for too long, marked as sent again. One at a
+    /// time, so a sender on a budget can leave the rest for its next pass.
+    pub fn next_due(&mut self, now: i64) -> Option<(u32, T)> {
+        let (&seq, pending) = self
+            .unacked
+            .iter_mut()
+            .find(|(_, pending)| now - pending.last_sent >= RESEND_AFTER_MS)?;
+        pending.last_sent = now;
+        Some((seq, pending.message.clone()))
+    }
+}
+
+impl<T: Clone> Default for ReliableSender<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hands reliable messages on in the order they were sent, each exactly once. Messages that
+/// arrive ahead of a gap wait for it to be filled.
+pub struct ReliableReceiver<T> {
+    next_seq: u32,
+    early: HashMap<u32, T>,
+}
+
+impl<T> ReliableReceiver<T> {
+    pub fn new() -> Self {
+        Self {
+            next_seq: 0,
+            early: HashMap::new(),
+        }
+    }
+
+    /// The messages that are now ready to go on, oldest first. `None` if the message is
+    /// too far ahead to hold on to, in which case it must not be acked, so it comes again.
+    pub fn accept(&mut self, seq: u32, message: T) -> Option<Vec<T>> {
+        // distances are taken around the wrap, so the numbers may run past u32::MAX
+        let ahead = seq.wrapping_sub(self.next_seq);
+        if (ahead as i32) < 0 {
+            // already handed on, the ack for it went missing
+            return Some(Vec::new());
+        }
+        if ahead >= RECEIVE_WINDOW {
+            return None;
+        }
+        self.early.entry(seq).or_insert(message);
+
+        let mut ready = Vec::new();
+        while let Some(message) = self.early.remove(&self.next_seq) {
+            ready.push(message);
+            self.next_seq = self.next_seq.wrapping_add(1);
+        }
+        Some(ready)
+    }
+}
+
+impl<T> Default for ReliableReceiver<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts the numbered packets that come in, reliable or not, so the sender can work out
+/// how many never made it.
+pub struct PacketCounter {
+    latest: Option<u32>,
+    count: u32,
+}
+
+impl PacketCounter {
+    pub fn new() -> Self {
+        Self {
+            latest: None,
+            count: 0,
+        }
+    }
+
+    pub fn on_packet(&mut self, seq: u32) {
+        self.count = self.count.wrapping_add(1);
+        // one that was overtaken on the way does not move the latest back
+        if self
+            .latest
+            .is_none_or(|latest| (seq.wrapping_sub(latest) as i32) > 0)
+        {
+            self.latest = Some(seq);
+        }
+    }
+
+    /// The latest number seen and how many packets came in all told, once any have.
+    pub fn receipt(&self) -> Option<(u32, u32)> {
+        self.latest.map(|latest| (latest, self.count))
+    }
+}
+
+impl Default for PacketCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PacketCounter, ReliableReceiver, ReliableSender, RECEIVE_WINDOW, RESEND_AFTER_MS};
+
+    #[test]
+    fn resends_come_one_at_a_time_oldest_first() {
+        let mut sender = ReliableSender::new();
+        for message in ["a", "b", "c"] {
+            sender.track(message, 0);
+        }
+        assert_eq!(sender.next_due(RESEND_AFTER_MS - 1), None);
+        assert_eq!(sender.next_due(RESEND_AFTER_MS), Some((0, "a")));
+        assert_eq!(sender.ack(1, RESEND_AFTER_MS), Some(RESEND_AFTER_MS));
+        // the ones left for later are still due
+        assert_eq!(sender.next_due(RESEND_AFTER_MS + 50), Some((2, "c")));
+        assert_eq!(sender.next_due(RESEND_AFTER_MS + 50), None);
+        assert_eq!(sender.next_due(2 * RESEND_AFTER_MS), Some((0, "a")));
+    }
+
+    #[test]
+    fn out_of_order_messages_are_held_until_the_gap_fills() {
+        let mut receiver = ReliableReceiver::new();
+        assert_eq!(receiver.accept(1, "b"), Some(vec![]));
+        assert_eq!(receiver.accept(2, "c"), Some(vec![]));
+        assert_eq!(receiver.accept(0, "a"), Some(vec!["a", "b", "c"]));
+        assert_eq!(receiver.accept(3, "d"), Some(vec!["d"]));
+    }
+
+    #[test]
+    fn duplicates_are_acked_but_not_handed_on() {
+        let mut receiver = ReliableReceiver::new();
+        assert_eq!(receiver.accept(0, "a"), Some(vec!["a"]));
+        assert_eq!(receiver.accept(0, "a"), Some(vec![]));
+        // a copy of one still waiting on a gap
+        assert_eq!(receiver.accept(2, "c"), Some(vec![]));
+        assert_eq!(receiver.accept(2, "c"), Some(vec![]));
+        assert_eq!(receiver.accept(1, "b"), Some(vec!["b", "c"]));
+    }
+
+    #[test]
+    fn messages_past_the_window_are_refused() {
+        let mut receiver = ReliableReceiver::new();
+        assert_eq!(receiver.accept(RECEIVE_WINDOW, "far"), None);
+        assert_eq!(receiver.accept(RECEIVE_WINDOW - 1, "last"), Some(vec![]));
+        assert_eq!(receiver.accept(0, "first"), Some(vec!["first"]));
+        // the window moved along with the first one
+        assert_eq!(receiver.accept(RECEIVE_WINDOW, "far"), Some(vec![]));
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_around() {
+        let mut receiver = ReliableReceiver {
+            next_seq: u32::MAX,
+            ..ReliableReceiver::new()
+        };
+        assert_eq!(receiver.accept(0, "after"), Some(vec![]));
+        assert_eq!(
+            receiver.accept(u32::MAX, "before"),
+            Some(vec!["before", "after"])
+        );
+        assert_eq!(receiver.accept(u32::MAX, "before"), Some(vec![]));
+        assert_eq!(receiver.accept(1, "next"), Some(vec!["next"]));
+    }
+
+    #[test]
+    fn packets_are_counted_up_to_the_latest() {
+        let mut counter = PacketCounter::new();
+        assert_eq!(counter.receipt(), None);
+        counter.on_packet(0);
+        counter.on_packet(2);
+        // overtaken by 2
+        counter.on_packet(1);
+        counter.on_packet(5);
+        assert_eq!(counter.receipt(), Some((5, 4)));
+    }
+}
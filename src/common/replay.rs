@@ -0,0 +1,117 @@
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::{client_to_server::ClientToServerMessageBundle, migration::RoomSnapshot};
+
+/// Every replay file starts with these, then `REPLAY_VERSION` as a little endian u32.
+const REPLAY_MAGIC: [u8; 4] = *b"RPLY";
+/// Goes up whenever records, the messages in them or `RoomSnapshot` change shape, since
+/// bincode can only read back exactly what it wrote.
+pub const REPLAY_VERSION: u32 = 1;
+
+/// Records claiming to be longer than this are taken for a broken file rather than trusted
+/// with an allocation.
+const MAX_RECORD_SIZE: usize = 16 * 1024 * 1024;
+
+/// One entry of a replay file. After its header a file is a run of these, each bincode
+/// encoded behind its length as a little endian u32.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ReplayRecord {
+    /// The whole room, before any inbound bundles of `snapshot.step_count` are handled.
+    Keyframe(Box<RoomSnapshot>),
+    /// A bundle the room handled while its step count was `step`.
+    Inbound {
+        step: u32,
+        bundle: ClientToServerMessageBundle,
+    },
+    /// Recording stopped after `step` steps.
+    End { step: u32 },
+}
+
+pub fn write_header(writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(&REPLAY_MAGIC)?;
+    writer.write_all(&REPLAY_VERSION.to_le_bytes())
+}
+
+/// Turns away files from before replays had a header, or from another version, instead
+/// of misreading them.
+fn read_header(reader: &mut impl Read) -> io::Result<()> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != REPLAY_MAGIC {
+        return Err(io::Error::other(
+            "not a replay, or one recorded before replays were versioned",
+        ));
+    }
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != REPLAY_VERSION {
+        return Err(io::Error::other(format!(
+            "replay is version {}, this build plays version {}",
+            version, REPLAY_VERSION
+        )));
+    }
+    Ok(())
+}
+
+pub fn write_record(writer: &mut impl Write, record: &ReplayRecord) -> io::Result<()> {
+    let bytes = bincode::serialize(record).map_err(io::Error::other)?;
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)
+}
+
+/// Checks the header, then reads records until the end of the file. A record cut short,
+/// as left by a server that stopped mid-write, ends the replay there.
+pub fn read_records(reader: &mut impl Read) -> io::Result<Vec<ReplayRecord>> {
+    read_header(reader)?;
+    let mut records = Vec::new();
+    loop {
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_RECORD_SIZE {
+            return Err(io::Error::other(format!(
+                "replay record of {} bytes is too large",
+                len
+            )));
+        }
+        let mut bytes = vec![0u8; len];
+        match reader.read_exact(&mut bytes) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        records.push(bincode::deserialize(&bytes).map_err(io::Error::other)?);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_records, write_header, write_record, ReplayRecord, REPLAY_VERSION};
+
+    fn file(version: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_header(&mut bytes).unwrap();
+        bytes[4..8].copy_from_slice(&version.to_le_bytes());
+        write_record(&mut bytes, &ReplayRecord::End { step: 42 }).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn only_replays_of_this_version_are_read() {
+        let records = read_records(&mut file(REPLAY_VERSION).as_slice()).unwrap();
+        assert!(matches!(records[..], [ReplayRecord::End { step: 42 }]));
+
+        assert!(read_records(&mut file(REPLAY_VERSION + 1).as_slice()).is_err());
+        // from before the header: straight into the first record
+        let headless = file(REPLAY_VERSION)[8..].to_vec();
+        assert!(read_records(&mut headless.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use std::hash::Hasher;
+
+use serde::{Deserialize, Serialize};
+
+use super::checksum::Checksum;
+
+/// Small xorshift generator. The same seed always gives the same stream, on every
+/// machine, so anything drawn from it can be replayed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// Seeds from a string such as a room code.
+    pub fn from_str_seed(seed: &str) -> Self {
+        let mut checksum = Checksum::new();
+        checksum.write(seed.as_bytes());
+        Self::new(checksum.finish())
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 32) as u32
+    }
+
+    /// Somewhere in `0.0..1.0`.
+    pub fn unit(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    pub fn chance(&mut self, one_in: u32) -> bool {
+        self.next_u32().is_multiple_of(one_in)
+    }
+
+    pub fn state(&self) -> u64 {
+        self.0
+    }
+}
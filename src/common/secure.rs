@@ -0,0 +1,419 @@
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+};
+use std::net::SocketAddr;
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret, StaticSecret};
+
+/// First byte of the datagrams that make up a secure connection. They sit well clear of
+/// the kinds `compression` uses, which is what goes inside the sealed ones.
+const KEY_REQUEST: u8 = 0x80;
+const KEY_REPLY: u8 = 0x81;
+/// Sealed, with the sender's public key and cookie up front so the server can set up the
+/// session from the packet alone.
+const SEALED_INIT: u8 = 0x82;
+const SEALED: u8 = 0x83;
+
+const KEY_SIZE: usize = 32;
+const COUNTER_SIZE: usize = 8;
+const COOKIE_SIZE: usize = 16;
+/// Cookies are good for the period they were made in and the one after.
+const COOKIE_PERIOD_MS: i64 = 30_000;
+/// Key requests are padded to this, so the reply is never bigger than what asked for it
+/// and the server can't be used to flood someone else's address.
+pub const KEY_REQUEST_SIZE: usize = 64;
+/// Packets this far behind the newest one are taken for replays, whether or not they are.
+const REPLAY_WINDOW: u64 = 64;
+
+/// Proof that whoever sent a packet gets to see what is sent to its address, handed out
+/// with the server's key.
+pub type Cookie = [u8; COOKIE_SIZE];
+
+/// What a client that the server has no session with yet sends along with its packets.
+pub struct Announcement {
+    pub public: PublicKey,
+    pub cookie: Cookie,
+}
+
+/// What a datagram turned out to be, before anything is decrypted.
+pub enum Datagram {
+    /// A client would like the server's public key.
+    KeyRequest,
+    KeyReply {
+        server_public: PublicKey,
+        cookie: Cookie,
+    },
+    Sealed {
+        announced: Option<Announcement>,
+    },
+    /// Not part of a secure connection.
+    Plain,
+    /// Claims to be part of one, but is cut short.
+    Invalid,
+}
+
+pub fn classify(datagram: &[u8]) -> Datagram {
+    match datagram.first() {
+        Some(&KEY_REQUEST) if datagram.len() >= KEY_REQUEST_SIZE => Datagram::KeyRequest,
+        Some(&KEY_REPLY) => match read_key_and_cookie(&datagram[1..]) {
+            Some((server_public, cookie)) => Datagram::KeyReply {
+                server_public,
+                cookie,
+            },
+            None => Datagram::Invalid,
+        },
+        Some(&SEALED_INIT) => match read_key_and_cookie(&datagram[1..]) {
+            Some((public, cookie)) => Datagram::Sealed {
+                announced: Some(Announcement { public, cookie }),
+            },
+            None => Datagram::Invalid,
+        },
+        Some(&SEALED) => Datagram::Sealed { announced: None },
+        Some(&KEY_REQUEST) => Datagram::Invalid,
+        _ => Datagram::Plain,
+    }
+}
+
+fn read_key_and_cookie(bytes: &[u8]) -> Option<(PublicKey, Cookie)> {
+    let key: [u8; KEY_SIZE] = bytes.get(..KEY_SIZE)?.try_into().ok()?;
+    let cookie = bytes
+        .get(KEY_SIZE..KEY_SIZE + COOKIE_SIZE)?
+        .try_into()
+        .ok()?;
+    Some((PublicKey::from(key), cookie))
+}
+
+pub fn key_request() -> Vec<u8> {
+    let mut datagram = vec![0; KEY_REQUEST_SIZE];
+    datagram[0] = KEY_REQUEST;
+    datagram
+}
+
+/// The server's long term key pair. Every session is set up from it and the client's key
+/// for that session, so answering a key request needs no state at all. Neither does
+/// checking cookies, which are worked out again from the address they were sent to.
+pub struct ServerKey {
+    secret: StaticSecret,
+    pub public: PublicKey,
+    cookie_secret: [u8; KEY_SIZE],
+}
+
+impl ServerKey {
+    pub fn generate() -> Self {
+        let secret = StaticSecret::random();
+        let public = PublicKey::from(&secret);
+        // any 32 random bytes will do
+        let cookie_secret = StaticSecret::random().to_bytes();
+        Self {
+            secret,
+            public,
+            cookie_secret,
+        }
+    }
+
+    /// Answers a key request from `address`, with a cookie only that address gets to see.
+    pub fn reply(&self, address: SocketAddr, now: i64) -> Vec<u8> {
+        let mut datagram = Vec::with_capacity(1 + KEY_SIZE + COOKIE_SIZE);
+        datagram.push(KEY_REPLY);
+        datagram.extend_from_slice(self.public.as_bytes());
+        let cookie = self.cookie_mac(address, now / COOKIE_PERIOD_MS).finalize();
+        datagram.extend_from_slice(&cookie.into_bytes()[..COOKIE_SIZE]);
+        datagram
+    }
+
+    /// True if `cookie` was handed to `address` recently.
+    pub fn accepts(&self, cookie: &Cookie, address: SocketAddr, now: i64) -> bool {
+        let period = now / COOKIE_PERIOD_MS;
+        [period, period - 1].into_iter().any(|period| {
+            self.cookie_mac(address, period)
+                .verify_truncated_left(cookie)
+                .is_ok()
+        })
+    }
+
+    fn cookie_mac(&self, address: SocketAddr, period: i64) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.cookie_secret)
+            .expect("hmac takes keys of any length");
+        mac.update(address.to_string().as_bytes());
+        mac.update(&period.to_le_bytes());
+        mac
+    }
+}
+
+/// Short form of a public key, to compare by eye.
+pub fn fingerprint(public: &PublicKey) -> String {
+    public.as_bytes()[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Remembers which recent counters have been seen. Packets may arrive out of order, but
+/// none may arrive twice.
+#[derive(Default)]
+struct ReplayWindow {
+    newest: Option<u64>,
+    /// Bit `i` is set if `newest - i` has been seen.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn is_fresh(&self, counter: u64) -> bool {
+        match self.newest {
+            None => true,
+            Some(newest) if counter > newest => true,
+            Some(newest) => {
+                let age = newest - counter;
+                age < REPLAY_WINDOW && self.seen & (1 << age) == 0
+            }
+        }
+    }
+
+    fn mark(&mut self, counter: u64) {
+        match self.newest {
+            Some(newest) if counter <= newest => self.seen |= 1 << (newest - counter),
+            Some(newest) => {
+                let shift = counter - newest;
+                self.seen = if shift < REPLAY_WINDOW {
+                    (self.seen << shift) | 1
+                } else {
+                    1
+                };
+                self.newest = Some(counter);
+            }
+            None => {
+                self.seen = 1;
+                self.newest = Some(counter);
+            }
+        }
+    }
+}
+
+/// One end of a secure connection. Each direction has its own key and counts its packets
+/// from zero, the count doubling as the nonce.
+pub struct Session {
+    send: ChaCha20Poly1305,
+    receive: ChaCha20Poly1305,
+    next_counter: u64,
+    window: ReplayWindow,
+    /// The client's public key and cookie, sent along until the server shows it has the
+    /// session too.
+    announce: Option<Announcement>,
+    client_public: PublicKey,
+}
+
+impl Session {
+    /// Client side, once the server's key is in. `None` if that key is no good.
+    pub fn client(
+        secret: EphemeralSecret,
+        server_public: PublicKey,
+        cookie: Cookie,
+    ) -> Option<Self> {
+        let public = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&server_public);
+        let (to_server, to_client) = derive_keys(&shared, &public, &server_public)?;
+        let announce = Announcement { public, cookie };
+        Some(Self::new(&to_server, &to_client, Some(announce), public))
+    }
+
+    /// Server side, from the key a client announced. Only worth keeping once a packet
+    /// opens with it.
+    pub fn server(key: &ServerKey, client_public: PublicKey) -> Option<Self> {
+        let shared = key.secret.diffie_hellman(&client_public);
+        let (to_server, to_client) = derive_keys(&shared, &client_public, &key.public)?;
+        Some(Self::new(&to_client, &to_server, None, client_public))
+    }
+
+    fn new(
+        send: &[u8; KEY_SIZE],
+        receive: &[u8; KEY_SIZE],
+        announce: Option<Announcement>,
+        client_public: PublicKey,
+    ) -> Self {
+        Self {
+            send: ChaCha20Poly1305::new(Key::from_slice(send)),
+            receive: ChaCha20Poly1305::new(Key::from_slice(receive)),
+            next_counter: 0,
+            window: ReplayWindow::default(),
+            announce,
+            client_public,
+        }
+    }
+
+    /// The key the client picked for this session, which no other session shares.
+    pub fn client_public(&self) -> &PublicKey {
+        &self.client_public
+    }
+
+    pub fn seal(&mut self, packet: &[u8]) -> Vec<u8> {
+        let counter = self.next_counter;
+        self.next_counter += 1;
+
+        let mut datagram =
+            Vec::with_capacity(1 + KEY_SIZE + COOKIE_SIZE + COUNTER_SIZE + packet.len() + 16);
+        match &self.announce {
+            Some(announce) => {
+                datagram.push(SEALED_INIT);
+                datagram.extend_from_slice(announce.public.as_bytes());
+                datagram.extend_from_slice(&announce.cookie);
+            }
+            None => datagram.push(SEALED),
+        }
+        datagram.extend_from_slice(&counter.to_le_bytes());
+        let payload = Payload {
+            msg: packet,
+            aad: &datagram,
+        };
+        let ciphertext = self
+            .send
+            .encrypt(&nonce(counter), payload)
+            .expect("packets are far below the size limit");
+        datagram.extend_from_slice(&ciphertext);
+        datagram
+    }
+
+    /// The packet inside a sealed datagram. `None` if it was tampered with, sealed with
+    /// another key, or has been seen before.
+    pub fn open(&mut self, datagram: &[u8]) -> Option<Vec<u8>> {
+        let header_size = match *datagram.first()? {
+            SEALED_INIT => 1 + KEY_SIZE + COOKIE_SIZE + COUNTER_SIZE,
+            SEALED => 1 + COUNTER_SIZE,
+            _ => return None,
+        };
+        let header = datagram.get(..header_size)?;
+        let counter = u64::from_le_bytes(header[header_size - COUNTER_SIZE..].try_into().ok()?);
+        if !self.window.is_fresh(counter) {
+            return None;
+        }
+        let payload = Payload {
+            msg: &datagram[header_size..],
+            aad: header,
+        };
+        let packet = self.receive.decrypt(&nonce(counter), payload).ok()?;
+        self.window.mark(counter);
+        // whatever the other end sealed, it has the session
+        self.announce = None;
+        Some(packet)
+    }
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    Nonce::clone_from_slice(&nonce)
+}
+
+/// One key per direction, tied to both public keys. `None` for keys that would leave the
+/// shared secret up to whoever picked them.
+fn derive_keys(
+    shared: &SharedSecret,
+    client_public: &PublicKey,
+    server_public: &PublicKey,
+) -> Option<([u8; KEY_SIZE], [u8; KEY_SIZE])> {
+    if !shared.was_contributory() {
+        return None;
+    }
+    let mut salt = [0; 2 * KEY_SIZE];
+    salt[..KEY_SIZE].copy_from_slice(client_public.as_bytes());
+    salt[KEY_SIZE..].copy_from_slice(server_public.as_bytes());
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes());
+
+    let mut to_server = [0; KEY_SIZE];
+    let mut to_client = [0; KEY_SIZE];
+    hkdf.expand(b"shootogethorthings client to server", &mut to_server)
+        .expect("a key is a valid output length");
+    hkdf.expand(b"shootogethorthings server to client", &mut to_client)
+        .expect("a key is a valid output length");
+    Some((to_server, to_client))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use x25519_dalek::{EphemeralSecret, PublicKey};
+
+    use super::{classify, Datagram, ReplayWindow, ServerKey, Session, COOKIE_PERIOD_MS};
+
+    fn connect() -> (Session, Session) {
+        let server_key = ServerKey::generate();
+        let secret = EphemeralSecret::random();
+        let client_public = PublicKey::from(&secret);
+        let client = Session::client(secret, server_key.public, [0; 16]).unwrap();
+        let server = Session::server(&server_key, client_public).unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn sealed_packets_open_once_on_the_other_end() {
+        let (mut client, mut server) = connect();
+
+        let first = client.seal(b"hello");
+        assert!(matches!(
+            classify(&first),
+            Datagram::Sealed { announced: Some(_) }
+        ));
+        assert_eq!(server.open(&first).as_deref(), Some(&b"hello"[..]));
+        assert_eq!(server.open(&first), None);
+
+        // the client stops announcing itself once the server has answered
+        let answer = server.seal(b"welcome");
+        assert_eq!(client.open(&answer).as_deref(), Some(&b"welcome"[..]));
+        assert!(matches!(
+            classify(&client.seal(b"again")),
+            Datagram::Sealed { announced: None }
+        ));
+    }
+
+    #[test]
+    fn tampered_or_foreign_packets_do_not_open() {
+        let (mut client, mut server) = connect();
+        let (mut stranger, _) = connect();
+
+        let mut tampered = client.seal(b"move");
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(server.open(&tampered), None);
+        assert_eq!(server.open(&stranger.seal(b"move")), None);
+        assert_eq!(
+            server.open(&client.seal(b"move")).as_deref(),
+            Some(&b"move"[..])
+        );
+    }
+
+    #[test]
+    fn cookies_only_work_for_the_address_they_were_sent_to() {
+        let server_key = ServerKey::generate();
+        let address: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+        let elsewhere: SocketAddr = "192.0.2.2:5000".parse().unwrap();
+        let now = 10 * COOKIE_PERIOD_MS;
+
+        let Datagram::KeyReply { cookie, .. } = classify(&server_key.reply(address, now)) else {
+            panic!("a key reply should classify as one");
+        };
+        assert!(server_key.accepts(&cookie, address, now));
+        assert!(server_key.accepts(&cookie, address, now + COOKIE_PERIOD_MS));
+        assert!(!server_key.accepts(&cookie, address, now + 2 * COOKIE_PERIOD_MS));
+        assert!(!server_key.accepts(&cookie, elsewhere, now));
+        assert!(!ServerKey::generate().accepts(&cookie, address, now));
+    }
+
+    #[test]
+    fn replay_window_takes_late_packets_but_not_repeats() {
+        let mut window = ReplayWindow::default();
+        for counter in [0, 1, 5, 3] {
+            assert!(window.is_fresh(counter));
+            window.mark(counter);
+        }
+        assert!(window.is_fresh(2));
+        assert!(!window.is_fresh(3));
+        assert!(!window.is_fresh(5));
+
+        window.mark(100);
+        assert!(!window.is_fresh(2));
+        assert!(window.is_fresh(99));
+    }
+}
@@ -1,8 +1,33 @@
-use glam::Vec2;
+use std::net::SocketAddr;
+
+use glam::{I16Vec2, Vec2};
 use serde::{Deserialize, Serialize};
 
-use super::game_objects::Player;
+use super::{
+    game_objects::{MatchPhase, Player, PlayerStats, RoomInfo, RosterEntry, SyncMode},
+    inputs::TickInputs,
+    migration::RoomSnapshot,
+};
 
+/// What actually goes over the wire from the server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerToClientPacket {
+    /// Every packet to a client is numbered, resends too, so the client can tell the
+    /// server how many went missing.
+    pub seq: u32,
+    /// Set when the message needs to be acked.
+    pub reliable_seq: Option<u32>,
+    pub message: ServerToClientMessage,
+}
+
+/// An entity's position as it goes out in snapshots, see `quantize_pos`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PackedPosition {
+    pub entity_id: u32,
+    pub pos: I16Vec2,
+}
+
+/// New variants go at the end: replays and older peers know the others by position.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ServerToClientMessage {
     ClientIDAssignment {
@@ -13,6 +38,7 @@ pub enum ServerToClientMessage {
     },
     ClientJoined {
         id: u32,
+        name: String,
     },
     ClientLeft {
         id: u32,
@@ -21,9 +47,39 @@ pub enum ServerToClientMessage {
         from: u32,
         message: String,
     },
+    /// Everyone currently connected, sent whenever that changes.
+    Roster {
+        players: Vec<RosterEntry>,
+        /// Client allowed to start the match.
+        host: Option<u32>,
+    },
+    /// Shown to a single client in the chat, e.g. why their request went nowhere.
+    Notice {
+        message: String,
+    },
+    RoomList {
+        rooms: Vec<RoomInfo>,
+    },
+    JoinedRoom {
+        code: String,
+    },
+    LeftRoom,
+    MatchPhaseChanged {
+        phase: MatchPhase,
+    },
+    SyncModeChanged {
+        mode: SyncMode,
+    },
+    /// Another player's inputs from step `tick` on, relayed during a rollback session.
+    PeerInputs {
+        client_id: u32,
+        tick: u32,
+        inputs: Vec<TickInputs>,
+    },
     SpawnPlayer {
         owner_client_id: u32,
         entity_id: u32,
+        pos: Vec2,
     },
     EntityPosition {
         entity_id: u32,
@@ -35,4 +91,114 @@ pub enum ServerToClientMessage {
     RequestAllEntitiesFor {
         for_client_id: u32,
     },
+    SpawnItem {
+        entity_id: u32,
+        pos: Vec2,
+    },
+    ItemGrabbed {
+        entity_id: u32,
+        holder_entity_id: u32,
+    },
+    ItemReleased {
+        entity_id: u32,
+        pos: Vec2,
+        vel: Vec2,
+    },
+    GrabDenied {
+        entity_id: u32,
+    },
+    Ack {
+        seq: u32,
+    },
+    /// Quantized, see `quantize_pos` and `quantize_vel`.
+    ProjectileFired {
+        owner_client_id: u32,
+        pos: I16Vec2,
+        vel: I16Vec2,
+    },
+    SpawnEnemy {
+        entity_id: u32,
+        pos: Vec2,
+    },
+    DespawnEntity {
+        entity_id: u32,
+    },
+    PlayerHealth {
+        entity_id: u32,
+        hp: u32,
+        downed: bool,
+    },
+    Scoreboard {
+        stats: Vec<PlayerStats>,
+    },
+    WaveStarted {
+        wave: u32,
+    },
+    WaveEnded {
+        wave: u32,
+        cleared: bool,
+        stats: Vec<PlayerStats>,
+    },
+    /// Clients load the level from their own `levels` directory.
+    LoadLevel {
+        level_id: String,
+        checksum: u32,
+    },
+    /// Sent to the whole room every second. Whoever is named takes over if the server goes
+    /// quiet, and everyone else reconnects to `addr`.
+    Successor {
+        client_id: u32,
+        addr: SocketAddr,
+    },
+    /// Sent to the successor only, so it has a world to carry on with. A snapshot is too big
+    /// for one datagram, so it goes out in `RoomSnapshot::to_parts`.
+    MigrationSnapshotPart {
+        /// Step the snapshot is from, which tells the parts of one apart from the next.
+        id: u32,
+        index: u32,
+        count: u32,
+        bytes: Vec<u8>,
+    },
+    /// `world_checksum` of the room as of `step`, sent every few steps. Reliable, so it
+    /// arrives after everything the server sent before it and a client checks the world
+    /// as of that very step.
+    WorldChecksum {
+        step: u32,
+        checksum: u64,
+    },
+    /// The whole room, for a client that found itself out of sync.
+    WorldSnapshot {
+        snapshot: Box<RoomSnapshot>,
+    },
+    /// Sent on joining a room. A server that takes over from this one only lets us back in
+    /// under our old client id with it.
+    RejoinToken {
+        token: u128,
+    },
+    /// One client's share of the enemies, positions quantized against the level bounds.
+    EnemyPositions {
+        positions: Vec<PackedPosition>,
+    },
+    /// Answers the client's `Hello` with what was agreed on.
+    Hello {
+        /// Packets both ways may be compressed from now on.
+        compression: bool,
+    },
+}
+
+impl ServerToClientMessage {
+    /// Whether this message is resent until the client acks it.
+    pub fn is_reliable(&self) -> bool {
+        !matches!(
+            self,
+            ServerToClientMessage::EntityPosition { .. }
+                | ServerToClientMessage::EnemyPositions { .. }
+                | ServerToClientMessage::Ack { .. }
+                | ServerToClientMessage::ProjectileFired { .. }
+                | ServerToClientMessage::Successor { .. }
+                | ServerToClientMessage::MigrationSnapshotPart { .. }
+                | ServerToClientMessage::WorldSnapshot { .. }
+                | ServerToClientMessage::PeerInputs { .. }
+        )
+    }
 }
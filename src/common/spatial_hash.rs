@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use glam::{IVec2, Vec2};
+
+/// Uniform grid broadphase. Items are bucketed by every cell their bounding box touches,
+/// so a query only has to look at the handful of cells around the queried area.
+pub struct SpatialHash<T> {
+    pub cell_size: f32,
+    cells: HashMap<IVec2, Vec<T>>,
+}
+
+impl<T: Copy + Ord> SpatialHash<T> {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn cell_of(&self, pos: Vec2) -> IVec2 {
+        (pos / self.cell_size).floor().as_ivec2()
+    }
+
+    pub fn insert(&mut self, item: T, min: Vec2, max: Vec2) {
+        let min_cell = self.cell_of(min);
+        let max_cell = self.cell_of(max);
+        for y in min_cell.y..=max_cell.y {
+            for x in min_cell.x..=max_cell.x {
+                self.cells.entry(IVec2::new(x, y)).or_default().push(item);
+            }
+        }
+    }
+
+    /// Every item whose cells overlap the box, each reported once.
+    pub fn query(&self, min: Vec2, max: Vec2) -> Vec<T> {
+        let min_cell = self.cell_of(min);
+        let max_cell = self.cell_of(max);
+        let mut found = Vec::new();
+        for y in min_cell.y..=max_cell.y {
+            for x in min_cell.x..=max_cell.x {
+                if let Some(items) = self.cells.get(&IVec2::new(x, y)) {
+                    found.extend_from_slice(items);
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+}
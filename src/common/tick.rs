@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+/// Simulation steps per second, on the server and every client.
+pub const FRAMES_PER_SECOND: u32 = 60;
+/// Most steps run in one go after falling behind, say after a stall or the window being
+/// dragged. Anything past that is skipped rather than run all at once.
+pub const MAX_CATCH_UP_STEPS: u32 = 15;
+
+/// Decides how many fixed steps to run. Counted from whole nanoseconds since the start
+/// rather than by adding up frame times, so rounding never builds up into drift.
+pub struct FixedTick {
+    started: Instant,
+    steps: u64,
+}
+
+impl FixedTick {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            steps: 0,
+        }
+    }
+
+    /// Steps to run now to catch up with the clock.
+    pub fn due(&mut self) -> u32 {
+        self.due_after(self.started.elapsed())
+    }
+
+    /// Steps to run `elapsed` after the start. Never more than `MAX_CATCH_UP_STEPS`;
+    /// the ones past that are dropped, so a long stall is not followed by a burst of
+    /// steps long enough to stall again.
+    fn due_after(&mut self, elapsed: Duration) -> u32 {
+        let target = (elapsed.as_nanos() * FRAMES_PER_SECOND as u128 / 1_000_000_000) as u64;
+        let behind = target.saturating_sub(self.steps);
+        self.steps = self.steps.max(target);
+        behind.min(MAX_CATCH_UP_STEPS as u64) as u32
+    }
+}
+
+impl Default for FixedTick {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FixedTick, FRAMES_PER_SECOND, MAX_CATCH_UP_STEPS};
+
+    fn after_steps(steps: u32) -> Duration {
+        Duration::from_nanos(steps as u64 * 1_000_000_000 / FRAMES_PER_SECOND as u64 + 1)
+    }
+
+    #[test]
+    fn runs_what_the_clock_says_and_no_more_after_a_stall() {
+        let mut tick = FixedTick::new();
+        assert_eq!(tick.due_after(Duration::ZERO), 0);
+        assert_eq!(tick.due_after(after_steps(3)), 3);
+        assert_eq!(tick.due_after(after_steps(3)), 0);
+        assert_eq!(tick.due_after(after_steps(4)), 1);
+
+        // ten seconds without a frame
+        let stalled = 4 + FRAMES_PER_SECOND * 10;
+        assert_eq!(tick.due_after(after_steps(stalled)), MAX_CATCH_UP_STEPS);
+        // and the rest is not owed afterwards
+        assert_eq!(tick.due_after(after_steps(stalled)), 0);
+        assert_eq!(tick.due_after(after_steps(stalled + 2)), 2);
+    }
+}
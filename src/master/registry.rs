@@ -0,0 +1,252 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use tokio::{io, net::UdpSocket};
+
+use crate::common::{
+    master::{FromMasterMessage, ServerListing, ToMasterMessage},
+    network_settings::{
+        MASTER_EXPIRY_MS, MASTER_LISTINGS_PER_IP, MASTER_LIST_REQUEST_SIZE, MASTER_MAX_NAME_LENGTH,
+    },
+    util::get_utc_now,
+};
+
+struct Registration {
+    listing: ServerListing,
+    last_seen: i64,
+}
+
+/// Every game server that has sent a heartbeat recently, keyed by game address.
+pub struct Registry {
+    servers: HashMap<SocketAddr, Registration>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            servers: HashMap::new(),
+        }
+    }
+
+    /// Lists the server, or keeps it listed. False if it is not taken: its name is too long,
+    /// or its address already lists as many servers as one may.
+    pub fn heartbeat(&mut self, listing: ServerListing, now: i64) -> bool {
+        if listing.name.len() > MASTER_MAX_NAME_LENGTH
+            || listing.level_id.len() > MASTER_MAX_NAME_LENGTH
+        {
+            return false;
+        }
+        if !self.servers.contains_key(&listing.addr) {
+            let listed = self
+                .servers
+                .keys()
+                .filter(|addr| addr.ip() == listing.addr.ip())
+                .count();
+            if listed >= MASTER_LISTINGS_PER_IP {
+                return false;
+            }
+            println!("registered {} ({})", listing.name, listing.addr);
+        }
+        self.servers.insert(
+            listing.addr,
+            Registration {
+                listing,
+                last_seen: now,
+            },
+        );
+        true
+    }
+
+    pub fn prune_expired(&mut self, now: i64) {
+        self.servers.retain(|addr, registration| {
+            let alive = now - registration.last_seen < MASTER_EXPIRY_MS;
+            if !alive {
+                println!("expired {} ({})", registration.listing.name, addr);
+            }
+            alive
+        });
+    }
+
+    pub fn listings(&self) -> Vec<ServerListing> {
+        let mut listings: Vec<ServerListing> = self
+            .servers
+            .values()
+            .map(|registration| registration.listing.clone())
+            .collect();
+        listings.sort_by(|a, b| a.name.cmp(&b.name).then(a.addr.cmp(&b.addr)));
+        listings
+    }
+
+    /// As much of the list from listing `from` on as fits in `max_bytes` once serialized.
+    pub fn page(&self, from: u32, max_bytes: usize) -> FromMasterMessage {
+        let empty = FromMasterMessage::ServerList {
+            servers: Vec::new(),
+            next: Some(from),
+        };
+        let mut size = serialized_size(&empty);
+        let mut servers = Vec::new();
+        let mut next = None;
+        for (index, listing) in self.listings().into_iter().enumerate().skip(from as usize) {
+            size = size.saturating_add(serialized_size(&listing));
+            if size > max_bytes {
+                // a request with no room for even one listing is not asked to go on forever
+                next = (!servers.is_empty()).then_some(index as u32);
+                break;
+            }
+            servers.push(listing);
+        }
+        FromMasterMessage::ServerList { servers, next }
+    }
+}
+
+fn serialized_size<T: serde::Serialize>(value: &T) -> usize {
+    bincode::serialized_size(value).map_or(usize::MAX, |size| size as usize)
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub async fn run(listen_addr: &str) -> io::Result<()> {
+    let socket = UdpSocket::bind(listen_addr).await?;
+    println!("Master server listening on {}", listen_addr);
+
+    let mut registry = Registry::new();
+    let mut buffer = [0; MASTER_LIST_REQUEST_SIZE];
+    let prune_interval = tokio::time::Duration::from_millis(1000);
+    loop {
+        // wake up now and then even if nobody talks to us, so expired servers go away
+        let received = tokio::time::timeout(prune_interval, socket.recv_from(&mut buffer)).await;
+        let now = get_utc_now();
+        registry.prune_expired(now);
+
+        let Ok(received) = received else {
+            continue;
+        };
+        let (nbytes, from) = match received {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Receive error: {}", e);
+                continue;
+            }
+        };
+        let message: ToMasterMessage = match bincode::deserialize(&buffer[..nbytes]) {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("Error parsing message from {}: {:?}", from, e);
+                continue;
+            }
+        };
+
+        match message {
+            ToMasterMessage::Heartbeat {
+                name,
+                game_port,
+                players,
+                level_id,
+            } => {
+                let listing = ServerListing {
+                    addr: SocketAddr::new(from.ip(), game_port),
+                    name,
+                    players,
+                    level_id,
+                };
+                registry.heartbeat(listing, now);
+            }
+            ToMasterMessage::ListServers { from: first, .. } => {
+                let reply = registry.page(first, nbytes);
+                match bincode::serialize(&reply) {
+                    Ok(binary_reply) => {
+                        if let Err(e) = socket.send_to(&binary_reply, from).await {
+                            eprintln!("Reply to {} failed: {}", from, e);
+                        }
+                    }
+                    Err(e) => eprintln!("Error serializing server list: {:?}", e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::common::{
+        master::{FromMasterMessage, ServerListing, ToMasterMessage},
+        network_settings::{
+            MASTER_EXPIRY_MS, MASTER_LISTINGS_PER_IP, MASTER_LIST_REQUEST_SIZE,
+            MASTER_MAX_NAME_LENGTH,
+        },
+    };
+
+    use super::Registry;
+
+    fn listing(ip: [u8; 4], port: u16, name: &str) -> ServerListing {
+        ServerListing {
+            addr: SocketAddr::from((ip, port)),
+            name: name.to_string(),
+            players: 0,
+            level_id: "arena".to_string(),
+        }
+    }
+
+    #[test]
+    fn servers_that_stop_heartbeating_expire() {
+        let mut registry = Registry::new();
+        assert!(registry.heartbeat(listing([10, 0, 0, 1], 8080, "a"), 0));
+        assert!(registry.heartbeat(listing([10, 0, 0, 2], 8080, "b"), 0));
+        assert!(registry.heartbeat(listing([10, 0, 0, 2], 8080, "b"), MASTER_EXPIRY_MS));
+        registry.prune_expired(MASTER_EXPIRY_MS);
+        let names: Vec<String> = registry.listings().into_iter().map(|l| l.name).collect();
+        assert_eq!(names, vec!["b"]);
+    }
+
+    #[test]
+    fn one_address_lists_only_so_many_servers() {
+        let mut registry = Registry::new();
+        for port in 0..MASTER_LISTINGS_PER_IP as u16 {
+            assert!(registry.heartbeat(listing([10, 0, 0, 1], 8000 + port, "a"), 0));
+        }
+        assert!(!registry.heartbeat(listing([10, 0, 0, 1], 9000, "a"), 0));
+        // the ones already listed keep heartbeating, and others are not held back
+        assert!(registry.heartbeat(listing([10, 0, 0, 1], 8000, "a"), 1));
+        assert!(registry.heartbeat(listing([10, 0, 0, 2], 9000, "a"), 1));
+        assert_eq!(registry.listings().len(), MASTER_LISTINGS_PER_IP + 1);
+
+        let long_name = "x".repeat(MASTER_MAX_NAME_LENGTH + 1);
+        assert!(!registry.heartbeat(listing([10, 0, 0, 3], 8000, &long_name), 1));
+    }
+
+    #[test]
+    fn pages_are_no_bigger_than_the_request_and_cover_the_list() {
+        let mut registry = Registry::new();
+        for ip in 0..100 {
+            let name = format!("server {:02}", ip);
+            registry.heartbeat(listing([10, 0, 1, ip], 8080, &name), 0);
+        }
+        let request_size = bincode::serialize(&ToMasterMessage::list_servers(0))
+            .unwrap()
+            .len();
+        assert_eq!(request_size, MASTER_LIST_REQUEST_SIZE);
+
+        let mut seen = Vec::new();
+        let mut from = Some(0);
+        while let Some(first) = from {
+            let page = registry.page(first, request_size);
+            assert!(bincode::serialize(&page).unwrap().len() <= request_size);
+            let FromMasterMessage::ServerList { servers, next } = page;
+            assert!(!servers.is_empty());
+            seen.extend(servers.into_iter().map(|listing| listing.name));
+            from = next;
+        }
+        let all: Vec<String> = registry.listings().into_iter().map(|l| l.name).collect();
+        assert_eq!(seen, all);
+
+        // an unpadded request gets nothing worth sending it for
+        let FromMasterMessage::ServerList { servers, next } = registry.page(0, 16);
+        assert!(servers.is_empty());
+        assert_eq!(next, None);
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+
+use crate::common::tick::FRAMES_PER_SECOND;
+
+use super::{
+    client_bookkeeping::{CLIENT_LINKS, CLIENT_OUTBOUND_MAILBOXES},
+    settings::{
+        LINK_ADJUST_INTERVAL_MS, LINK_BURST_MS, LINK_DECREASE_FACTOR,
+        LINK_INCREASE_BYTES_PER_SECOND, LINK_LOSS_LIMIT, LINK_MAX_BYTES_PER_SECOND,
+        LINK_MIN_BYTES_PER_SECOND, LINK_RTT_RISE_MS, LINK_START_BYTES_PER_SECOND,
+        SNAPSHOT_INTERVAL_STEPS, SNAPSHOT_MAX_INTERVAL_STEPS, SNAPSHOT_MIN_INTERVAL_STEPS,
+        SNAPSHOT_SHARE,
+    },
+    udp_networking::INCOMING_MESSAGE_QUEUE,
+};
+
+/// How much a new sample moves the smoothed rtt and loss.
+const SMOOTHING: f32 = 0.125;
+
+/// One client's connection: how many bytes it may be sent, and how it has been holding up.
+/// The budget grows while things go well and is cut back as soon as packets go missing or
+/// the round trip starts to stretch, the way TCP backs off.
+pub struct Link {
+    /// Current budget.
+    pub bytes_per_second: f32,
+    /// Steps between snapshots to this client.
+    pub snapshot_interval: u32,
+    /// Smoothed, `None` until the first ack comes back.
+    pub rtt_ms: Option<f32>,
+    /// Lowest round trip seen, taken as what the connection does when it is not congested.
+    pub min_rtt_ms: Option<f32>,
+    /// Smoothed share of packets the client says never reached it.
+    pub loss: f32,
+    /// Bytes per second actually sent, over the last stats report.
+    pub throughput: f32,
+    /// Agreed on in the handshake. Big packets to this client go out compressed.
+    pub compression: bool,
+
+    /// Bytes that may still go out. Goes negative when a packet is bigger than what was
+    /// left, which the refill then pays off.
+    tokens: f32,
+    last_refill: i64,
+    /// Number the next packet goes out with.
+    next_packet: u32,
+    /// The client's latest receipt: the last packet number it had seen, and how many
+    /// packets it had in all.
+    last_receipt: (u32, u32),
+    /// Counted from receipts since the last adjustment.
+    expected: u32,
+    received: u32,
+    last_adjusted: i64,
+    /// Counted since the last stats report.
+    bytes_sent: u64,
+    last_report: i64,
+}
+
+impl Link {
+    pub fn new(now: i64) -> Self {
+        Self {
+            bytes_per_second: LINK_START_BYTES_PER_SECOND,
+            snapshot_interval: SNAPSHOT_INTERVAL_STEPS,
+            rtt_ms: None,
+            min_rtt_ms: None,
+            loss: 0.0,
+            throughput: 0.0,
+            compression: false,
+
+            tokens: LINK_START_BYTES_PER_SECOND * LINK_BURST_MS as f32 / 1000.0,
+            last_refill: now,
+            next_packet: 0,
+            // as if the client had seen packet -1 and nothing else
+            last_receipt: (u32::MAX, 0),
+            expected: 0,
+            received: 0,
+            last_adjusted: now,
+            bytes_sent: 0,
+            last_report: now,
+        }
+    }
+
+    /// Tops the budget up for the time since the last refill, up to one burst's worth.
+    pub fn refill(&mut self, now: i64) {
+        let elapsed = (now - self.last_refill).max(0) as f32 / 1000.0;
+        self.last_refill = now;
+        let burst = self.bytes_per_second * LINK_BURST_MS as f32 / 1000.0;
+        self.tokens = (self.tokens + self.bytes_per_second * elapsed).min(burst);
+    }
+
+    pub fn has_budget(&self) -> bool {
+        self.tokens > 0.0
+    }
+
+    pub fn on_sent(&mut self, bytes: usize) {
+        self.tokens -= bytes as f32;
+        self.bytes_sent += bytes as u64;
+    }
+
+    /// Number for the packet about to go out.
+    pub fn next_packet_seq(&mut self) -> u32 {
+        let seq = self.next_packet;
+        self.next_packet = self.next_packet.wrapping_add(1);
+        seq
+    }
+
+    /// The client had seen `count` packets by the time it saw number `up_to`. Only what
+    /// changed since its last receipt is counted; a receipt overtaken by a later one is
+    /// ignored.
+    pub fn on_receipt(&mut self, up_to: u32, count: u32) {
+        let (last_up_to, last_count) = self.last_receipt;
+        let expected = up_to.wrapping_sub(last_up_to);
+        if expected == 0 || (expected as i32) < 0 {
+            return;
+        }
+        self.expected = self.expected.saturating_add(expected);
+        self.received = self.received.saturating_add(count.wrapping_sub(last_count));
+        self.last_receipt = (up_to, count);
+    }
+
+    pub fn on_rtt(&mut self, rtt_ms: i64) {
+        let sample = rtt_ms as f32;
+        self.rtt_ms = Some(match self.rtt_ms {
+            Some(rtt) => rtt + (sample - rtt) * SMOOTHING,
+            None => sample,
+        });
+        self.min_rtt_ms = Some(self.min_rtt_ms.map_or(sample, |min| min.min(sample)));
+    }
+
+    /// Once a second, backs off if the connection looks congested and creeps back up
+    /// if it does not.
+    pub fn adjust(&mut self, now: i64) {
+        if now - self.last_adjusted < LINK_ADJUST_INTERVAL_MS {
+            return;
+        }
+        self.last_adjusted = now;
+
+        if self.expected > 0 {
+            // packets that were overtaken are counted late, so a sample can come out short
+            let sample = (1.0 - self.received as f32 / self.expected as f32).clamp(0.0, 1.0);
+            self.loss += (sample - self.loss) * SMOOTHING;
+        }
+        self.expected = 0;
+        self.received = 0;
+
+        let delayed = match (self.rtt_ms, self.min_rtt_ms) {
+            (Some(rtt), Some(min)) => rtt > min + LINK_RTT_RISE_MS,
+            _ => false,
+        };
+        if self.loss > LINK_LOSS_LIMIT || delayed {
+            self.bytes_per_second =
+                (self.bytes_per_second * LINK_DECREASE_FACTOR).max(LINK_MIN_BYTES_PER_SECOND);
+            self.snapshot_interval = (self.snapshot_interval + 1).min(SNAPSHOT_MAX_INTERVAL_STEPS);
+        } else {
+            self.bytes_per_second = (self.bytes_per_second + LINK_INCREASE_BYTES_PER_SECOND)
+                .min(LINK_MAX_BYTES_PER_SECOND);
+            self.snapshot_interval = self
+                .snapshot_interval
+                .saturating_sub(1)
+                .max(SNAPSHOT_MIN_INTERVAL_STEPS);
+        }
+    }
+
+    /// Bytes of entity updates one snapshot may take, so that snapshots at the current
+    /// rate use their share of the budget and leave the rest for everything else.
+    pub fn snapshot_budget(&self) -> usize {
+        let per_step = self.bytes_per_second * SNAPSHOT_SHARE / FRAMES_PER_SECOND as f32;
+        (per_step * self.snapshot_interval as f32) as usize
+    }
+
+    /// Works out the throughput since the last report.
+    fn measure(&mut self, now: i64) {
+        let elapsed = (now - self.last_report).max(1) as f32 / 1000.0;
+        self.throughput = self.bytes_sent as f32 / elapsed;
+        self.bytes_sent = 0;
+        self.last_report = now;
+    }
+}
+
+/// Prints how every remote client's connection is doing, and how many messages had to be
+/// dropped on the way.
+pub async fn report_links(now: i64) {
+    // copied out up front, so the mailboxes are not held while the report is printed
+    let dropped: HashMap<u32, u64> = CLIENT_OUTBOUND_MAILBOXES
+        .read()
+        .await
+        .iter()
+        .map(|(client_id, mailbox)| (*client_id, mailbox.dropped()))
+        .collect();
+
+    let mut links_write = CLIENT_LINKS.write().await;
+    if links_write.is_empty() {
+        return;
+    }
+    let mut client_ids: Vec<u32> = links_write.keys().copied().collect();
+    client_ids.sort_unstable();
+    println!("---- link stats ----");
+    println!("inbound dropped {}", INCOMING_MESSAGE_QUEUE.dropped());
+    for client_id in client_ids {
+        let link = links_write
+            .get_mut(&client_id)
+            .expect("client id was just listed");
+        link.measure(now);
+        let rtt = link
+            .rtt_ms
+            .map(|rtt| format!("{:.0} ms", rtt))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "client {}: {:.1} of {:.1} kB/s{}, rtt {}, loss {:.1}%, snapshots every {} steps, dropped {}",
+            client_id,
+            link.throughput / 1000.0,
+            link.bytes_per_second / 1000.0,
+            if link.compression { " compressed" } else { "" },
+            rtt,
+            link.loss * 100.0,
+            link.snapshot_interval,
+            dropped.get(&client_id).copied().unwrap_or(0)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        common::tick::FRAMES_PER_SECOND,
+        server::settings::{
+            LINK_ADJUST_INTERVAL_MS, LINK_BURST_MS, LINK_DECREASE_FACTOR,
+            LINK_INCREASE_BYTES_PER_SECOND, LINK_START_BYTES_PER_SECOND, SNAPSHOT_INTERVAL_STEPS,
+            SNAPSHOT_SHARE,
+        },
+    };
+
+    use super::Link;
+
+    /// Bytes of budget a link starts with and tops up to.
+    fn burst() -> usize {
+        (LINK_START_BYTES_PER_SECOND * LINK_BURST_MS as f32 / 1000.0) as usize
+    }
+
+    #[test]
+    fn refills_pay_off_overdrafts_and_stop_at_a_burst() {
+        let mut link = Link::new(0);
+        assert!(link.has_budget());
+        link.on_sent(burst() + 400);
+        assert!(!link.has_budget());
+
+        // 10ms is not enough to pay off 400 bytes
+        link.refill(10);
+        assert!(!link.has_budget());
+        link.refill(100);
+        assert!(link.has_budget());
+
+        // a long quiet spell still only gives one burst
+        link.refill(10_000);
+        link.on_sent(burst());
+        assert!(!link.has_budget());
+        // nor does the clock going back give anything
+        link.refill(5_000);
+        assert!(!link.has_budget());
+    }
+
+    #[test]
+    fn adjusting_backs_off_on_loss_and_creeps_up_otherwise() {
+        let mut link = Link::new(0);
+        let interval = LINK_ADJUST_INTERVAL_MS;
+
+        // not due yet
+        link.on_receipt(99, 100);
+        link.adjust(interval / 2);
+        assert_eq!(link.bytes_per_second, LINK_START_BYTES_PER_SECOND);
+
+        // everything arrived
+        link.adjust(interval);
+        assert_eq!(link.loss, 0.0);
+        let grown = LINK_START_BYTES_PER_SECOND + LINK_INCREASE_BYTES_PER_SECOND;
+        assert_eq!(link.bytes_per_second, grown);
+        assert_eq!(link.snapshot_interval, SNAPSHOT_INTERVAL_STEPS - 1);
+
+        // half of the next hundred went missing
+        link.on_receipt(199, 150);
+        // an older receipt that was overtaken counts for nothing
+        link.on_receipt(150, 120);
+        assert_eq!((link.expected, link.received), (100, 50));
+        link.adjust(interval * 2);
+        assert!(link.loss > 0.0);
+        assert_eq!(link.bytes_per_second, grown * LINK_DECREASE_FACTOR);
+        assert_eq!(link.snapshot_interval, SNAPSHOT_INTERVAL_STEPS);
+    }
+
+    #[test]
+    fn a_stretching_round_trip_counts_as_congestion() {
+        let mut link = Link::new(0);
+        link.on_rtt(20);
+        for _ in 0..50 {
+            link.on_rtt(200);
+        }
+        link.adjust(LINK_ADJUST_INTERVAL_MS);
+        assert_eq!(
+            link.bytes_per_second,
+            LINK_START_BYTES_PER_SECOND * LINK_DECREASE_FACTOR
+        );
+    }
+
+    #[test]
+    fn snapshots_take_their_share_of_the_budget() {
+        let mut link = Link::new(0);
+        let per_second = |link: &Link| {
+            link.snapshot_budget() as f32 * FRAMES_PER_SECOND as f32 / link.snapshot_interval as f32
+        };
+        let share = LINK_START_BYTES_PER_SECOND * SNAPSHOT_SHARE;
+        assert!((per_second(&link) - share).abs() < share * 0.01);
+
+        // sent half as often, each snapshot may be twice the size
+        let budget = link.snapshot_budget();
+        link.snapshot_interval *= 2;
+        assert!(link.snapshot_budget().abs_diff(budget * 2) <= 1);
+        assert!((per_second(&link) - share).abs() < share * 0.01);
+    }
+}
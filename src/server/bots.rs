@@ -0,0 +1,366 @@
+use std::collections::BTreeMap;
+
+use glam::Vec2;
+
+use crate::common::{
+    client_to_server::ClientToServerMessageData,
+    game_objects::{MatchPhase, RosterEntry, ENEMY_RADIUS, PLAYER_RADIUS},
+    inputs::PlayingInputs,
+    server_to_client::ServerToClientMessage,
+};
+
+use super::{
+    match_phase::spawn_player_for,
+    message_processing::apply_input,
+    roster::unique_name,
+    settings::{
+        BOT_FIRE_COOLDOWN, BOT_FOLLOW_DISTANCE, BOT_POSITION_BROADCAST_INTERVAL,
+        BOT_PROJECTILE_DAMAGE, BOT_PROJECTILE_RADIUS, BOT_PROJECTILE_SPEED, BOT_PROJECTILE_STEPS,
+        BOT_SIGHT, BOT_SPEED, CO_OP_SLOTS, FIRST_BOT_CLIENT_ID, REVIVE_RANGE,
+    },
+    state::State,
+};
+
+/// Inputs closer to the goal than this count as arrived.
+const STEER_DEADZONE: f32 = 2.0;
+
+pub struct Bot {
+    pub inputs: PlayingInputs,
+    pub previous_inputs: PlayingInputs,
+    pub fire_cooldown: u32,
+}
+
+impl Bot {
+    pub fn new() -> Self {
+        Self {
+            inputs: PlayingInputs::new(),
+            previous_inputs: PlayingInputs::new(),
+            fire_cooldown: 0,
+        }
+    }
+}
+
+impl Default for Bot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A bot's shot. Clients only see it fly; the server decides what it hits.
+pub struct Shot {
+    pub owner_client_id: u32,
+    pub pos: Vec2,
+    pub vel: Vec2,
+    pub steps_left: u32,
+}
+
+/// Server-played teammates, keyed by the client id they have in the roster.
+pub struct Bots {
+    pub brains: BTreeMap<u32, Bot>,
+    pub shots: Vec<Shot>,
+}
+
+impl Bots {
+    pub fn new() -> Self {
+        Self {
+            brains: BTreeMap::new(),
+            shots: Vec::new(),
+        }
+    }
+}
+
+impl Default for Bots {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tops the room up with bots while there is at least one human to play with.
+/// Returns whether the roster changed.
+pub fn fill_slots(state: &mut State) -> bool {
+    if !state.roster.values().any(|entry| !entry.bot) {
+        return false;
+    }
+    let mut changed = false;
+    while state.roster.len() < CO_OP_SLOTS {
+        add_bot(state);
+        changed = true;
+    }
+    changed
+}
+
+fn add_bot(state: &mut State) {
+    let client_id = (FIRST_BOT_CLIENT_ID..)
+        .find(|client_id| !state.roster.contains_key(client_id))
+        .expect("a free bot id");
+    let name = format!("Bot {}", state.bots.brains.len() + 1);
+    let name = unique_name(&state.roster, client_id, name);
+    let color = state.free_color();
+    state.roster.insert(
+        client_id,
+        RosterEntry {
+            client_id,
+            name: name.clone(),
+            color,
+            ready: true,
+            bot: true,
+        },
+    );
+    state.bots.brains.insert(client_id, Bot::new());
+    state.stats_mut(client_id);
+    println!("{} fills a slot in room {}", name, state.code);
+
+    state.outbox.push(ServerToClientMessage::ClientJoined {
+        id: client_id,
+        name,
+    });
+    if state.phase != MatchPhase::InGame {
+        return;
+    }
+    // a human who left mid-match leaves their player behind for us
+    let left_behind = state
+        .players
+        .values()
+        .find(|player| !state.roster.contains_key(&player.owner_client_id))
+        .map(|player| player.entity_id);
+    match left_behind {
+        Some(eid) => hand_over(state, eid, client_id),
+        None => spawn_player_for(state, client_id),
+    }
+}
+
+/// A human joined a full room: the newest bot leaves and the human takes over its player.
+pub fn make_room_for(state: &mut State, client_id: u32) {
+    if state.roster.len() <= CO_OP_SLOTS {
+        return;
+    }
+    let Some(&bot_id) = state.bots.brains.keys().next_back() else {
+        return;
+    };
+    state.bots.brains.remove(&bot_id);
+    let bot_name = state.name_of(bot_id);
+    state.roster.remove(&bot_id);
+    println!("{} takes over from {}", state.name_of(client_id), bot_name);
+    state
+        .outbox
+        .push(ServerToClientMessage::ClientLeft { id: bot_id });
+
+    let Some(eid) = state.player_of_client(bot_id) else {
+        return;
+    };
+    if state.player_of_client(client_id).is_some() {
+        state.players.remove(&eid);
+        state
+            .outbox
+            .push(ServerToClientMessage::DespawnEntity { entity_id: eid });
+        return;
+    }
+    hand_over(state, eid, client_id);
+}
+
+/// Gives player `eid` to `client_id` as it is, health and all.
+fn hand_over(state: &mut State, eid: u32, client_id: u32) {
+    state
+        .outbox
+        .push(ServerToClientMessage::DespawnEntity { entity_id: eid });
+    let Some(player) = state.players.get_mut(&eid) else {
+        return;
+    };
+    player.owner_client_id = client_id;
+    player.vel = Vec2::ZERO;
+    state.outbox.push(ServerToClientMessage::SpawnPlayer {
+        owner_client_id: client_id,
+        entity_id: eid,
+        pos: player.pos,
+    });
+    state.outbox.push(ServerToClientMessage::PlayerHealth {
+        entity_id: eid,
+        hp: player.hp,
+        downed: player.downed,
+    });
+}
+
+/// Bots decide on their inputs, then act on them the way a client would, handing the
+/// server the same inputs a client sends.
+pub fn step_bots(state: &mut State) {
+    let bot_ids: Vec<u32> = state.bots.brains.keys().copied().collect();
+    for client_id in bot_ids {
+        let Some(eid) = state.player_of_client(client_id) else {
+            continue;
+        };
+        let pos = state.push_out_of_walls(state.players[&eid].pos, PLAYER_RADIUS);
+        let player = state.players.get_mut(&eid).expect("bot player exists");
+        player.pos = pos;
+        if player.downed {
+            player.vel = Vec2::ZERO;
+            continue;
+        }
+
+        let (inputs, aim, revive_target) = think(state, client_id, pos);
+        act(state, client_id, eid, pos, inputs, aim, revive_target);
+    }
+    step_shots(state);
+
+    if state
+        .step_count
+        .is_multiple_of(BOT_POSITION_BROADCAST_INTERVAL)
+    {
+        let bot_ids: Vec<u32> = state.bots.brains.keys().copied().collect();
+        for client_id in bot_ids {
+            let Some(player) = state
+                .player_of_client(client_id)
+                .and_then(|eid| state.players.get(&eid))
+            else {
+                continue;
+            };
+            let input = ClientToServerMessageData::EntityPosition {
+                entity_id: player.entity_id,
+                pos: player.pos,
+            };
+            announce(state, client_id, input);
+        }
+    }
+}
+
+/// Revive whoever is down, otherwise stay with the first human; shoot the nearest enemy.
+fn think(state: &State, client_id: u32, pos: Vec2) -> (PlayingInputs, Vec2, Option<u32>) {
+    let mut inputs = PlayingInputs::new();
+    let mut revive_target = None;
+
+    let downed_teammate = state
+        .players
+        .values()
+        .filter(|player| player.downed && player.owner_client_id != client_id)
+        .min_by(|a, b| a.pos.distance(pos).total_cmp(&b.pos.distance(pos)));
+    let goal = match downed_teammate {
+        Some(teammate) if teammate.pos.distance(pos) < REVIVE_RANGE => {
+            inputs.revive = true;
+            revive_target = Some(teammate.entity_id);
+            None
+        }
+        Some(teammate) => Some(teammate.pos),
+        None => state
+            .roster
+            .values()
+            .find(|entry| !entry.bot)
+            .and_then(|leader| state.player_of_client(leader.client_id))
+            .and_then(|eid| state.players.get(&eid))
+            .map(|leader| leader.pos)
+            .filter(|leader_pos| leader_pos.distance(pos) > BOT_FOLLOW_DISTANCE),
+    };
+    if let Some(goal) = goal {
+        let to_goal = goal - pos;
+        inputs.left = to_goal.x < -STEER_DEADZONE;
+        inputs.right = to_goal.x > STEER_DEADZONE;
+        inputs.up = to_goal.y < -STEER_DEADZONE;
+        inputs.down = to_goal.y > STEER_DEADZONE;
+    }
+
+    let nearest_enemy = state
+        .enemies
+        .values()
+        .map(|enemy| enemy.pos)
+        .filter(|enemy_pos| enemy_pos.distance(pos) < BOT_SIGHT)
+        .min_by(|a, b| a.distance(pos).total_cmp(&b.distance(pos)));
+    inputs.shoot = nearest_enemy.is_some();
+    (inputs, nearest_enemy.unwrap_or(pos), revive_target)
+}
+
+fn act(
+    state: &mut State,
+    client_id: u32,
+    eid: u32,
+    pos: Vec2,
+    inputs: PlayingInputs,
+    aim: Vec2,
+    revive_target: Option<u32>,
+) {
+    let mut vel = Vec2::ZERO;
+    if inputs.up {
+        vel.y = -BOT_SPEED;
+    } else if inputs.down {
+        vel.y = BOT_SPEED;
+    }
+    if inputs.left {
+        vel.x = -BOT_SPEED;
+    } else if inputs.right {
+        vel.x = BOT_SPEED;
+    }
+    if let Some(player) = state.players.get_mut(&eid) {
+        player.vel = vel;
+    }
+
+    let Some(brain) = state.bots.brains.get_mut(&client_id) else {
+        return;
+    };
+    brain.previous_inputs = brain.inputs;
+    brain.inputs = inputs;
+    brain.fire_cooldown = brain.fire_cooldown.saturating_sub(1);
+    let revive_pressed = inputs.revive && !brain.previous_inputs.revive;
+
+    let dir = (aim - pos).normalize_or_zero();
+    if inputs.shoot && brain.fire_cooldown == 0 && dir != Vec2::ZERO {
+        brain.fire_cooldown = BOT_FIRE_COOLDOWN;
+        let vel = dir * BOT_PROJECTILE_SPEED;
+        if announce(
+            state,
+            client_id,
+            ClientToServerMessageData::Fire { pos, vel },
+        ) {
+            state.bots.shots.push(Shot {
+                owner_client_id: client_id,
+                pos,
+                vel,
+                steps_left: BOT_PROJECTILE_STEPS,
+            });
+        }
+    }
+
+    if let (true, Some(entity_id)) = (revive_pressed, revive_target) {
+        apply_input(
+            state,
+            client_id,
+            ClientToServerMessageData::RevivePlayer { entity_id },
+        );
+    }
+}
+
+/// Hands the server a bot's input and tells the room about it if it went through. Bots
+/// are not members, so the whole room hears it. Whether the input was taken.
+fn announce(state: &mut State, client_id: u32, input: ClientToServerMessageData) -> bool {
+    match apply_input(state, client_id, input) {
+        Some(outbound_message) => {
+            state.outbox.push(outbound_message);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Moves bot shots along. They stop at walls and at the first enemy they touch, which the
+/// bot reports like a client reports its hits.
+fn step_shots(state: &mut State) {
+    let mut shots = std::mem::take(&mut state.bots.shots);
+    shots.retain_mut(|shot| {
+        shot.pos += shot.vel;
+        shot.steps_left = shot.steps_left.saturating_sub(1);
+        let hit = state
+            .enemies
+            .values()
+            .find(|enemy| enemy.pos.distance(shot.pos) <= ENEMY_RADIUS + BOT_PROJECTILE_RADIUS)
+            .map(|enemy| enemy.entity_id);
+        if let Some(entity_id) = hit {
+            let input = ClientToServerMessageData::HitEnemy {
+                entity_id,
+                damage: BOT_PROJECTILE_DAMAGE,
+                pos: shot.pos,
+                view_step: state.step_count,
+            };
+            apply_input(state, shot.owner_client_id, input);
+            return false;
+        }
+        let in_wall = state.push_out_of_walls(shot.pos, BOT_PROJECTILE_RADIUS) != shot.pos;
+        shot.steps_left > 0 && !in_wall
+    });
+    state.bots.shots = shots;
+}
@@ -0,0 +1,124 @@
+use super::settings::{CHAT_BURST, CHAT_REFILL_MS, MAX_CHAT_LENGTH};
+
+/// Token bucket per client: `CHAT_BURST` messages at once, then one per `CHAT_REFILL_MS`.
+pub struct ChatLimiter {
+    tokens: u32,
+    last_refill: i64,
+}
+
+impl ChatLimiter {
+    pub fn new(now: i64) -> Self {
+        Self {
+            tokens: CHAT_BURST,
+            last_refill: now,
+        }
+    }
+
+    pub fn allow(&mut self, now: i64) -> bool {
+        let refills = (now - self.last_refill) / CHAT_REFILL_MS;
+        if refills > 0 {
+            self.tokens = (self.tokens + refills as u32).min(CHAT_BURST);
+            self.last_refill += refills * CHAT_REFILL_MS;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
+/// Invisible characters that change how the text around them looks: bidi overrides and
+/// isolates, which could turn the rest of a line around, zero widths, joiners and tags.
+fn is_format(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{0600}'..='\u{0605}'
+            | '\u{061C}'
+            | '\u{06DD}'
+            | '\u{070F}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{206F}'
+            | '\u{FEFF}'
+            | '\u{FFF9}'..='\u{FFFB}'
+            | '\u{E0000}'..='\u{E007F}'
+    )
+}
+
+/// Strips control and format characters and surrounding whitespace and caps the length.
+/// Returns `None` if nothing worth sending is left.
+pub fn sanitize(message: &str) -> Option<String> {
+    let cleaned: String = message
+        .chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .filter(|c| !c.is_control() && !is_format(*c))
+        .take(MAX_CHAT_LENGTH)
+        .collect();
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::settings::{CHAT_BURST, CHAT_REFILL_MS, MAX_CHAT_LENGTH};
+
+    use super::{sanitize, ChatLimiter};
+
+    #[test]
+    fn sanitizing_keeps_only_visible_text() {
+        assert_eq!(sanitize("  hi\tthere\n"), Some("hi there".to_string()));
+        assert_eq!(sanitize("bell\u{7}"), Some("bell".to_string()));
+        assert_eq!(sanitize(" \n\t "), None);
+        assert_eq!(sanitize("héllo 👋"), Some("héllo 👋".to_string()));
+    }
+
+    #[test]
+    fn sanitizing_strips_bidi_and_format_characters() {
+        // would otherwise show as "gnp.exe" written backwards
+        assert_eq!(
+            sanitize("file\u{202E}gnp.exe"),
+            Some("filegnp.exe".to_string())
+        );
+        assert_eq!(
+            sanitize("\u{2067}a\u{2069}\u{200B}b\u{FEFF}"),
+            Some("ab".to_string())
+        );
+        assert_eq!(sanitize("\u{200D}\u{E0041}"), None);
+    }
+
+    #[test]
+    fn sanitizing_caps_the_length() {
+        let long = "x".repeat(MAX_CHAT_LENGTH * 2);
+        assert_eq!(
+            sanitize(&long).map(|message| message.chars().count()),
+            Some(MAX_CHAT_LENGTH)
+        );
+    }
+
+    #[test]
+    fn chat_is_limited_to_a_burst_then_one_per_refill() {
+        let mut limiter = ChatLimiter::new(0);
+        for _ in 0..CHAT_BURST {
+            assert!(limiter.allow(0));
+        }
+        assert!(!limiter.allow(0));
+        assert!(!limiter.allow(CHAT_REFILL_MS - 1));
+        assert!(limiter.allow(CHAT_REFILL_MS));
+        assert!(!limiter.allow(CHAT_REFILL_MS));
+
+        // a long pause refills only up to a burst
+        let later = CHAT_REFILL_MS * 100;
+        for _ in 0..CHAT_BURST {
+            assert!(limiter.allow(later));
+        }
+        assert!(!limiter.allow(later));
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     net::SocketAddr,
     sync::{
         atomic::{AtomicBool, AtomicU32},
@@ -7,7 +7,6 @@ use std::{
     },
 };
 
-use crossbeam::queue::ArrayQueue;
 use lazy_static::lazy_static;
 use tokio::sync::RwLock;
 
@@ -16,12 +15,20 @@ use crate::{
         client_to_server::{
             ClientToServerMessage, ClientToServerMessageBundle, ClientToServerMessageData,
         },
+        mailbox::Mailbox,
+        reliability::{ReliableReceiver, ReliableSender},
+        secure::Session,
         server_to_client::ServerToClientMessage,
+        util::get_utc_now,
+    },
+    server::{
+        bandwidth::Link,
+        settings::{CLIENT_BACKLOG_LIMIT, CLIENT_MAILBOX_CAPACITY},
+        udp_networking::{CLIENT_DISCONNECTED, INCOMING_MESSAGE_QUEUE},
     },
-    server::udp_networking::{CLIENT_DISCONNECTED, INCOMING_MESSAGE_QUEUE},
 };
 
-pub type ClientMessageQueue = Arc<ArrayQueue<ServerToClientMessage>>;
+pub type ClientMessageQueue = Arc<Mailbox<ServerToClientMessage>>;
 
 lazy_static! {
     pub static ref NEXT_CONNECTION_ID: Arc<AtomicU32> = Arc::new(AtomicU32::new(0));
@@ -31,6 +38,17 @@ lazy_static! {
         Arc::new(RwLock::new(HashMap::new()));
     pub static ref CLIENT_OUTBOUND_MAILBOXES: RwLock<HashMap<u32, ClientMessageQueue>> =
         RwLock::new(HashMap::new());
+    pub static ref CLIENT_RELIABLE_SENDERS: RwLock<HashMap<u32, ReliableSender<ServerToClientMessage>>> =
+        RwLock::new(HashMap::new());
+    pub static ref CLIENT_RELIABLE_RECEIVERS: RwLock<HashMap<u32, ReliableReceiver<ClientToServerMessage>>> =
+        RwLock::new(HashMap::new());
+    /// Byte budgets and connection quality of remote clients.
+    pub static ref CLIENT_LINKS: RwLock<HashMap<u32, Link>> = RwLock::new(HashMap::new());
+    /// Keys of remote clients that went through the key exchange.
+    pub static ref CLIENT_SESSIONS: RwLock<HashMap<u32, Session>> = RwLock::new(HashMap::new());
+    /// Clients in this very process. Their mailboxes are emptied by the client directly
+    /// instead of going out over the socket.
+    pub static ref LOCAL_CLIENTS: RwLock<HashSet<u32>> = RwLock::new(HashSet::new());
 }
 
 ////////////////////////    CLIENT BOOKKEEPING    ////////////////////////
@@ -38,10 +56,21 @@ pub fn get_next_connection_id() -> u32 {
     NEXT_CONNECTION_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
 }
 
-pub async fn add_client(socket_address: SocketAddr) -> u32 {
+/// `session` is set for clients that went through the key exchange, and everything sent
+/// to them is sealed with it.
+pub async fn add_client(socket_address: SocketAddr, session: Option<Session>) -> u32 {
     let id = get_next_connection_id();
 
-    let mailbox = Arc::new(ArrayQueue::new(100));
+    // Insert into CLIENT_SESSIONS first, so not even the id assignment goes out in the clear
+    if let Some(session) = session {
+        let mut sessions_write = CLIENT_SESSIONS.write().await;
+        sessions_write.insert(id, session);
+    }
+
+    let mailbox = Arc::new(Mailbox::with_backlog_limit(
+        CLIENT_MAILBOX_CAPACITY,
+        CLIENT_BACKLOG_LIMIT,
+    ));
 
     // Insert into CLIENT_OUTBOUND_MAILBOXES
     {
@@ -49,6 +78,20 @@ pub async fn add_client(socket_address: SocketAddr) -> u32 {
         clients_write.insert(id, mailbox);
     }
 
+    // Insert into CLIENT_RELIABLE_SENDERS and CLIENT_RELIABLE_RECEIVERS
+    {
+        let mut senders_write = CLIENT_RELIABLE_SENDERS.write().await;
+        senders_write.insert(id, ReliableSender::new());
+        let mut receivers_write = CLIENT_RELIABLE_RECEIVERS.write().await;
+        receivers_write.insert(id, ReliableReceiver::new());
+    }
+
+    // Insert into CLIENT_LINKS
+    {
+        let mut links_write = CLIENT_LINKS.write().await;
+        links_write.insert(id, Link::new(get_utc_now()));
+    }
+
     // Insert into CLIENT_DISCONNECTED flag map
     {
         let disconnected = Arc::new(AtomicBool::new(false));
@@ -68,18 +111,13 @@ pub async fn add_client(socket_address: SocketAddr) -> u32 {
         socket_address_to_client_id_write.insert(socket_address, id);
     }
 
-    // announce that theres a new connection
+    // announce that theres a new connection, reliable so it is never dropped
     {
         let to_self_message = ClientToServerMessageBundle::new(
             id,
             ClientToServerMessage::new(ClientToServerMessageData::Connect),
         );
-        if INCOMING_MESSAGE_QUEUE.push(to_self_message).is_err() {
-            eprintln!(
-                "Inbound message queue full: dropping disconnect message from {}",
-                id
-            );
-        }
+        let _ = INCOMING_MESSAGE_QUEUE.push(to_self_message);
     }
 
     // tell client his id
@@ -87,12 +125,7 @@ pub async fn add_client(socket_address: SocketAddr) -> u32 {
         let new_id_message = ServerToClientMessage::ClientIDAssignment { new_client_id: id };
         let client_outbound_mailboxes_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
         if let Some(client_mailbox) = client_outbound_mailboxes_read.get(&id) {
-            if client_mailbox.push(new_id_message).is_err() {
-                eprintln!(
-                    "Inbound message queue full: dropping disconnect message from {}",
-                    id
-                );
-            }
+            let _ = client_mailbox.push(new_id_message);
         }
     }
 
@@ -100,6 +133,45 @@ pub async fn add_client(socket_address: SocketAddr) -> u32 {
     id
 }
 
+/// Registers a client running in this process, for listen servers. The returned mailbox
+/// is what the server sends it; it talks back through `INCOMING_MESSAGE_QUEUE`.
+pub async fn add_local_client() -> (u32, ClientMessageQueue) {
+    let id = get_next_connection_id();
+
+    // the host's own client is never cut off for falling behind
+    let mailbox = Arc::new(Mailbox::new(CLIENT_MAILBOX_CAPACITY));
+    {
+        let mut clients_write = CLIENT_OUTBOUND_MAILBOXES.write().await;
+        clients_write.insert(id, mailbox.clone());
+    }
+    {
+        let mut client_status_write = CLIENT_DISCONNECTED.write().await;
+        client_status_write.insert(id, Arc::new(AtomicBool::new(false)));
+    }
+    {
+        let mut local_clients_write = LOCAL_CLIENTS.write().await;
+        local_clients_write.insert(id);
+    }
+
+    // announce that theres a new connection
+    {
+        let to_self_message = ClientToServerMessageBundle::new(
+            id,
+            ClientToServerMessage::new(ClientToServerMessageData::Connect),
+        );
+        let _ = INCOMING_MESSAGE_QUEUE.push(to_self_message);
+    }
+
+    // tell client his id
+    {
+        let new_id_message = ServerToClientMessage::ClientIDAssignment { new_client_id: id };
+        let _ = mailbox.push(new_id_message);
+    }
+
+    println!("New local client. Assigned ID: {}", id);
+    (id, mailbox)
+}
+
 ///  Removes client allocated bookkeeping resources.
 pub async fn remove_client(id: u32) {
     // Remove from CLIENT_OUTBOUND_MAILBOXES
@@ -108,12 +180,34 @@ pub async fn remove_client(id: u32) {
         clients_write.remove(&id);
     }
 
+    // Remove from CLIENT_RELIABLE_SENDERS and CLIENT_RELIABLE_RECEIVERS
+    {
+        let mut senders_write = CLIENT_RELIABLE_SENDERS.write().await;
+        senders_write.remove(&id);
+        let mut receivers_write = CLIENT_RELIABLE_RECEIVERS.write().await;
+        receivers_write.remove(&id);
+    }
+
+    // Remove from CLIENT_LINKS and CLIENT_SESSIONS
+    {
+        let mut links_write = CLIENT_LINKS.write().await;
+        links_write.remove(&id);
+        let mut sessions_write = CLIENT_SESSIONS.write().await;
+        sessions_write.remove(&id);
+    }
+
     // Remove from CLIENT_DISCONNECTED flag map
     {
         let mut client_status_write = CLIENT_DISCONNECTED.write().await;
         client_status_write.remove(&id);
     }
 
+    // a local client has no socket to forget
+    if LOCAL_CLIENTS.write().await.remove(&id) {
+        println!("Local client {} cleaned up.", id);
+        return;
+    }
+
     // Remove from SOCKET_ADDRESS_TO_CLIENT_ID
     {
         // fetch id from SOCKET_ADDRESS_TO_CLIENT_ID
@@ -138,3 +232,86 @@ pub async fn remove_client(id: u32) {
 
     println!("Client {} network resources cleaned up.", id);
 }
+
+/// Cuts off a client whose reliable messages have piled up past the backlog limit. One that
+/// can't keep up would otherwise hold on to more and more of them forever.
+pub async fn disconnect_backlogged_client(id: u32) {
+    if !CLIENT_OUTBOUND_MAILBOXES.read().await.contains_key(&id) {
+        return;
+    }
+    println!("Client {} fell too far behind, disconnecting", id);
+    cut_off(id).await;
+}
+
+/// Cuts off a client that sends faster than the server gets through its messages. One
+/// that keeps it up would otherwise crowd everyone else out of the inbound queues.
+pub async fn disconnect_flooding_client(id: u32) {
+    if !CLIENT_OUTBOUND_MAILBOXES.read().await.contains_key(&id) {
+        return;
+    }
+    println!("Client {} is sending too much, disconnecting", id);
+    cut_off(id).await;
+}
+
+async fn cut_off(id: u32) {
+    remove_client(id).await;
+
+    // whatever they still had waiting goes, so there is room to let the front desk and
+    // their room know they are gone
+    INCOMING_MESSAGE_QUEUE.forget_sender(id);
+    let message_bundle = ClientToServerMessageBundle::new(
+        id,
+        ClientToServerMessage::new(ClientToServerMessageData::Disconnect),
+    );
+    if INCOMING_MESSAGE_QUEUE.push(message_bundle).is_err() {
+        eprintln!("Could not tell the rooms client {} is gone", id);
+    }
+}
+
+/// Moves a client's bookkeeping over to another id, for clients coming back after a
+/// migration under the id they had before.
+pub async fn rekey_client(from: u32, to: u32) {
+    {
+        let mut clients_write = CLIENT_OUTBOUND_MAILBOXES.write().await;
+        if let Some(mailbox) = clients_write.remove(&from) {
+            clients_write.insert(to, mailbox);
+        }
+    }
+    {
+        let mut senders_write = CLIENT_RELIABLE_SENDERS.write().await;
+        if let Some(sender) = senders_write.remove(&from) {
+            senders_write.insert(to, sender);
+        }
+        let mut receivers_write = CLIENT_RELIABLE_RECEIVERS.write().await;
+        if let Some(receiver) = receivers_write.remove(&from) {
+            receivers_write.insert(to, receiver);
+        }
+    }
+    // the transmit task locks links before senders, so never hold both here
+    {
+        let mut links_write = CLIENT_LINKS.write().await;
+        if let Some(link) = links_write.remove(&from) {
+            links_write.insert(to, link);
+        }
+        let mut sessions_write = CLIENT_SESSIONS.write().await;
+        if let Some(session) = sessions_write.remove(&from) {
+            sessions_write.insert(to, session);
+        }
+    }
+    {
+        let mut client_status_write = CLIENT_DISCONNECTED.write().await;
+        if let Some(disconnected) = client_status_write.remove(&from) {
+            client_status_write.insert(to, disconnected);
+        }
+    }
+    {
+        let mut client_socket_addresses_write = CLIENT_ID_TO_SOCKET_ADDRESS.write().await;
+        if let Some(socket_address) = client_socket_addresses_write.remove(&from) {
+            client_socket_addresses_write.insert(to, socket_address);
+            let mut socket_address_to_client_id_write = SOCKET_ADDRESS_TO_CLIENT_ID.write().await;
+            socket_address_to_client_id_write.insert(socket_address, to);
+        }
+    }
+
+    println!("Client {} is back as {}", from, to);
+}
@@ -0,0 +1,115 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use tokio::net::UdpSocket;
+
+use crate::common::{
+    discovery::{DiscoveryQuery, DiscoveryReply},
+    master::ToMasterMessage,
+    network_settings::{
+        master_server_addr, CLIENT_CONNECT_TO_ADDR, DISCOVERY_PORT, MASTER_HEARTBEAT_INTERVAL_MS,
+    },
+};
+
+use super::rooms::PLAYERS_IN_ROOMS;
+
+fn game_port() -> u16 {
+    CLIENT_CONNECT_TO_ADDR
+        .parse::<SocketAddr>()
+        .map(|addr| addr.port())
+        .unwrap_or_default()
+}
+
+/// Answers LAN discovery broadcasts with what the server browser shows.
+pub async fn answer_discovery_queries(name: String, level_id: String) {
+    let socket = match UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!(
+                "Discovery disabled, could not bind port {}: {}",
+                DISCOVERY_PORT, e
+            );
+            return;
+        }
+    };
+    let game_port = game_port();
+    println!("Answering discovery queries on port {}", DISCOVERY_PORT);
+
+    let mut buffer = [0; 256];
+    loop {
+        let (nbytes, from) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!("Discovery receive error: {}", e);
+                continue;
+            }
+        };
+        let Ok(query) = bincode::deserialize::<DiscoveryQuery>(&buffer[..nbytes]) else {
+            continue;
+        };
+
+        let players = PLAYERS_IN_ROOMS.load(std::sync::atomic::Ordering::SeqCst);
+        let reply = DiscoveryReply {
+            send_time: query.send_time,
+            name: name.clone(),
+            players,
+            level_id: level_id.clone(),
+            game_port,
+        };
+        match bincode::serialize(&reply) {
+            Ok(binary_reply) => {
+                if let Err(e) = socket.send_to(&binary_reply, from).await {
+                    eprintln!("Discovery reply to {} failed: {}", from, e);
+                }
+            }
+            Err(e) => eprintln!("Error serializing discovery reply: {:?}", e),
+        }
+    }
+}
+
+/// Heartbeats the master server so internet players can find us. The first one registers.
+pub async fn register_with_master(name: String, level_id: String) {
+    let master_server_addr = master_server_addr();
+    let master_addr = match master_server_addr
+        .to_socket_addrs()
+        .map(|mut addrs| addrs.next())
+    {
+        Ok(Some(addr)) => addr,
+        _ => {
+            eprintln!(
+                "Master server registration disabled, could not resolve {}",
+                master_server_addr
+            );
+            return;
+        }
+    };
+    let socket = match UdpSocket::bind("0.0.0.0:0").await {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("Master server registration disabled: {}", e);
+            return;
+        }
+    };
+    println!("Registering with master server {}", master_addr);
+
+    let game_port = game_port();
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(
+        MASTER_HEARTBEAT_INTERVAL_MS,
+    ));
+    loop {
+        interval.tick().await;
+        let heartbeat = ToMasterMessage::Heartbeat {
+            name: name.clone(),
+            game_port,
+            players: PLAYERS_IN_ROOMS.load(std::sync::atomic::Ordering::SeqCst),
+            level_id: level_id.clone(),
+        };
+        match bincode::serialize(&heartbeat) {
+            Ok(binary_heartbeat) => {
+                if let Err(e) = socket.send_to(&binary_heartbeat, master_addr).await {
+                    eprintln!("Heartbeat to master server failed: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Error serializing heartbeat: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::common::{
+    game_objects::{PlayerStats, ENEMY_RADIUS, PLAYER_RADIUS},
+    server_to_client::ServerToClientMessage,
+};
+
+use super::{
+    settings::{ENEMY_ATTACK_COOLDOWN, ENEMY_CONTACT_DAMAGE, ENEMY_SPEED, MAX_HIT_DAMAGE},
+    state::State,
+};
+
+/// Enemies walk at the nearest standing player and hurt whoever they touch.
+pub fn step_enemies(state: &mut State) {
+    let mut moved = Vec::new();
+    for enemy in state.enemies.values_mut() {
+        enemy.attack_cooldown = enemy.attack_cooldown.saturating_sub(1);
+
+        let Some(target) = state
+            .players
+            .values_mut()
+            .filter(|player| !player.downed)
+            .min_by(|a, b| {
+                a.pos
+                    .distance_squared(enemy.pos)
+                    .total_cmp(&b.pos.distance_squared(enemy.pos))
+            })
+        else {
+            continue;
+        };
+
+        let to_target = target.pos - enemy.pos;
+        let touching = to_target.length() <= PLAYER_RADIUS + ENEMY_RADIUS;
+        if !touching {
+            enemy.pos += to_target.normalize_or_zero() * ENEMY_SPEED;
+            moved.push(enemy.entity_id);
+            continue;
+        }
+        if enemy.attack_cooldown > 0 {
+            continue;
+        }
+
+        enemy.attack_cooldown = ENEMY_ATTACK_COOLDOWN;
+        target.hp = target.hp.saturating_sub(ENEMY_CONTACT_DAMAGE);
+        if target.hp == 0 {
+            target.downed = true;
+            state.stats_dirty = true;
+            state
+                .stats
+                .entry(target.owner_client_id)
+                .or_insert_with(|| PlayerStats::new(target.owner_client_id))
+                .deaths += 1;
+        }
+        state.outbox.push(ServerToClientMessage::PlayerHealth {
+            entity_id: target.entity_id,
+            hp: target.hp,
+            downed: target.downed,
+        });
+    }
+
+    for eid in moved {
+        let pos = state.push_out_of_walls(state.enemies[&eid].pos, ENEMY_RADIUS);
+        if let Some(enemy) = state.enemies.get_mut(&eid) {
+            enemy.pos = pos;
+        }
+    }
+}
+
+/// Applies a hit a player landed, crediting their owner with the damage and the kill.
+pub fn hit_enemy(state: &mut State, client_id: u32, entity_id: u32, damage: u32) {
+    let Some(enemy) = state.enemies.get_mut(&entity_id) else {
+        return;
+    };
+    let dealt = damage.min(MAX_HIT_DAMAGE).min(enemy.hp);
+    enemy.hp -= dealt;
+    let killed = enemy.hp == 0;
+    if killed {
+        state.enemies.remove(&entity_id);
+    }
+
+    let stats = state.stats_mut(client_id);
+    stats.damage_dealt += dealt;
+    if killed {
+        stats.kills += 1;
+        state
+            .outbox
+            .push(ServerToClientMessage::DespawnEntity { entity_id });
+    }
+}
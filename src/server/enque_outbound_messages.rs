@@ -1,36 +1,73 @@
-use crate::common::server_to_client::ServerToClientMessage;
+use std::collections::BTreeSet;
 
-use super::client_bookkeeping::CLIENT_OUTBOUND_MAILBOXES;
+use crate::common::{mailbox::Overflow, server_to_client::ServerToClientMessage};
+
+use super::client_bookkeeping::{
+    disconnect_backlogged_client, ClientMessageQueue, CLIENT_OUTBOUND_MAILBOXES,
+};
 
 ////////////////////////    ENQUEUE OUTBOUND MESSAGES    ////////////////////////
+/// Returns false if the client has fallen too far behind and has to go. Dropped
+/// unreliable messages are counted by the mailbox.
+fn enqueue(queue: &ClientMessageQueue, message: ServerToClientMessage) -> bool {
+    !matches!(queue.push(message), Err(Overflow::Backlogged))
+}
+
 pub async fn send_to_one_client(client_id: u32, message: ServerToClientMessage) {
-    let clients_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
-    if let Some(queue) = clients_read.get(&client_id) {
-        if queue.push(message).is_err() {
-            eprintln!("Failed to enqueue message for client {}", client_id);
+    let backlogged = {
+        let clients_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
+        match clients_read.get(&client_id) {
+            Some(queue) => !enqueue(queue, message),
+            None => {
+                eprintln!("Failed to find client {}", client_id);
+                false
+            }
         }
-    } else {
-        eprintln!("Failed to find client {}", client_id);
+    };
+    if backlogged {
+        disconnect_backlogged_client(client_id).await;
     }
 }
 
-pub async fn broadcast_to_all_except(sender_id: u32, message: ServerToClientMessage) {
-    let clients_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
-    for (&client_id, queue) in clients_read.iter() {
-        if client_id == sender_id {
-            continue; // Skip the sender
-        }
-        if queue.push(message.clone()).is_err() {
-            eprintln!("Failed to enqueue message for client {}", client_id);
+/// Sends to every member of a room except the sender.
+pub async fn broadcast_to_room_except(
+    members: &BTreeSet<u32>,
+    sender_id: u32,
+    message: ServerToClientMessage,
+) {
+    let mut backlogged = Vec::new();
+    {
+        let clients_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
+        for client_id in members {
+            if *client_id == sender_id {
+                continue; // Skip the sender
+            }
+            if let Some(queue) = clients_read.get(client_id) {
+                if !enqueue(queue, message.clone()) {
+                    backlogged.push(*client_id);
+                }
+            }
         }
     }
+    for client_id in backlogged {
+        disconnect_backlogged_client(client_id).await;
+    }
 }
 
-pub async fn broadcast_to_all(message: ServerToClientMessage) {
-    let clients_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
-    for (_, queue) in clients_read.iter() {
-        if queue.push(message.clone()).is_err() {
-            eprintln!("Failed to enqueue message for client");
+/// Sends to every member of a room.
+pub async fn broadcast_to_room(members: &BTreeSet<u32>, message: ServerToClientMessage) {
+    let mut backlogged = Vec::new();
+    {
+        let clients_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
+        for client_id in members {
+            if let Some(queue) = clients_read.get(client_id) {
+                if !enqueue(queue, message.clone()) {
+                    backlogged.push(*client_id);
+                }
+            }
         }
     }
+    for client_id in backlogged {
+        disconnect_backlogged_client(client_id).await;
+    }
 }
@@ -1,30 +1,344 @@
-use std::time::Instant;
+use std::sync::Arc;
 
-use super::{message_processing::process_message_queue, state::State};
+use crate::common::{
+    checksum::world_checksum, game_objects::Item, level::Tile,
+    server_to_client::ServerToClientMessage, tick::FixedTick,
+};
 
-pub const FRAMES_PER_SECOND: u32 = 60;
-const TIMESTEP: f32 = 1.0 / FRAMES_PER_SECOND as f32;
+use super::{
+    enque_outbound_messages::broadcast_to_room,
+    lag_compensation::record_history,
+    match_phase::step_match,
+    message_processing::process_message_queue,
+    migration::share_with_successor,
+    relevancy::send_snapshots,
+    replay::{record_keyframe, update_recording},
+    rooms::Room,
+    settings::{CHECKSUM_INTERVAL_STEPS, MIGRATION_INTERVAL_STEPS, REPLAY_KEYFRAME_INTERVAL_STEPS},
+    state::State,
+};
 
-pub async fn main_loop(state: &mut State) {
-    let mut previous_time = Instant::now();
-    loop {
-        process_message_queue(state).await;
+/// Runs one room's world until the room is closed.
+pub async fn main_loop(state: &mut State, room: Arc<Room>) {
+    let mut tick = FixedTick::new();
+    while !room.closed.load(std::sync::atomic::Ordering::SeqCst) {
+        process_message_queue(state, &room.inbox).await;
+        update_recording(state);
 
-        let current_time = Instant::now();
-        let dt = (current_time - previous_time).as_secs_f32();
-        previous_time = current_time;
+        let mut share = false;
+        let due = tick.due();
+        for _ in 0..due {
+            step(state);
+            share |= state.step_count.is_multiple_of(MIGRATION_INTERVAL_STEPS);
+            if state
+                .step_count
+                .is_multiple_of(REPLAY_KEYFRAME_INTERVAL_STEPS)
+            {
+                record_keyframe(state);
+            }
+            if state.step_count.is_multiple_of(CHECKSUM_INTERVAL_STEPS) {
+                push_checksum(state);
+            }
+        }
 
-        state.time_since_last_update += dt;
-        while state.time_since_last_update > TIMESTEP {
-            state.time_since_last_update -= TIMESTEP;
+        flush_outbox(state).await;
+        if due > 0 {
+            send_snapshots(state).await;
+        }
+        if share {
+            share_with_successor(state).await;
+        }
 
-            step(state);
+        let info = state.room_info();
+        if *room.info.read().await != info {
+            *room.info.write().await = info;
         }
+
+        // leave the runtime to the other rooms until there is something to do
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
     }
+    println!("room {} closed", state.code);
 }
 
 pub fn step(state: &mut State) {
     for (_, player) in state.players.iter_mut() {
         player.step();
     }
+    step_match(state);
+    state.step_count += 1;
+    record_history(state);
+}
+
+/// Lets clients check that they still see the same world. Queued behind everything the
+/// step produced, so a client that is caught up has seen all of it.
+fn push_checksum(state: &mut State) {
+    let checksum = world_checksum(state.waves.number, &state.entity_digests());
+    state.outbox.push(ServerToClientMessage::WorldChecksum {
+        step: state.step_count,
+        checksum,
+    });
+}
+
+/// Sends out everything the steps produced, plus the scoreboard if it changed.
+pub async fn flush_outbox(state: &mut State) {
+    if state.stats_dirty {
+        state.stats_dirty = false;
+        let stats = state.scoreboard();
+        state
+            .outbox
+            .push(ServerToClientMessage::Scoreboard { stats });
+    }
+    for message in state.outbox.drain(..) {
+        broadcast_to_room(&state.members, message).await;
+    }
+}
+
+/// Loose items lying on the level's pickup tiles at startup.
+pub fn spawn_items(state: &mut State) {
+    for pos in state.level.tile_centers(Tile::Pickup) {
+        let eid = state.take_eid();
+        state.items.insert(eid, Item::new(eid, pos));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::Vec2;
+
+    use crate::{
+        common::{
+            client_to_server::{ClientToServerMessageBundle, ClientToServerMessageData},
+            game_objects::{
+                ITEM_DRAG, ITEM_HALF_EXTENT, PLAYER_RADIUS, PROJECTILE_SPEED, THROW_SPEED,
+            },
+            level::Level,
+            mailbox::Mailbox,
+        },
+        server::settings::GRAB_SLACK,
+    };
+
+    use super::{process_message_queue, spawn_items, step, State};
+
+    const HUMANS: [u32; 2] = [1000, 1001];
+    const STEPS: u32 = 1500;
+
+    type InputLog = Vec<(u32, ClientToServerMessageBundle)>;
+
+    fn bundle(client_id: u32, message: ClientToServerMessageData) -> ClientToServerMessageBundle {
+        ClientToServerMessageBundle {
+            client_id,
+            send_time: 0,
+            received_time: 0,
+            message,
+        }
+    }
+
+    /// What the two humans do this step: join, get ready, start, then run in circles and
+    /// shoot whatever is closest, when it is close enough to hit.
+    fn play(state: &State) -> Vec<ClientToServerMessageBundle> {
+        let step = state.step_count;
+        let mut inputs = Vec::new();
+        for (index, client_id) in HUMANS.into_iter().enumerate() {
+            match step {
+                0 => {
+                    inputs.push(bundle(client_id, ClientToServerMessageData::Connect));
+                    let name = format!("human{}", index);
+                    inputs.push(bundle(client_id, ClientToServerMessageData::Join { name }));
+                    let ready = ClientToServerMessageData::SetReady { ready: true };
+                    inputs.push(bundle(client_id, ready));
+                }
+                1 if index == 0 => {
+                    inputs.push(bundle(client_id, ClientToServerMessageData::StartMatch))
+                }
+                _ => {}
+            }
+            let Some(player) = state
+                .player_of_client(client_id)
+                .and_then(|eid| state.players.get(&eid))
+            else {
+                continue;
+            };
+            let angle = step as f32 * 0.05 + index as f32;
+            let pos = player.pos + Vec2::from_angle(angle) * 1.5;
+            let moved = ClientToServerMessageData::EntityPosition {
+                entity_id: player.entity_id,
+                pos,
+            };
+            inputs.push(bundle(client_id, moved));
+            if step.is_multiple_of(10) {
+                if let Some(enemy) = state.enemies.values().min_by(|a, b| {
+                    a.pos
+                        .distance(player.pos)
+                        .total_cmp(&b.pos.distance(player.pos))
+                }) {
+                    let aim = (enemy.pos - player.pos).normalize_or_zero();
+                    if aim == Vec2::ZERO || enemy.pos.distance(player.pos) > 80.0 {
+                        continue;
+                    }
+                    let fire = ClientToServerMessageData::Fire {
+                        pos: player.pos,
+                        vel: aim * PROJECTILE_SPEED,
+                    };
+                    inputs.push(bundle(client_id, fire));
+                    let hit = ClientToServerMessageData::HitEnemy {
+                        entity_id: enemy.entity_id,
+                        damage: 10,
+                        pos: enemy.pos,
+                        view_step: step,
+                    };
+                    inputs.push(bundle(client_id, hit));
+                }
+            }
+        }
+        inputs
+    }
+
+    /// Runs a room for `STEPS` steps, fed from `log` or, without one, from `play`.
+    /// Returns the inputs it used and the world hash after every step.
+    async fn run(log: Option<&InputLog>) -> (InputLog, Vec<u64>) {
+        let level = Level::parse("arena", include_str!("../../levels/arena.txt")).unwrap();
+        let mut state = State::new("SEED".to_string(), level);
+        spawn_items(&mut state);
+        let inbox = Arc::new(Mailbox::new(1));
+
+        let mut used = InputLog::new();
+        let mut hashes = Vec::new();
+        for _ in 0..STEPS {
+            let inputs = match log {
+                Some(log) => log
+                    .iter()
+                    .filter(|(step, _)| *step == state.step_count)
+                    .map(|(_, bundle)| bundle.clone())
+                    .collect(),
+                None => play(&state),
+            };
+            for bundle in inputs {
+                used.push((state.step_count, bundle.clone()));
+                inbox.push(bundle).unwrap();
+                process_message_queue(&mut state, &inbox).await;
+            }
+            step(&mut state);
+            state.outbox.clear();
+            hashes.push(state.world_hash());
+        }
+        (used, hashes)
+    }
+
+    #[tokio::test]
+    async fn same_inputs_give_the_same_world_every_step() {
+        let (log, live) = run(None).await;
+        let (_, first) = run(Some(&log)).await;
+        let (_, second) = run(Some(&log)).await;
+
+        for (step, ((live, first), second)) in live.iter().zip(&first).zip(&second).enumerate() {
+            assert_eq!(
+                first, second,
+                "replays of the same log split at step {}",
+                step
+            );
+            assert_eq!(
+                live, first,
+                "replay split from the live run at step {}",
+                step
+            );
+        }
+        // the world has to have actually done something for this to mean anything
+        assert!(live.windows(2).filter(|pair| pair[0] != pair[1]).count() > STEPS as usize / 2);
+    }
+
+    #[tokio::test]
+    async fn items_are_only_grabbed_in_reach_and_thrown_so_far() {
+        let level = Level::parse("arena", include_str!("../../levels/arena.txt")).unwrap();
+        let mut state = State::new("SEED".to_string(), level);
+        spawn_items(&mut state);
+        let inbox = Arc::new(Mailbox::new(1));
+        let send = |client_id, message| {
+            inbox.push(bundle(client_id, message)).unwrap();
+        };
+        send(HUMANS[0], ClientToServerMessageData::Connect);
+        let name = "human".to_string();
+        send(HUMANS[0], ClientToServerMessageData::Join { name });
+        send(
+            HUMANS[0],
+            ClientToServerMessageData::SetReady { ready: true },
+        );
+        send(HUMANS[0], ClientToServerMessageData::StartMatch);
+        process_message_queue(&mut state, &inbox).await;
+        let eid = state.player_of_client(HUMANS[0]).unwrap();
+        let (&entity_id, item) = state.items.iter().next().unwrap();
+        let item_pos = item.pos;
+
+        // from across the map
+        state.players.get_mut(&eid).unwrap().pos = item_pos + Vec2::new(200.0, 0.0);
+        send(
+            HUMANS[0],
+            ClientToServerMessageData::RequestGrab { entity_id },
+        );
+        process_message_queue(&mut state, &inbox).await;
+        assert_eq!(state.items[&entity_id].held_by, None);
+
+        state.players.get_mut(&eid).unwrap().pos = item_pos + Vec2::new(20.0, 0.0);
+        send(
+            HUMANS[0],
+            ClientToServerMessageData::RequestGrab { entity_id },
+        );
+        process_message_queue(&mut state, &inbox).await;
+        assert_eq!(state.items[&entity_id].held_by, Some(eid));
+
+        // let go of far away and thrown hard, it still lands near the holder
+        let holder_pos = state.players[&eid].pos;
+        let release = ClientToServerMessageData::ReleaseItem {
+            entity_id,
+            pos: holder_pos + Vec2::new(0.0, -300.0),
+            vel: Vec2::new(0.0, -100.0),
+        };
+        send(HUMANS[0], release);
+        process_message_queue(&mut state, &inbox).await;
+        let item = &state.items[&entity_id];
+        assert_eq!(item.held_by, None);
+        let most = PLAYER_RADIUS + ITEM_HALF_EXTENT + GRAB_SLACK + THROW_SPEED / (1.0 - ITEM_DRAG);
+        assert!(item.pos.distance(holder_pos) <= most + 0.5);
+        assert!(item.pos.y < holder_pos.y);
+    }
+
+    #[tokio::test]
+    async fn whoever_leaves_mid_match_leaves_their_player_to_a_bot() {
+        let level = Level::parse("arena", include_str!("../../levels/arena.txt")).unwrap();
+        let mut state = State::new("SEED".to_string(), level);
+        let inbox = Arc::new(Mailbox::new(1));
+        let send = |client_id, message| {
+            inbox.push(bundle(client_id, message)).unwrap();
+        };
+        for (index, client_id) in HUMANS.into_iter().enumerate() {
+            send(client_id, ClientToServerMessageData::Connect);
+            let name = format!("human{}", index);
+            send(client_id, ClientToServerMessageData::Join { name });
+            send(
+                client_id,
+                ClientToServerMessageData::SetReady { ready: true },
+            );
+        }
+        send(HUMANS[0], ClientToServerMessageData::StartMatch);
+        process_message_queue(&mut state, &inbox).await;
+        let players = state.players.len();
+        let left_behind = state.player_of_client(HUMANS[1]).unwrap();
+
+        send(HUMANS[1], ClientToServerMessageData::Disconnect);
+        process_message_queue(&mut state, &inbox).await;
+        assert_eq!(state.players.len(), players);
+        let heir = state.players[&left_behind].owner_client_id;
+        assert!(state.roster[&heir].bot);
+
+        // with nobody left to play with, there is no bot to take it
+        let last = state.player_of_client(HUMANS[0]).unwrap();
+        send(HUMANS[0], ClientToServerMessageData::Disconnect);
+        process_message_queue(&mut state, &inbox).await;
+        assert!(!state.players.contains_key(&last));
+        assert!(state
+            .players
+            .values()
+            .all(|player| state.roster.contains_key(&player.owner_client_id)));
+    }
 }
@@ -0,0 +1,323 @@
+use std::collections::VecDeque;
+
+use glam::Vec2;
+
+use crate::common::{
+    game_objects::{ENEMY_RADIUS, FIRE_COOLDOWN, PROJECTILE_SPEED},
+    migration::GunfireSnapshot,
+};
+
+use super::{
+    settings::{
+        FIRE_BURST, LAG_COMPENSATION_HIT_SLACK, LAG_COMPENSATION_MAX_REWIND_STEPS,
+        SHOT_LIFETIME_STEPS,
+    },
+    state::State,
+};
+
+/// Where every enemy was at the end of one step.
+struct Frame {
+    step: u32,
+    enemies: Vec<(u32, Vec2)>,
+}
+
+/// Enemy positions over the last few steps, so a hit can be checked against the world
+/// the shooter was looking at rather than the one the server has moved on to.
+pub struct History {
+    frames: VecDeque<Frame>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Remembers the enemies as of `step`, forgetting anything older than the rewind cap.
+    pub fn record(&mut self, step: u32, enemies: Vec<(u32, Vec2)>) {
+        self.frames.push_back(Frame { step, enemies });
+        while self.frames.len() > LAG_COMPENSATION_MAX_REWIND_STEPS as usize + 1 {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Every remembered step, oldest first, as `record` took them.
+    pub fn frames(&self) -> Vec<(u32, Vec<(u32, Vec2)>)> {
+        self.frames
+            .iter()
+            .map(|frame| (frame.step, frame.enemies.clone()))
+            .collect()
+    }
+
+    /// Where the enemy was at the end of `step`, if it existed then and that is still
+    /// remembered.
+    pub fn position_at(&self, entity_id: u32, step: u32) -> Option<Vec2> {
+        let frame = self.frames.iter().rev().find(|frame| frame.step == step)?;
+        frame
+            .enemies
+            .iter()
+            .find(|(id, _)| *id == entity_id)
+            .map(|(_, pos)| *pos)
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A shot a client told the server about, as of the step the server heard of it.
+struct Shot {
+    step: u32,
+    pos: Vec2,
+    dir: Vec2,
+}
+
+/// Shots one client fired lately, which are all its hits can come from. Firing is a token
+/// bucket: `FIRE_BURST` shots at once, then one per `FIRE_COOLDOWN` steps, so shots that
+/// arrive bunched up still count but a faster trigger gets nowhere.
+pub struct Gunfire {
+    shots: VecDeque<Shot>,
+    tokens: u32,
+    last_refill: u32,
+}
+
+impl Gunfire {
+    pub fn new(step: u32) -> Self {
+        Self {
+            shots: VecDeque::new(),
+            tokens: FIRE_BURST,
+            last_refill: step,
+        }
+    }
+
+    pub fn to_snapshot(&self, client_id: u32) -> GunfireSnapshot {
+        GunfireSnapshot {
+            client_id,
+            tokens: self.tokens,
+            last_refill: self.last_refill,
+            shots: self
+                .shots
+                .iter()
+                .map(|shot| (shot.step, shot.pos, shot.dir))
+                .collect(),
+        }
+    }
+
+    pub fn from_snapshot(snapshot: &GunfireSnapshot) -> Self {
+        Self {
+            shots: snapshot
+                .shots
+                .iter()
+                .map(|&(step, pos, dir)| Shot { step, pos, dir })
+                .collect(),
+            tokens: snapshot.tokens,
+            last_refill: snapshot.last_refill,
+        }
+    }
+
+    /// Records a shot fired from `pos` as of `step`. False if it came too soon after the
+    /// last ones or flies faster than any weapon shoots.
+    pub fn fire(&mut self, step: u32, pos: Vec2, vel: Vec2) -> bool {
+        let refills = step.saturating_sub(self.last_refill) / FIRE_COOLDOWN;
+        if refills > 0 {
+            self.tokens = (self.tokens + refills).min(FIRE_BURST);
+            self.last_refill += refills * FIRE_COOLDOWN;
+        }
+        let dir = vel.normalize_or_zero();
+        if self.tokens == 0 || dir == Vec2::ZERO || vel.length() > PROJECTILE_SPEED * 1.01 {
+            return false;
+        }
+        self.tokens -= 1;
+        self.shots.push_back(Shot { step, pos, dir });
+        true
+    }
+
+    /// Uses up the shot that could have reached `pos` by `step`, if there is one. Shots
+    /// stop at the first enemy they touch, so none of them hits twice.
+    pub fn take_shot_through(&mut self, step: u32, pos: Vec2) -> bool {
+        while self
+            .shots
+            .front()
+            .is_some_and(|shot| step.saturating_sub(shot.step) > SHOT_LIFETIME_STEPS)
+        {
+            self.shots.pop_front();
+        }
+        let reaches = |shot: &Shot| {
+            // the hit is reported a while after the shot is, but by how long depends on
+            // both trips over the network, so it gets as much leeway as a rewind does
+            let steps = step.saturating_sub(shot.step) + LAG_COMPENSATION_MAX_REWIND_STEPS;
+            let to_hit = pos - shot.pos;
+            let along = to_hit.dot(shot.dir);
+            let across = to_hit.perp_dot(shot.dir).abs();
+            along >= -LAG_COMPENSATION_HIT_SLACK
+                && along <= steps as f32 * PROJECTILE_SPEED + LAG_COMPENSATION_HIT_SLACK
+                && across <= LAG_COMPENSATION_HIT_SLACK
+        };
+        match self.shots.iter().position(reaches) {
+            Some(index) => {
+                self.shots.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Stores this step's enemy positions. Called at the end of every step.
+pub fn record_history(state: &mut State) {
+    let enemies = state
+        .enemies
+        .values()
+        .map(|enemy| (enemy.entity_id, enemy.pos))
+        .collect();
+    state.history.record(state.step_count, enemies);
+}
+
+/// Step to rewind to for a client that saw the world as of `view_step`. Never into the
+/// future, and never further back than the cap, so a client claiming to lag badly gains
+/// nothing by it.
+pub fn rewind_step(state: &State, view_step: u32) -> u32 {
+    let oldest = state
+        .step_count
+        .saturating_sub(LAG_COMPENSATION_MAX_REWIND_STEPS);
+    view_step.clamp(oldest, state.step_count)
+}
+
+/// Whether a shot at `pos` could have touched the enemy where the shooter saw it, and came
+/// from a shot the client actually fired, which it uses up. Enemies the history does not
+/// know about yet are checked where they are now.
+pub fn confirm_hit(
+    state: &mut State,
+    client_id: u32,
+    entity_id: u32,
+    view_step: u32,
+    pos: Vec2,
+) -> bool {
+    let step = rewind_step(state, view_step);
+    let Some(target) = state
+        .history
+        .position_at(entity_id, step)
+        .or_else(|| state.enemies.get(&entity_id).map(|enemy| enemy.pos))
+    else {
+        return false;
+    };
+    if target.distance(pos) > ENEMY_RADIUS + LAG_COMPENSATION_HIT_SLACK {
+        return false;
+    }
+    let step_count = state.step_count;
+    state
+        .gunfire
+        .get_mut(&client_id)
+        .is_some_and(|gunfire| gunfire.take_shot_through(step_count, pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use crate::{
+        common::{
+            game_objects::{FIRE_COOLDOWN, PROJECTILE_SPEED},
+            level::Level,
+        },
+        server::{
+            settings::{FIRE_BURST, LAG_COMPENSATION_MAX_REWIND_STEPS},
+            state::State,
+        },
+    };
+
+    use super::{confirm_hit, rewind_step, Gunfire, History};
+
+    const SHOOTER: u32 = 1000;
+    const ENEMY: u32 = 7;
+
+    fn state_at(step_count: u32) -> State {
+        let level = Level::parse("arena", include_str!("../../levels/arena.txt")).unwrap();
+        let mut state = State::new("SEED".to_string(), level);
+        state.step_count = step_count;
+        state
+    }
+
+    #[test]
+    fn history_remembers_positions_by_step() {
+        let mut history = History::new();
+        for step in 0..=LAG_COMPENSATION_MAX_REWIND_STEPS + 5 {
+            history.record(step, vec![(ENEMY, Vec2::new(step as f32, 0.0))]);
+        }
+        let newest = LAG_COMPENSATION_MAX_REWIND_STEPS + 5;
+        assert_eq!(
+            history.position_at(ENEMY, newest),
+            Some(Vec2::new(newest as f32, 0.0))
+        );
+        assert_eq!(history.position_at(ENEMY, 6), Some(Vec2::new(6.0, 0.0)));
+        // too old to be kept, and never there at all
+        assert_eq!(history.position_at(ENEMY, 4), None);
+        assert_eq!(history.position_at(ENEMY + 1, newest), None);
+    }
+
+    #[test]
+    fn rewinds_are_clamped_to_the_cap_and_the_present() {
+        let state = state_at(100);
+        assert_eq!(rewind_step(&state, 90), 90);
+        assert_eq!(
+            rewind_step(&state, 0),
+            100 - LAG_COMPENSATION_MAX_REWIND_STEPS
+        );
+        assert_eq!(rewind_step(&state, 500), 100);
+        assert_eq!(rewind_step(&state_at(3), 0), 0);
+    }
+
+    #[test]
+    fn hits_need_a_shot_that_reaches_the_target() {
+        let mut state = state_at(100);
+        let target = Vec2::new(200.0, 100.0);
+        state.history.record(95, vec![(ENEMY, target)]);
+
+        // nothing was fired
+        assert!(!confirm_hit(&mut state, SHOOTER, ENEMY, 95, target));
+
+        let mut gunfire = Gunfire::new(90);
+        assert!(gunfire.fire(90, Vec2::new(150.0, 100.0), Vec2::X * PROJECTILE_SPEED));
+        state.gunfire.insert(SHOOTER, gunfire);
+        // nowhere near the enemy as it was then, or not on the shot's path
+        assert!(!confirm_hit(
+            &mut state,
+            SHOOTER,
+            ENEMY,
+            95,
+            target + Vec2::Y * 40.0
+        ));
+        assert!(!confirm_hit(&mut state, SHOOTER + 1, ENEMY, 95, target));
+
+        assert!(confirm_hit(&mut state, SHOOTER, ENEMY, 95, target));
+        // the shot is spent
+        assert!(!confirm_hit(&mut state, SHOOTER, ENEMY, 95, target));
+    }
+
+    #[test]
+    fn shots_fly_only_so_far() {
+        let mut gunfire = Gunfire::new(0);
+        assert!(gunfire.fire(0, Vec2::ZERO, Vec2::X * PROJECTILE_SPEED));
+        let reach = (10 + LAG_COMPENSATION_MAX_REWIND_STEPS) as f32 * PROJECTILE_SPEED;
+        assert!(!gunfire.take_shot_through(10, Vec2::X * (reach + 20.0)));
+        assert!(!gunfire.take_shot_through(10, Vec2::NEG_X * 20.0));
+        assert!(gunfire.take_shot_through(10, Vec2::X * reach));
+    }
+
+    #[test]
+    fn firing_is_rate_limited() {
+        let mut gunfire = Gunfire::new(0);
+        let vel = Vec2::X * PROJECTILE_SPEED;
+        for _ in 0..FIRE_BURST {
+            assert!(gunfire.fire(0, Vec2::ZERO, vel));
+        }
+        assert!(!gunfire.fire(0, Vec2::ZERO, vel));
+        assert!(gunfire.fire(FIRE_COOLDOWN, Vec2::ZERO, vel));
+        assert!(!gunfire.fire(FIRE_COOLDOWN, Vec2::ZERO, vel));
+        // faster than any weapon shoots
+        assert!(!Gunfire::new(0).fire(0, Vec2::ZERO, vel * 2.0));
+    }
+}
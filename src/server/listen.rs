@@ -0,0 +1,49 @@
+use std::net::SocketAddr;
+
+use tokio::io;
+
+use crate::common::{level::Level, migration::RoomSnapshot};
+
+use super::{rooms::dispatch_loop, udp_networking};
+
+/// Runs a server inside whatever process calls this, such as a client taking over a
+/// session whose server went away. Returns the address it listens on.
+pub async fn start(
+    bind_addr: SocketAddr,
+    level: Level,
+    restored: Option<RoomSnapshot>,
+    local_client: Option<u32>,
+) -> io::Result<SocketAddr> {
+    let local_addr = bind(bind_addr).await?;
+    serve(level, restored, local_client);
+    Ok(local_addr)
+}
+
+/// Opens the server's socket, so a client that wants to join its own server only
+/// connects once it knows there will be one. Returns the address it listens on.
+pub async fn bind(bind_addr: SocketAddr) -> io::Result<SocketAddr> {
+    udp_networking::init(bind_addr).await
+}
+
+/// Starts handing out rooms on the socket `bind` opened.
+pub fn serve(level: Level, restored: Option<RoomSnapshot>, local_client: Option<u32>) {
+    tokio::spawn(dispatch_loop(level, restored, local_client));
+}
+
+/// Starts a server that carries on the room in `snapshot`, for the client `local_client`
+/// who was named successor.
+pub async fn resume(
+    bind_addr: SocketAddr,
+    snapshot: RoomSnapshot,
+    local_client: u32,
+) -> io::Result<SocketAddr> {
+    let level = Level::load(&snapshot.level_id)
+        .map_err(|e| io::Error::other(format!("loading {}: {}", snapshot.level_id, e)))?;
+    if level.checksum != snapshot.level_checksum {
+        return Err(io::Error::other(format!(
+            "level {} differs from the one the room was using",
+            snapshot.level_id
+        )));
+    }
+    start(bind_addr, level, Some(snapshot), Some(local_client)).await
+}
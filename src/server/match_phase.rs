@@ -0,0 +1,143 @@
+use crate::common::{
+    game_objects::{MatchPhase, Player, PlayerStats},
+    server_to_client::ServerToClientMessage,
+};
+
+use super::{
+    bots::step_bots,
+    enemies::step_enemies,
+    game::spawn_items,
+    settings::{RESULTS_STEPS, REVIVE_HP, REVIVE_RANGE},
+    state::State,
+    waves::{step_waves, Waves},
+};
+
+pub fn step_match(state: &mut State) {
+    state.phase_steps += 1;
+    match state.phase {
+        MatchPhase::Lobby => {}
+        MatchPhase::InGame => {
+            step_bots(state);
+            step_enemies(state);
+            step_waves(state);
+        }
+        MatchPhase::Results => {
+            if state.phase_steps >= RESULTS_STEPS {
+                return_to_lobby(state);
+            }
+        }
+    }
+}
+
+fn set_phase(state: &mut State, phase: MatchPhase) {
+    println!("match phase: {:?}", phase);
+    state.phase = phase;
+    state.phase_steps = 0;
+    state
+        .outbox
+        .push(ServerToClientMessage::MatchPhaseChanged { phase });
+}
+
+/// Everyone in the roster gets a player and the first wave starts counting down.
+pub fn start_match(state: &mut State) {
+    let client_ids: Vec<u32> = state.roster.keys().copied().collect();
+    for client_id in client_ids {
+        spawn_player_for(state, client_id);
+    }
+    state.waves = Waves::new();
+    set_phase(state, MatchPhase::InGame);
+}
+
+pub fn end_match(state: &mut State) {
+    set_phase(state, MatchPhase::Results);
+}
+
+/// Clears the world and the scores and unreadies everyone for the next match.
+pub fn return_to_lobby(state: &mut State) {
+    let despawned: Vec<u32> = state
+        .players
+        .keys()
+        .chain(state.enemies.keys())
+        .chain(state.items.keys())
+        .copied()
+        .collect();
+    for entity_id in despawned {
+        state
+            .outbox
+            .push(ServerToClientMessage::DespawnEntity { entity_id });
+    }
+    state.players.clear();
+    state.enemies.clear();
+    state.items.clear();
+
+    spawn_items(state);
+    for item in state.items.values() {
+        state.outbox.push(ServerToClientMessage::SpawnItem {
+            entity_id: item.entity_id,
+            pos: item.pos,
+        });
+    }
+
+    state.waves = Waves::new();
+    state.stats = state
+        .roster
+        .keys()
+        .map(|&client_id| (client_id, PlayerStats::new(client_id)))
+        .collect();
+    state.stats_dirty = true;
+
+    state.bots.shots.clear();
+    for entry in state.roster.values_mut() {
+        entry.ready = entry.bot;
+    }
+    let roster = state.roster_message();
+    state.outbox.push(roster);
+    set_phase(state, MatchPhase::Lobby);
+}
+
+/// Spawns a player for someone, e.g. at match start or when dropping into a running match.
+pub fn spawn_player_for(state: &mut State, client_id: u32) {
+    if state.player_of_client(client_id).is_some() {
+        return;
+    }
+    let eid = state.take_eid();
+    let pos = state.player_spawn_point();
+    let mut player = Player::new(client_id, eid);
+    player.pos = pos;
+    state.players.insert(eid, player);
+    println!("spawned player {} for {}", eid, state.name_of(client_id));
+
+    state.outbox.push(ServerToClientMessage::SpawnPlayer {
+        owner_client_id: client_id,
+        entity_id: eid,
+        pos,
+    });
+}
+
+/// Gets a downed player back up, if the reviver is standing close enough to them.
+pub fn revive_player(state: &mut State, client_id: u32, entity_id: u32) {
+    let Some(reviver) = state
+        .player_of_client(client_id)
+        .and_then(|eid| state.players.get(&eid))
+        .filter(|player| !player.downed)
+        .map(|player| player.pos)
+    else {
+        return;
+    };
+    let Some(target) = state.players.get_mut(&entity_id) else {
+        return;
+    };
+    if !target.downed || target.pos.distance(reviver) > REVIVE_RANGE {
+        return;
+    }
+
+    target.downed = false;
+    target.hp = REVIVE_HP;
+    let outbound_message = ServerToClientMessage::PlayerHealth {
+        entity_id,
+        hp: target.hp,
+        downed: target.downed,
+    };
+    state.stats_mut(client_id).revives += 1;
+    state.outbox.push(outbound_message);
+}
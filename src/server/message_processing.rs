@@ -1,26 +1,45 @@
 use crate::{
     common::{
-        client_to_server::ClientToServerMessageData, game_objects::Player,
+        client_to_server::ClientToServerMessageData,
+        game_objects::{
+            Item, MatchPhase, RosterEntry, SyncMode, ITEM_HALF_EXTENT, PLAYER_COLOR_COUNT,
+            PLAYER_GRAB_RADIUS, PLAYER_RADIUS, THROW_SPEED,
+        },
+        quantize::{quantize_pos, quantize_vel},
         server_to_client::ServerToClientMessage,
     },
     server::{
-        client_bookkeeping::CLIENT_ID_TO_SOCKET_ADDRESS,
-        enque_outbound_messages::{broadcast_to_all, broadcast_to_all_except, send_to_one_client},
+        bots::{fill_slots, make_room_for},
+        chat::{sanitize, ChatLimiter},
+        enemies::hit_enemy,
+        enque_outbound_messages::{
+            broadcast_to_room, broadcast_to_room_except, send_to_one_client,
+        },
+        lag_compensation::{confirm_hit, Gunfire},
+        match_phase::{return_to_lobby, revive_player, spawn_player_for, start_match},
+        migration::snapshot,
+        replay::record_inbound,
+        roster::{unique_name, validate_name},
+        settings::{FIRE_POSITION_SLACK, GRAB_SLACK, ROLLBACK_MAX_PLAYERS},
+        waves::WavePhase,
     },
 };
 
-use super::{state::State, udp_networking::INCOMING_MESSAGE_QUEUE};
+use super::{rooms::RoomInbox, state::State};
 
 pub const DEBUG_PRINT_PROCESSED_MESSAGES: bool = false;
 
-pub async fn process_message_queue(state: &mut State) {
+pub async fn process_message_queue(state: &mut State, inbox: &RoomInbox) {
     // prune_latest_only_messages().await;
 
-    while let Some(message_bundle) = INCOMING_MESSAGE_QUEUE.pop() {
+    while let Some(message_bundle) = inbox.pop() {
+        record_inbound(state, &message_bundle);
         let client_id = message_bundle.client_id;
         match message_bundle.message {
             ClientToServerMessageData::Connect => {
-                println!("Client {} connected", client_id);
+                println!("Client {} entered room {}", client_id, state.code);
+                state.members.insert(client_id);
+                state.stats_mut(client_id);
 
                 // send welcome
                 let outbound_message = ServerToClientMessage::Welcome {
@@ -28,87 +47,458 @@ pub async fn process_message_queue(state: &mut State) {
                 };
                 send_to_one_client(client_id, outbound_message).await;
 
+                // the level has to be loaded before anything is placed in it
+                let outbound_message = ServerToClientMessage::LoadLevel {
+                    level_id: state.level.id.clone(),
+                    checksum: state.level.checksum,
+                };
+                send_to_one_client(client_id, outbound_message).await;
+
+                // catch the newcomer up on what already exists
+                let outbound_message = ServerToClientMessage::SyncModeChanged {
+                    mode: state.sync_mode,
+                };
+                send_to_one_client(client_id, outbound_message).await;
+                let outbound_message =
+                    ServerToClientMessage::MatchPhaseChanged { phase: state.phase };
+                send_to_one_client(client_id, outbound_message).await;
+                for player in state.players.values() {
+                    let outbound_message = ServerToClientMessage::SpawnPlayer {
+                        owner_client_id: player.owner_client_id,
+                        entity_id: player.entity_id,
+                        pos: player.pos,
+                    };
+                    send_to_one_client(client_id, outbound_message).await;
+                }
+                for item in state.items.values() {
+                    let outbound_message = ServerToClientMessage::SpawnItem {
+                        entity_id: item.entity_id,
+                        pos: item.pos,
+                    };
+                    send_to_one_client(client_id, outbound_message).await;
+                    if let Some(holder_entity_id) = item.held_by {
+                        let outbound_message = ServerToClientMessage::ItemGrabbed {
+                            entity_id: item.entity_id,
+                            holder_entity_id,
+                        };
+                        send_to_one_client(client_id, outbound_message).await;
+                    }
+                }
+                for player in state.players.values() {
+                    let outbound_message = ServerToClientMessage::PlayerHealth {
+                        entity_id: player.entity_id,
+                        hp: player.hp,
+                        downed: player.downed,
+                    };
+                    send_to_one_client(client_id, outbound_message).await;
+                }
+                if let WavePhase::Fighting = state.waves.phase {
+                    let outbound_message = ServerToClientMessage::WaveStarted {
+                        wave: state.waves.number,
+                    };
+                    send_to_one_client(client_id, outbound_message).await;
+                }
+                // enemies come with the snapshots, starting with the ones in range
+                state.interests.remove(&client_id);
+            }
+            ClientToServerMessageData::Join { name } => {
+                let name = validate_name(&name).unwrap_or_else(|| format!("Player {}", client_id));
+                let name = unique_name(&state.roster, client_id, name);
+                let rejoin = state.roster.contains_key(&client_id);
+                let color = state.free_color();
+                state
+                    .roster
+                    .entry(client_id)
+                    .or_insert_with(|| RosterEntry {
+                        client_id,
+                        name: String::new(),
+                        color,
+                        ready: false,
+                        bot: false,
+                    })
+                    .name = name.clone();
+                if state.host.is_none() {
+                    state.host = Some(client_id);
+                }
+                println!("Client {} joined as {}", client_id, name);
+                let token = *state
+                    .rejoin_tokens
+                    .entry(client_id)
+                    .or_insert_with(|| uuid::Uuid::new_v4().as_u128());
+                let outbound_message = ServerToClientMessage::RejoinToken { token };
+                send_to_one_client(client_id, outbound_message).await;
+                if !rejoin {
+                    make_room_for(state, client_id);
+                    fill_slots(state);
+                }
+
+                // drop straight into a running match, unless it is a rollback session,
+                // whose players all started simulating from the same step
+                if state.phase == MatchPhase::InGame {
+                    if state.sync_mode == SyncMode::Rollback {
+                        let outbound_message = ServerToClientMessage::Notice {
+                            message: "you join in from the next match".to_string(),
+                        };
+                        send_to_one_client(client_id, outbound_message).await;
+                    } else {
+                        spawn_player_for(state, client_id);
+                    }
+                }
+
                 // announce the join
-                let outbound_message = ServerToClientMessage::ClientJoined { id: client_id };
-                broadcast_to_all_except(client_id, outbound_message).await;
+                if !rejoin {
+                    let outbound_message = ServerToClientMessage::ClientJoined {
+                        id: client_id,
+                        name,
+                    };
+                    broadcast_to_room_except(&state.members, client_id, outbound_message).await;
+                }
+                broadcast_to_room(&state.members, state.roster_message()).await;
             }
             ClientToServerMessageData::Disconnect => {
-                println!("Client {} disconnected", client_id);
+                println!("{} left room {}", state.name_of(client_id), state.code);
+                state.members.remove(&client_id);
+                state.chat_limiters.remove(&client_id);
+                state.gunfire.remove(&client_id);
+                state.rejoin_tokens.remove(&client_id);
+                state.interests.remove(&client_id);
+
+                // drop whatever their player was carrying
+                if let Some(holder_entity_id) = state.player_of_client(client_id) {
+                    for item in state.items.values_mut() {
+                        if item.held_by == Some(holder_entity_id) {
+                            item.held_by = None;
+                            let outbound_message = ServerToClientMessage::ItemReleased {
+                                entity_id: item.entity_id,
+                                pos: item.pos,
+                                vel: glam::Vec2::ZERO,
+                            };
+                            broadcast_to_room_except(&state.members, client_id, outbound_message)
+                                .await;
+                        }
+                    }
+                }
+
+                // the bot filling their slot takes over their player
+                if state.roster.remove(&client_id).is_some() {
+                    if state.host == Some(client_id) {
+                        state.host = state
+                            .roster
+                            .values()
+                            .find(|entry| !entry.bot)
+                            .map(|entry| entry.client_id);
+                    }
+                    fill_slots(state);
+                    broadcast_to_room_except(&state.members, client_id, state.roster_message())
+                        .await;
+                }
+
+                // with no bot to take it, their player goes with them
+                if let Some(entity_id) = state.player_of_client(client_id) {
+                    state.players.remove(&entity_id);
+                    state
+                        .outbox
+                        .push(ServerToClientMessage::DespawnEntity { entity_id });
+                }
 
                 // announce the leave
                 let outbound_message = ServerToClientMessage::ClientLeft { id: client_id };
-                broadcast_to_all_except(client_id, outbound_message).await;
+                broadcast_to_room_except(&state.members, client_id, outbound_message).await;
             }
             ClientToServerMessageData::ChatMessage { message } => {
-                println!("{} says: {}", client_id, message);
+                let now = message_bundle.received_time;
+                let allowed = state
+                    .chat_limiters
+                    .entry(client_id)
+                    .or_insert_with(|| ChatLimiter::new(now))
+                    .allow(now);
+                if !allowed {
+                    let outbound_message = ServerToClientMessage::Notice {
+                        message: "slow down".to_string(),
+                    };
+                    send_to_one_client(client_id, outbound_message).await;
+                    continue;
+                }
+                let Some(message) = sanitize(&message) else {
+                    continue;
+                };
+                println!("{} says: {}", state.name_of(client_id), message);
 
-                // broadcast the message
+                // the sender included, so they see it as it was sent on
                 let outbound_message = ServerToClientMessage::ChatMessage {
                     from: client_id,
                     message,
                 };
-                broadcast_to_all_except(client_id, outbound_message).await;
+                broadcast_to_room(&state.members, outbound_message).await;
             }
-            ClientToServerMessageData::RequestToSpawnPlayer => {
-                println!("{} requested to spawn a player", client_id);
-
-                let eid = state.next_eid;
-                state.next_eid += 1;
-
-                // spawn the player
-                let player = Player::new(client_id, eid);
-                state.players.insert(eid, player);
-                println!("spawned player {}", eid);
-
-                // announce the spawn
-                let outbound_message = ServerToClientMessage::SpawnPlayer {
-                    owner_client_id: client_id,
-                    entity_id: eid,
+            ClientToServerMessageData::SetColor { color } => {
+                if state.phase != MatchPhase::Lobby {
+                    continue;
+                }
+                let Some(entry) = state.roster.get_mut(&client_id) else {
+                    continue;
                 };
-                broadcast_to_all(outbound_message).await;
+                entry.color = color % PLAYER_COLOR_COUNT;
+                broadcast_to_room(&state.members, state.roster_message()).await;
             }
-            ClientToServerMessageData::EntityPosition { entity_id, pos } => {
-                if let Some(player) = state.players.get_mut(&entity_id) {
-                    player.pos = pos;
+            ClientToServerMessageData::SetReady { ready } => {
+                if state.phase != MatchPhase::Lobby {
+                    continue;
                 }
-
-                let outbound_message = ServerToClientMessage::EntityPosition { entity_id, pos };
-                broadcast_to_all_except(client_id, outbound_message).await;
+                let Some(entry) = state.roster.get_mut(&client_id) else {
+                    continue;
+                };
+                entry.ready = ready;
+                broadcast_to_room(&state.members, state.roster_message()).await;
+            }
+            ClientToServerMessageData::SetSyncMode { mode } => {
+                if state.host != Some(client_id) || state.phase != MatchPhase::Lobby {
+                    continue;
+                }
+                state.sync_mode = mode;
+                println!("room {} syncs by {:?}", state.code, mode);
+                let outbound_message = ServerToClientMessage::SyncModeChanged { mode };
+                broadcast_to_room(&state.members, outbound_message).await;
+            }
+            ClientToServerMessageData::StartMatch => {
+                if state.host != Some(client_id) || state.phase != MatchPhase::Lobby {
+                    continue;
+                }
+                if !state.roster.values().all(|entry| entry.ready) {
+                    let outbound_message = ServerToClientMessage::Notice {
+                        message: "not everyone is ready".to_string(),
+                    };
+                    send_to_one_client(client_id, outbound_message).await;
+                    continue;
+                }
+                if state.sync_mode == SyncMode::Rollback
+                    && state.human_count() > ROLLBACK_MAX_PLAYERS
+                {
+                    let outbound_message = ServerToClientMessage::Notice {
+                        message: format!("rollback takes at most {} players", ROLLBACK_MAX_PLAYERS),
+                    };
+                    send_to_one_client(client_id, outbound_message).await;
+                    continue;
+                }
+                println!("{} started the match", state.name_of(client_id));
+                start_match(state);
+            }
+            ClientToServerMessageData::ReturnToLobby => {
+                if state.host != Some(client_id) || state.phase != MatchPhase::Results {
+                    continue;
+                }
+                return_to_lobby(state);
+            }
+            input @ (ClientToServerMessageData::EntityPosition { .. }
+            | ClientToServerMessageData::Fire { .. }
+            | ClientToServerMessageData::HitEnemy { .. }
+            | ClientToServerMessageData::RevivePlayer { .. }) => {
+                let Some(outbound_message) = apply_input(state, client_id, input) else {
+                    continue;
+                };
+                // everyone simulates everyone else from their inputs instead
+                if state.sync_mode == SyncMode::Rollback {
+                    continue;
+                }
+                broadcast_to_room_except(&state.members, client_id, outbound_message).await;
             }
             ClientToServerMessageData::RequestAllEntities { from_client_id } => {
                 println!("{} requested full ecs state", client_id);
                 let _ = from_client_id;
 
-                // see if theres any other players to request world state from,
-                {
-                    let mut chosen_one: Option<u32> = None;
-                    let client_id_to_socket_address_read = CLIENT_ID_TO_SOCKET_ADDRESS.read().await;
-                    let client_ids = client_id_to_socket_address_read
-                        .keys()
-                        .cloned()
-                        .collect::<Vec<_>>();
-                    for a_client_id in client_ids.iter() {
-                        if *a_client_id != client_id {
-                            chosen_one = Some(*a_client_id);
-                            break;
-                        }
-                    }
-                    if let Some(chosen_one) = chosen_one {
-                        send_to_one_client(
-                            chosen_one,
-                            ServerToClientMessage::RequestAllEntitiesFor {
-                                for_client_id: client_id,
-                            },
-                        )
-                        .await;
-                    }
+                // see if theres any other player in the room to request world state from,
+                // bots have no world of their own to send
+                let chosen_one =
+                    state.members.iter().copied().find(|member| {
+                        *member != client_id && !state.bots.brains.contains_key(member)
+                    });
+                if let Some(chosen_one) = chosen_one {
+                    send_to_one_client(
+                        chosen_one,
+                        ServerToClientMessage::RequestAllEntitiesFor {
+                            for_client_id: client_id,
+                        },
+                    )
+                    .await;
                 }
             }
             ClientToServerMessageData::AllTheEntitiesFor {
                 client_id: _for_client_id,
                 entities: _entities,
             } => {}
+            ClientToServerMessageData::RequestGrab { entity_id } => {
+                // first request in reach wins; everyone else is told no
+                let holder = state
+                    .player_of_client(client_id)
+                    .and_then(|eid| state.players.get(&eid))
+                    .filter(|player| {
+                        state.items.get(&entity_id).is_some_and(|item| {
+                            item.pos.distance(player.pos) <= PLAYER_GRAB_RADIUS + GRAB_SLACK
+                        })
+                    })
+                    .map(|player| player.entity_id);
+                let already_holding = holder.is_some_and(|holder| {
+                    state
+                        .items
+                        .values()
+                        .any(|item| item.held_by == Some(holder))
+                });
+                let item = state.items.get_mut(&entity_id);
+                match (item, holder) {
+                    (Some(item), Some(holder)) if item.held_by.is_none() && !already_holding => {
+                        item.held_by = Some(holder);
+                        let outbound_message = ServerToClientMessage::ItemGrabbed {
+                            entity_id,
+                            holder_entity_id: holder,
+                        };
+                        broadcast_to_room(&state.members, outbound_message).await;
+                    }
+                    _ => {
+                        let outbound_message = ServerToClientMessage::GrabDenied { entity_id };
+                        send_to_one_client(client_id, outbound_message).await;
+                    }
+                }
+            }
+            ClientToServerMessageData::ReleaseItem {
+                entity_id,
+                pos,
+                vel,
+            } => {
+                let Some(holder) = state
+                    .player_of_client(client_id)
+                    .and_then(|eid| state.players.get(&eid))
+                else {
+                    continue;
+                };
+                let holder_pos = holder.pos;
+                let bounds = state.level.bounds();
+                let Some(item) = state
+                    .items
+                    .get_mut(&entity_id)
+                    .filter(|item| item.held_by == Some(holder.entity_id))
+                else {
+                    continue;
+                };
+                if !pos.is_finite() || !vel.is_finite() {
+                    continue;
+                }
+
+                // carried items sit at the holder's edge and are thrown at most so hard
+                let reach = PLAYER_RADIUS + ITEM_HALF_EXTENT + GRAB_SLACK;
+                let checked_pos = holder_pos + (pos - holder_pos).clamp_length_max(reach);
+                let checked_vel = vel.clamp_length_max(THROW_SPEED);
+                item.held_by = None;
+                // where clients will see it stop, for whoever asks after it has
+                item.pos = Item::settle(checked_pos, checked_vel, bounds);
+
+                let outbound_message = ServerToClientMessage::ItemReleased {
+                    entity_id,
+                    pos: checked_pos,
+                    vel: checked_vel,
+                };
+                if checked_pos == pos && checked_vel == vel {
+                    broadcast_to_room_except(&state.members, client_id, outbound_message).await;
+                } else {
+                    // the thrower has to be put right too
+                    broadcast_to_room(&state.members, outbound_message).await;
+                }
+            }
+            // acks are consumed by the network task
+            // handled by the network tasks and the front desk before reaching a room
+            ClientToServerMessageData::Ack { .. }
+            | ClientToServerMessageData::Hello { .. }
+            | ClientToServerMessageData::Receipt { .. }
+            | ClientToServerMessageData::Rejoin { .. }
+            | ClientToServerMessageData::ListRooms
+            | ClientToServerMessageData::CreateRoom
+            | ClientToServerMessageData::JoinRoom { .. }
+            | ClientToServerMessageData::LeaveRoom => {}
+            ClientToServerMessageData::Inputs { tick, inputs } => {
+                if state.sync_mode != SyncMode::Rollback
+                    || state.phase != MatchPhase::InGame
+                    || state.player_of_client(client_id).is_none()
+                {
+                    continue;
+                }
+                let outbound_message = ServerToClientMessage::PeerInputs {
+                    client_id,
+                    tick,
+                    inputs,
+                };
+                broadcast_to_room_except(&state.members, client_id, outbound_message).await;
+            }
+            ClientToServerMessageData::RequestSnapshot => {
+                println!("Client {} is out of sync, sending a snapshot", client_id);
+                let outbound_message = ServerToClientMessage::WorldSnapshot {
+                    snapshot: Box::new(snapshot(state)),
+                };
+                send_to_one_client(client_id, outbound_message).await;
+            }
+        }
+    }
+}
+
+/// What a player's inputs do to the world, whether a client or one of the room's bots
+/// plays them. Returns what the rest of the room should hear of it, or `None` if it was
+/// turned down or there is nothing to tell.
+pub fn apply_input(
+    state: &mut State,
+    client_id: u32,
+    input: ClientToServerMessageData,
+) -> Option<ServerToClientMessage> {
+    match input {
+        ClientToServerMessageData::EntityPosition { entity_id, pos } => {
+            match state.players.get_mut(&entity_id) {
+                Some(player) if player.owner_client_id == client_id => player.pos = pos,
+                _ => return None,
+            }
+            Some(ServerToClientMessage::EntityPosition { entity_id, pos })
+        }
+        ClientToServerMessageData::Fire { pos, vel } => {
+            // only shots from where the shooter stands count, and only so many of them
+            let from_shooter = state
+                .player_of_client(client_id)
+                .and_then(|eid| state.players.get(&eid))
+                .is_some_and(|player| {
+                    !player.downed && player.pos.distance(pos) <= FIRE_POSITION_SLACK
+                });
+            let step = state.step_count;
+            if !from_shooter
+                || !state
+                    .gunfire
+                    .entry(client_id)
+                    .or_insert_with(|| Gunfire::new(step))
+                    .fire(step, pos, vel)
+            {
+                return None;
+            }
+            Some(ServerToClientMessage::ProjectileFired {
+                owner_client_id: client_id,
+                pos: quantize_pos(pos, state.level.bounds()),
+                vel: quantize_vel(vel),
+            })
+        }
+        ClientToServerMessageData::HitEnemy {
+            entity_id,
+            damage,
+            pos,
+            view_step,
+        } => {
+            // the shooter's client decides what it hit, the server decides what that does
+            let shooter_standing = state
+                .player_of_client(client_id)
+                .and_then(|eid| state.players.get(&eid))
+                .is_some_and(|player| !player.downed);
+            if shooter_standing && confirm_hit(state, client_id, entity_id, view_step, pos) {
+                hit_enemy(state, client_id, entity_id, damage);
+            }
+            None
+        }
+        ClientToServerMessageData::RevivePlayer { entity_id } => {
+            revive_player(state, client_id, entity_id);
+            None
         }
+        _ => None,
     }
 }
 
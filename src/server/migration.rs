@@ -0,0 +1,307 @@
+use crate::common::{
+    level::Level,
+    migration::{BotSnapshot, RoomSnapshot, ShotSnapshot},
+    server_to_client::ServerToClientMessage,
+};
+
+use super::{
+    bots::{Bot, Shot},
+    client_bookkeeping::CLIENT_ID_TO_SOCKET_ADDRESS,
+    enque_outbound_messages::{broadcast_to_room, send_to_one_client},
+    lag_compensation::Gunfire,
+    state::State,
+    waves::{WavePhase, Waves},
+};
+
+pub fn snapshot(state: &State) -> RoomSnapshot {
+    let intermission_steps_left = match state.waves.phase {
+        WavePhase::Fighting => None,
+        WavePhase::Intermission { steps_left } => Some(steps_left),
+    };
+    RoomSnapshot {
+        code: state.code.clone(),
+        level_id: state.level.id.clone(),
+        level_checksum: state.level.checksum,
+
+        next_id: state.next_id,
+        next_eid: state.next_eid,
+        step_count: state.step_count,
+        rng: state.rng,
+        phase: state.phase,
+        phase_steps: state.phase_steps,
+        sync_mode: state.sync_mode,
+        roster: state.roster.values().cloned().collect(),
+        host: state.host,
+        players: state.players.values().cloned().collect(),
+        items: state.items.values().cloned().collect(),
+        enemies: state.enemies.values().cloned().collect(),
+        wave: state.waves.number,
+        intermission_steps_left,
+        stats: state.scoreboard(),
+        bots: state
+            .bots
+            .brains
+            .iter()
+            .map(|(&client_id, bot)| BotSnapshot {
+                client_id,
+                inputs: bot.inputs,
+                previous_inputs: bot.previous_inputs,
+                fire_cooldown: bot.fire_cooldown,
+            })
+            .collect(),
+        bot_shots: state
+            .bots
+            .shots
+            .iter()
+            .map(|shot| ShotSnapshot {
+                owner_client_id: shot.owner_client_id,
+                pos: shot.pos,
+                vel: shot.vel,
+                steps_left: shot.steps_left,
+            })
+            .collect(),
+        history: state.history.frames(),
+        gunfire: state
+            .gunfire
+            .iter()
+            .map(|(&client_id, gunfire)| gunfire.to_snapshot(client_id))
+            .collect(),
+        rejoin_tokens: state
+            .rejoin_tokens
+            .iter()
+            .map(|(&client_id, &token)| (client_id, token))
+            .collect(),
+    }
+}
+
+/// Rebuilds a room's world from a snapshot. Nobody is a member until they rejoin.
+pub fn restore(snapshot: RoomSnapshot, level: Level) -> State {
+    let mut state = State::new(snapshot.code, level);
+    state.next_id = snapshot.next_id;
+    state.next_eid = snapshot.next_eid;
+    state.step_count = snapshot.step_count;
+    state.rng = snapshot.rng;
+    state.phase = snapshot.phase;
+    state.phase_steps = snapshot.phase_steps;
+    state.sync_mode = snapshot.sync_mode;
+    state.roster = snapshot
+        .roster
+        .into_iter()
+        .map(|entry| (entry.client_id, entry))
+        .collect();
+    state.host = snapshot.host;
+    for bot in snapshot.bots {
+        state.bots.brains.insert(
+            bot.client_id,
+            Bot {
+                inputs: bot.inputs,
+                previous_inputs: bot.previous_inputs,
+                fire_cooldown: bot.fire_cooldown,
+            },
+        );
+    }
+    state.bots.shots = snapshot
+        .bot_shots
+        .into_iter()
+        .map(|shot| Shot {
+            owner_client_id: shot.owner_client_id,
+            pos: shot.pos,
+            vel: shot.vel,
+            steps_left: shot.steps_left,
+        })
+        .collect();
+    for (step, enemies) in snapshot.history {
+        state.history.record(step, enemies);
+    }
+    state.gunfire = snapshot
+        .gunfire
+        .iter()
+        .map(|gunfire| (gunfire.client_id, Gunfire::from_snapshot(gunfire)))
+        .collect();
+    state.rejoin_tokens = snapshot.rejoin_tokens.into_iter().collect();
+    state.players = snapshot
+        .players
+        .into_iter()
+        .map(|player| (player.entity_id, player))
+        .collect();
+    state.items = snapshot
+        .items
+        .into_iter()
+        .map(|item| (item.entity_id, item))
+        .collect();
+    state.enemies = snapshot
+        .enemies
+        .into_iter()
+        .map(|enemy| (enemy.entity_id, enemy))
+        .collect();
+    state.waves = Waves {
+        number: snapshot.wave,
+        phase: match snapshot.intermission_steps_left {
+            Some(steps_left) => WavePhase::Intermission { steps_left },
+            None => WavePhase::Fighting,
+        },
+    };
+    state.stats = snapshot
+        .stats
+        .into_iter()
+        .map(|stats| (stats.client_id, stats))
+        .collect();
+    state
+}
+
+/// Names the longest-standing remote member as successor and sends it a fresh snapshot.
+pub async fn share_with_successor(state: &State) {
+    let Some(&client_id) = state.roster.keys().find(|client_id| {
+        state.members.contains(client_id) && state.local_client != Some(**client_id)
+    }) else {
+        return;
+    };
+    let Some(addr) = CLIENT_ID_TO_SOCKET_ADDRESS
+        .read()
+        .await
+        .get(&client_id)
+        .copied()
+    else {
+        return;
+    };
+
+    let parts = snapshot(state).to_parts();
+    let count = parts.len() as u32;
+    for (index, bytes) in parts.into_iter().enumerate() {
+        let outbound_message = ServerToClientMessage::MigrationSnapshotPart {
+            id: state.step_count,
+            index: index as u32,
+            count,
+            bytes,
+        };
+        send_to_one_client(client_id, outbound_message).await;
+    }
+    let outbound_message = ServerToClientMessage::Successor { client_id, addr };
+    broadcast_to_room(&state.members, outbound_message).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::Vec2;
+
+    use crate::{
+        common::{
+            client_to_server::{ClientToServerMessageBundle, ClientToServerMessageData},
+            level::Level,
+            mailbox::Mailbox,
+            migration::{SnapshotParts, SNAPSHOT_PART_SIZE},
+        },
+        server::{
+            game::{spawn_items, step},
+            message_processing::process_message_queue,
+        },
+    };
+
+    use super::{restore, snapshot, State};
+
+    const HUMANS: [u32; 2] = [1000, 1001];
+
+    fn bundle(client_id: u32, message: ClientToServerMessageData) -> ClientToServerMessageBundle {
+        ClientToServerMessageBundle {
+            client_id,
+            send_time: 0,
+            received_time: 0,
+            message,
+        }
+    }
+
+    /// A room a few hundred steps into a match, with enemies, bots and history to carry.
+    async fn room_in_a_match(level: &Level) -> State {
+        let mut state = State::new("SEED".to_string(), level.clone());
+        spawn_items(&mut state);
+        let inbox = Arc::new(Mailbox::new(1));
+        for step_count in 0..400 {
+            let mut inputs = Vec::new();
+            for (index, client_id) in HUMANS.into_iter().enumerate() {
+                if step_count == 0 {
+                    inputs.push(bundle(client_id, ClientToServerMessageData::Connect));
+                    let name = format!("human{}", index);
+                    inputs.push(bundle(client_id, ClientToServerMessageData::Join { name }));
+                    let ready = ClientToServerMessageData::SetReady { ready: true };
+                    inputs.push(bundle(client_id, ready));
+                } else if step_count == 1 && index == 0 {
+                    inputs.push(bundle(client_id, ClientToServerMessageData::StartMatch));
+                }
+                if let Some(player) = state
+                    .player_of_client(client_id)
+                    .and_then(|eid| state.players.get(&eid))
+                {
+                    let angle = step_count as f32 * 0.05 + index as f32;
+                    let moved = ClientToServerMessageData::EntityPosition {
+                        entity_id: player.entity_id,
+                        pos: player.pos + Vec2::from_angle(angle),
+                    };
+                    inputs.push(bundle(client_id, moved));
+                }
+            }
+            for input in inputs {
+                inbox.push(input).unwrap();
+                process_message_queue(&mut state, &inbox).await;
+            }
+            step(&mut state);
+            state.outbox.clear();
+        }
+        state
+    }
+
+    #[tokio::test]
+    async fn a_restored_room_plays_on_like_the_original() {
+        let level = Level::parse("arena", include_str!("../../levels/arena.txt")).unwrap();
+        let mut original = room_in_a_match(&level).await;
+        assert!(!original.enemies.is_empty());
+        assert!(!original.bots.brains.is_empty());
+
+        // the way it reaches the successor: in parts, in whatever order they arrive
+        let parts = snapshot(&original).to_parts();
+        assert!(parts.len() > 1);
+        assert!(parts.iter().all(|part| part.len() <= SNAPSHOT_PART_SIZE));
+        let count = parts.len() as u32;
+        let mut gathered = SnapshotParts::new();
+        let mut received = None;
+        for (index, part) in parts.into_iter().enumerate().rev() {
+            assert!(received.is_none());
+            received = gathered.accept(original.step_count, index as u32, count, part);
+        }
+        let mut restored = restore(received.unwrap(), level);
+
+        assert_eq!(restored.world_hash(), original.world_hash());
+        assert_eq!(restored.rejoin_tokens, original.rejoin_tokens);
+        assert_eq!(
+            restored.roster.keys().collect::<Vec<_>>(),
+            original.roster.keys().collect::<Vec<_>>()
+        );
+        for _ in 0..300 {
+            step(&mut original);
+            step(&mut restored);
+            original.outbox.clear();
+            restored.outbox.clear();
+            assert_eq!(
+                restored.world_hash(),
+                original.world_hash(),
+                "diverged at step {}",
+                original.step_count
+            );
+        }
+    }
+
+    #[test]
+    fn parts_of_an_older_snapshot_do_not_mix_in() {
+        let mut gathered = SnapshotParts::new();
+        assert!(gathered.accept(60, 0, 2, vec![1; 8]).is_none());
+        // the next snapshot started before the last part of this one came in
+        assert!(gathered.accept(120, 1, 2, vec![2; 8]).is_none());
+        assert!(gathered.accept(60, 1, 2, vec![1; 8]).is_none());
+        // nonsense is turned away
+        assert!(gathered.accept(60, 2, 2, vec![1; 8]).is_none());
+        assert!(gathered
+            .accept(60, 0, 1, vec![0; SNAPSHOT_PART_SIZE + 1])
+            .is_none());
+    }
+}
@@ -1,7 +1,21 @@
+pub mod bandwidth;
+pub mod bots;
+pub mod chat;
 pub mod client_bookkeeping;
+pub mod discovery;
+pub mod enemies;
 pub mod enque_outbound_messages;
 pub mod game;
+pub mod lag_compensation;
+pub mod listen;
+pub mod match_phase;
 pub mod message_processing;
+pub mod migration;
+pub mod relevancy;
+pub mod replay;
+pub mod rooms;
+pub mod roster;
 pub mod settings;
 pub mod state;
 pub mod udp_networking;
+pub mod waves;
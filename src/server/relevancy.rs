@@ -0,0 +1,314 @@
+use std::collections::{BTreeSet, HashMap};
+
+use glam::Vec2;
+
+use crate::common::{
+    quantize::quantize_pos,
+    server_to_client::{PackedPosition, ServerToClientMessage},
+    spatial_hash::SpatialHash,
+};
+
+use super::{
+    bandwidth::Link,
+    client_bookkeeping::CLIENT_LINKS,
+    enque_outbound_messages::send_to_one_client,
+    settings::{INTEREST_CELL_SIZE, INTEREST_NEAR_BONUS, INTEREST_RADIUS},
+    state::State,
+};
+
+/// What one client is owed. Players are everyone's teammates and always relevant, so
+/// their positions still go to the whole room as they come in; this only decides which
+/// enemies each client hears about. Enemies are spawned on a client as they come into
+/// range and despawned again as they leave it.
+pub struct Interest {
+    /// Enemies this client has been told to spawn and not yet to despawn.
+    known: BTreeSet<u32>,
+    /// Keyed by entity id. Grows every snapshot an entity is in range but left out, so
+    /// far away enemies still get their turn behind the close ones.
+    priorities: HashMap<u32, f32>,
+    /// Step this client was last sent a snapshot at.
+    last_snapshot_step: Option<u32>,
+}
+
+impl Interest {
+    pub fn new() -> Self {
+        Self {
+            known: BTreeSet::new(),
+            priorities: HashMap::new(),
+            last_snapshot_step: None,
+        }
+    }
+}
+
+impl Default for Interest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where a client looks at the world from: their player, or the middle of the level
+/// while they have none.
+fn viewpoint(state: &State, client_id: u32) -> Vec2 {
+    state
+        .player_of_client(client_id)
+        .and_then(|eid| state.players.get(&eid))
+        .map(|player| player.pos)
+        .unwrap_or_else(|| state.level.bounds() / 2.0)
+}
+
+/// Sends the members that are due one the enemy positions that matter most to them, as
+/// many as fit in their budget. How often and how much depends on their connection;
+/// clients in this process have no connection to spare and get the defaults.
+pub async fn send_snapshots(state: &mut State) {
+    let rates: HashMap<u32, (u32, usize)> = {
+        let links_read = CLIENT_LINKS.read().await;
+        state
+            .members
+            .iter()
+            .map(|client_id| {
+                let rate = match links_read.get(client_id) {
+                    Some(link) => (link.snapshot_interval, link.snapshot_budget()),
+                    None => {
+                        let link = Link::new(0);
+                        (link.snapshot_interval, link.snapshot_budget())
+                    }
+                };
+                (*client_id, rate)
+            })
+            .collect()
+    };
+
+    let mut grid = SpatialHash::new(INTEREST_CELL_SIZE);
+    for enemy in state.enemies.values() {
+        grid.insert(enemy.entity_id, enemy.pos, enemy.pos);
+    }
+
+    let members: Vec<u32> = state.members.iter().copied().collect();
+    for client_id in members {
+        let (interval, budget) = rates[&client_id];
+        let interest = state.interests.entry(client_id).or_default();
+        let due = interest
+            .last_snapshot_step
+            .is_none_or(|last| state.step_count >= last + interval);
+        if !due {
+            continue;
+        }
+        interest.last_snapshot_step = Some(state.step_count);
+        for message in build_snapshot(state, &grid, client_id, budget) {
+            send_to_one_client(client_id, message).await;
+        }
+    }
+}
+
+/// Despawns the enemies that left a client's range and spawns those that came into it,
+/// then ranks the enemies in range by how long they have waited and how close they are,
+/// and takes from the top until the budget is spent.
+fn build_snapshot(
+    state: &mut State,
+    grid: &SpatialHash<u32>,
+    client_id: u32,
+    budget: usize,
+) -> Vec<ServerToClientMessage> {
+    let view = viewpoint(state, client_id);
+    let bounds = state.level.bounds();
+    let reach = Vec2::splat(INTEREST_RADIUS);
+    let relevant = grid.query(view - reach, view + reach);
+
+    let interest = state.interests.entry(client_id).or_default();
+    let mut messages = Vec::new();
+    // enemies that died were already despawned on everyone
+    interest.known.retain(|eid| state.enemies.contains_key(eid));
+    let left: Vec<u32> = interest
+        .known
+        .iter()
+        .copied()
+        .filter(|eid| relevant.binary_search(eid).is_err())
+        .collect();
+    for entity_id in left {
+        interest.known.remove(&entity_id);
+        messages.push(ServerToClientMessage::DespawnEntity { entity_id });
+    }
+    for &entity_id in &relevant {
+        if interest.known.insert(entity_id) {
+            messages.push(ServerToClientMessage::SpawnEnemy {
+                entity_id,
+                pos: state.enemies[&entity_id].pos,
+            });
+        }
+    }
+    // whatever left the area starts from nothing when it comes back
+    interest
+        .priorities
+        .retain(|eid, _| relevant.binary_search(eid).is_ok());
+
+    let mut ranked = Vec::new();
+    for entity_id in relevant {
+        let pos = state.enemies[&entity_id].pos;
+        let closeness = (1.0 - pos.distance(view) / INTEREST_RADIUS).max(0.0);
+        let priority = interest.priorities.entry(entity_id).or_insert(0.0);
+        *priority += 1.0 + INTEREST_NEAR_BONUS * closeness;
+        ranked.push((*priority, entity_id, pos));
+    }
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    let empty = ServerToClientMessage::EnemyPositions {
+        positions: Vec::new(),
+    };
+    let mut spent = bincode::serialized_size(&empty).unwrap_or(0) as usize;
+    let mut positions = Vec::new();
+    for (_, entity_id, pos) in ranked {
+        let packed = PackedPosition {
+            entity_id,
+            pos: quantize_pos(pos, bounds),
+        };
+        let size = bincode::serialized_size(&packed).unwrap_or(0) as usize;
+        if spent + size > budget {
+            break;
+        }
+        spent += size;
+        interest.priorities.insert(entity_id, 0.0);
+        positions.push(packed);
+    }
+    if !positions.is_empty() {
+        messages.push(ServerToClientMessage::EnemyPositions { positions });
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use crate::common::{
+        game_objects::{Enemy, Player},
+        level::Level,
+        quantize::quantize_pos,
+        server_to_client::{PackedPosition, ServerToClientMessage},
+        spatial_hash::SpatialHash,
+    };
+
+    use super::{build_snapshot, State, INTEREST_CELL_SIZE};
+
+    const CLIENT: u32 = 1000;
+
+    fn room(player_pos: Vec2, enemies: &[(u32, Vec2)]) -> State {
+        let level = Level::parse("arena", include_str!("../../levels/arena.txt")).unwrap();
+        let mut state = State::new("SEED".to_string(), level);
+        state.members.insert(CLIENT);
+        let mut player = Player::new(CLIENT, 0);
+        player.pos = player_pos;
+        state.players.insert(0, player);
+        for (eid, pos) in enemies {
+            state.enemies.insert(*eid, Enemy::new(*eid, *pos));
+        }
+        state
+    }
+
+    fn grid(state: &State) -> SpatialHash<u32> {
+        let mut grid = SpatialHash::new(INTEREST_CELL_SIZE);
+        for enemy in state.enemies.values() {
+            grid.insert(enemy.entity_id, enemy.pos, enemy.pos);
+        }
+        grid
+    }
+
+    fn snapshot(state: &mut State, budget: usize) -> Vec<ServerToClientMessage> {
+        let grid = grid(state);
+        build_snapshot(state, &grid, CLIENT, budget)
+    }
+
+    fn spawned(messages: &[ServerToClientMessage]) -> Vec<u32> {
+        messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerToClientMessage::SpawnEnemy { entity_id, .. } => Some(*entity_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn despawned(messages: &[ServerToClientMessage]) -> Vec<u32> {
+        messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerToClientMessage::DespawnEntity { entity_id } => Some(*entity_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn positioned(messages: &[ServerToClientMessage]) -> Vec<u32> {
+        messages
+            .iter()
+            .flat_map(|message| match message {
+                ServerToClientMessage::EnemyPositions { positions } => {
+                    positions.iter().map(|packed| packed.entity_id).collect()
+                }
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn enemies_are_spawned_coming_into_range_and_despawned_leaving_it() {
+        let mut state = room(
+            Vec2::new(40.0, 40.0),
+            &[(10, Vec2::new(50.0, 40.0)), (11, Vec2::new(350.0, 350.0))],
+        );
+
+        let messages = snapshot(&mut state, usize::MAX);
+        assert_eq!(spawned(&messages), vec![10]);
+        assert!(despawned(&messages).is_empty());
+        assert_eq!(positioned(&messages), vec![10]);
+
+        // nothing new to spawn the second time round
+        let messages = snapshot(&mut state, usize::MAX);
+        assert!(spawned(&messages).is_empty());
+        assert_eq!(positioned(&messages), vec![10]);
+
+        state.players.get_mut(&0).unwrap().pos = Vec2::new(340.0, 340.0);
+        let messages = snapshot(&mut state, usize::MAX);
+        assert_eq!(despawned(&messages), vec![10]);
+        assert_eq!(spawned(&messages), vec![11]);
+
+        // a dead enemy was despawned on everyone already
+        state.enemies.remove(&11);
+        let messages = snapshot(&mut state, usize::MAX);
+        assert!(despawned(&messages).is_empty());
+        assert!(positioned(&messages).is_empty());
+    }
+
+    #[test]
+    fn far_enemies_get_their_turn_behind_close_ones() {
+        let near = 10;
+        let far = 11;
+        let mut state = room(
+            Vec2::new(40.0, 40.0),
+            &[(near, Vec2::new(45.0, 40.0)), (far, Vec2::new(190.0, 40.0))],
+        );
+        let bounds = state.level.bounds();
+        // room for exactly one position
+        let empty = ServerToClientMessage::EnemyPositions {
+            positions: Vec::new(),
+        };
+        let packed = PackedPosition {
+            entity_id: near,
+            pos: quantize_pos(Vec2::ZERO, bounds),
+        };
+        let budget = (bincode::serialized_size(&empty).unwrap()
+            + bincode::serialized_size(&packed).unwrap()) as usize;
+
+        let sent: Vec<Vec<u32>> = (0..4)
+            .map(|_| positioned(&snapshot(&mut state, budget)))
+            .collect();
+        assert!(sent.iter().all(|positions| positions.len() == 1));
+        assert_eq!(sent[0], vec![near]);
+        let far_turns = sent.iter().filter(|positions| positions[0] == far).count();
+        assert!(far_turns >= 1);
+        assert!(far_turns < 4 - far_turns);
+
+        // too small for even one, and nothing is sent
+        let messages = snapshot(&mut state, budget - 1);
+        assert!(positioned(&messages).is_empty());
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::common::{
+    client_to_server::ClientToServerMessageBundle,
+    game_objects::MatchPhase,
+    level::Level,
+    mailbox::Mailbox,
+    replay::{read_records, write_header, write_record, ReplayRecord},
+};
+
+use super::{
+    game,
+    message_processing::process_message_queue,
+    migration::{restore, snapshot},
+    rooms::RoomInbox,
+    settings::{RECORD_REPLAYS, REPLAY_DIR},
+    state::State,
+};
+
+/// Writes one match of a room to `replays/<code>-<start time>.replay`.
+pub struct Recorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(code: &str) -> io::Result<Self> {
+        std::fs::create_dir_all(REPLAY_DIR)?;
+        let started = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+        let path = Path::new(REPLAY_DIR).join(format!("{}-{}.replay", code, started));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        write_header(&mut writer)?;
+        Ok(Self { path, writer })
+    }
+}
+
+fn record(state: &mut State, record: ReplayRecord) {
+    let Some(recorder) = &mut state.recorder else {
+        return;
+    };
+    if let Err(e) = write_record(&mut recorder.writer, &record) {
+        eprintln!("Error writing replay {}: {}", recorder.path.display(), e);
+        state.recorder = None;
+    }
+}
+
+/// Starts recording when a match starts and finishes the file when it ends.
+pub fn update_recording(state: &mut State) {
+    let in_game = state.phase == MatchPhase::InGame;
+    if in_game && state.recorder.is_none() && RECORD_REPLAYS {
+        match Recorder::create(&state.code) {
+            Ok(recorder) => state.recorder = Some(recorder),
+            Err(e) => eprintln!("Error starting replay for room {}: {}", state.code, e),
+        }
+        record_keyframe(state);
+    } else if !in_game && state.recorder.is_some() {
+        let step = state.step_count;
+        record(state, ReplayRecord::End { step });
+        if let Some(mut recorder) = state.recorder.take() {
+            match recorder.writer.flush() {
+                Ok(()) => println!("replay saved to {}", recorder.path.display()),
+                Err(e) => eprintln!("Error writing replay {}: {}", recorder.path.display(), e),
+            }
+        }
+    }
+}
+
+pub fn record_inbound(state: &mut State, bundle: &ClientToServerMessageBundle) {
+    if state.recorder.is_some() {
+        let step = state.step_count;
+        let bundle = bundle.clone();
+        record(state, ReplayRecord::Inbound { step, bundle });
+    }
+}
+
+/// Lets playback start from here instead of from the beginning of the match.
+pub fn record_keyframe(state: &mut State) {
+    if state.recorder.is_some() {
+        let mut keyframe = snapshot(state);
+        // only good for getting back in after a migration, and not for sharing
+        keyframe.rejoin_tokens.clear();
+        record(state, ReplayRecord::Keyframe(Box::new(keyframe)));
+        // a server that dies mid-match still leaves everything up to here playable
+        if let Some(recorder) = &mut state.recorder {
+            let _ = recorder.writer.flush();
+        }
+    }
+}
+
+/// A recorded match, re-simulated by feeding the room its inbound bundles again.
+pub struct Playback {
+    records: Vec<ReplayRecord>,
+    level: Level,
+    inbox: RoomInbox,
+    /// Index of the next record to apply.
+    cursor: usize,
+    pub state: State,
+    pub first_step: u32,
+    pub last_step: u32,
+}
+
+impl Playback {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let records = read_records(&mut BufReader::new(File::open(path)?))?;
+        let Some(ReplayRecord::Keyframe(first)) = records.first() else {
+            return Err(io::Error::other("replay does not start with a keyframe"));
+        };
+        let level = Level::load(&first.level_id)
+            .map_err(|e| io::Error::other(format!("loading {}: {}", first.level_id, e)))?;
+        if level.checksum != first.level_checksum {
+            eprintln!(
+                "Level {} changed since the replay was recorded, expect it to play out differently",
+                first.level_id
+            );
+        }
+        Self::from_records(records, level)
+    }
+
+    /// Plays records that were already read, in `level`.
+    pub fn from_records(records: Vec<ReplayRecord>, level: Level) -> io::Result<Self> {
+        let Some(ReplayRecord::Keyframe(first)) = records.first() else {
+            return Err(io::Error::other("replay does not start with a keyframe"));
+        };
+        let first_step = first.step_count;
+        let last_step = records
+            .iter()
+            .map(|record| match record {
+                ReplayRecord::Keyframe(snapshot) => snapshot.step_count,
+                ReplayRecord::Inbound { step, .. } | ReplayRecord::End { step } => *step,
+            })
+            .max()
+            .unwrap_or(first_step);
+        let state = restore((**first).clone(), level.clone());
+        Ok(Self {
+            records,
+            level,
+            inbox: Arc::new(Mailbox::new(1)),
+            cursor: 1,
+            state,
+            first_step,
+            last_step,
+        })
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.state.step_count >= self.last_step
+    }
+
+    /// Hands the room whatever it received before this step, then runs the step.
+    pub async fn step(&mut self) {
+        while let Some(record) = self.records.get(self.cursor) {
+            match record {
+                ReplayRecord::Inbound { step, bundle } if *step <= self.state.step_count => {
+                    let _ = self.inbox.push(bundle.clone());
+                    process_message_queue(&mut self.state, &self.inbox).await;
+                }
+                ReplayRecord::Keyframe(snapshot)
+                    if snapshot.step_count <= self.state.step_count => {}
+                ReplayRecord::End { .. } => {}
+                _ => break,
+            }
+            self.cursor += 1;
+        }
+        game::step(&mut self.state);
+        // nobody is listening
+        self.state.outbox.clear();
+    }
+
+    /// Jumps to `step`, starting from the closest keyframe before it when that is quicker.
+    pub async fn seek(&mut self, step: u32) {
+        let step = step.clamp(self.first_step, self.last_step);
+        let Some((index, keyframe)) =
+            self.records
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(index, record)| match record {
+                    ReplayRecord::Keyframe(snapshot) if snapshot.step_count <= step => {
+                        Some((index, snapshot))
+                    }
+                    _ => None,
+                })
+        else {
+            return;
+        };
+        // going forward within the same stretch between keyframes is just playing on
+        let ahead = step >= self.state.step_count;
+        if !ahead || keyframe.step_count > self.state.step_count {
+            self.state = restore((**keyframe).clone(), self.level.clone());
+            self.cursor = index + 1;
+        }
+        while self.state.step_count < step {
+            self.step().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::Vec2;
+
+    use crate::common::{
+        client_to_server::{ClientToServerMessageBundle, ClientToServerMessageData},
+        game_objects::PROJECTILE_SPEED,
+        level::Level,
+        mailbox::Mailbox,
+        replay::ReplayRecord,
+    };
+
+    use super::{
+        game::{self, spawn_items},
+        process_message_queue, snapshot, Playback, State,
+    };
+
+    const HUMANS: [u32; 2] = [1000, 1001];
+    const STEPS: u32 = 900;
+    const KEYFRAME_INTERVAL: u32 = 200;
+
+    fn bundle(client_id: u32, message: ClientToServerMessageData) -> ClientToServerMessageBundle {
+        ClientToServerMessageBundle {
+            client_id,
+            send_time: 0,
+            received_time: 0,
+            message,
+        }
+    }
+
+    /// Two humans join and start a match, then wander and shoot; the bots filling the
+    /// other slots shoot on their own.
+    fn play(state: &State) -> Vec<ClientToServerMessageBundle> {
+        let step = state.step_count;
+        let mut inputs = Vec::new();
+        for (index, client_id) in HUMANS.into_iter().enumerate() {
+            if step == 0 {
+                inputs.push(bundle(client_id, ClientToServerMessageData::Connect));
+                let name = format!("human{}", index);
+                inputs.push(bundle(client_id, ClientToServerMessageData::Join { name }));
+                let ready = ClientToServerMessageData::SetReady { ready: true };
+                inputs.push(bundle(client_id, ready));
+            } else if step == 1 && index == 0 {
+                inputs.push(bundle(client_id, ClientToServerMessageData::StartMatch));
+            }
+            let Some(player) = state
+                .player_of_client(client_id)
+                .and_then(|eid| state.players.get(&eid))
+            else {
+                continue;
+            };
+            let angle = step as f32 * 0.03 + index as f32;
+            let moved = ClientToServerMessageData::EntityPosition {
+                entity_id: player.entity_id,
+                pos: player.pos + Vec2::from_angle(angle),
+            };
+            inputs.push(bundle(client_id, moved));
+            if let Some(enemy) = state
+                .enemies
+                .values()
+                .find(|enemy| enemy.pos.distance(player.pos) < 80.0)
+                .filter(|_| step.is_multiple_of(9))
+            {
+                let aim = (enemy.pos - player.pos).normalize_or_zero();
+                let fire = ClientToServerMessageData::Fire {
+                    pos: player.pos,
+                    vel: aim * PROJECTILE_SPEED,
+                };
+                inputs.push(bundle(client_id, fire));
+                let hit = ClientToServerMessageData::HitEnemy {
+                    entity_id: enemy.entity_id,
+                    damage: 10,
+                    pos: enemy.pos,
+                    view_step: step,
+                };
+                inputs.push(bundle(client_id, hit));
+            }
+        }
+        inputs
+    }
+
+    /// Runs a room the way a recording server would, keyframes and all.
+    async fn record(level: &Level) -> Vec<ReplayRecord> {
+        let mut state = State::new("SEED".to_string(), level.clone());
+        spawn_items(&mut state);
+        let inbox = Arc::new(Mailbox::new(1));
+
+        let mut records = Vec::new();
+        for _ in 0..STEPS {
+            let step = state.step_count;
+            if step.is_multiple_of(KEYFRAME_INTERVAL) {
+                records.push(ReplayRecord::Keyframe(Box::new(snapshot(&state))));
+            }
+            for bundle in play(&state) {
+                records.push(ReplayRecord::Inbound {
+                    step,
+                    bundle: bundle.clone(),
+                });
+                inbox.push(bundle).unwrap();
+                process_message_queue(&mut state, &inbox).await;
+            }
+            game::step(&mut state);
+            state.outbox.clear();
+        }
+        records.push(ReplayRecord::End {
+            step: state.step_count,
+        });
+        records
+    }
+
+    #[tokio::test]
+    async fn seeking_lands_on_the_world_playing_gets_to() {
+        let level = Level::parse("arena", include_str!("../../levels/arena.txt")).unwrap();
+        let records = record(&level).await;
+        // keyframes have to carry bots and history over for this to mean anything
+        assert!(records.iter().any(|record| matches!(
+            record,
+            ReplayRecord::Keyframe(snapshot)
+                if !snapshot.bots.is_empty() && !snapshot.history.is_empty()
+        )));
+
+        let mut playback = Playback::from_records(records.clone(), level.clone()).unwrap();
+        let mut played = vec![playback.state.world_hash()];
+        while !playback.at_end() {
+            playback.step().await;
+            played.push(playback.state.world_hash());
+        }
+
+        let mut seeker = Playback::from_records(records, level).unwrap();
+        for target in [650, 210, 420, 399, 800, 5, 600] {
+            seeker.seek(target).await;
+            assert_eq!(
+                seeker.state.world_hash(),
+                played[target as usize],
+                "seeking to step {} gave a different world",
+                target
+            );
+        }
+    }
+}
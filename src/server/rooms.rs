@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU32},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use tokio::sync::RwLock;
+
+use crate::common::{
+    client_to_server::{
+        ClientToServerMessage, ClientToServerMessageBundle, ClientToServerMessageData,
+    },
+    game_objects::{MatchPhase, RoomInfo},
+    level::Level,
+    mailbox::{Mailbox, Overflow},
+    migration::RoomSnapshot,
+    server_to_client::ServerToClientMessage,
+};
+
+use super::{
+    client_bookkeeping::{disconnect_flooding_client, rekey_client, NEXT_CONNECTION_ID},
+    enque_outbound_messages::send_to_one_client,
+    game::spawn_items,
+    migration::restore,
+    settings::{
+        INBOUND_CLIENT_LIMIT, MIGRATION_REJOIN_TIMEOUT_MS, ROOM_CODE_LENGTH, ROOM_INBOX_CAPACITY,
+    },
+    state::State,
+    udp_networking::INCOMING_MESSAGE_QUEUE,
+};
+
+pub type RoomInbox = Arc<Mailbox<ClientToServerMessageBundle>>;
+
+lazy_static! {
+    /// Clients in a room, which is what server listings count as players. Connections
+    /// still browsing the room list are left out.
+    pub static ref PLAYERS_IN_ROOMS: Arc<AtomicU32> = Arc::new(AtomicU32::new(0));
+}
+
+/// The front desk's handle on a room. The world itself lives in the room's own task.
+pub struct Room {
+    pub code: String,
+    pub inbox: RoomInbox,
+    /// Kept up to date by the room's loop, for the room list.
+    pub info: RwLock<RoomInfo>,
+    pub closed: AtomicBool,
+}
+
+/// Everything that is not about a particular room: names, room listings, who is where.
+struct FrontDesk {
+    level: Level,
+    rooms: HashMap<String, Arc<Room>>,
+    client_rooms: HashMap<u32, String>,
+    names: HashMap<u32, String>,
+    /// After taking over from another server: who was in the restored room and has not
+    /// come back yet, by their old client id, with the room and the token they must show.
+    awaiting_rejoin: HashMap<u32, (String, Option<u128>)>,
+    rejoin_deadline: Option<Instant>,
+    local_client: Option<u32>,
+}
+
+/// Takes every inbound message, handles room management itself and forwards the rest
+/// to the room the sender is in. A restored room picks up where another server left off.
+/// `local_client` is the client sharing this process, if any.
+pub async fn dispatch_loop(
+    level: Level,
+    restored: Option<RoomSnapshot>,
+    local_client: Option<u32>,
+) {
+    let mut desk = FrontDesk {
+        level,
+        rooms: HashMap::new(),
+        client_rooms: HashMap::new(),
+        names: HashMap::new(),
+        awaiting_rejoin: HashMap::new(),
+        rejoin_deadline: None,
+        local_client,
+    };
+    if let Some(snapshot) = restored {
+        restore_room(&mut desk, snapshot);
+    }
+    loop {
+        while let Some(message_bundle) = INCOMING_MESSAGE_QUEUE.pop() {
+            dispatch(&mut desk, message_bundle).await;
+        }
+        PLAYERS_IN_ROOMS.store(
+            desk.client_rooms.len() as u32,
+            std::sync::atomic::Ordering::SeqCst,
+        );
+        if desk
+            .rejoin_deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            give_up_on_rejoins(&mut desk);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+}
+
+async fn dispatch(desk: &mut FrontDesk, message_bundle: ClientToServerMessageBundle) {
+    let client_id = message_bundle.client_id;
+    match &message_bundle.message {
+        ClientToServerMessageData::Connect => {
+            println!("Client {} connected", client_id);
+        }
+        ClientToServerMessageData::Join { name } => {
+            desk.names.insert(client_id, name.clone());
+            // a rename while in a room
+            if desk.client_rooms.contains_key(&client_id) {
+                forward(desk, message_bundle);
+            } else {
+                send_room_list(desk, client_id).await;
+            }
+        }
+        ClientToServerMessageData::Rejoin {
+            previous_client_id,
+            token,
+            name,
+        } => {
+            let previous_client_id = *previous_client_id;
+            // a wrong token leaves the id waiting for whoever really had it
+            let code = match desk.awaiting_rejoin.get(&previous_client_id) {
+                Some((_, expected)) if *expected == Some(*token) => desk
+                    .awaiting_rejoin
+                    .remove(&previous_client_id)
+                    .map(|(code, _)| code)
+                    .filter(|code| desk.rooms.contains_key(code)),
+                _ => None,
+            };
+            let Some(code) = code else {
+                // nothing to come back to, treat it as a fresh join
+                desk.names.insert(client_id, name.clone());
+                send_room_list(desk, client_id).await;
+                return;
+            };
+            rekey_client(client_id, previous_client_id).await;
+            desk.names.insert(previous_client_id, name.clone());
+            let message = ServerToClientMessage::ClientIDAssignment {
+                new_client_id: previous_client_id,
+            };
+            send_to_one_client(previous_client_id, message).await;
+            enter_room(desk, previous_client_id, &code).await;
+        }
+        ClientToServerMessageData::ListRooms => {
+            send_room_list(desk, client_id).await;
+        }
+        ClientToServerMessageData::CreateRoom => {
+            let code = create_room(desk);
+            enter_room(desk, client_id, &code).await;
+        }
+        ClientToServerMessageData::JoinRoom { code } => {
+            let code = code.trim().to_uppercase();
+            if desk.rooms.contains_key(&code) {
+                enter_room(desk, client_id, &code).await;
+            } else {
+                let message = ServerToClientMessage::Notice {
+                    message: format!("no room {}", code),
+                };
+                send_to_one_client(client_id, message).await;
+            }
+        }
+        ClientToServerMessageData::LeaveRoom => {
+            leave_room(desk, client_id);
+            send_to_one_client(client_id, ServerToClientMessage::LeftRoom).await;
+            send_room_list(desk, client_id).await;
+        }
+        ClientToServerMessageData::Disconnect => {
+            leave_room(desk, client_id);
+            desk.names.remove(&client_id);
+            println!("Client {} disconnected", client_id);
+        }
+        _ => forward(desk, message_bundle),
+    }
+}
+
+fn forward(desk: &FrontDesk, message_bundle: ClientToServerMessageBundle) {
+    let Some(room) = desk
+        .client_rooms
+        .get(&message_bundle.client_id)
+        .and_then(|code| desk.rooms.get(code))
+    else {
+        return;
+    };
+    deliver(room, message_bundle);
+}
+
+/// Queues a message in a room's inbox. Dropped unreliable messages are counted by the
+/// inbox; a client with too much waiting there already is cut off, and what it had waiting
+/// goes, to make room for its leaving.
+fn deliver(room: &Room, message_bundle: ClientToServerMessageBundle) {
+    let client_id = message_bundle.client_id;
+    if let Err(Overflow::Flooded) = room.inbox.push(message_bundle) {
+        room.inbox.forget_sender(client_id);
+        tokio::spawn(disconnect_flooding_client(client_id));
+    }
+}
+
+/// Hands a client to a room as if it had just connected and joined there.
+async fn enter_room(desk: &mut FrontDesk, client_id: u32, code: &str) {
+    if desk.client_rooms.get(&client_id).map(String::as_str) == Some(code) {
+        return;
+    }
+    leave_room(desk, client_id);
+    desk.client_rooms.insert(client_id, code.to_string());
+    println!("Client {} joined room {}", client_id, code);
+
+    send_to_one_client(
+        client_id,
+        ServerToClientMessage::JoinedRoom {
+            code: code.to_string(),
+        },
+    )
+    .await;
+
+    let name = desk
+        .names
+        .get(&client_id)
+        .cloned()
+        .unwrap_or_else(|| format!("Player {}", client_id));
+    for data in [
+        ClientToServerMessageData::Connect,
+        ClientToServerMessageData::Join { name },
+    ] {
+        let message_bundle =
+            ClientToServerMessageBundle::new(client_id, ClientToServerMessage::new(data));
+        forward(desk, message_bundle);
+    }
+}
+
+/// Takes a client out of its room, and closes the room if that was the last one in it.
+fn leave_room(desk: &mut FrontDesk, client_id: u32) {
+    if !desk.client_rooms.contains_key(&client_id) {
+        return;
+    }
+    let message_bundle = ClientToServerMessageBundle::new(
+        client_id,
+        ClientToServerMessage::new(ClientToServerMessageData::Disconnect),
+    );
+    forward(desk, message_bundle);
+
+    let Some(code) = desk.client_rooms.remove(&client_id) else {
+        return;
+    };
+    if !desk.client_rooms.values().any(|other| *other == code) {
+        if let Some(room) = desk.rooms.remove(&code) {
+            room.closed.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+}
+
+/// Opens a room with a fresh world and starts its loop. Returns its code.
+fn create_room(desk: &mut FrontDesk) -> String {
+    let code = loop {
+        let code = new_room_code();
+        if !desk.rooms.contains_key(&code) {
+            break code;
+        }
+    };
+
+    let mut state = State::new(code.clone(), desk.level.clone());
+    spawn_items(&mut state);
+    open_room(desk, state);
+    code
+}
+
+/// Opens a room from another server's snapshot and waits for its members to come back.
+fn restore_room(desk: &mut FrontDesk, snapshot: RoomSnapshot) {
+    let state = restore(snapshot, desk.level.clone());
+    // fresh connections must not collide with the ids people are coming back with
+    let next_id = state
+        .roster
+        .values()
+        .filter(|entry| !entry.bot)
+        .map(|entry| entry.client_id)
+        .max()
+        .map_or(0, |max| max + 1);
+    NEXT_CONNECTION_ID.fetch_max(next_id, std::sync::atomic::Ordering::SeqCst);
+    for entry in state.roster.values().filter(|entry| !entry.bot) {
+        // nobody can prove a claim to an id the old server gave no token for, so it is
+        // only waited for to be given up on
+        let token = state.rejoin_tokens.get(&entry.client_id).copied();
+        desk.awaiting_rejoin
+            .insert(entry.client_id, (state.code.clone(), token));
+    }
+    desk.rejoin_deadline =
+        Some(Instant::now() + Duration::from_millis(MIGRATION_REJOIN_TIMEOUT_MS));
+    println!("room {} restored", state.code);
+    open_room(desk, state);
+}
+
+/// Whoever has not rejoined by now is not coming back.
+fn give_up_on_rejoins(desk: &mut FrontDesk) {
+    desk.rejoin_deadline = None;
+    for (client_id, (code, _)) in desk.awaiting_rejoin.drain() {
+        let Some(room) = desk.rooms.get(&code) else {
+            continue;
+        };
+        let message_bundle = ClientToServerMessageBundle::new(
+            client_id,
+            ClientToServerMessage::new(ClientToServerMessageData::Disconnect),
+        );
+        deliver(room, message_bundle);
+    }
+    // restored rooms nobody came back to
+    let client_rooms = &desk.client_rooms;
+    desk.rooms.retain(|code, room| {
+        let occupied = client_rooms.values().any(|other| other == code);
+        if !occupied {
+            room.closed.store(true, std::sync::atomic::Ordering::SeqCst);
+        }
+        occupied
+    });
+}
+
+fn open_room(desk: &mut FrontDesk, mut state: State) {
+    let code = state.code.clone();
+    state.local_client = desk.local_client;
+    let room = Arc::new(Room {
+        code: code.clone(),
+        inbox: Arc::new(Mailbox::with_sender_limit(
+            ROOM_INBOX_CAPACITY,
+            INBOUND_CLIENT_LIMIT,
+        )),
+        info: RwLock::new(state.room_info()),
+        closed: AtomicBool::new(false),
+    });
+    desk.rooms.insert(code.clone(), room.clone());
+    tokio::spawn(async move {
+        super::game::main_loop(&mut state, room).await;
+    });
+    println!("room {} opened", code);
+}
+
+async fn send_room_list(desk: &FrontDesk, client_id: u32) {
+    let mut rooms = Vec::new();
+    for room in desk.rooms.values() {
+        rooms.push(room.info.read().await.clone());
+    }
+    // rooms still in the lobby first, then by code
+    rooms.sort_by(|a, b| {
+        (a.phase != MatchPhase::Lobby, &a.code).cmp(&(b.phase != MatchPhase::Lobby, &b.code))
+    });
+    send_to_one_client(client_id, ServerToClientMessage::RoomList { rooms }).await;
+}
+
+/// A few letters that are easy to read out to a friend.
+fn new_room_code() -> String {
+    const LETTERS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ";
+    uuid::Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(ROOM_CODE_LENGTH)
+        .map(|byte| LETTERS[*byte as usize % LETTERS.len()] as char)
+        .collect()
+}
@@ -0,0 +1,41 @@
+use std::collections::BTreeMap;
+
+use crate::common::game_objects::RosterEntry;
+
+use super::settings::MAX_NAME_LENGTH;
+
+/// Keeps letters, digits, spaces, `-` and `_`, and caps the length.
+/// Returns `None` if nothing usable is left.
+pub fn validate_name(requested: &str) -> Option<String> {
+    let cleaned: String = requested
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+        .take(MAX_NAME_LENGTH)
+        .collect();
+    let cleaned = cleaned.trim();
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned.to_string())
+    }
+}
+
+/// Appends a number if someone else already goes by this name.
+pub fn unique_name(roster: &BTreeMap<u32, RosterEntry>, client_id: u32, name: String) -> String {
+    let taken = |candidate: &str| {
+        roster
+            .values()
+            .any(|entry| entry.client_id != client_id && entry.name.eq_ignore_ascii_case(candidate))
+    };
+    if !taken(&name) {
+        return name;
+    }
+    (2..)
+        .map(|n| {
+            let suffix = n.to_string();
+            let stem: String = name.chars().take(MAX_NAME_LENGTH - suffix.len()).collect();
+            format!("{}{}", stem, suffix)
+        })
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
@@ -1 +1,129 @@
+pub const DEFAULT_LEVEL: &str = "arena";
+pub const WALL_GRID_CELL_SIZE: f32 = 32.0;
 
+pub const ENEMY_SPEED: f32 = 0.5;
+pub const ENEMY_CONTACT_DAMAGE: u32 = 10;
+pub const ENEMY_ATTACK_COOLDOWN: u32 = 30;
+
+/// Each client is sent the enemy positions that matter most to them every this many steps
+/// to begin with. Slow connections get them less often, down to the max.
+pub const SNAPSHOT_INTERVAL_STEPS: u32 = 4;
+pub const SNAPSHOT_MIN_INTERVAL_STEPS: u32 = 2;
+pub const SNAPSHOT_MAX_INTERVAL_STEPS: u32 = 12;
+/// Part of a client's byte budget that snapshots may take.
+pub const SNAPSHOT_SHARE: f32 = 0.5;
+/// Clients only hear about enemies in the grid cells within this far of their player.
+pub const INTEREST_RADIUS: f32 = 192.0;
+pub const INTEREST_CELL_SIZE: f32 = 64.0;
+/// Extra priority an enemy right next to a player gets over one at the edge of the area.
+pub const INTEREST_NEAR_BONUS: f32 = 3.0;
+
+pub const ENEMIES_PER_WAVE: u32 = 3;
+pub const INTERMISSION_STEPS: u32 = 5 * 60;
+
+/// Upper bound on what a single reported hit may do, so clients can't one-shot everything.
+pub const MAX_HIT_DAMAGE: u32 = 20;
+/// Hits are checked against where the shooter saw their target, at most this many steps ago.
+pub const LAG_COMPENSATION_MAX_REWIND_STEPS: u32 = 18;
+/// How far outside an enemy a reported hit may land and still count. Covers the
+/// projectile's own size and enemy positions only going out every few steps.
+pub const LAG_COMPENSATION_HIT_SLACK: f32 = 6.0;
+/// Shots a client may fire back to back before it has to wait out `FIRE_COOLDOWN`, for
+/// shots that reach the server bunched up.
+pub const FIRE_BURST: u32 = 3;
+/// Hits are only believed from shots fired at most this many steps before.
+pub const SHOT_LIFETIME_STEPS: u32 = 240;
+/// How far from where the server has a player a shot of theirs may start.
+pub const FIRE_POSITION_SLACK: f32 = 48.0;
+/// How much further than the client allows an item may be grabbed or let go of, as player
+/// positions only reach the server every few steps.
+pub const GRAB_SLACK: f32 = 24.0;
+pub const REVIVE_RANGE: f32 = 40.0;
+pub const REVIVE_HP: u32 = 50;
+
+/// Longer chat messages are cut off, in characters.
+pub const MAX_CHAT_LENGTH: usize = 120;
+/// Messages a client may send back to back before it has to slow down.
+pub const CHAT_BURST: u32 = 4;
+/// One more message is allowed after every this many milliseconds.
+pub const CHAT_REFILL_MS: i64 = 1500;
+
+pub const MAX_NAME_LENGTH: usize = 16;
+/// How long the results stay up before everyone is sent back to the lobby.
+pub const RESULTS_STEPS: u32 = 15 * 60;
+
+pub const ROOM_CODE_LENGTH: usize = 4;
+
+/// Shown in server browsers unless a name is given on the command line.
+pub const DEFAULT_SERVER_NAME: &str = "shootogethorthings server";
+
+/// The room names a successor and sends it a snapshot every this many steps.
+pub const MIGRATION_INTERVAL_STEPS: u32 = 60;
+/// How long a server that took over waits for the old room's clients to come back.
+pub const MIGRATION_REJOIN_TIMEOUT_MS: u64 = 10_000;
+
+/// Messages from every client waiting for the front desk, and for each room.
+pub const INBOUND_MAILBOX_CAPACITY: usize = 32;
+pub const ROOM_INBOX_CAPACITY: usize = 64;
+/// Messages one client may have waiting in either of those. A client that sends more than
+/// the server gets through is disconnected.
+pub const INBOUND_CLIENT_LIMIT: usize = 256;
+/// Messages waiting to go out to one client. Reliable ones queue up past this when it is
+/// full, and a client with more than `CLIENT_BACKLOG_LIMIT` waiting is disconnected.
+pub const CLIENT_MAILBOX_CAPACITY: usize = 100;
+pub const CLIENT_BACKLOG_LIMIT: usize = 1000;
+
+/// Bytes per second each client may be sent. Starts at the first and moves between the
+/// other two with how the connection holds up.
+pub const LINK_START_BYTES_PER_SECOND: f32 = 16_000.0;
+pub const LINK_MIN_BYTES_PER_SECOND: f32 = 4_000.0;
+pub const LINK_MAX_BYTES_PER_SECOND: f32 = 64_000.0;
+/// Unspent budget carries over for at most this long, so a quiet client can't save up a flood.
+pub const LINK_BURST_MS: i64 = 100;
+/// Connections are re-evaluated this often.
+pub const LINK_ADJUST_INTERVAL_MS: i64 = 1000;
+/// Added to the budget every adjustment that finds the connection healthy.
+pub const LINK_INCREASE_BYTES_PER_SECOND: f32 = 2_000.0;
+/// The budget is multiplied by this when the connection looks congested.
+pub const LINK_DECREASE_FACTOR: f32 = 0.75;
+/// A connection that has to resend more than this share of its reliable messages is congested.
+pub const LINK_LOSS_LIMIT: f32 = 0.05;
+/// So is one whose round trip grew this much over the best it has done, in milliseconds.
+pub const LINK_RTT_RISE_MS: f32 = 50.0;
+/// Per-client throughput is printed this often.
+pub const SERVER_STATS_INTERVAL_MS: i64 = 10_000;
+/// Agree to compress packets with clients that ask for it.
+pub const ALLOW_COMPRESSION: bool = true;
+/// Let clients in that skip the key exchange. Whatever a client with a secure connection
+/// sends in the clear is ignored either way.
+pub const ALLOW_PLAINTEXT_CLIENTS: bool = true;
+
+/// Rooms send clients a checksum of their world every this many steps.
+pub const CHECKSUM_INTERVAL_STEPS: u32 = 30;
+
+/// Every match is written to a replay file in `REPLAY_DIR`.
+pub const RECORD_REPLAYS: bool = true;
+pub const REPLAY_DIR: &str = "replays";
+/// Replays hold a full copy of the room every this many steps, for seeking.
+pub const REPLAY_KEYFRAME_INTERVAL_STEPS: u32 = 300;
+
+/// Rollback sessions are only offered to rooms with at most this many people in them.
+pub const ROLLBACK_MAX_PLAYERS: usize = 4;
+
+/// Rooms are topped up with bots until this many players are in.
+pub const CO_OP_SLOTS: usize = 4;
+/// Bots are numbered from here up, in each room on its own, so they never take an id a
+/// connection could get.
+pub const FIRST_BOT_CLIENT_ID: u32 = 1 << 31;
+/// Bots move like a client's player would.
+pub const BOT_SPEED: f32 = 2.0;
+pub const BOT_FIRE_COOLDOWN: u32 = 8;
+pub const BOT_PROJECTILE_SPEED: f32 = 5.0;
+pub const BOT_PROJECTILE_RADIUS: f32 = 2.0;
+pub const BOT_PROJECTILE_DAMAGE: u32 = 10;
+pub const BOT_PROJECTILE_STEPS: u32 = 90;
+/// Bots shoot at enemies closer than this.
+pub const BOT_SIGHT: f32 = 160.0;
+/// Bots catch up with their leader once they fall further behind than this.
+pub const BOT_FOLLOW_DISTANCE: f32 = 48.0;
+pub const BOT_POSITION_BROADCAST_INTERVAL: u32 = 4;
@@ -1,27 +1,254 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::Hasher,
+};
 
-use crate::common::game_objects::Player;
+use glam::Vec2;
+
+use crate::common::{
+    checksum::{entity_digests, Checksum, EntityDigest},
+    collision::circle_vs_rect,
+    game_objects::{
+        Enemy, Item, MatchPhase, Player, PlayerStats, RoomInfo, RosterEntry, SyncMode,
+        PLAYER_COLOR_COUNT,
+    },
+    level::{Level, Tile},
+    rng::Rng,
+    server_to_client::ServerToClientMessage,
+    spatial_hash::SpatialHash,
+};
+
+use super::{
+    bots::Bots,
+    chat::ChatLimiter,
+    lag_compensation::{Gunfire, History},
+    relevancy::Interest,
+    replay::Recorder,
+    settings::WALL_GRID_CELL_SIZE,
+    waves::Waves,
+};
 
 pub struct State {
-    pub time_since_last_update: f32,
+    /// Code of the room this world belongs to.
+    pub code: String,
+    /// Clients in the room. Everything this world broadcasts goes to them.
+    pub members: BTreeSet<u32>,
+    /// Client running this server in its own process. It goes down along with the server,
+    /// so it is never named successor.
+    pub local_client: Option<u32>,
+
+    pub level: Level,
+    /// Level walls as (center, half extents), bucketed in `wall_grid` by index.
+    pub walls: Vec<(Vec2, Vec2)>,
+    pub wall_grid: SpatialHash<usize>,
+
     pub next_id: u32,
     pub next_eid: u32,
-    pub players: HashMap<u32, Player>,
+    pub step_count: u32,
+    /// Everything random in the world is drawn from here, so the seed and the inputs
+    /// decide how a match goes.
+    pub rng: Rng,
+    pub phase: MatchPhase,
+    /// Steps spent in the current phase.
+    pub phase_steps: u32,
+    pub sync_mode: SyncMode,
+    /// Keyed by client id, so it lists people in the order they joined.
+    pub roster: BTreeMap<u32, RosterEntry>,
+    pub host: Option<u32>,
+    // ordered maps, so every step visits entities in the same order
+    pub players: BTreeMap<u32, Player>,
+    pub items: BTreeMap<u32, Item>,
+    pub enemies: BTreeMap<u32, Enemy>,
+    pub waves: Waves,
+    pub bots: Bots,
+    /// Recent enemy positions, for checking hits from lagging clients.
+    pub history: History,
+    /// Keyed by client id: the shots each member fired lately.
+    pub gunfire: HashMap<u32, Gunfire>,
+    /// Keyed by client id: the secret each member needs to rejoin a server that takes over.
+    pub rejoin_tokens: HashMap<u32, u128>,
+
+    /// Keyed by client id. Kept after a client leaves so the results still show them.
+    pub stats: BTreeMap<u32, PlayerStats>,
+    pub stats_dirty: bool,
+
+    pub chat_limiters: HashMap<u32, ChatLimiter>,
+    /// Keyed by client id: which enemies each member is owed updates on.
+    pub interests: HashMap<u32, Interest>,
+
+    /// Messages produced during a step, broadcast to everyone once the step is done.
+    pub outbox: Vec<ServerToClientMessage>,
+    /// Set while a match is being recorded.
+    pub recorder: Option<Recorder>,
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(code: String, level: Level) -> Self {
+        let walls = level.wall_boxes();
+        let mut wall_grid = SpatialHash::new(WALL_GRID_CELL_SIZE);
+        for (index, (center, half_extents)) in walls.iter().enumerate() {
+            wall_grid.insert(index, *center - *half_extents, *center + *half_extents);
+        }
+
+        let rng = Rng::from_str_seed(&code);
         Self {
-            time_since_last_update: 0.0,
+            code,
+            members: BTreeSet::new(),
+            local_client: None,
+
+            level,
+            walls,
+            wall_grid,
+
             next_id: 0,
             next_eid: 0,
-            players: HashMap::new(),
+            step_count: 0,
+            rng,
+            phase: MatchPhase::Lobby,
+            phase_steps: 0,
+            sync_mode: SyncMode::Snapshot,
+            roster: BTreeMap::new(),
+            host: None,
+            players: BTreeMap::new(),
+            items: BTreeMap::new(),
+            enemies: BTreeMap::new(),
+            waves: Waves::new(),
+            bots: Bots::new(),
+            history: History::new(),
+            gunfire: HashMap::new(),
+            rejoin_tokens: HashMap::new(),
+
+            stats: BTreeMap::new(),
+            stats_dirty: false,
+
+            chat_limiters: HashMap::new(),
+            interests: HashMap::new(),
+
+            outbox: Vec::new(),
+            recorder: None,
         }
     }
-}
 
-impl Default for State {
-    fn default() -> Self {
-        Self::new()
+    pub fn take_eid(&mut self) -> u32 {
+        let eid = self.next_eid;
+        self.next_eid += 1;
+        eid
+    }
+
+    /// Next player spawn tile, handed out round robin. The level center if it has none.
+    pub fn player_spawn_point(&self) -> Vec2 {
+        let spawns = self.level.tile_centers(Tile::PlayerSpawn);
+        if spawns.is_empty() {
+            return self.level.bounds() / 2.0;
+        }
+        spawns[self.players.len() % spawns.len()]
+    }
+
+    /// Moves a circle out of any walls it overlaps.
+    pub fn push_out_of_walls(&self, mut pos: Vec2, radius: f32) -> Vec2 {
+        let reach = Vec2::splat(radius);
+        for index in self.wall_grid.query(pos - reach, pos + reach) {
+            let (center, half_extents) = self.walls[index];
+            if let Some(push) = circle_vs_rect(pos, radius, center, half_extents) {
+                pos += push;
+            }
+        }
+        pos.clamp(reach, self.level.bounds() - reach)
+    }
+
+    /// Display name of a client, falling back to its id before it has joined.
+    pub fn name_of(&self, client_id: u32) -> String {
+        self.roster
+            .get(&client_id)
+            .map(|entry| entry.name.clone())
+            .unwrap_or_else(|| format!("client {}", client_id))
+    }
+
+    pub fn room_info(&self) -> RoomInfo {
+        RoomInfo {
+            code: self.code.clone(),
+            players: self.members.len() as u32,
+            level_id: self.level.id.clone(),
+            phase: self.phase,
+        }
+    }
+
+    /// First color nobody in the roster has picked yet.
+    pub fn free_color(&self) -> u8 {
+        (0..PLAYER_COLOR_COUNT)
+            .find(|color| !self.roster.values().any(|entry| entry.color == *color))
+            .unwrap_or(0)
+    }
+
+    pub fn roster_message(&self) -> ServerToClientMessage {
+        ServerToClientMessage::Roster {
+            players: self.roster.values().cloned().collect(),
+            host: self.host,
+        }
+    }
+
+    /// Roster entries played by people rather than bots.
+    pub fn human_count(&self) -> usize {
+        self.roster.values().filter(|entry| !entry.bot).count()
+    }
+
+    /// Entity id of the player owned by this client, if it has spawned one.
+    pub fn player_of_client(&self, client_id: u32) -> Option<u32> {
+        self.players
+            .values()
+            .find(|player| player.owner_client_id == client_id)
+            .map(|player| player.entity_id)
+    }
+
+    pub fn stats_mut(&mut self, client_id: u32) -> &mut PlayerStats {
+        self.stats_dirty = true;
+        self.stats
+            .entry(client_id)
+            .or_insert_with(|| PlayerStats::new(client_id))
+    }
+
+    pub fn scoreboard(&self) -> Vec<PlayerStats> {
+        let mut stats: Vec<PlayerStats> = self.stats.values().copied().collect();
+        stats.sort_by_key(|stats| stats.client_id);
+        stats
+    }
+
+    /// Hash of everything the simulation decides. Client ids are left out: they come
+    /// from the process-wide connection counter, not from the room.
+    pub fn world_hash(&self) -> u64 {
+        let mut hash = Checksum::new();
+        hash.write_u32(self.step_count);
+        hash.write_u64(self.rng.state());
+        hash.write_u8(self.phase as u8);
+        hash.write_u32(self.waves.number);
+        for player in self.players.values() {
+            hash.write_u32(player.entity_id);
+            hash.write_vec2(player.pos);
+            hash.write_vec2(player.vel);
+            hash.write_u32(player.hp);
+            hash.write_u8(player.downed as u8);
+        }
+        for item in self.items.values() {
+            hash.write_u32(item.entity_id);
+            hash.write_vec2(item.pos);
+            hash.write_u32(item.held_by.unwrap_or(u32::MAX));
+        }
+        for enemy in self.enemies.values() {
+            hash.write_u32(enemy.entity_id);
+            hash.write_vec2(enemy.pos);
+            hash.write_u32(enemy.hp);
+            hash.write_u32(enemy.attack_cooldown);
+        }
+        for shot in &self.bots.shots {
+            hash.write_vec2(shot.pos);
+            hash.write_vec2(shot.vel);
+            hash.write_u32(shot.steps_left);
+        }
+        hash.finish()
+    }
+
+    /// Sorted, as `world_checksum` wants them.
+    pub fn entity_digests(&self) -> Vec<EntityDigest> {
+        entity_digests(self.players.values(), self.items.values())
     }
 }
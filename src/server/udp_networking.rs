@@ -1,10 +1,10 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     net::SocketAddr,
     sync::{atomic::AtomicBool, Arc},
 };
 
-use crossbeam::queue::ArrayQueue;
 use lazy_static::lazy_static;
 use tokio::{
     io::{self},
@@ -12,39 +12,65 @@ use tokio::{
     sync::RwLock,
 };
 
-use super::client_bookkeeping::{CLIENT_ID_TO_SOCKET_ADDRESS, CLIENT_OUTBOUND_MAILBOXES};
+use super::{
+    bandwidth::report_links,
+    client_bookkeeping::{
+        CLIENT_ID_TO_SOCKET_ADDRESS, CLIENT_LINKS, CLIENT_OUTBOUND_MAILBOXES,
+        CLIENT_RELIABLE_RECEIVERS, CLIENT_RELIABLE_SENDERS, CLIENT_SESSIONS, LOCAL_CLIENTS,
+    },
+    settings::{
+        ALLOW_COMPRESSION, ALLOW_PLAINTEXT_CLIENTS, INBOUND_CLIENT_LIMIT, INBOUND_MAILBOX_CAPACITY,
+        SERVER_STATS_INTERVAL_MS,
+    },
+};
 use crate::{
     common::{
-        client_to_server::{ClientToServerMessage, ClientToServerMessageBundle},
-        network_settings::CLIENT_CONNECT_TO_ADDR,
+        client_to_server::{
+            ClientToServerMessage, ClientToServerMessageBundle, ClientToServerMessageData,
+        },
+        compression,
+        mailbox::{Mailbox, Overflow},
+        secure::{self, Announcement, Datagram, ServerKey, Session},
+        server_to_client::{ServerToClientMessage, ServerToClientPacket},
+        util::get_utc_now,
+    },
+    server::client_bookkeeping::{
+        add_client, disconnect_flooding_client, SOCKET_ADDRESS_TO_CLIENT_ID,
     },
-    server::client_bookkeeping::{add_client, SOCKET_ADDRESS_TO_CLIENT_ID},
 };
 
 lazy_static! {
-    pub static ref INCOMING_MESSAGE_QUEUE: Arc<ArrayQueue<ClientToServerMessageBundle>> =
-        Arc::new(ArrayQueue::new(32));
+    pub static ref INCOMING_MESSAGE_QUEUE: Arc<Mailbox<ClientToServerMessageBundle>> =
+        Arc::new(Mailbox::with_sender_limit(INBOUND_MAILBOX_CAPACITY, INBOUND_CLIENT_LIMIT));
     pub static ref CLIENT_DISCONNECTED: Arc<RwLock<HashMap<u32, Arc<AtomicBool>>>> =
         Arc::new(RwLock::new(HashMap::new()));
+    /// Made up fresh every run. Clients have nothing to check it against, so it keeps out
+    /// whoever merely knows their address, not a man in the middle.
+    static ref SERVER_KEY: ServerKey = ServerKey::generate();
 }
 
 ////////////////////////    CLIENT RX/TX TASKS    ////////////////////////
 
-pub async fn init() -> tokio::io::Result<()> {
+/// Binds the game socket and starts the rx/tx tasks. Returns the address actually bound.
+pub async fn init(bind_addr: SocketAddr) -> tokio::io::Result<SocketAddr> {
     println!("Initializing socket...");
-    let socket = Arc::new(UdpSocket::bind(CLIENT_CONNECT_TO_ADDR).await.unwrap());
-    println!("Socket Initialized!");
+    let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+    let local_addr = socket.local_addr()?;
+    println!("Socket Initialized on {}!", local_addr);
+    println!("Server key {}", secure::fingerprint(&SERVER_KEY.public));
     println!("Spawning rx/tx tasks...");
     tokio::spawn(continuously_read_any_inbound_messages(socket.clone()));
     tokio::spawn(continuously_transmit_any_outbound_messages(socket.clone()));
-    Ok(())
+    Ok(local_addr)
 }
 
 pub async fn continuously_read_any_inbound_messages(socket: Arc<UdpSocket>) -> io::Result<()> {
     println!("Listening for incoming messages...");
-    let mut buffer = [0; 1024];
+    // as big as a datagram can get, anything smaller gets cut short
+    let mut buffer = [0; 65536];
     loop {
         let (nbytes, socket_address) = socket.recv_from(&mut buffer).await?;
+        let datagram = &buffer[..nbytes];
 
         // check if new client
         let maybe_client_id: Option<u32> = {
@@ -53,20 +79,52 @@ pub async fn continuously_read_any_inbound_messages(socket: Arc<UdpSocket>) -> i
                 .get(&socket_address)
                 .copied()
         };
-        let client_id = match maybe_client_id {
-            Some(client_id) => client_id,
-            None => add_client(socket_address).await,
+        let (client_id, packet) = match secure::classify(datagram) {
+            Datagram::KeyRequest => {
+                // answered without a trace, anyone may ask
+                let reply = SERVER_KEY.reply(socket_address, get_utc_now());
+                if let Err(e) = socket.send_to(&reply, socket_address).await {
+                    eprintln!("Send error: {}", e);
+                }
+                continue;
+            }
+            Datagram::Sealed { announced } => {
+                match open_sealed(datagram, announced, maybe_client_id, socket_address).await {
+                    Some((client_id, packet)) => (client_id, Cow::Owned(packet)),
+                    None => continue,
+                }
+            }
+            Datagram::Plain => match accept_plaintext(maybe_client_id, socket_address).await {
+                Some(client_id) => (client_id, Cow::Borrowed(datagram)),
+                None => continue,
+            },
+            Datagram::KeyReply { .. } | Datagram::Invalid => continue,
         };
 
-        let result: Result<ClientToServerMessage, _> = bincode::deserialize(&buffer[..nbytes]);
+        let Some(bytes) = compression::unpack(&packet) else {
+            eprintln!("Malformed packet from client {}", client_id);
+            continue;
+        };
+        let result: Result<ClientToServerMessage, _> = bincode::deserialize(&bytes);
         match result {
             Ok(result) => {
-                let message_bundle = ClientToServerMessageBundle::new(client_id, result);
-                if INCOMING_MESSAGE_QUEUE.push(message_bundle).is_err() {
-                    eprintln!(
-                        "Inbound message queue full: dropping message from {}",
-                        client_id
-                    );
+                for message in accept_reliability(client_id, result).await {
+                    if let ClientToServerMessageData::Hello { compression } = message.data {
+                        answer_hello(client_id, compression).await;
+                        continue;
+                    }
+                    if let ClientToServerMessageData::Receipt { up_to, count } = message.data {
+                        if let Some(link) = CLIENT_LINKS.write().await.get_mut(&client_id) {
+                            link.on_receipt(up_to, count);
+                        }
+                        continue;
+                    }
+                    let message_bundle = ClientToServerMessageBundle::new(client_id, message);
+                    // dropped unreliable messages are counted by the queue
+                    if let Err(Overflow::Flooded) = INCOMING_MESSAGE_QUEUE.push(message_bundle) {
+                        disconnect_flooding_client(client_id).await;
+                        break;
+                    }
                 }
             }
             Err(e) => {
@@ -77,12 +135,128 @@ pub async fn continuously_read_any_inbound_messages(socket: Arc<UdpSocket>) -> i
     }
 }
 
+/// Opens a sealed packet with the sender's session. A sender without one becomes a client
+/// once its packet opens with the key it announced; until then nothing is kept for it, so
+/// forged packets cost no more than the attempt to open them. The cookie it announced has
+/// to be the one sent to its address, so it can't be someone else's packet sent again from
+/// elsewhere, nor come from an address that never asked for the key.
+async fn open_sealed(
+    datagram: &[u8],
+    announced: Option<Announcement>,
+    maybe_client_id: Option<u32>,
+    socket_address: SocketAddr,
+) -> Option<(u32, Vec<u8>)> {
+    if let Some(client_id) = maybe_client_id {
+        let mut sessions_write = CLIENT_SESSIONS.write().await;
+        let packet = sessions_write.get_mut(&client_id)?.open(datagram)?;
+        return Some((client_id, packet));
+    }
+    let announced = announced?;
+    if !SERVER_KEY.accepts(&announced.cookie, socket_address, get_utc_now()) {
+        return None;
+    }
+    // every session has a key of its own, whoever shows up with one in use copied it
+    let in_use = CLIENT_SESSIONS
+        .read()
+        .await
+        .values()
+        .any(|session| *session.client_public() == announced.public);
+    if in_use {
+        return None;
+    }
+    let mut session = Session::server(&SERVER_KEY, announced.public)?;
+    let packet = session.open(datagram)?;
+    let client_id = add_client(socket_address, Some(session)).await;
+    Some((client_id, packet))
+}
+
+/// Who sent a packet in the clear, if it is to be believed. Nothing from a client with a
+/// session is, since anyone could have sent it in their name.
+async fn accept_plaintext(maybe_client_id: Option<u32>, socket_address: SocketAddr) -> Option<u32> {
+    match maybe_client_id {
+        Some(client_id) if CLIENT_SESSIONS.read().await.contains_key(&client_id) => None,
+        Some(client_id) => Some(client_id),
+        None if ALLOW_PLAINTEXT_CLIENTS => Some(add_client(socket_address, None).await),
+        None => None,
+    }
+}
+
+/// Handles acks in both directions. Returns the messages that are ready for the game, in
+/// the order the client sent them.
+async fn accept_reliability(
+    client_id: u32,
+    message: ClientToServerMessage,
+) -> Vec<ClientToServerMessage> {
+    if let ClientToServerMessageData::Ack { seq } = message.data {
+        let rtt = {
+            let mut senders_write = CLIENT_RELIABLE_SENDERS.write().await;
+            senders_write
+                .get_mut(&client_id)
+                .and_then(|sender| sender.ack(seq, get_utc_now()))
+        };
+        if let Some(rtt) = rtt {
+            let mut links_write = CLIENT_LINKS.write().await;
+            if let Some(link) = links_write.get_mut(&client_id) {
+                link.on_rtt(rtt);
+            }
+        }
+        return Vec::new();
+    }
+
+    let Some(seq) = message.reliable_seq else {
+        return vec![message];
+    };
+
+    let ready = {
+        let mut receivers_write = CLIENT_RELIABLE_RECEIVERS.write().await;
+        match receivers_write.get_mut(&client_id) {
+            Some(receiver) => receiver.accept(seq, message),
+            None => Some(vec![message]),
+        }
+    };
+    let Some(ready) = ready else {
+        return Vec::new();
+    };
+
+    // ack every copy, since the previous ack may have been lost
+    let clients_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
+    if let Some(queue) = clients_read.get(&client_id) {
+        let _ = queue.push(ServerToClientMessage::Ack { seq });
+    }
+    ready
+}
+
+/// Settles what the client asked for in its `Hello`, and tells it what was agreed on.
+async fn answer_hello(client_id: u32, compression: bool) {
+    let compression = compression && ALLOW_COMPRESSION;
+    if let Some(link) = CLIENT_LINKS.write().await.get_mut(&client_id) {
+        link.compression = compression;
+    }
+    let clients_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
+    if let Some(queue) = clients_read.get(&client_id) {
+        let _ = queue.push(ServerToClientMessage::Hello { compression });
+    }
+}
+
 pub async fn continuously_transmit_any_outbound_messages(socket: Arc<UdpSocket>) -> io::Result<()> {
+    let mut last_report = get_utc_now();
     // transmit any outbound messages
     loop {
+        let now = get_utc_now();
+        if now - last_report >= SERVER_STATS_INTERVAL_MS {
+            last_report = now;
+            report_links(now).await;
+        }
+
         // loop through every mailbox
         let clients_read = CLIENT_OUTBOUND_MAILBOXES.read().await;
+        let local_clients_read = LOCAL_CLIENTS.read().await;
         for (&client_id, queue) in clients_read.iter() {
+            // local clients empty their own mailboxes
+            if local_clients_read.contains(&client_id) {
+                continue;
+            }
+
             // is there a socket for this client?
             let maybe_socket_address: Option<SocketAddr> = {
                 let client_id_to_socket_address_read = CLIENT_ID_TO_SOCKET_ADDRESS.read().await;
@@ -96,26 +270,95 @@ pub async fn continuously_transmit_any_outbound_messages(socket: Arc<UdpSocket>)
 
             // if yes, send his messages
             if let Some(socket_address) = maybe_socket_address {
-                // send messages if theres a registered socket for this client
-                const MAX_MESSAGES_PER_CLIENT_FRAME: usize = 128;
-                let mut messages_sent_this_client = 0;
-                while let Some(message) = queue.pop() {
-                    // dont let one noisy client clog up message processing
-                    if messages_sent_this_client >= MAX_MESSAGES_PER_CLIENT_FRAME {
+                let mut links_write = CLIENT_LINKS.write().await;
+                let Some(link) = links_write.get_mut(&client_id) else {
+                    continue;
+                };
+                link.refill(now);
+                link.adjust(now);
+
+                // anything the client hasn't acked in a while goes out again, first since
+                // the client can't go on past it, and on the same budget; what doesn't fit
+                // waits for the next pass
+                while link.has_budget() {
+                    let resend = {
+                        let mut senders_write = CLIENT_RELIABLE_SENDERS.write().await;
+                        senders_write
+                            .get_mut(&client_id)
+                            .and_then(|sender| sender.next_due(now))
+                    };
+                    let Some((seq, message)) = resend else {
                         break;
-                    }
-                    match bincode::serialize(&message) {
-                        Ok(binary_message) => {
-                            socket.send_to(&binary_message, socket_address).await?;
-                        }
-                        Err(e) => {
-                            eprintln!("Error serializing message: {:?}", e);
-                        }
-                    }
-                    messages_sent_this_client += 1;
+                    };
+                    let packet = ServerToClientPacket {
+                        seq: link.next_packet_seq(),
+                        reliable_seq: Some(seq),
+                        message,
+                    };
+                    let bytes = transmit_packet(
+                        &socket,
+                        &packet,
+                        client_id,
+                        socket_address,
+                        link.compression,
+                    )
+                    .await?;
+                    link.on_sent(bytes);
+                }
+
+                // dont let one noisy client clog up the connection, or their own
+                while link.has_budget() {
+                    let Some(message) = queue.pop() else {
+                        break;
+                    };
+                    let reliable_seq = if message.is_reliable() {
+                        let mut senders_write = CLIENT_RELIABLE_SENDERS.write().await;
+                        senders_write
+                            .get_mut(&client_id)
+                            .map(|sender| sender.track(message.clone(), now))
+                    } else {
+                        None
+                    };
+                    let packet = ServerToClientPacket {
+                        seq: link.next_packet_seq(),
+                        reliable_seq,
+                        message,
+                    };
+                    let bytes = transmit_packet(
+                        &socket,
+                        &packet,
+                        client_id,
+                        socket_address,
+                        link.compression,
+                    )
+                    .await?;
+                    link.on_sent(bytes);
                 }
             }
         }
         // tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
     }
 }
+
+/// Sealed if the client has a session. Returns the number of bytes sent.
+async fn transmit_packet(
+    socket: &UdpSocket,
+    packet: &ServerToClientPacket,
+    client_id: u32,
+    socket_address: SocketAddr,
+    compress: bool,
+) -> io::Result<usize> {
+    match bincode::serialize(packet) {
+        Ok(binary_message) => {
+            let mut datagram = compression::pack(&binary_message, compress);
+            if let Some(session) = CLIENT_SESSIONS.write().await.get_mut(&client_id) {
+                datagram = session.seal(&datagram);
+            }
+            socket.send_to(&datagram, socket_address).await
+        }
+        Err(e) => {
+            eprintln!("Error serializing message: {:?}", e);
+            Ok(0)
+        }
+    }
+}
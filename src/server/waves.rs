@@ -0,0 +1,147 @@
+use glam::Vec2;
+
+use crate::common::{
+    game_objects::{Enemy, ENEMY_RADIUS, PLAYER_MAX_HP},
+    level::Tile,
+    server_to_client::ServerToClientMessage,
+};
+
+use super::{
+    match_phase::end_match,
+    settings::{ENEMIES_PER_WAVE, INTERMISSION_STEPS},
+    state::State,
+};
+
+pub enum WavePhase {
+    Fighting,
+    Intermission { steps_left: u32 },
+}
+
+pub struct Waves {
+    pub number: u32,
+    pub phase: WavePhase,
+}
+
+impl Waves {
+    pub fn new() -> Self {
+        Self {
+            number: 0,
+            phase: WavePhase::Intermission {
+                steps_left: INTERMISSION_STEPS,
+            },
+        }
+    }
+}
+
+impl Default for Waves {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn step_waves(state: &mut State) {
+    match state.waves.phase {
+        WavePhase::Fighting => {
+            let all_down =
+                !state.players.is_empty() && state.players.values().all(|player| player.downed);
+            if state.enemies.is_empty() || all_down {
+                end_wave(state, !all_down);
+            }
+            // a failed wave is the end of the match
+            if all_down {
+                end_match(state);
+            }
+        }
+        WavePhase::Intermission { steps_left } => {
+            // nobody to fight
+            if state.players.is_empty() {
+                return;
+            }
+            if steps_left == 0 {
+                start_wave(state);
+            } else {
+                state.waves.phase = WavePhase::Intermission {
+                    steps_left: steps_left - 1,
+                };
+            }
+        }
+    }
+}
+
+fn start_wave(state: &mut State) {
+    state.waves.number += 1;
+    let wave = state.waves.number;
+    state.waves.phase = WavePhase::Fighting;
+    state
+        .outbox
+        .push(ServerToClientMessage::WaveStarted { wave });
+
+    let count = ENEMIES_PER_WAVE * wave;
+    let spawners = state.level.tile_centers(Tile::EnemySpawner);
+    // which spawner goes first, or how far round the edge
+    let shift = state.rng.unit();
+    for i in 0..count {
+        let eid = state.take_eid();
+        let pos = if spawners.is_empty() {
+            spawn_point(state.level.bounds(), i, count, shift)
+        } else {
+            let first = (shift * spawners.len() as f32) as usize;
+            spawners[(first + i as usize) % spawners.len()]
+        };
+        // members hear of it once it is near them
+        state.enemies.insert(eid, Enemy::new(eid, pos));
+    }
+    println!("wave {} started with {} enemies", wave, count);
+}
+
+fn end_wave(state: &mut State, cleared: bool) {
+    for eid in std::mem::take(&mut state.enemies).into_keys() {
+        state
+            .outbox
+            .push(ServerToClientMessage::DespawnEntity { entity_id: eid });
+    }
+
+    // everyone gets back up for the next one
+    for player in state.players.values_mut() {
+        if player.downed || player.hp < PLAYER_MAX_HP {
+            player.downed = false;
+            player.hp = PLAYER_MAX_HP;
+            state.outbox.push(ServerToClientMessage::PlayerHealth {
+                entity_id: player.entity_id,
+                hp: player.hp,
+                downed: player.downed,
+            });
+        }
+    }
+
+    let wave = state.waves.number;
+    state.waves.phase = WavePhase::Intermission {
+        steps_left: INTERMISSION_STEPS,
+    };
+    state.outbox.push(ServerToClientMessage::WaveEnded {
+        wave,
+        cleared,
+        stats: state.scoreboard(),
+    });
+    println!("wave {} ended, cleared: {}", wave, cleared);
+}
+
+/// For levels without spawners: spreads spawns evenly around the edge of the level,
+/// starting `shift` of the way round.
+fn spawn_point(bounds: Vec2, index: u32, count: u32, shift: f32) -> Vec2 {
+    let inset = Vec2::splat(ENEMY_RADIUS);
+    let size = bounds - inset * 2.0;
+    let perimeter = 2.0 * (size.x + size.y);
+    let along = ((index as f32 + 0.5) / count as f32 + shift).fract() * perimeter;
+
+    let edge = if along < size.x {
+        Vec2::new(along, 0.0)
+    } else if along < size.x + size.y {
+        Vec2::new(size.x, along - size.x)
+    } else if along < 2.0 * size.x + size.y {
+        Vec2::new(2.0 * size.x + size.y - along, size.y)
+    } else {
+        Vec2::new(0.0, perimeter - along)
+    };
+    inset + edge
+}
@@ -0,0 +1,103 @@
+mod bots;
+mod client;
+mod common;
+mod server;
+
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use bots::bot::{Bot, Script};
+use common::{game_objects::SyncMode, tick::FRAMES_PER_SECOND};
+
+/// Desync is sampled this often, in ticks.
+const DESYNC_SAMPLE_INTERVAL: u32 = 30;
+
+#[tokio::main]
+async fn main() {
+    // bots [count] [server] [seconds] [bots per room] [--circle] [--rollback]
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    let script = if std::env::args().any(|arg| arg == "--circle") {
+        Script::Circle
+    } else {
+        Script::Random
+    };
+    let sync_mode = if std::env::args().any(|arg| arg == "--rollback") {
+        SyncMode::Rollback
+    } else {
+        SyncMode::Snapshot
+    };
+    let count: usize = args.first().and_then(|arg| arg.parse().ok()).unwrap_or(8);
+    let server = args
+        .get(1)
+        .map(String::as_str)
+        .unwrap_or(common::network_settings::SERVER_HOST_ADDR);
+    let seconds: u64 = args.get(2).and_then(|arg| arg.parse().ok()).unwrap_or(30);
+    let per_room: usize = args
+        .get(3)
+        .and_then(|arg| arg.parse().ok())
+        .unwrap_or(4)
+        .max(1);
+
+    let server_addr: SocketAddr = match server.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => addr,
+        _ => {
+            eprintln!("Could not resolve {}", server);
+            return;
+        }
+    };
+
+    println!(
+        "starting {} bots against {}, {} per room, for {}s",
+        count, server_addr, per_room, seconds
+    );
+    let mut bots = Vec::with_capacity(count);
+    for index in 0..count {
+        let leader = index % per_room == 0;
+        let group_size = per_room.min(count - index / per_room * per_room);
+        match Bot::connect(index, server_addr, script, sync_mode, leader, group_size).await {
+            Ok(bot) => bots.push(bot),
+            Err(e) => eprintln!("bot{} could not connect: {}", index, e),
+        }
+    }
+    // rooms are made of whichever bots got through, led by the first of them
+    for room in 0..count.div_ceil(per_room) {
+        let members: Vec<usize> = (0..bots.len())
+            .filter(|&index| bots[index].index / per_room == room)
+            .collect();
+        for (position, &index) in members.iter().enumerate() {
+            bots[index].leader = position == 0;
+            bots[index].group_size = members.len();
+        }
+    }
+
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs_f32(
+        1.0 / FRAMES_PER_SECOND as f32,
+    ));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let total_ticks = seconds as u32 * FRAMES_PER_SECOND;
+    for tick in 0..total_ticks {
+        interval.tick().await;
+
+        // followers go wherever their leader ended up
+        for index in 0..bots.len() {
+            if bots[index].leader {
+                continue;
+            }
+            let room = bots[index].index / per_room;
+            bots[index].target_room = bots
+                .iter()
+                .find(|bot| bot.leader && bot.index / per_room == room)
+                .and_then(|bot| bot.state.room.clone());
+        }
+        for bot in bots.iter_mut() {
+            bot.tick().await;
+        }
+        if tick.is_multiple_of(DESYNC_SAMPLE_INTERVAL) {
+            bots::report::measure_desync(&mut bots);
+        }
+    }
+
+    bots::report::print_report(&bots, seconds).await;
+}
@@ -2,69 +2,67 @@ use common::client_to_server::{ClientToServerMessage, ClientToServerMessageData}
 
 use client::{
     event_processing::process_events_and_input, message_processing::process_message_queue,
+    network_entities::interval_transmit_position, settings::POSITION_TRANSMIT_FREQUENCY,
     state::State,
 };
 use hecs::World;
+use std::net::SocketAddr;
 mod client;
 mod common;
 mod server;
 
-pub const FRAMES_PER_SECOND: u32 = 60;
-const TIMESTEP: f32 = 1.0 / FRAMES_PER_SECOND as f32;
-
-const POSITION_TRANSMIT_FREQUENCY: u32 = 4;
-
 #[tokio::main]
 async fn main() -> tokio::io::Result<()> {
-    let result = client::udp_networking::init_connection().await;
-    if let Err(e) = result {
-        eprintln!("Error connecting to server: {:?}", e);
-        return Ok(());
-    }
+    // client [name] [--host] [--replay file]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let hosting = args.iter().any(|arg| arg == "--host");
+    let replay = args
+        .iter()
+        .position(|arg| arg == "--replay")
+        .and_then(|index| args.get(index + 1))
+        .cloned();
+    let name = args
+        .iter()
+        .find(|arg| !arg.starts_with("--") && Some(*arg) != replay.as_ref())
+        .cloned()
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "player".to_string());
 
-    // request a new player
-    if client::udp_networking::OUTBOUND_MESSAGE_QUEUE
-        .push(ClientToServerMessage::new(
-            ClientToServerMessageData::RequestToSpawnPlayer,
-        ))
-        .is_err()
-    {
-        eprintln!("Outbound message queue full: dropping message");
-    }
+    let (mut rl, mut rlt, mut render_texture) = client::graphics::init_graphics();
 
-    // request all players
-    if client::udp_networking::OUTBOUND_MESSAGE_QUEUE
-        .push(ClientToServerMessage::new(
-            ClientToServerMessageData::RequestAllEntities {
-                from_client_id: client::udp_networking::CLIENT_ID
-                    .load(std::sync::atomic::Ordering::SeqCst),
-            },
-        ))
-        .is_err()
-    {
-        eprintln!("Outbound message queue full: dropping message");
+    if let Some(path) = replay {
+        watch_replay(&path, &mut rl, &mut rlt, &mut render_texture).await;
+        return Ok(());
     }
 
-    let (mut rl, mut rlt, mut render_texture) = client::graphics::init_graphics();
-
     ////////////////    MAIN LOOP    ////////////////
     let mut ecs = World::new();
     let mut state = client::state::State::new();
     let mut current_frame: u32 = 0;
+    let mut tick = common::tick::FixedTick::new();
+
+    if hosting {
+        host(&name, &mut state).await;
+    } else {
+        // look for servers until one is picked
+        tokio::spawn(client::discovery::discover_servers());
+    }
 
     while !rl.window_should_close() {
         process_events_and_input(&mut rl, &mut state);
+        if let Some(server_addr) = state.connect_to.take() {
+            connect(server_addr, &name, &mut state).await;
+        }
         process_message_queue(&mut ecs, &mut state).await;
+        if client::migration::host_lost(&state) {
+            client::migration::migrate(&mut ecs, &mut state, &name).await;
+        }
 
-        interval_transmit_position(current_frame, POSITION_TRANSMIT_FREQUENCY, &state);
-
-        let dt = rl.get_frame_time();
-        state.time_since_last_update += dt;
-        while state.time_since_last_update > TIMESTEP {
-            state.time_since_last_update -= TIMESTEP;
-
-            client::game::step(&mut ecs, &mut state);
+        for _ in 0..tick.due() {
+            client::game::advance(&mut ecs, &mut state);
             current_frame += 1;
+
+            interval_transmit_position(current_frame, POSITION_TRANSMIT_FREQUENCY, &ecs, &state);
         }
 
         client::graphics::render(&mut rl, &mut rlt, &mut render_texture, &ecs, &state);
@@ -76,24 +74,104 @@ async fn main() -> tokio::io::Result<()> {
     Ok(())
 }
 
-pub fn interval_transmit_position(_current_frame: u32, _interval: u32, _state: &State) {
-    // if current_frame % interval != 0 {
-    //     return;
-    // }
-    // for player in state.players.values() {
-    //     if let Some(client_id) = state.client_id {
-    //         if player.owner_client_id == client_id
-    //             && client::udp_networking::OUTBOUND_MESSAGE_QUEUE
-    //                 .push(ClientToServerMessage::new(
-    //                     ClientToServerMessageData::EntityPosition {
-    //                         entity_id: player.entity_id,
-    //                         pos: player.pos,
-    //                     },
-    //                 ))
-    //                 .is_err()
-    //         {
-    //             eprintln!("Outbound message queue full: dropping message");
-    //         }
-    //     }
-    // }
+/// Opens the connection to the server picked in the browser and introduces ourselves.
+pub async fn connect(server_addr: SocketAddr, name: &str, state: &mut State) {
+    let local_addr = match client::udp_networking::init_connection(server_addr).await {
+        Ok(local_addr) => local_addr,
+        Err(e) => {
+            eprintln!("Error connecting to server: {:?}", e);
+            return;
+        }
+    };
+    client::discovery::DISCOVERING.store(false, std::sync::atomic::Ordering::SeqCst);
+    state.server = Some(server_addr);
+    state.local_addr = Some(local_addr);
+    state.last_heard_from_server = common::util::get_utc_now();
+
+    if client::udp_networking::OUTBOUND_MESSAGE_QUEUE
+        .push(ClientToServerMessage::new(
+            ClientToServerMessageData::Join {
+                name: name.to_string(),
+            },
+        ))
+        .is_err()
+    {
+        eprintln!("Outbound message queue full: dropping message");
+    }
+}
+
+/// Runs the server in this process, still open to remote players, and joins it over a
+/// local channel. If it can't be started we end up in the server browser instead.
+pub async fn host(name: &str, state: &mut State) {
+    let level_id = server::settings::DEFAULT_LEVEL;
+    let level = match common::level::Level::load(level_id) {
+        Ok(level) => level,
+        Err(e) => {
+            eprintln!("Error loading level {}: {}", level_id, e);
+            tokio::spawn(client::discovery::discover_servers());
+            return;
+        }
+    };
+    let bind_addr = common::network_settings::CLIENT_CONNECT_TO_ADDR
+        .parse()
+        .expect("CLIENT_CONNECT_TO_ADDR is a socket address");
+
+    let listen_addr = match server::listen::bind(bind_addr).await {
+        Ok(listen_addr) => listen_addr,
+        Err(e) => {
+            eprintln!("Error hosting on {}: {}", bind_addr, e);
+            tokio::spawn(client::discovery::discover_servers());
+            return;
+        }
+    };
+    let local_client = client::udp_networking::init_local_connection().await;
+    server::listen::serve(level, None, Some(local_client));
+    let server_name = format!("{}'s game", name);
+    tokio::spawn(server::discovery::answer_discovery_queries(
+        server_name.clone(),
+        level_id.to_string(),
+    ));
+    tokio::spawn(server::discovery::register_with_master(
+        server_name,
+        level_id.to_string(),
+    ));
+    state.server = Some(std::net::SocketAddr::from((
+        [127, 0, 0, 1],
+        listen_addr.port(),
+    )));
+    state.hosting = true;
+    state.last_heard_from_server = common::util::get_utc_now();
+
+    if client::udp_networking::OUTBOUND_MESSAGE_QUEUE
+        .push(ClientToServerMessage::new(
+            ClientToServerMessageData::Join {
+                name: name.to_string(),
+            },
+        ))
+        .is_err()
+    {
+        eprintln!("Outbound message queue full: dropping message");
+    }
+}
+
+/// Plays a recorded match back instead of connecting anywhere.
+pub async fn watch_replay(
+    path: &str,
+    rl: &mut raylib::RaylibHandle,
+    rlt: &mut raylib::RaylibThread,
+    render_texture: &mut raylib::prelude::RenderTexture2D,
+) {
+    let mut ecs = World::new();
+    let mut state = client::state::State::new();
+    if let Err(e) = client::replay::open(&mut ecs, &mut state, std::path::Path::new(path)) {
+        eprintln!("Error opening replay {}: {}", path, e);
+        return;
+    }
+
+    while !rl.window_should_close() && state.running {
+        client::replay::process_replay_input(rl, &mut state);
+        let dt = rl.get_frame_time();
+        client::replay::advance(rl, &mut ecs, &mut state, dt).await;
+        client::graphics::render(rl, rlt, render_texture, &ecs, &state);
+    }
 }
@@ -0,0 +1,12 @@
+mod common;
+mod master;
+
+#[tokio::main]
+async fn main() {
+    let listen_addr = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| common::network_settings::MASTER_LISTEN_ADDR.to_string());
+    if let Err(e) = master::registry::run(&listen_addr).await {
+        eprintln!("Master server error: {:?}", e);
+    }
+}
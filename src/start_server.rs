@@ -3,8 +3,37 @@ mod server;
 
 #[tokio::main]
 async fn main() {
-    let _ = server::udp_networking::init().await;
+    let bind_addr = common::network_settings::CLIENT_CONNECT_TO_ADDR
+        .parse()
+        .expect("CLIENT_CONNECT_TO_ADDR is a socket address");
+    if let Err(e) = server::udp_networking::init(bind_addr).await {
+        eprintln!("Error binding {}: {}", bind_addr, e);
+        return;
+    }
 
-    let mut state = server::state::State::new();
-    server::game::main_loop(&mut state).await;
+    // server [level] [name]
+    let level_id = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| server::settings::DEFAULT_LEVEL.to_string());
+    let name = std::env::args()
+        .nth(2)
+        .unwrap_or_else(|| server::settings::DEFAULT_SERVER_NAME.to_string());
+    let level = match common::level::Level::load(&level_id) {
+        Ok(level) => level,
+        Err(e) => {
+            eprintln!("Error loading level {}: {}", level_id, e);
+            return;
+        }
+    };
+    println!("Loaded level {} ({})", level.name, level_id);
+
+    tokio::spawn(server::discovery::answer_discovery_queries(
+        name.clone(),
+        level_id.clone(),
+    ));
+    tokio::spawn(server::discovery::register_with_master(
+        name,
+        level_id.clone(),
+    ));
+    server::rooms::dispatch_loop(level, None, None).await;
 }